@@ -0,0 +1,769 @@
+//! Backends that execute pcli2 commands for [`crate::client::Pcli2Client`].
+//!
+//! [`SubprocessBackend`] runs the real pcli2 executable. [`FixtureBackend`]
+//! answers from a JSON fixture file, for deterministic tests and demos
+//! without a Physna account, and [`RecordingBackend`] wraps another backend
+//! and captures every call in the same fixture format.
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{info, warn};
+
+use crate::error::Pcli2Error;
+use crate::pcli::{MAX_PCLI2_OUTPUT_BYTES, PCLI2_BIN_ENV, PCLI2_TIMEOUT, shell_escape_arg};
+
+/// Future returned by [`Pcli2Backend`] methods
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Pcli2Error>> + Send + 'a>>;
+
+/// Executes pcli2 command lines
+///
+/// Backends report what pcli2 printed and how it exited; turning a
+/// non-zero exit into a [`Pcli2Error`] is left to the caller.
+pub trait Pcli2Backend: Send + Sync {
+    /// Short name reported by the status tool
+    fn name(&self) -> &'static str;
+
+    /// Run pcli2 with `argv`
+    fn run<'a>(&'a self, argv: &'a [String], label: &'a str) -> BackendFuture<'a, Pcli2Output>;
+
+    /// Run a pcli2 command that writes the file named by `--file`, returning its contents in [`Pcli2Output::file`]
+    ///
+    /// `argv` does not include `--file`; the backend chooses where the file goes.
+    fn download<'a>(&'a self, argv: &'a [String], label: &'a str)
+    -> BackendFuture<'a, Pcli2Output>;
+}
+
+/// What a finished pcli2 run printed and how it exited
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pcli2Output {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, or `None` if pcli2 was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Contents of the `--file` output of a successful download
+    pub file: Option<Vec<u8>>,
+    pub duration: Duration,
+}
+
+impl Pcli2Output {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// Standard output of a successful run, or the classified failure
+    pub fn into_stdout(self, label: &str) -> Result<String, Pcli2Error> {
+        self.check(label)?;
+        Ok(self.stdout.trim_end().to_string())
+    }
+
+    /// Downloaded file of a successful run, or the classified failure
+    pub fn into_file(self, label: &str) -> Result<Vec<u8>, Pcli2Error> {
+        self.check(label)?;
+        self.file
+            .ok_or_else(|| Pcli2Error::Failed(format!("{} failed: pcli2 wrote no file", label)))
+    }
+
+    fn check(&self, label: &str) -> Result<(), Pcli2Error> {
+        if self.success() {
+            return Ok(());
+        }
+        let status = match self.exit_code {
+            Some(code) => format!("exit status: {}", code),
+            None => "killed by signal".to_string(),
+        };
+        let message = format!(
+            "{} failed (code {}):\n{}\n{}",
+            label,
+            status,
+            self.stdout.trim_end(),
+            self.stderr.trim_end()
+        );
+        Err(Pcli2Error::from_exit(message, self.exit_code, &self.stderr))
+    }
+}
+
+/// Runs the pcli2 executable as a subprocess
+#[derive(Debug, Clone)]
+pub struct SubprocessBackend {
+    executable: String,
+}
+
+impl SubprocessBackend {
+    pub fn new(executable: impl Into<String>) -> Self {
+        Self {
+            executable: executable.into(),
+        }
+    }
+
+    /// Use the executable named by `PCLI2_BIN`, or `pcli2` from the `PATH`
+    pub fn from_env() -> Self {
+        Self::new(env::var(PCLI2_BIN_ENV).unwrap_or_else(|_| "pcli2".to_string()))
+    }
+
+    pub fn executable(&self) -> &str {
+        &self.executable
+    }
+
+    async fn spawn(&self, argv: &[String]) -> Result<Pcli2Output, Pcli2Error> {
+        let started = Instant::now();
+        let rendered = argv
+            .iter()
+            .map(|arg| shell_escape_arg(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!("▶ pcli2 {}", rendered);
+        let mut child = tokio::process::Command::new(&self.executable)
+            .args(argv)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Pcli2Error::SpawnFailed(format!("Failed to execute pcli2: {}", e)))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Pcli2Error::SpawnFailed("Failed to capture pcli2 stdout".to_string()))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| Pcli2Error::SpawnFailed("Failed to capture pcli2 stderr".to_string()))?;
+
+        let stdout_task = tokio::spawn(read_limited(stdout, MAX_PCLI2_OUTPUT_BYTES, "stdout"));
+        let stderr_task = tokio::spawn(read_limited(stderr, MAX_PCLI2_OUTPUT_BYTES, "stderr"));
+
+        let output = tokio::time::timeout(PCLI2_TIMEOUT, async {
+            let status = child
+                .wait()
+                .await
+                .map_err(|err| Pcli2Error::Failed(format!("Failed waiting for pcli2: {}", err)))?;
+            let stdout = stdout_task.await.map_err(|err| {
+                Pcli2Error::Failed(format!("Failed to read pcli2 stdout: {}", err))
+            })??;
+            let stderr = stderr_task.await.map_err(|err| {
+                Pcli2Error::Failed(format!("Failed to read pcli2 stderr: {}", err))
+            })??;
+            Ok((status, stdout, stderr))
+        })
+        .await;
+
+        let (status, stdout, stderr) = match output {
+            Ok(Ok(output)) => output,
+            Ok(Err(error)) => {
+                let _ = child.kill().await;
+                return Err(error);
+            }
+            Err(_) => {
+                let _ = child.kill().await;
+                return Err(Pcli2Error::Timeout(PCLI2_TIMEOUT));
+            }
+        };
+
+        Ok(Pcli2Output {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            exit_code: status.code(),
+            file: None,
+            duration: started.elapsed(),
+        })
+    }
+
+    async fn spawn_to_file(&self, argv: &[String]) -> Result<Pcli2Output, Pcli2Error> {
+        let temp_path = temp_download_path()?;
+        let temp_path_str = temp_path.to_str().ok_or_else(|| {
+            Pcli2Error::Failed("Failed to build temporary download path".to_string())
+        })?;
+        let mut argv = argv.to_vec();
+        argv.push("--file".to_string());
+        argv.push(temp_path_str.to_string());
+        let output = self.spawn(&argv).await;
+
+        let bytes = fs::read(&temp_path);
+        let _ = fs::remove_file(&temp_path);
+        let mut output = output?;
+        if output.success() {
+            let bytes = bytes.map_err(|err| {
+                Pcli2Error::Failed(format!("Failed to read pcli2 output file: {}", err))
+            })?;
+            output.file = Some(bytes);
+        }
+        Ok(output)
+    }
+}
+
+impl Default for SubprocessBackend {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl Pcli2Backend for SubprocessBackend {
+    fn name(&self) -> &'static str {
+        "subprocess"
+    }
+
+    fn run<'a>(&'a self, argv: &'a [String], _label: &'a str) -> BackendFuture<'a, Pcli2Output> {
+        Box::pin(self.spawn(argv))
+    }
+
+    fn download<'a>(
+        &'a self,
+        argv: &'a [String],
+        _label: &'a str,
+    ) -> BackendFuture<'a, Pcli2Output> {
+        Box::pin(self.spawn_to_file(argv))
+    }
+}
+
+fn temp_download_path() -> Result<PathBuf, Pcli2Error> {
+    // Thumbnails are the only files pcli2 writes
+    Ok(env::temp_dir().join(format!("{}.png", temp_name("download")?)))
+}
+
+/// Stands in for a staging directory in recorded and replayed command lines
+pub const UPLOAD_DIR_PLACEHOLDER: &str = "{upload_dir}";
+
+/// Kind of temporary name used for staging directories
+const UPLOAD_KIND: &str = "upload";
+
+/// A new directory for staging files uploaded by pcli2, removed by the caller
+pub(crate) fn temp_upload_dir() -> Result<PathBuf, Pcli2Error> {
+    let dir = env::temp_dir().join(temp_name(UPLOAD_KIND)?);
+    fs::create_dir(&dir).map_err(|err| {
+        Pcli2Error::Failed(format!(
+            "Failed to create upload directory {:?}: {}",
+            dir, err
+        ))
+    })?;
+    Ok(dir)
+}
+
+/// `argv` with staging directories replaced by [`UPLOAD_DIR_PLACEHOLDER`]
+///
+/// Staging directories are named after the process and the time, so an
+/// upload could otherwise never match its recording.
+pub fn stable_args(argv: &[String]) -> Vec<String> {
+    let prefix = env::temp_dir()
+        .join(format!("pcli2-{}-", UPLOAD_KIND))
+        .to_string_lossy()
+        .into_owned();
+    argv.iter()
+        .map(|arg| match arg.find(&prefix) {
+            Some(start) => {
+                let rest = &arg[start + prefix.len()..];
+                let end = rest.find(std::path::is_separator).unwrap_or(rest.len());
+                format!(
+                    "{}{}{}",
+                    &arg[..start],
+                    UPLOAD_DIR_PLACEHOLDER,
+                    &rest[end..]
+                )
+            }
+            None => arg.clone(),
+        })
+        .collect()
+}
+
+fn temp_name(kind: &str) -> Result<String, Pcli2Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| Pcli2Error::Failed(format!("Failed to read system time: {}", err)))?
+        .as_nanos();
+    let pid = std::process::id();
+    Ok(format!("pcli2-{}-{}-{}", kind, pid, timestamp))
+}
+
+pub async fn read_limited<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limit: usize,
+    label: &str,
+) -> Result<Vec<u8>, Pcli2Error> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let read = reader.read(&mut chunk).await.map_err(|err| {
+            Pcli2Error::Failed(format!("Failed to read pcli2 {}: {}", label, err))
+        })?;
+        if read == 0 {
+            break;
+        }
+        if buf.len() + read > limit {
+            return Err(Pcli2Error::OutputTooLarge(format!(
+                "pcli2 {} exceeded maximum output size of {} bytes",
+                label, limit
+            )));
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    Ok(buf)
+}
+
+/// Canned answer for one pcli2 command line
+///
+/// Fixture files hold an array of these; cassettes recorded with
+/// `serve --record` hold one per file.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Fixture {
+    /// Arguments to match; `*` matches any single argument
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stdout: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    /// Non-zero exit code, making the call fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Base64 contents of the file written by `--file` commands such as thumbnails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_base64: Option<String>,
+    /// How long the recorded call took
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl Fixture {
+    /// Fixture answering `argv` the way `output` did
+    pub fn recorded(argv: &[String], output: &Pcli2Output) -> Self {
+        Self {
+            args: stable_args(argv),
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            exit_code: match output.exit_code {
+                Some(0) => None,
+                // Killed by a signal
+                None => Some(-1),
+                code => code,
+            },
+            file_base64: output
+                .file
+                .as_ref()
+                .map(|bytes| BASE64_STANDARD.encode(bytes)),
+            duration_ms: Some(output.duration.as_millis() as u64),
+        }
+    }
+
+    fn matches(&self, argv: &[String]) -> bool {
+        self.args.len() == argv.len()
+            && self
+                .args
+                .iter()
+                .zip(argv)
+                .all(|(pattern, arg)| pattern == "*" || pattern == arg)
+    }
+
+    fn output(&self, label: &str) -> Result<Pcli2Output, Pcli2Error> {
+        let file = match &self.file_base64 {
+            Some(encoded) => Some(BASE64_STANDARD.decode(encoded).map_err(|err| {
+                Pcli2Error::Failed(format!("{} failed: invalid file_base64: {}", label, err))
+            })?),
+            None => None,
+        };
+        Ok(Pcli2Output {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            exit_code: Some(self.exit_code.unwrap_or(0)),
+            file,
+            duration: Duration::from_millis(self.duration_ms.unwrap_or(0)),
+        })
+    }
+}
+
+/// Answers pcli2 calls from fixtures instead of running pcli2
+///
+/// The first fixture whose `args` match the command line wins; a command
+/// with no matching fixture fails. When several fixtures have exactly the
+/// same `args`, as in a cassette with repeated calls, they answer
+/// successive calls in order and the last one repeats.
+#[derive(Debug, Default)]
+pub struct FixtureBackend {
+    fixtures: Vec<Fixture>,
+    served: Mutex<HashMap<Vec<String>, usize>>,
+}
+
+impl FixtureBackend {
+    pub fn new(fixtures: Vec<Fixture>) -> Self {
+        Self {
+            fixtures,
+            served: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load fixtures from a JSON file holding an array of [`Fixture`]s
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read fixtures {:?}: {}", path, err))?;
+        let fixtures = serde_json::from_str(&contents)
+            .map_err(|err| format!("Invalid fixtures {:?}: {}", path, err))?;
+        Ok(Self::new(fixtures))
+    }
+
+    /// Load a cassette directory written by [`RecordingBackend::with_cassette`]
+    pub fn load_cassette(dir: &Path) -> Result<Self, String> {
+        let mut fixtures = Vec::new();
+        for path in cassette_files(dir)? {
+            let contents = fs::read_to_string(&path)
+                .map_err(|err| format!("Failed to read cassette entry {:?}: {}", path, err))?;
+            let fixture = serde_json::from_str(&contents)
+                .map_err(|err| format!("Invalid cassette entry {:?}: {}", path, err))?;
+            fixtures.push(fixture);
+        }
+        Ok(Self::new(fixtures))
+    }
+
+    pub fn fixtures(&self) -> &[Fixture] {
+        &self.fixtures
+    }
+
+    fn answer(&self, argv: &[String], label: &str) -> Result<Pcli2Output, Pcli2Error> {
+        info!("▶ pcli2 {} (fixture)", argv.join(" "));
+        let argv = stable_args(argv);
+        let first = self
+            .fixtures
+            .iter()
+            .find(|fixture| fixture.matches(&argv))
+            .ok_or_else(|| {
+                Pcli2Error::Failed(format!(
+                    "{} failed: no fixture matches `pcli2 {}`",
+                    label,
+                    argv.join(" ")
+                ))
+            })?;
+        let same: Vec<&Fixture> = self
+            .fixtures
+            .iter()
+            .filter(|fixture| fixture.args == first.args)
+            .collect();
+        let mut served = self
+            .served
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = served.entry(first.args.clone()).or_insert(0);
+        let fixture = same[(*count).min(same.len() - 1)];
+        *count += 1;
+        fixture.output(label)
+    }
+}
+
+impl Pcli2Backend for FixtureBackend {
+    fn name(&self) -> &'static str {
+        "fixture"
+    }
+
+    fn run<'a>(&'a self, argv: &'a [String], label: &'a str) -> BackendFuture<'a, Pcli2Output> {
+        let result = self.answer(argv, label);
+        Box::pin(async move { result })
+    }
+
+    fn download<'a>(
+        &'a self,
+        argv: &'a [String],
+        label: &'a str,
+    ) -> BackendFuture<'a, Pcli2Output> {
+        let result = self.answer(argv, label);
+        Box::pin(async move { result })
+    }
+}
+
+/// Cassette entries in recording order
+///
+/// Entries are numbered, so they are ordered by number rather than by name.
+fn cassette_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Failed to read cassette {:?}: {}", dir, err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort_by_key(|path| {
+        let number = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        (number.is_none(), number, path.clone())
+    });
+    Ok(files)
+}
+
+/// Passes calls to another backend and records them as fixtures
+///
+/// Calls that fail without pcli2 exiting, such as spawn failures and
+/// timeouts, are recorded with exit code 1 and the error message as stderr.
+pub struct RecordingBackend {
+    inner: Arc<dyn Pcli2Backend>,
+    recorded: Mutex<Vec<Fixture>>,
+    /// Cassette directory and the number of entries it held before recording
+    cassette: Option<(PathBuf, usize)>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn Pcli2Backend>) -> Self {
+        Self {
+            inner,
+            recorded: Mutex::new(Vec::new()),
+            cassette: None,
+        }
+    }
+
+    /// Also write each call to `dir` as soon as it finishes, one JSON file per call
+    ///
+    /// Calls are appended after any already in the cassette.
+    pub fn with_cassette(inner: Arc<dyn Pcli2Backend>, dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Failed to create cassette {:?}: {}", dir, err))?;
+        let existing = cassette_files(dir)?.len();
+        Ok(Self {
+            inner,
+            recorded: Mutex::new(Vec::new()),
+            cassette: Some((dir.to_path_buf(), existing)),
+        })
+    }
+
+    /// Calls recorded so far, oldest first
+    pub fn fixtures(&self) -> Vec<Fixture> {
+        self.lock().clone()
+    }
+
+    /// Write the recorded calls as a fixture file readable by [`FixtureBackend::load`]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(&self.fixtures())
+            .map_err(|err| format!("Failed to serialize fixtures: {}", err))?;
+        fs::write(path, contents)
+            .map_err(|err| format!("Failed to write fixtures {:?}: {}", path, err))
+    }
+
+    fn record(&self, argv: &[String], result: &Result<Pcli2Output, Pcli2Error>) {
+        let fixture = match result {
+            Ok(output) => Fixture::recorded(argv, output),
+            Err(error) => Fixture {
+                args: stable_args(argv),
+                stderr: error.to_string(),
+                exit_code: Some(1),
+                ..Fixture::default()
+            },
+        };
+        let mut recorded = self.lock();
+        if let Some((dir, existing)) = &self.cassette {
+            let path = dir.join(format!("{:010}.json", existing + recorded.len() + 1));
+            let written = serde_json::to_string_pretty(&fixture)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| fs::write(&path, contents).map_err(|err| err.to_string()));
+            if let Err(err) = written {
+                warn!("Failed to write cassette entry {:?}: {}", path, err);
+            }
+        }
+        recorded.push(fixture);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Fixture>> {
+        self.recorded
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Pcli2Backend for RecordingBackend {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn run<'a>(&'a self, argv: &'a [String], label: &'a str) -> BackendFuture<'a, Pcli2Output> {
+        Box::pin(async move {
+            let result = self.inner.run(argv, label).await;
+            self.record(argv, &result);
+            result
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        argv: &'a [String],
+        label: &'a str,
+    ) -> BackendFuture<'a, Pcli2Output> {
+        Box::pin(async move {
+            let result = self.inner.download(argv, label).await;
+            self.record(argv, &result);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn fixture(args: &[&str], stdout: &str) -> Fixture {
+        Fixture {
+            args: argv(args),
+            stdout: stdout.to_string(),
+            ..Fixture::default()
+        }
+    }
+
+    async fn run(backend: &dyn Pcli2Backend, args: &[&str]) -> Result<String, Pcli2Error> {
+        let label = format!("pcli2 {}", args[..2].join(" "));
+        backend.run(&argv(args), &label).await?.into_stdout(&label)
+    }
+
+    async fn download(backend: &dyn Pcli2Backend, args: &[&str]) -> Result<Vec<u8>, Pcli2Error> {
+        let label = format!("pcli2 {}", args[..2].join(" "));
+        backend
+            .download(&argv(args), &label)
+            .await?
+            .into_file(&label)
+    }
+
+    #[tokio::test]
+    async fn test_fixture_backend_matches_wildcards() {
+        let backend = FixtureBackend::new(vec![
+            fixture(&["asset", "get", "--uuid", "a"], "asset a"),
+            fixture(&["asset", "get", "--uuid", "*"], "any asset"),
+        ]);
+        assert_eq!(
+            run(&backend, &["asset", "get", "--uuid", "a"])
+                .await
+                .as_deref(),
+            Ok("asset a")
+        );
+        assert_eq!(
+            run(&backend, &["asset", "get", "--uuid", "b"])
+                .await
+                .as_deref(),
+            Ok("any asset")
+        );
+        let missing = run(&backend, &["asset", "get", "--path", "/a.stl"])
+            .await
+            .unwrap_err();
+        assert!(missing.to_string().contains("no fixture matches"));
+    }
+
+    #[tokio::test]
+    async fn test_failing_fixture_is_classified() {
+        let mut failing = fixture(&["asset", "dependencies", "--uuid", "x"], "");
+        failing.exit_code = Some(1);
+        failing.stderr = "Error: Asset not found".to_string();
+        let backend = FixtureBackend::new(vec![failing]);
+        let error = run(&backend, &["asset", "dependencies", "--uuid", "x"])
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_repeated_fixtures_answer_in_order() {
+        let backend = FixtureBackend::new(vec![
+            fixture(&["tenant", "state"], "first"),
+            fixture(&["tenant", "*"], "other"),
+            fixture(&["tenant", "state"], "second"),
+        ]);
+        let mut answers = Vec::new();
+        for _ in 0..3 {
+            answers.push(run(&backend, &["tenant", "state"]).await.unwrap());
+        }
+        assert_eq!(answers, ["first", "second", "second"]);
+        assert_eq!(
+            run(&backend, &["tenant", "list"]).await.as_deref(),
+            Ok("other")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_staged_uploads_replay_from_any_directory() {
+        let staged = |dir: &Path| {
+            argv(&[
+                "asset",
+                "create",
+                "--file",
+                &dir.join("part.stl").to_string_lossy(),
+            ])
+        };
+        let recorded_dir = temp_upload_dir().unwrap();
+        let recorded = Fixture::recorded(
+            &staged(&recorded_dir),
+            &Pcli2Output {
+                stdout: "created".to_string(),
+                exit_code: Some(0),
+                ..Pcli2Output::default()
+            },
+        );
+        let _ = fs::remove_dir_all(&recorded_dir);
+        let placeholder = Path::new(UPLOAD_DIR_PLACEHOLDER).join("part.stl");
+        assert_eq!(recorded.args[3], placeholder.to_string_lossy());
+
+        let replay = FixtureBackend::new(vec![recorded]);
+        let replay_dir = temp_upload_dir().unwrap();
+        let label = "pcli2 asset create";
+        let output = replay
+            .run(&staged(&replay_dir), label)
+            .await
+            .and_then(|output| output.into_stdout(label));
+        let _ = fs::remove_dir_all(&replay_dir);
+        assert_eq!(output.as_deref(), Ok("created"));
+    }
+
+    #[test]
+    fn test_cassette_entries_sort_by_number() {
+        let dir = env::temp_dir().join(format!("pcli2-cassette-order-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["100000.json", "99999.json", "00002.json"] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+        let names: Vec<String> = cassette_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(names, ["00002.json", "99999.json", "100000.json"]);
+    }
+
+    #[tokio::test]
+    async fn test_recordings_replay_through_fixture_backend() {
+        let mut thumbnail = fixture(&["asset", "thumbnail", "--uuid", "a"], "");
+        thumbnail.file_base64 = Some(BASE64_STANDARD.encode(b"png"));
+        let source = FixtureBackend::new(vec![fixture(&["tenant", "list"], "acme"), thumbnail]);
+        let dir = env::temp_dir().join(format!("pcli2-cassette-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recorder = RecordingBackend::with_cassette(Arc::new(source), &dir).unwrap();
+        run(&recorder, &["tenant", "list"]).await.unwrap();
+        download(&recorder, &["asset", "thumbnail", "--uuid", "a"])
+            .await
+            .unwrap();
+        assert!(run(&recorder, &["tenant", "use"]).await.is_err());
+
+        let path = env::temp_dir().join(format!("pcli2-fixtures-{}.json", std::process::id()));
+        recorder.save(&path).unwrap();
+        let from_file = FixtureBackend::load(&path).unwrap();
+        let from_cassette = FixtureBackend::load_cassette(&dir).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&dir);
+
+        for replay in [from_file, from_cassette] {
+            assert_eq!(replay.fixtures().len(), 3);
+            assert_eq!(
+                run(&replay, &["tenant", "list"]).await.as_deref(),
+                Ok("acme")
+            );
+            assert_eq!(
+                download(&replay, &["asset", "thumbnail", "--uuid", "a"]).await,
+                Ok(b"png".to_vec())
+            );
+            let error = run(&replay, &["tenant", "use"]).await.unwrap_err();
+            assert!(error.to_string().contains("no fixture matches"));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! `pcli2-mcp cache` commands for inspecting and managing the thumbnail cache.
+//!
+//! The commands work on the cache directory itself, so they need no running
+//! server and no LLM. Changes take the same directory lock as a live server,
+//! which keeps a purge from racing a thumbnail being written.
+
+use crate::cli::{
+    ARG_ALL, ARG_CACHE_DIR, ARG_EXPIRED, ARG_FILE, ARG_JSON, ARG_KEY, ARG_SOURCE, CMD_EXPORT,
+    CMD_LIST, CMD_PURGE, CMD_SHOW, CMD_STATS, DEFAULT_HOST, DEFAULT_PORT_STR,
+};
+use crate::signing::{DEFAULT_URL_TTL, UrlSigner, default_signing_key_path};
+use crate::thumbnail::{
+    DEFAULT_TTL, PurgeFilter, ThumbnailCache, ThumbnailCacheConfig, ThumbnailEntry,
+    default_cache_dir,
+};
+use anyhow::{Result, anyhow};
+use chrono::{TimeZone, Utc};
+use clap::ArgMatches;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Size of the cache as reported by `cache stats`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CacheSummary {
+    pub directory: PathBuf,
+    /// Thumbnails and variants
+    pub entries: usize,
+    pub originals: usize,
+    pub variants: usize,
+    pub bytes: u64,
+    /// Entries past their TTL, removed by the next sweep or `cache purge --expired`
+    pub expired: usize,
+}
+
+impl CacheSummary {
+    pub fn new(directory: &Path, entries: &[ThumbnailEntry]) -> Self {
+        let variants = entries
+            .iter()
+            .filter(|entry| entry.key.contains('_'))
+            .count();
+        Self {
+            directory: directory.to_path_buf(),
+            entries: entries.len(),
+            originals: entries.len() - variants,
+            variants,
+            bytes: entries.iter().map(|entry| entry.bytes).sum(),
+            expired: entries.iter().filter(|entry| entry.expired).count(),
+        }
+    }
+}
+
+pub fn run_cache(matches: &ArgMatches) -> Result<()> {
+    let Some((command, sub_matches)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let cache = open_cache(sub_matches.get_one::<PathBuf>(ARG_CACHE_DIR))?;
+    match command {
+        CMD_LIST => run_list(&cache, sub_matches.get_flag(ARG_JSON)),
+        CMD_STATS => run_stats(&cache, sub_matches.get_flag(ARG_JSON)),
+        CMD_SHOW => run_show(&cache, required(sub_matches.get_one::<String>(ARG_KEY))?),
+        CMD_PURGE => run_purge(&cache, &purge_filter(sub_matches)),
+        CMD_EXPORT => run_export(
+            &cache,
+            required(sub_matches.get_one::<String>(ARG_KEY))?,
+            required(sub_matches.get_one::<PathBuf>(ARG_FILE))?,
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Open the cache in `dir` with the server's default TTL
+fn open_cache(dir: Option<&PathBuf>) -> Result<ThumbnailCache> {
+    let dir = match dir {
+        Some(dir) => dir.clone(),
+        None => default_cache_dir().map_err(|err| anyhow!(err))?,
+    };
+    // Links are never handed out here, so the base URL does not matter
+    let port = DEFAULT_PORT_STR.parse().unwrap_or(8080);
+    let mut config = ThumbnailCacheConfig::new(dir, DEFAULT_TTL, DEFAULT_HOST, port);
+    // Asset keys are derived from the signing key, so purging by UUID needs it
+    if let Ok(path) = default_signing_key_path()
+        && path.exists()
+        && let Ok(signer) = UrlSigner::load_or_create(&path, DEFAULT_URL_TTL)
+    {
+        config = config.with_signer(signer);
+    }
+    ThumbnailCache::new(config).map_err(|err| anyhow!(err))
+}
+
+fn run_list(cache: &ThumbnailCache, json: bool) -> Result<()> {
+    let entries = cache.list().map_err(|err| anyhow!(err))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        print!("{}", format_entries(&entries));
+    }
+    Ok(())
+}
+
+fn run_stats(cache: &ThumbnailCache, json: bool) -> Result<()> {
+    let entries = cache.list().map_err(|err| anyhow!(err))?;
+    let summary = CacheSummary::new(cache.cache_dir(), &entries);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!("Directory: {}", summary.directory.display());
+        println!(
+            "Entries:   {} ({} thumbnails, {} variants)",
+            summary.entries, summary.originals, summary.variants
+        );
+        println!("Size:      {}", format_bytes(summary.bytes));
+        println!("Expired:   {}", summary.expired);
+    }
+    Ok(())
+}
+
+fn run_show(cache: &ThumbnailCache, key: &str) -> Result<()> {
+    let entry = cache
+        .entry(key)
+        .ok_or_else(|| anyhow!("No thumbnail is cached under '{}'", key))?;
+    let mut details = serde_json::to_value(&entry)?;
+    if let (Some(metadata), Value::Object(map)) = (&entry.metadata, &mut details) {
+        let expires_at = metadata.cached_at + cache.ttl().as_millis() as i64;
+        map.insert(
+            "expires_at".to_string(),
+            Value::from(format_time(expires_at)),
+        );
+    }
+    println!("{}", serde_json::to_string_pretty(&details)?);
+    Ok(())
+}
+
+fn run_purge(cache: &ThumbnailCache, filter: &PurgeFilter) -> Result<()> {
+    let removed = cache.purge(filter).map_err(|err| anyhow!(err))?;
+    println!(
+        "Removed {} thumbnail(s) from {}",
+        removed,
+        cache.cache_dir().display()
+    );
+    Ok(())
+}
+
+fn run_export(cache: &ThumbnailCache, key: &str, file: &Path) -> Result<()> {
+    let bytes = cache.export(key, file).map_err(|err| anyhow!(err))?;
+    println!(
+        "Exported {} ({}) to {}",
+        key,
+        format_bytes(bytes),
+        file.display()
+    );
+    Ok(())
+}
+
+fn purge_filter(matches: &ArgMatches) -> PurgeFilter {
+    if let Some(source) = matches.get_one::<String>(ARG_SOURCE) {
+        PurgeFilter::Source(source.clone())
+    } else if matches.get_flag(ARG_ALL) {
+        PurgeFilter::All
+    } else {
+        debug_assert!(matches.get_flag(ARG_EXPIRED));
+        PurgeFilter::Expired
+    }
+}
+
+fn required<T>(value: Option<&T>) -> Result<&T> {
+    value.ok_or_else(|| anyhow!("Missing required argument"))
+}
+
+/// Table of entries, one per line, under a header
+pub fn format_entries(entries: &[ThumbnailEntry]) -> String {
+    if entries.is_empty() {
+        return "The thumbnail cache is empty\n".to_string();
+    }
+    let width = entries
+        .iter()
+        .map(|entry| entry.key.len())
+        .max()
+        .unwrap_or(0)
+        .max("KEY".len());
+    let mut out = format!(
+        "{:<width$}  {:>9}  {:<19}  {:<19}  SOURCE\n",
+        "KEY",
+        "SIZE",
+        "CACHED (UTC)",
+        "LAST USED (UTC)",
+        width = width
+    );
+    for entry in entries {
+        let (cached, used, source) = match &entry.metadata {
+            Some(metadata) => (
+                format_time(metadata.cached_at),
+                metadata
+                    .last_accessed
+                    .map(format_time)
+                    .unwrap_or_else(|| "-".to_string()),
+                metadata.source.as_str(),
+            ),
+            None => ("-".to_string(), "-".to_string(), "(no metadata)"),
+        };
+        let expired = if entry.expired { " (expired)" } else { "" };
+        out.push_str(&format!(
+            "{:<width$}  {:>9}  {:<19}  {:<19}  {}{}\n",
+            entry.key,
+            format_bytes(entry.bytes),
+            cached,
+            used,
+            source,
+            expired,
+            width = width
+        ));
+    }
+    out
+}
+
+fn format_time(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    match bytes as f64 {
+        size if size < KB => format!("{} B", bytes),
+        size if size < KB * KB => format!("{:.1} KB", size / KB),
+        size => format!("{:.1} MB", size / (KB * KB)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thumbnail::ThumbnailMetadata;
+
+    fn entry(key: &str, bytes: u64, expired: bool) -> ThumbnailEntry {
+        ThumbnailEntry {
+            key: key.to_string(),
+            path: Some(PathBuf::from(format!("/cache/{}.png", key))),
+            bytes,
+            metadata: Some(ThumbnailMetadata {
+                cached_at: 1_700_000_000_000,
+                source: format!("/Root/{}.stl", key),
+                content_hash: None,
+                last_accessed: None,
+            }),
+            expired,
+        }
+    }
+
+    #[test]
+    fn test_summary_counts_variants_and_expired_entries() {
+        let entries = [
+            entry("a", 2048, false),
+            entry("a_w0-h0-m128-jpeg", 512, false),
+            entry("b", 100, true),
+        ];
+        let summary = CacheSummary::new(Path::new("/cache"), &entries);
+        assert_eq!(
+            summary,
+            CacheSummary {
+                directory: PathBuf::from("/cache"),
+                entries: 3,
+                originals: 2,
+                variants: 1,
+                bytes: 2660,
+                expired: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_format_entries() {
+        assert_eq!(format_entries(&[]), "The thumbnail cache is empty\n");
+
+        let mut missing = entry("c", 10, false);
+        missing.metadata = None;
+        let table = format_entries(&[entry("a", 2048, false), entry("b", 100, true), missing]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("KEY  "));
+        assert_eq!(
+            lines[1],
+            "a       2.0 KB  2023-11-14 22:13:20  -                    /Root/a.stl"
+        );
+        assert!(lines[2].ends_with("/Root/b.stl (expired)"));
+        assert!(lines[3].ends_with("(no metadata)"));
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MB");
+    }
+}
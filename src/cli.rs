@@ -1,18 +1,71 @@
-use clap::{Arg, Command, value_parser};
+use crate::limits::parse_tool_limit;
+use crate::public_url::parse_public_url;
+use crate::s3::{DEFAULT_PREFIX, DEFAULT_REGION};
+use crate::store::{STORE_FS, STORE_MEMORY, STORE_S3};
+use clap::{Arg, ArgAction, ArgGroup, Command, builder::RangedU64ValueParser, value_parser};
+use std::path::PathBuf;
 
 pub const CMD_SERVE: &str = "serve";
 pub const CMD_CONFIG: &str = "config";
 pub const CMD_HELP: &str = "help";
+pub const CMD_INSTALL: &str = "install";
+pub const CMD_UNINSTALL: &str = "uninstall";
+pub const CMD_CACHE: &str = "cache";
+pub const CMD_LIST: &str = "list";
+pub const CMD_STATS: &str = "stats";
+pub const CMD_SHOW: &str = "show";
+pub const CMD_PURGE: &str = "purge";
+pub const CMD_EXPORT: &str = "export";
 
 pub const ARG_PORT: &str = "port";
 pub const ARG_CLIENT: &str = "client";
 pub const ARG_COMMAND: &str = "command";
 pub const ARG_HOST: &str = "host";
 pub const ARG_LOG_LEVEL: &str = "log_level";
+pub const ARG_PATH: &str = "path";
+pub const ARG_DRY_RUN: &str = "dry_run";
+pub const ARG_MAX_TOOL_TIMEOUT: &str = "max_tool_timeout";
+pub const ARG_MAX_CONCURRENT: &str = "max_concurrent";
+pub const ARG_MAX_CONCURRENT_PER_TENANT: &str = "max_concurrent_per_tenant";
+pub const ARG_MAX_QUEUE: &str = "max_queue";
+pub const ARG_TOOL_LIMIT: &str = "tool_limit";
+pub const ARG_FIXTURES: &str = "fixtures";
+pub const ARG_RECORD: &str = "record";
+pub const ARG_REPLAY: &str = "replay";
+pub const ARG_MAX_RESPONSE_BYTES: &str = "max_response_bytes";
+pub const ARG_MAX_RESPONSE_TOKENS: &str = "max_response_tokens";
+pub const ARG_THUMBNAIL_CACHE_MAX_MB: &str = "thumbnail_cache_max_mb";
+pub const ARG_THUMBNAIL_CACHE_MAX_ENTRIES: &str = "thumbnail_cache_max_entries";
+pub const ARG_THUMBNAIL_SWEEP_INTERVAL: &str = "thumbnail_sweep_interval";
+pub const ARG_PUBLIC_URL: &str = "public_url";
+pub const ARG_THUMBNAIL_URL_TTL: &str = "thumbnail_url_ttl";
+pub const ARG_SIGNING_KEY_FILE: &str = "signing_key_file";
+pub const ARG_THUMBNAIL_STORE: &str = "thumbnail_store";
+pub const ARG_S3_ENDPOINT: &str = "s3_endpoint";
+pub const ARG_S3_BUCKET: &str = "s3_bucket";
+pub const ARG_S3_REGION: &str = "s3_region";
+pub const ARG_S3_PREFIX: &str = "s3_prefix";
+pub const ARG_S3_PRESIGN: &str = "s3_presign";
+pub const ARG_UPLOAD_ROOT: &str = "upload_root";
+pub const ARG_CACHE_DIR: &str = "cache_dir";
+pub const ARG_JSON: &str = "json";
+pub const ARG_KEY: &str = "key";
+pub const ARG_FILE: &str = "file";
+pub const ARG_EXPIRED: &str = "expired";
+pub const ARG_ALL: &str = "all";
+pub const ARG_SOURCE: &str = "source";
 
 pub const DEFAULT_PORT_STR: &str = "8080";
 pub const DEFAULT_HOST: &str = "localhost";
 pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const DEFAULT_MAX_TOOL_TIMEOUT_STR: &str = "1800";
+pub const DEFAULT_MAX_CONCURRENT_STR: &str = "8";
+pub const DEFAULT_MAX_CONCURRENT_PER_TENANT_STR: &str = "4";
+pub const DEFAULT_MAX_QUEUE_STR: &str = "64";
+pub const DEFAULT_MAX_RESPONSE_BYTES_STR: &str = "65536";
+pub const DEFAULT_THUMBNAIL_CACHE_MAX_MB_STR: &str = "1024";
+pub const DEFAULT_THUMBNAIL_SWEEP_INTERVAL_STR: &str = "600";
+pub const DEFAULT_THUMBNAIL_URL_TTL_STR: &str = "86400";
 
 pub const CLIENT_CLAUDE: &str = "claude";
 pub const CLIENT_QWEN_CODE: &str = "qwen-code";
@@ -30,6 +83,7 @@ pub fn build_cli() -> Command {
         .disable_help_subcommand(true)
         .subcommand(serve_command())
         .subcommand(config_command())
+        .subcommand(cache_command())
         .subcommand(help_command())
 }
 
@@ -59,35 +113,330 @@ fn serve_command() -> Command {
                 .default_value(DEFAULT_LOG_LEVEL)
                 .help("Logging level (e.g. trace, debug, info, warn, error)"),
         )
+        .arg(
+            Arg::new(ARG_MAX_TOOL_TIMEOUT)
+                .long("max-tool-timeout")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64).range(1..=1800))
+                .default_value(DEFAULT_MAX_TOOL_TIMEOUT_STR)
+                .help("Maximum timeout a client may request per tool call, in seconds"),
+        )
+        .arg(
+            Arg::new(ARG_MAX_CONCURRENT)
+                .long("max-concurrent")
+                .value_name("N")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value(DEFAULT_MAX_CONCURRENT_STR)
+                .help("Maximum number of pcli2 processes running at once"),
+        )
+        .arg(
+            Arg::new(ARG_MAX_CONCURRENT_PER_TENANT)
+                .long("max-concurrent-per-tenant")
+                .value_name("N")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value(DEFAULT_MAX_CONCURRENT_PER_TENANT_STR)
+                .help("Maximum number of pcli2 processes running at once for one tenant"),
+        )
+        .arg(
+            Arg::new(ARG_MAX_QUEUE)
+                .long("max-queue")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value(DEFAULT_MAX_QUEUE_STR)
+                .help("Maximum number of tool calls waiting for a pcli2 slot before rejecting"),
+        )
+        .arg(
+            Arg::new(ARG_TOOL_LIMIT)
+                .long("tool-limit")
+                .value_name("TOOL=N")
+                .action(ArgAction::Append)
+                .value_parser(parse_tool_limit)
+                .help("Per-tool concurrency limit, e.g. pcli2_folder_part_match=1 (repeatable)"),
+        )
+        .arg(
+            Arg::new(ARG_MAX_RESPONSE_BYTES)
+                .long("max-response-bytes")
+                .value_name("BYTES")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1024..))
+                .default_value(DEFAULT_MAX_RESPONSE_BYTES_STR)
+                .help("Largest tool output returned at once; longer output is paged"),
+        )
+        .arg(
+            Arg::new(ARG_MAX_RESPONSE_TOKENS)
+                .long("max-response-tokens")
+                .value_name("TOKENS")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(256..))
+                .help("Like --max-response-bytes, estimating 4 bytes per token"),
+        )
+        .arg(
+            Arg::new(ARG_THUMBNAIL_CACHE_MAX_MB)
+                .long("thumbnail-cache-max-mb")
+                .value_name("MB")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value(DEFAULT_THUMBNAIL_CACHE_MAX_MB_STR)
+                .help("Largest size of the thumbnail cache before least recently used thumbnails are evicted"),
+        )
+        .arg(
+            Arg::new(ARG_THUMBNAIL_CACHE_MAX_ENTRIES)
+                .long("thumbnail-cache-max-entries")
+                .value_name("N")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .help("Largest number of cached thumbnails before least recently used ones are evicted"),
+        )
+        .arg(
+            Arg::new(ARG_THUMBNAIL_SWEEP_INTERVAL)
+                .long("thumbnail-sweep-interval")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64))
+                .default_value(DEFAULT_THUMBNAIL_SWEEP_INTERVAL_STR)
+                .help("Seconds between background sweeps of expired and excess thumbnails (0 disables)"),
+        )
+        .arg(
+            Arg::new(ARG_PUBLIC_URL)
+                .long("public-url")
+                .value_name("URL")
+                .value_parser(parse_public_url)
+                .help("Externally reachable URL for thumbnail links, or 'request' to use the origin of each MCP request"),
+        )
+        .arg(
+            Arg::new(ARG_THUMBNAIL_URL_TTL)
+                .long("thumbnail-url-ttl")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64).range(60..))
+                .default_value(DEFAULT_THUMBNAIL_URL_TTL_STR)
+                .help("How long signed thumbnail links stay valid, in seconds"),
+        )
+        .arg(
+            Arg::new(ARG_SIGNING_KEY_FILE)
+                .long("signing-key-file")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Secret for signing thumbnail links, created if missing (default: ~/.pcli2-mcp/signing.key)"),
+        )
+        .arg(
+            Arg::new(ARG_THUMBNAIL_STORE)
+                .long("thumbnail-store")
+                .value_name("STORE")
+                .value_parser([STORE_FS, STORE_MEMORY, STORE_S3])
+                .default_value(STORE_FS)
+                .help("Where thumbnails are kept: the cache directory, process memory, or an S3-compatible bucket"),
+        )
+        .arg(
+            Arg::new(ARG_S3_ENDPOINT)
+                .long("s3-endpoint")
+                .value_name("URL")
+                .help("S3-compatible service URL, e.g. http://minio:9000 (default: AWS S3 in --s3-region)"),
+        )
+        .arg(
+            Arg::new(ARG_S3_BUCKET)
+                .long("s3-bucket")
+                .value_name("BUCKET")
+                .required_if_eq(ARG_THUMBNAIL_STORE, STORE_S3)
+                .help("Bucket for --thumbnail-store s3; credentials come from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"),
+        )
+        .arg(
+            Arg::new(ARG_S3_REGION)
+                .long("s3-region")
+                .value_name("REGION")
+                .default_value(DEFAULT_REGION)
+                .help("Region used to sign S3 requests"),
+        )
+        .arg(
+            Arg::new(ARG_S3_PREFIX)
+                .long("s3-prefix")
+                .value_name("PREFIX")
+                .default_value(DEFAULT_PREFIX)
+                .help("Key prefix of cached thumbnails in the bucket"),
+        )
+        .arg(
+            Arg::new(ARG_S3_PRESIGN)
+                .long("s3-presign")
+                .action(ArgAction::SetTrue)
+                .help("Redirect thumbnail requests to presigned bucket URLs instead of streaming the images"),
+        )
+        .arg(
+            Arg::new(ARG_FIXTURES)
+                .long("fixtures")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Answer pcli2 calls from a JSON fixture file instead of running pcli2"),
+        )
+        .arg(
+            Arg::new(ARG_RECORD)
+                .long("record")
+                .value_name("DIR")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all([ARG_FIXTURES, ARG_REPLAY])
+                .help("Record every pcli2 call into a cassette directory"),
+        )
+        .arg(
+            Arg::new(ARG_REPLAY)
+                .long("replay")
+                .value_name("DIR")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with(ARG_FIXTURES)
+                .help("Answer pcli2 calls from a cassette recorded with --record"),
+        )
+        .arg(
+            Arg::new(ARG_UPLOAD_ROOT)
+                .long("upload-root")
+                .value_name("DIR")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory that server-local files uploaded by the asset create tools must be in"),
+        )
 }
 
 fn config_command() -> Command {
     Command::new(CMD_CONFIG)
         .about("Print JSON config for MCP clients")
+        .args_conflicts_with_subcommands(true)
+        .arg(client_arg("Target client config to render"))
+        .arg(config_host_arg())
+        .arg(config_port_arg())
+        .subcommand(config_install_command())
+        .subcommand(config_uninstall_command())
+}
+
+fn config_install_command() -> Command {
+    Command::new(CMD_INSTALL)
+        .about("Merge the pcli2 server entry into a client's config file")
+        .arg(client_arg("Client whose config file should be updated"))
+        .arg(config_host_arg())
+        .arg(config_port_arg())
+        .arg(config_path_arg())
+        .arg(dry_run_arg())
+}
+
+fn config_uninstall_command() -> Command {
+    Command::new(CMD_UNINSTALL)
+        .about("Remove the pcli2 server entry from a client's config file")
+        .arg(client_arg("Client whose config file should be updated"))
+        .arg(config_path_arg())
+        .arg(dry_run_arg())
+}
+
+fn cache_command() -> Command {
+    Command::new(CMD_CACHE)
+        .about("Inspect and manage the thumbnail cache")
+        .subcommand_required(true)
         .arg(
-            Arg::new(ARG_CLIENT)
-                .long("client")
-                .value_name("CLIENT")
-                .value_parser([CLIENT_CLAUDE, CLIENT_QWEN_CODE, CLIENT_QWEN_AGENT])
-                .default_value(CLIENT_CLAUDE)
-                .help("Target client config to render"),
+            Arg::new(ARG_CACHE_DIR)
+                .long("cache-dir")
+                .value_name("DIR")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Thumbnail cache directory (defaults to ~/.pcli2-mcp/thumbnails)"),
         )
-        .arg(
-            Arg::new(ARG_HOST)
-                .long("host")
-                .value_name("HOST")
-                .default_value(DEFAULT_HOST)
-                .help("Host for the MCP server URL"),
+        .subcommand(
+            Command::new(CMD_LIST)
+                .about("List cached thumbnails and variants")
+                .arg(json_arg()),
         )
-        .arg(
-            Arg::new(ARG_PORT)
-                .short('p')
-                .long("port")
-                .value_name("PORT")
-                .value_parser(value_parser!(u16))
-                .default_value(DEFAULT_PORT_STR)
-                .help("Port the local server will listen on"),
+        .subcommand(
+            Command::new(CMD_STATS)
+                .about("Show the size of the thumbnail cache")
+                .arg(json_arg()),
+        )
+        .subcommand(
+            Command::new(CMD_SHOW)
+                .about("Show the metadata of a cached thumbnail")
+                .arg(cache_key_arg()),
+        )
+        .subcommand(
+            Command::new(CMD_PURGE)
+                .about("Remove thumbnails from the cache")
+                .arg(
+                    Arg::new(ARG_EXPIRED)
+                        .long("expired")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove thumbnails past their TTL"),
+                )
+                .arg(
+                    Arg::new(ARG_ALL)
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove every thumbnail"),
+                )
+                .arg(
+                    Arg::new(ARG_SOURCE)
+                        .long("source")
+                        .value_name("ASSET")
+                        .help("Remove the thumbnails of an asset path or UUID"),
+                )
+                .group(
+                    ArgGroup::new("purge_filter")
+                        .args([ARG_EXPIRED, ARG_ALL, ARG_SOURCE])
+                        .required(true),
+                ),
         )
+        .subcommand(
+            Command::new(CMD_EXPORT)
+                .about("Copy a cached thumbnail to a file")
+                .arg(cache_key_arg())
+                .arg(
+                    Arg::new(ARG_FILE)
+                        .value_name("FILE")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help("File to write the image to"),
+                ),
+        )
+}
+
+fn cache_key_arg() -> Arg {
+    Arg::new(ARG_KEY)
+        .value_name("KEY")
+        .required(true)
+        .help("Cache key, as listed by `cache list`")
+}
+
+fn json_arg() -> Arg {
+    Arg::new(ARG_JSON)
+        .long("json")
+        .action(ArgAction::SetTrue)
+        .help("Print JSON instead of a table")
+}
+
+fn client_arg(help: &'static str) -> Arg {
+    Arg::new(ARG_CLIENT)
+        .long("client")
+        .value_name("CLIENT")
+        .value_parser([CLIENT_CLAUDE, CLIENT_QWEN_CODE, CLIENT_QWEN_AGENT])
+        .default_value(CLIENT_CLAUDE)
+        .help(help)
+}
+
+fn config_host_arg() -> Arg {
+    Arg::new(ARG_HOST)
+        .long("host")
+        .value_name("HOST")
+        .default_value(DEFAULT_HOST)
+        .help("Host for the MCP server URL")
+}
+
+fn config_port_arg() -> Arg {
+    Arg::new(ARG_PORT)
+        .short('p')
+        .long("port")
+        .value_name("PORT")
+        .value_parser(value_parser!(u16))
+        .default_value(DEFAULT_PORT_STR)
+        .help("Port the local server will listen on")
+}
+
+fn config_path_arg() -> Arg {
+    Arg::new(ARG_PATH)
+        .long("path")
+        .value_name("FILE")
+        .value_parser(value_parser!(PathBuf))
+        .help("Client config file to edit (defaults to the client's standard location)")
+}
+
+fn dry_run_arg() -> Arg {
+    Arg::new(ARG_DRY_RUN)
+        .long("dry-run")
+        .action(ArgAction::SetTrue)
+        .help("Show the changes as a diff without writing the file")
 }
 
 fn help_command() -> Command {
@@ -97,7 +446,7 @@ fn help_command() -> Command {
             Arg::new(ARG_COMMAND)
                 .value_name("COMMAND")
                 .required(false)
-                .value_parser([CMD_SERVE, CMD_CONFIG, CMD_HELP])
+                .value_parser([CMD_SERVE, CMD_CONFIG, CMD_CACHE, CMD_HELP])
                 .help("Command to show help for"),
         )
 }
@@ -105,6 +454,7 @@ fn help_command() -> Command {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::public_url::PublicUrl;
 
     #[test]
     fn test_build_cli() {
@@ -127,6 +477,172 @@ mod tests {
         assert!(args.contains(&ARG_HOST.to_string()));
         assert!(args.contains(&ARG_PORT.to_string()));
         assert!(args.contains(&ARG_LOG_LEVEL.to_string()));
+        assert!(args.contains(&ARG_MAX_TOOL_TIMEOUT.to_string()));
+        assert!(args.contains(&ARG_MAX_CONCURRENT.to_string()));
+        assert!(args.contains(&ARG_TOOL_LIMIT.to_string()));
+        assert!(args.contains(&ARG_FIXTURES.to_string()));
+        assert!(args.contains(&ARG_RECORD.to_string()));
+        assert!(args.contains(&ARG_REPLAY.to_string()));
+        assert!(args.contains(&ARG_MAX_RESPONSE_BYTES.to_string()));
+        assert!(args.contains(&ARG_MAX_RESPONSE_TOKENS.to_string()));
+        assert!(args.contains(&ARG_THUMBNAIL_CACHE_MAX_MB.to_string()));
+        assert!(args.contains(&ARG_THUMBNAIL_CACHE_MAX_ENTRIES.to_string()));
+        assert!(args.contains(&ARG_THUMBNAIL_SWEEP_INTERVAL.to_string()));
+        assert!(args.contains(&ARG_PUBLIC_URL.to_string()));
+        assert!(args.contains(&ARG_THUMBNAIL_URL_TTL.to_string()));
+        assert!(args.contains(&ARG_SIGNING_KEY_FILE.to_string()));
+        assert!(args.contains(&ARG_THUMBNAIL_STORE.to_string()));
+        assert!(args.contains(&ARG_S3_PRESIGN.to_string()));
+        assert!(args.contains(&ARG_UPLOAD_ROOT.to_string()));
+    }
+
+    #[test]
+    fn test_serve_thumbnail_store() {
+        let matches = build_cli()
+            .try_get_matches_from(["pcli2-mcp", "serve"])
+            .unwrap();
+        let (_, serve) = matches.subcommand().unwrap();
+        assert_eq!(
+            serve.get_one::<String>(ARG_THUMBNAIL_STORE).unwrap(),
+            STORE_FS
+        );
+
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "serve",
+                "--thumbnail-store",
+                "s3",
+                "--s3-bucket",
+                "thumbs",
+                "--s3-presign",
+            ])
+            .unwrap();
+        let (_, serve) = matches.subcommand().unwrap();
+        assert_eq!(serve.get_one::<String>(ARG_S3_BUCKET).unwrap(), "thumbs");
+        assert_eq!(
+            serve.get_one::<String>(ARG_S3_REGION).unwrap(),
+            DEFAULT_REGION
+        );
+        assert!(serve.get_flag(ARG_S3_PRESIGN));
+
+        // A bucket is required for the S3 store only
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--thumbnail-store", "s3"])
+                .is_err()
+        );
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--thumbnail-store", "disk"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_serve_record_conflicts_with_replay() {
+        let result = build_cli().try_get_matches_from([
+            "pcli2-mcp",
+            "serve",
+            "--record",
+            "session",
+            "--replay",
+            "session",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_serve_thumbnail_cache_limits() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "serve",
+                "--thumbnail-cache-max-entries",
+                "500",
+                "--thumbnail-sweep-interval",
+                "0",
+            ])
+            .unwrap();
+        let (_, serve) = matches.subcommand().unwrap();
+        assert_eq!(
+            *serve.get_one::<u64>(ARG_THUMBNAIL_CACHE_MAX_MB).unwrap(),
+            1024
+        );
+        assert_eq!(
+            *serve
+                .get_one::<usize>(ARG_THUMBNAIL_CACHE_MAX_ENTRIES)
+                .unwrap(),
+            500
+        );
+        assert_eq!(
+            *serve.get_one::<u64>(ARG_THUMBNAIL_SWEEP_INTERVAL).unwrap(),
+            0
+        );
+
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--thumbnail-cache-max-mb", "0"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_serve_public_url() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "serve",
+                "--public-url",
+                "https://pcli2.example.com/",
+            ])
+            .unwrap();
+        let (_, serve) = matches.subcommand().unwrap();
+        assert_eq!(
+            serve.get_one::<PublicUrl>(ARG_PUBLIC_URL),
+            Some(&PublicUrl::Fixed("https://pcli2.example.com".to_string()))
+        );
+
+        assert_eq!(*serve.get_one::<u64>(ARG_THUMBNAIL_URL_TTL).unwrap(), 86400);
+
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--public-url", "example.com"])
+                .is_err()
+        );
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--thumbnail-url-ttl", "5"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_serve_tool_limits() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "serve",
+                "--tool-limit",
+                "pcli2_folder_part_match=1",
+                "--tool-limit",
+                "pcli2_asset_get=3",
+            ])
+            .unwrap();
+        let (_, serve) = matches.subcommand().unwrap();
+        let limits: Vec<&(String, usize)> = serve
+            .get_many::<(String, usize)>(ARG_TOOL_LIMIT)
+            .unwrap()
+            .collect();
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits[0], &("pcli2_folder_part_match".to_string(), 1));
+        assert_eq!(*serve.get_one::<usize>(ARG_MAX_QUEUE).unwrap(), 64);
+
+        assert!(
+            build_cli()
+                .try_get_matches_from(["pcli2-mcp", "serve", "--tool-limit", "bad"])
+                .is_err()
+        );
     }
 
     #[test]
@@ -144,6 +660,78 @@ mod tests {
         assert!(args.contains(&ARG_PORT.to_string()));
     }
 
+    #[test]
+    fn test_config_install_command() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "config",
+                "install",
+                "--client",
+                "qwen-code",
+                "--path",
+                "/tmp/settings.json",
+                "--dry-run",
+            ])
+            .unwrap();
+        let (_, config) = matches.subcommand().unwrap();
+        let (name, install) = config.subcommand().unwrap();
+        assert_eq!(name, CMD_INSTALL);
+        assert_eq!(install.get_one::<String>(ARG_CLIENT).unwrap(), "qwen-code");
+        assert_eq!(
+            install.get_one::<PathBuf>(ARG_PATH).unwrap(),
+            &PathBuf::from("/tmp/settings.json")
+        );
+        assert!(install.get_flag(ARG_DRY_RUN));
+        assert_eq!(*install.get_one::<u16>(ARG_PORT).unwrap(), 8080);
+    }
+
+    #[test]
+    fn test_config_uninstall_command() {
+        let cmd = config_uninstall_command();
+        assert_eq!(cmd.get_name(), CMD_UNINSTALL);
+        let args: Vec<String> = cmd
+            .get_arguments()
+            .map(|a| a.get_id().to_string())
+            .collect();
+        assert!(args.contains(&ARG_CLIENT.to_string()));
+        assert!(args.contains(&ARG_PATH.to_string()));
+        assert!(args.contains(&ARG_DRY_RUN.to_string()));
+        assert!(!args.contains(&ARG_HOST.to_string()));
+    }
+
+    #[test]
+    fn test_cache_command() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "pcli2-mcp",
+                "cache",
+                "purge",
+                "--source",
+                "/Root/a.stl",
+                "--cache-dir",
+                "/tmp/thumbnails",
+            ])
+            .unwrap();
+        let (_, cache) = matches.subcommand().unwrap();
+        let (name, purge) = cache.subcommand().unwrap();
+        assert_eq!(name, CMD_PURGE);
+        assert_eq!(purge.get_one::<String>(ARG_SOURCE).unwrap(), "/Root/a.stl");
+        assert_eq!(
+            purge.get_one::<PathBuf>(ARG_CACHE_DIR).unwrap(),
+            &PathBuf::from("/tmp/thumbnails")
+        );
+
+        let parse = |args: &[&str]| {
+            build_cli().try_get_matches_from(["pcli2-mcp", "cache"].iter().chain(args))
+        };
+        assert!(parse(&["purge"]).is_err());
+        assert!(parse(&["purge", "--all", "--expired"]).is_err());
+        assert!(parse(&["export", "abc"]).is_err());
+        assert!(parse(&["export", "abc", "out.png"]).is_ok());
+        assert!(parse(&["list", "--json"]).is_ok());
+    }
+
     #[test]
     fn test_help_command() {
         let help_cmd = help_command();
@@ -0,0 +1,1660 @@
+//! Typed Rust API for running pcli2.
+//!
+//! Each pcli2 command has a request struct built with a constructor for its
+//! required inputs and chained setters for the optional ones. Requests are
+//! turned into a pcli2 command line by [`Pcli2Request::argv`] and executed
+//! with [`Pcli2Client::run`]:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), pcli2_mcp::error::Pcli2Error> {
+//! use pcli2_mcp::client::{AssetGeometricMatch, AssetRef, OutputFormat, Pcli2Client, Threshold};
+//!
+//! let client = Pcli2Client::default();
+//! let request = AssetGeometricMatch::new(AssetRef::path("/Root/Parts/bracket.stl"))
+//!     .threshold(Threshold::new(90.0)?)
+//!     .format(OutputFormat::Json);
+//! let matches = client.run(&request).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The MCP tools in [`crate::pcli`] are a thin adapter that builds these
+//! requests from tool arguments.
+
+use crate::backend::{Pcli2Backend, SubprocessBackend};
+use crate::error::Pcli2Error;
+use crate::inflight::InFlight;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::warn;
+
+/// Last words of pcli2 commands that only read data and so may be merged
+const READ_ONLY_VERBS: &[&str] = &[
+    "--version",
+    "list",
+    "get",
+    "path",
+    "state",
+    "resolve",
+    "dependencies",
+    "geometric-match",
+    "part-match",
+    "visual-match",
+    "text-match",
+];
+
+/// Attempts made for a pcli2 command failing with a transient error
+pub const PCLI2_MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry; doubled for each further retry
+pub const PCLI2_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// A pcli2 command that can be run by [`Pcli2Client`]
+pub trait Pcli2Request {
+    /// Short name used in log lines and error messages, e.g. `pcli2 asset get`
+    fn label(&self) -> String;
+    /// Arguments passed to the pcli2 executable
+    fn argv(&self) -> Vec<String>;
+}
+
+/// Runs typed pcli2 requests through a [`Pcli2Backend`]
+///
+/// Read-only commands identical to one already running share its result
+/// rather than running again. Rate-limited commands are retried with
+/// exponential backoff, as are read-only commands failing with an HTTP 5xx.
+#[derive(Clone)]
+pub struct Pcli2Client {
+    backend: Arc<dyn Pcli2Backend>,
+    in_flight: Arc<InFlight>,
+}
+
+impl Default for Pcli2Client {
+    /// Client running the pcli2 executable named by `PCLI2_BIN`
+    fn default() -> Self {
+        Self::new(Arc::new(SubprocessBackend::from_env()))
+    }
+}
+
+impl Pcli2Client {
+    pub fn new(backend: Arc<dyn Pcli2Backend>) -> Self {
+        Self {
+            backend,
+            in_flight: Arc::new(InFlight::default()),
+        }
+    }
+
+    /// Share in-flight merging with other clients using the same backend
+    pub fn with_in_flight(mut self, in_flight: Arc<InFlight>) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    pub fn backend(&self) -> &Arc<dyn Pcli2Backend> {
+        &self.backend
+    }
+
+    /// Run a request and return pcli2's standard output
+    pub async fn run<R: Pcli2Request + ?Sized>(&self, request: &R) -> Result<String, Pcli2Error> {
+        self.run_argv(request.argv(), &request.label()).await
+    }
+
+    /// Run a raw pcli2 command line and return its standard output
+    pub async fn run_argv(&self, argv: Vec<String>, label: &str) -> Result<String, Pcli2Error> {
+        let read_only = is_read_only_command(&argv);
+        let backend = Arc::clone(&self.backend);
+        let label = label.to_string();
+        if !read_only {
+            return with_retry(&label, false, || async {
+                backend.run(&argv, &label).await?.into_stdout(&label)
+            })
+            .await;
+        }
+        let key = argv.clone();
+        self.in_flight
+            .run(key, move || async move {
+                with_retry(&label, true, || async {
+                    backend.run(&argv, &label).await?.into_stdout(&label)
+                })
+                .await
+            })
+            .await
+    }
+
+    /// Download an asset thumbnail and return the PNG bytes
+    pub async fn thumbnail(&self, request: &AssetThumbnail) -> Result<Vec<u8>, Pcli2Error> {
+        let argv = request.argv();
+        let label = request.label();
+        let bytes = with_retry(&label, false, || async {
+            self.backend
+                .download(&argv, &label)
+                .await?
+                .into_file(&label)
+        })
+        .await?;
+        if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Err(Pcli2Error::Failed(
+                "Thumbnail output was not a valid PNG file.".to_string(),
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Download several thumbnails, at most `concurrency` at a time, in request order
+    pub async fn thumbnails(
+        &self,
+        requests: Vec<AssetThumbnail>,
+        concurrency: usize,
+    ) -> Vec<Result<Vec<u8>, Pcli2Error>> {
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut downloads = JoinSet::new();
+        let count = requests.len();
+        for (index, request) in requests.into_iter().enumerate() {
+            let client = self.clone();
+            let permits = Arc::clone(&permits);
+            downloads.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                (index, client.thumbnail(&request).await)
+            });
+        }
+
+        let mut results: Vec<Result<Vec<u8>, Pcli2Error>> = (0..count)
+            .map(|_| {
+                Err(Pcli2Error::Failed(
+                    "Thumbnail download was aborted".to_string(),
+                ))
+            })
+            .collect();
+        while let Some(joined) = downloads.join_next().await {
+            if let Ok((index, result)) = joined {
+                results[index] = result;
+            }
+        }
+        results
+    }
+}
+
+/// Whether a pcli2 argv only reads data, judged by its command words
+fn is_read_only_command(argv: &[String]) -> bool {
+    let words: Vec<&str> = argv
+        .iter()
+        .map(String::as_str)
+        .take_while(|arg| !arg.starts_with('-') || *arg == "--version")
+        .collect();
+    words
+        .last()
+        .is_some_and(|verb| READ_ONLY_VERBS.contains(verb))
+}
+
+async fn with_retry<T, F, Fut>(
+    label: &str,
+    read_only: bool,
+    mut attempt_once: F,
+) -> Result<T, Pcli2Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Pcli2Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match attempt_once().await {
+            // A 5xx may have been returned after a mutation was applied
+            Err(err @ (Pcli2Error::RateLimited(_) | Pcli2Error::Upstream5xx(_)))
+                if attempt < PCLI2_MAX_ATTEMPTS
+                    && (read_only || matches!(err, Pcli2Error::RateLimited(_))) =>
+            {
+                let delay = PCLI2_RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+                warn!(
+                    "{} failed ({}), retrying in {:?} (attempt {}/{})",
+                    label,
+                    err.code(),
+                    delay,
+                    attempt + 1,
+                    PCLI2_MAX_ATTEMPTS
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// An asset addressed by UUID or by path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetRef {
+    Uuid(String),
+    Path(String),
+}
+
+impl AssetRef {
+    pub fn uuid(uuid: impl Into<String>) -> Self {
+        Self::Uuid(uuid.into())
+    }
+
+    pub fn path(path: impl Into<String>) -> Self {
+        Self::Path(path.into())
+    }
+
+    fn push_args(&self, argv: &mut Vec<String>) {
+        match self {
+            Self::Uuid(uuid) => push_opt(argv, "--uuid", Some(uuid)),
+            Self::Path(path) => push_opt(argv, "--path", Some(path)),
+        }
+    }
+}
+
+/// A folder addressed by UUID or by path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FolderRef {
+    Uuid(String),
+    Path(String),
+}
+
+impl FolderRef {
+    pub fn uuid(uuid: impl Into<String>) -> Self {
+        Self::Uuid(uuid.into())
+    }
+
+    pub fn path(path: impl Into<String>) -> Self {
+        Self::Path(path.into())
+    }
+
+    fn push_args(&self, argv: &mut Vec<String>) {
+        match self {
+            Self::Uuid(uuid) => push_opt(argv, "--folder-uuid", Some(uuid)),
+            Self::Path(path) => push_opt(argv, "--folder-path", Some(path)),
+        }
+    }
+}
+
+/// Match threshold in percent, between 0 and 100
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Threshold(f64);
+
+impl Threshold {
+    pub fn new(value: f64) -> Result<Self, Pcli2Error> {
+        if !(0.0..=100.0).contains(&value) {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'threshold': value {} must be between 0 and 100",
+                value
+            )));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn value(self) -> f64 {
+        self.0
+    }
+}
+
+/// Number of assets a folder match processes at once, between 1 and 10
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Concurrency(u64);
+
+impl Concurrency {
+    pub fn new(value: u64) -> Result<Self, Pcli2Error> {
+        if !(1..=10).contains(&value) {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'concurrent': value {} must be between 1 and 10",
+                value
+            )));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+}
+
+/// Output format passed to pcli2 with `-f`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Tree,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self, Pcli2Error> {
+        match value {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "tree" => Ok(Self::Tree),
+            _ => Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'format': unsupported format '{}'",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Tree => "tree",
+        }
+    }
+}
+
+/// Type of an asset metadata field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataType {
+    Text,
+    Number,
+    Boolean,
+}
+
+impl MetadataType {
+    pub fn parse(value: &str) -> Result<Self, Pcli2Error> {
+        match value {
+            "text" => Ok(Self::Text),
+            "number" => Ok(Self::Number),
+            "boolean" => Ok(Self::Boolean),
+            _ => Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'type': unsupported metadata type '{}'",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Number => "number",
+            Self::Boolean => "boolean",
+        }
+    }
+}
+
+/// Output flags shared by most commands
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    pub headers: bool,
+    /// Only sent by the folder, asset and match commands that accept `--metadata`
+    pub metadata: bool,
+    pub pretty: bool,
+    pub format: Option<OutputFormat>,
+}
+
+impl Output {
+    fn push_args(&self, argv: &mut Vec<String>) {
+        push_flag(argv, self.headers, "--headers");
+        push_flag(argv, self.pretty, "--pretty");
+        push_opt(argv, "-f", self.format.map(OutputFormat::as_str));
+    }
+
+    /// [`push_args`](Self::push_args) for commands that also accept `--metadata`
+    fn push_args_with_metadata(&self, argv: &mut Vec<String>) {
+        push_flag(argv, self.headers, "--headers");
+        push_flag(argv, self.metadata, "--metadata");
+        push_flag(argv, self.pretty, "--pretty");
+        push_opt(argv, "-f", self.format.map(OutputFormat::as_str));
+    }
+}
+
+fn push_flag(argv: &mut Vec<String>, enabled: bool, flag: &str) {
+    if enabled {
+        argv.push(flag.to_string());
+    }
+}
+
+fn push_opt(argv: &mut Vec<String>, flag: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        argv.push(flag.to_string());
+        argv.push(value.to_string());
+    }
+}
+
+fn push_tenant(argv: &mut Vec<String>, tenant: &Option<String>) {
+    push_opt(argv, "-t", tenant.as_deref());
+}
+
+fn command(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+/// Setters for the [`Output`] flags, shared by every request with an `output` field
+macro_rules! output_setters {
+    ($($request:ty),* $(,)?) => {$(
+        impl $request {
+            pub fn headers(mut self, headers: bool) -> Self {
+                self.output.headers = headers;
+                self
+            }
+
+            pub fn pretty(mut self, pretty: bool) -> Self {
+                self.output.pretty = pretty;
+                self
+            }
+
+            pub fn format(mut self, format: OutputFormat) -> Self {
+                self.output.format = Some(format);
+                self
+            }
+        }
+    )*};
+}
+
+/// Setter for `--metadata`, shared by every request whose command accepts it
+macro_rules! metadata_setter {
+    ($($request:ty),* $(,)?) => {$(
+        impl $request {
+            pub fn metadata(mut self, metadata: bool) -> Self {
+                self.output.metadata = metadata;
+                self
+            }
+        }
+    )*};
+}
+
+/// Setter for the `-t` tenant option, shared by every request with a `tenant` field
+macro_rules! tenant_setter {
+    ($($request:ty),* $(,)?) => {$(
+        impl $request {
+            pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+                self.tenant = Some(tenant.into());
+                self
+            }
+        }
+    )*};
+}
+
+/// `pcli2 --version`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Version;
+
+impl Pcli2Request for Version {
+    fn label(&self) -> String {
+        "pcli2 --version".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        command(&["--version"])
+    }
+}
+
+/// `pcli2 config get`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigGet {
+    pub output: Output,
+}
+
+impl Pcli2Request for ConfigGet {
+    fn label(&self) -> String {
+        "pcli2 config get".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["config", "get"]);
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 config get path`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigGetPath {
+    pub output: Output,
+}
+
+impl Pcli2Request for ConfigGetPath {
+    fn label(&self) -> String {
+        "pcli2 config get path".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["config", "get", "path"]);
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 config environment list`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigEnvironmentList {
+    pub output: Output,
+}
+
+impl Pcli2Request for ConfigEnvironmentList {
+    fn label(&self) -> String {
+        "pcli2 config environment list".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["config", "environment", "list"]);
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 config environment get`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigEnvironmentGet {
+    pub name: Option<String>,
+    pub output: Output,
+}
+
+impl ConfigEnvironmentGet {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+impl Pcli2Request for ConfigEnvironmentGet {
+    fn label(&self) -> String {
+        "pcli2 config environment get".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["config", "environment", "get"]);
+        push_opt(&mut argv, "-n", self.name.as_deref());
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 tenant list`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TenantList {
+    pub output: Output,
+}
+
+impl Pcli2Request for TenantList {
+    fn label(&self) -> String {
+        "pcli2 tenant list".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["tenant", "list"]);
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 tenant get` for the active tenant
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TenantGet {
+    pub output: Output,
+}
+
+impl Pcli2Request for TenantGet {
+    fn label(&self) -> String {
+        "pcli2 tenant get".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["tenant", "get"]);
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 tenant state`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TenantState {
+    pub tenant: Option<String>,
+    pub state_type: Option<String>,
+    pub output: Output,
+}
+
+impl TenantState {
+    pub fn state_type(mut self, state_type: impl Into<String>) -> Self {
+        self.state_type = Some(state_type.into());
+        self
+    }
+}
+
+impl Pcli2Request for TenantState {
+    fn label(&self) -> String {
+        "pcli2 tenant state".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["tenant", "state"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_opt(&mut argv, "--type", self.state_type.as_deref());
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 tenant use`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantUse {
+    pub name: String,
+    pub refresh: bool,
+    pub output: Output,
+}
+
+impl TenantUse {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            refresh: false,
+            output: Output::default(),
+        }
+    }
+
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+}
+
+impl Pcli2Request for TenantUse {
+    fn label(&self) -> String {
+        "pcli2 tenant use".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["tenant", "use"]);
+        push_opt(&mut argv, "--name", Some(&self.name));
+        push_flag(&mut argv, self.refresh, "--refresh");
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// What `pcli2 <resource> list` lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListResource {
+    #[default]
+    Folder,
+    Asset,
+}
+
+impl ListResource {
+    pub fn parse(value: &str) -> Result<Self, Pcli2Error> {
+        match value {
+            "folder" => Ok(Self::Folder),
+            "asset" => Ok(Self::Asset),
+            _ => Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'resource': expected 'folder' or 'asset', got '{}'",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Folder => "folder",
+            Self::Asset => "asset",
+        }
+    }
+}
+
+/// `pcli2 folder list` or `pcli2 asset list`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct List {
+    pub resource: ListResource,
+    pub tenant: Option<String>,
+    pub folder_uuid: Option<String>,
+    pub folder_path: Option<String>,
+    pub reload: bool,
+    pub output: Output,
+}
+
+impl List {
+    pub fn new(resource: ListResource) -> Self {
+        Self {
+            resource,
+            ..Self::default()
+        }
+    }
+
+    pub fn folder(mut self, folder: FolderRef) -> Self {
+        match folder {
+            FolderRef::Uuid(uuid) => self.folder_uuid = Some(uuid),
+            FolderRef::Path(path) => self.folder_path = Some(path),
+        }
+        self
+    }
+
+    pub fn reload(mut self, reload: bool) -> Self {
+        self.reload = reload;
+        self
+    }
+}
+
+impl Pcli2Request for List {
+    fn label(&self) -> String {
+        format!("pcli2 {} list", self.resource.as_str())
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&[self.resource.as_str(), "list"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_flag(&mut argv, self.output.metadata, "--metadata");
+        push_flag(&mut argv, self.output.headers, "--headers");
+        push_flag(&mut argv, self.output.pretty, "--pretty");
+        push_opt(
+            &mut argv,
+            "-f",
+            self.output.format.map(OutputFormat::as_str),
+        );
+        push_opt(&mut argv, "--folder-uuid", self.folder_uuid.as_deref());
+        push_opt(&mut argv, "--folder-path", self.folder_path.as_deref());
+        push_flag(&mut argv, self.reload, "--reload");
+        argv
+    }
+}
+
+/// `pcli2 folder get`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderGet {
+    pub folder: FolderRef,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl FolderGet {
+    pub fn new(folder: FolderRef) -> Self {
+        Self {
+            folder,
+            tenant: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Pcli2Request for FolderGet {
+    fn label(&self) -> String {
+        "pcli2 folder get".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["folder", "get"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.folder.push_args(&mut argv);
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 folder resolve`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderResolve {
+    pub folder_path: String,
+    pub tenant: Option<String>,
+}
+
+impl FolderResolve {
+    pub fn new(folder_path: impl Into<String>) -> Self {
+        Self {
+            folder_path: folder_path.into(),
+            tenant: None,
+        }
+    }
+}
+
+impl Pcli2Request for FolderResolve {
+    fn label(&self) -> String {
+        "pcli2 folder resolve".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["folder", "resolve"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_opt(&mut argv, "--folder-path", Some(&self.folder_path));
+        argv
+    }
+}
+
+fn require_folders(folder_paths: Vec<String>) -> Result<Vec<String>, Pcli2Error> {
+    if folder_paths.is_empty() {
+        return Err(Pcli2Error::InvalidArguments(
+            "Missing required argument: 'folder_path'".to_string(),
+        ));
+    }
+    Ok(folder_paths)
+}
+
+fn push_folder_paths(argv: &mut Vec<String>, folder_paths: &[String]) {
+    for path in folder_paths {
+        push_opt(argv, "--folder-path", Some(path));
+    }
+}
+
+/// `pcli2 folder dependencies`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderDependencies {
+    pub folder_paths: Vec<String>,
+    pub tenant: Option<String>,
+    pub progress: bool,
+    pub output: Output,
+}
+
+impl FolderDependencies {
+    /// Fails if `folder_paths` is empty
+    pub fn new(folder_paths: Vec<String>) -> Result<Self, Pcli2Error> {
+        Ok(Self {
+            folder_paths: require_folders(folder_paths)?,
+            tenant: None,
+            progress: false,
+            output: Output::default(),
+        })
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+}
+
+impl Pcli2Request for FolderDependencies {
+    fn label(&self) -> String {
+        "pcli2 folder dependencies".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["folder", "dependencies"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_folder_paths(&mut argv, &self.folder_paths);
+        self.output.push_args_with_metadata(&mut argv);
+        push_flag(&mut argv, self.progress, "--progress");
+        argv
+    }
+}
+
+/// Kind of match run over a folder or for a single asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    Geometric,
+    Part,
+    Visual,
+}
+
+impl MatchKind {
+    fn subcommand(self) -> &'static str {
+        match self {
+            Self::Geometric => "geometric-match",
+            Self::Part => "part-match",
+            Self::Visual => "visual-match",
+        }
+    }
+}
+
+/// `pcli2 folder geometric-match`, `part-match` or `visual-match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderMatch {
+    pub kind: MatchKind,
+    pub folder_paths: Vec<String>,
+    pub tenant: Option<String>,
+    /// Ignored by visual matching
+    pub threshold: Option<Threshold>,
+    pub exclusive: bool,
+    pub concurrent: Option<Concurrency>,
+    pub progress: bool,
+    pub output: Output,
+}
+
+impl FolderMatch {
+    /// Fails if `folder_paths` is empty
+    pub fn new(kind: MatchKind, folder_paths: Vec<String>) -> Result<Self, Pcli2Error> {
+        Ok(Self {
+            kind,
+            folder_paths: require_folders(folder_paths)?,
+            tenant: None,
+            threshold: None,
+            exclusive: false,
+            concurrent: None,
+            progress: false,
+            output: Output::default(),
+        })
+    }
+
+    pub fn threshold(mut self, threshold: Threshold) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub fn concurrent(mut self, concurrent: Concurrency) -> Self {
+        self.concurrent = Some(concurrent);
+        self
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+}
+
+impl Pcli2Request for FolderMatch {
+    fn label(&self) -> String {
+        format!("pcli2 folder {}", self.kind.subcommand())
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["folder", self.kind.subcommand()]);
+        push_tenant(&mut argv, &self.tenant);
+        push_folder_paths(&mut argv, &self.folder_paths);
+        if self.kind != MatchKind::Visual
+            && let Some(threshold) = self.threshold
+        {
+            push_opt(
+                &mut argv,
+                "--threshold",
+                Some(&threshold.value().to_string()),
+            );
+        }
+        push_flag(&mut argv, self.exclusive, "--exclusive");
+        self.output.push_args_with_metadata(&mut argv);
+        if let Some(concurrent) = self.concurrent {
+            push_opt(
+                &mut argv,
+                "--concurrent",
+                Some(&concurrent.value().to_string()),
+            );
+        }
+        push_flag(&mut argv, self.progress, "--progress");
+        argv
+    }
+}
+
+/// `pcli2 asset get`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetGet {
+    pub asset: AssetRef,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl AssetGet {
+    pub fn new(asset: AssetRef) -> Self {
+        Self {
+            asset,
+            tenant: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Pcli2Request for AssetGet {
+    fn label(&self) -> String {
+        "pcli2 asset get".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "get"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset dependencies`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetDependencies {
+    pub asset: AssetRef,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl AssetDependencies {
+    pub fn new(asset: AssetRef) -> Self {
+        Self {
+            asset,
+            tenant: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Pcli2Request for AssetDependencies {
+    fn label(&self) -> String {
+        "pcli2 asset dependencies".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "dependencies"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset thumbnail`, run with [`Pcli2Client::thumbnail`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetThumbnail {
+    pub asset: AssetRef,
+    pub tenant: Option<String>,
+}
+
+impl AssetThumbnail {
+    pub fn new(asset: AssetRef) -> Self {
+        Self {
+            asset,
+            tenant: None,
+        }
+    }
+}
+
+impl Pcli2Request for AssetThumbnail {
+    fn label(&self) -> String {
+        "pcli2 asset thumbnail".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "thumbnail"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset reprocess`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetReprocess {
+    pub asset: AssetRef,
+    pub tenant: Option<String>,
+}
+
+impl AssetReprocess {
+    pub fn new(asset: AssetRef) -> Self {
+        Self {
+            asset,
+            tenant: None,
+        }
+    }
+}
+
+impl Pcli2Request for AssetReprocess {
+    fn label(&self) -> String {
+        "pcli2 asset reprocess".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "reprocess"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset geometric-match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetGeometricMatch {
+    pub target: AssetRef,
+    pub tenant: Option<String>,
+    pub threshold: Option<Threshold>,
+    pub output: Output,
+}
+
+impl AssetGeometricMatch {
+    pub fn new(target: AssetRef) -> Self {
+        Self {
+            target,
+            tenant: None,
+            threshold: None,
+            output: Output::default(),
+        }
+    }
+
+    pub fn threshold(mut self, threshold: Threshold) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+}
+
+impl Pcli2Request for AssetGeometricMatch {
+    fn label(&self) -> String {
+        "pcli2 asset geometric-match".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "geometric-match"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.target.push_args(&mut argv);
+        if let Some(threshold) = self.threshold {
+            push_opt(
+                &mut argv,
+                "--threshold",
+                Some(&threshold.value().to_string()),
+            );
+        }
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset part-match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetPartMatch {
+    pub target: AssetRef,
+    pub tenant: Option<String>,
+    pub threshold: Option<Threshold>,
+    pub output: Output,
+}
+
+impl AssetPartMatch {
+    pub fn new(target: AssetRef) -> Self {
+        Self {
+            target,
+            tenant: None,
+            threshold: None,
+            output: Output::default(),
+        }
+    }
+
+    pub fn threshold(mut self, threshold: Threshold) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+}
+
+impl Pcli2Request for AssetPartMatch {
+    fn label(&self) -> String {
+        "pcli2 asset part-match".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "part-match"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.target.push_args(&mut argv);
+        if let Some(threshold) = self.threshold {
+            push_opt(
+                &mut argv,
+                "--threshold",
+                Some(&threshold.value().to_string()),
+            );
+        }
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset visual-match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetVisualMatch {
+    pub target: AssetRef,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl AssetVisualMatch {
+    pub fn new(target: AssetRef) -> Self {
+        Self {
+            target,
+            tenant: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Pcli2Request for AssetVisualMatch {
+    fn label(&self) -> String {
+        "pcli2 asset visual-match".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "visual-match"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.target.push_args(&mut argv);
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset text-match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetTextMatch {
+    pub text: String,
+    pub tenant: Option<String>,
+    pub fuzzy: bool,
+    pub output: Output,
+}
+
+impl AssetTextMatch {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            tenant: None,
+            fuzzy: false,
+            output: Output::default(),
+        }
+    }
+
+    pub fn fuzzy(mut self, fuzzy: bool) -> Self {
+        self.fuzzy = fuzzy;
+        self
+    }
+}
+
+impl Pcli2Request for AssetTextMatch {
+    fn label(&self) -> String {
+        "pcli2 asset text-match".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "text-match"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_opt(&mut argv, "--text", Some(&self.text));
+        push_flag(&mut argv, self.fuzzy, "--fuzzy");
+        self.output.push_args_with_metadata(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset metadata create`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMetadataCreate {
+    pub asset: AssetRef,
+    pub name: String,
+    pub value: String,
+    pub tenant: Option<String>,
+    pub value_type: Option<MetadataType>,
+}
+
+impl AssetMetadataCreate {
+    pub fn new(asset: AssetRef, name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            asset,
+            name: name.into(),
+            value: value.into(),
+            tenant: None,
+            value_type: None,
+        }
+    }
+
+    pub fn value_type(mut self, value_type: MetadataType) -> Self {
+        self.value_type = Some(value_type);
+        self
+    }
+}
+
+impl Pcli2Request for AssetMetadataCreate {
+    fn label(&self) -> String {
+        "pcli2 asset metadata create".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "metadata", "create"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        push_opt(&mut argv, "--name", Some(&self.name));
+        push_opt(&mut argv, "--value", Some(&self.value));
+        push_opt(
+            &mut argv,
+            "--type",
+            self.value_type.map(MetadataType::as_str),
+        );
+        argv
+    }
+}
+
+/// `pcli2 asset metadata delete`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMetadataDelete {
+    pub asset: AssetRef,
+    pub names: Vec<String>,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl AssetMetadataDelete {
+    /// Fails if `names` is empty
+    pub fn new(asset: AssetRef, names: Vec<String>) -> Result<Self, Pcli2Error> {
+        if names.is_empty() {
+            return Err(Pcli2Error::InvalidArguments(
+                "Missing required argument: 'name'".to_string(),
+            ));
+        }
+        Ok(Self {
+            asset,
+            names,
+            tenant: None,
+            output: Output::default(),
+        })
+    }
+}
+
+impl Pcli2Request for AssetMetadataDelete {
+    fn label(&self) -> String {
+        "pcli2 asset metadata delete".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "metadata", "delete"]);
+        push_tenant(&mut argv, &self.tenant);
+        self.asset.push_args(&mut argv);
+        for name in &self.names {
+            push_opt(&mut argv, "--name", Some(name));
+        }
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset create`, uploading one file into a folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetCreate {
+    pub file: String,
+    pub folder_path: String,
+    pub tenant: Option<String>,
+    pub output: Output,
+}
+
+impl AssetCreate {
+    pub fn new(file: impl Into<String>, folder_path: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            folder_path: folder_path.into(),
+            tenant: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Pcli2Request for AssetCreate {
+    fn label(&self) -> String {
+        "pcli2 asset create".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "create"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_opt(&mut argv, "--file", Some(&self.file));
+        push_opt(&mut argv, "--folder-path", Some(&self.folder_path));
+        self.output.push_args(&mut argv);
+        argv
+    }
+}
+
+/// `pcli2 asset create-batch`, uploading the files matching a glob into a folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetCreateBatch {
+    pub files: String,
+    pub folder_path: String,
+    pub tenant: Option<String>,
+    pub concurrent: Option<Concurrency>,
+    pub progress: bool,
+    pub output: Output,
+}
+
+impl AssetCreateBatch {
+    pub fn new(files: impl Into<String>, folder_path: impl Into<String>) -> Self {
+        Self {
+            files: files.into(),
+            folder_path: folder_path.into(),
+            tenant: None,
+            concurrent: None,
+            progress: false,
+            output: Output::default(),
+        }
+    }
+
+    pub fn concurrent(mut self, concurrent: Concurrency) -> Self {
+        self.concurrent = Some(concurrent);
+        self
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+}
+
+impl Pcli2Request for AssetCreateBatch {
+    fn label(&self) -> String {
+        "pcli2 asset create-batch".to_string()
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = command(&["asset", "create-batch"]);
+        push_tenant(&mut argv, &self.tenant);
+        push_opt(&mut argv, "--files", Some(&self.files));
+        push_opt(&mut argv, "--folder-path", Some(&self.folder_path));
+        self.output.push_args(&mut argv);
+        if let Some(concurrent) = self.concurrent {
+            push_opt(
+                &mut argv,
+                "--concurrent",
+                Some(&concurrent.value().to_string()),
+            );
+        }
+        push_flag(&mut argv, self.progress, "--progress");
+        argv
+    }
+}
+
+output_setters!(
+    ConfigGet,
+    ConfigGetPath,
+    ConfigEnvironmentList,
+    ConfigEnvironmentGet,
+    TenantList,
+    TenantGet,
+    TenantState,
+    TenantUse,
+    List,
+    FolderGet,
+    FolderDependencies,
+    FolderMatch,
+    AssetGet,
+    AssetDependencies,
+    AssetGeometricMatch,
+    AssetPartMatch,
+    AssetVisualMatch,
+    AssetTextMatch,
+    AssetMetadataDelete,
+    AssetCreate,
+    AssetCreateBatch,
+);
+
+metadata_setter!(
+    List,
+    FolderGet,
+    FolderDependencies,
+    FolderMatch,
+    AssetGet,
+    AssetDependencies,
+    AssetGeometricMatch,
+    AssetPartMatch,
+    AssetVisualMatch,
+    AssetTextMatch,
+);
+
+tenant_setter!(
+    TenantState,
+    List,
+    FolderGet,
+    FolderResolve,
+    FolderDependencies,
+    FolderMatch,
+    AssetGet,
+    AssetDependencies,
+    AssetThumbnail,
+    AssetReprocess,
+    AssetGeometricMatch,
+    AssetPartMatch,
+    AssetVisualMatch,
+    AssetTextMatch,
+    AssetMetadataCreate,
+    AssetMetadataDelete,
+    AssetCreate,
+    AssetCreateBatch,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_read_only_command() {
+        let argv = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_read_only_command(&argv(&["--version"])));
+        assert!(is_read_only_command(&argv(&[
+            "folder", "list", "-t", "acme"
+        ])));
+        assert!(is_read_only_command(&argv(&[
+            "folder",
+            "geometric-match",
+            "--folder-path",
+            "/Root"
+        ])));
+        assert!(is_read_only_command(&argv(&["config", "get", "path"])));
+        assert!(!is_read_only_command(&argv(&[
+            "asset",
+            "reprocess",
+            "--uuid",
+            "u"
+        ])));
+        assert!(!is_read_only_command(&argv(&[
+            "asset", "metadata", "create"
+        ])));
+        assert!(!is_read_only_command(&argv(&[
+            "tenant", "use", "--name", "acme"
+        ])));
+        assert!(!is_read_only_command(&argv(&[
+            "asset",
+            "create-batch",
+            "--files",
+            "/uploads/*.stl"
+        ])));
+        assert!(!is_read_only_command(&argv(&[
+            "asset",
+            "thumbnail",
+            "--uuid",
+            "u"
+        ])));
+    }
+
+    #[tokio::test]
+    async fn test_thumbnail_requires_png() {
+        use crate::backend::{Fixture, FixtureBackend};
+        use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+        let fixture = |uuid: &str, bytes: &[u8]| Fixture {
+            args: command(&["asset", "thumbnail", "--uuid", uuid]),
+            file_base64: Some(BASE64_STANDARD.encode(bytes)),
+            ..Fixture::default()
+        };
+        let png = b"\x89PNG\r\n\x1a\nimage";
+        let client = Pcli2Client::new(Arc::new(FixtureBackend::new(vec![
+            fixture("png", png),
+            fixture("text", b"not an image"),
+        ])));
+        let thumbnail = |uuid: &str| AssetThumbnail::new(AssetRef::uuid(uuid));
+        assert_eq!(client.thumbnail(&thumbnail("png")).await, Ok(png.to_vec()));
+        assert!(client.thumbnail(&thumbnail("text")).await.is_err());
+    }
+
+    #[test]
+    fn test_asset_geometric_match_argv() {
+        let request = AssetGeometricMatch::new(AssetRef::path("/Root/part.stl"))
+            .tenant("acme")
+            .threshold(Threshold::new(85.5).unwrap())
+            .headers(true)
+            .format(OutputFormat::Csv);
+        assert_eq!(request.label(), "pcli2 asset geometric-match");
+        assert_eq!(
+            request.argv(),
+            command(&[
+                "asset",
+                "geometric-match",
+                "-t",
+                "acme",
+                "--path",
+                "/Root/part.stl",
+                "--threshold",
+                "85.5",
+                "--headers",
+                "-f",
+                "csv",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_folder_match_requires_folders() {
+        let err = FolderMatch::new(MatchKind::Part, Vec::new()).unwrap_err();
+        assert_eq!(err.code(), "invalid_arguments");
+    }
+
+    #[test]
+    fn test_visual_folder_match_ignores_threshold() {
+        let request = FolderMatch::new(MatchKind::Visual, vec!["/Root".to_string()])
+            .unwrap()
+            .threshold(Threshold::new(90.0).unwrap())
+            .concurrent(Concurrency::new(4).unwrap());
+        assert_eq!(
+            request.argv(),
+            command(&[
+                "folder",
+                "visual-match",
+                "--folder-path",
+                "/Root",
+                "--concurrent",
+                "4"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_ranges_are_validated() {
+        assert!(Threshold::new(100.0).is_ok());
+        assert!(Threshold::new(100.5).is_err());
+        assert!(Concurrency::new(0).is_err());
+        assert!(Concurrency::new(11).is_err());
+    }
+
+    #[test]
+    fn test_parse_enums() {
+        assert_eq!(OutputFormat::parse("tree").unwrap(), OutputFormat::Tree);
+        assert!(OutputFormat::parse("xml").is_err());
+        assert_eq!(MetadataType::parse("number").unwrap(), MetadataType::Number);
+        assert_eq!(ListResource::parse("asset").unwrap(), ListResource::Asset);
+    }
+
+    #[test]
+    fn test_metadata_delete_repeats_names() {
+        let request = AssetMetadataDelete::new(
+            AssetRef::uuid("u1"),
+            vec!["color".to_string(), "size".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            request.argv(),
+            command(&[
+                "asset", "metadata", "delete", "--uuid", "u1", "--name", "color", "--name", "size",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_asset_create_batch_argv() {
+        let request = AssetCreateBatch::new("/uploads/parts/*.stl", "/Root/Parts")
+            .tenant("acme")
+            .concurrent(Concurrency::new(4).unwrap())
+            .format(OutputFormat::Json);
+        assert_eq!(request.label(), "pcli2 asset create-batch");
+        assert_eq!(
+            request.argv(),
+            command(&[
+                "asset",
+                "create-batch",
+                "-t",
+                "acme",
+                "--files",
+                "/uploads/parts/*.stl",
+                "--folder-path",
+                "/Root/Parts",
+                "-f",
+                "json",
+                "--concurrent",
+                "4",
+            ])
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Installation of the pcli2 server entry into MCP client config files.
+//!
+//! `config install` and `config uninstall` locate a client's config file,
+//! merge or remove the `mcpServers.pcli2` entry and leave every other key
+//! untouched. The original file is backed up before it is rewritten.
+
+use crate::{
+    cli::{
+        ARG_CLIENT, ARG_DRY_RUN, ARG_HOST, ARG_PATH, ARG_PORT, CLIENT_CLAUDE, CLIENT_QWEN_AGENT,
+        CLIENT_QWEN_CODE, DEFAULT_HOST,
+    },
+    mcp::{MCP_SERVER_ALIAS, build_client_config},
+};
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use clap::ArgMatches;
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const MCP_SERVERS_KEY: &str = "mcpServers";
+
+/// Outcome of applying an install or uninstall to a config document
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// The pcli2 entry was added
+    Added,
+    /// An existing pcli2 entry was replaced with a different one
+    Replaced,
+    /// The pcli2 entry was removed
+    Removed,
+    /// The document already had the desired state
+    Unchanged,
+}
+
+pub fn run_config_install(matches: &ArgMatches) -> Result<()> {
+    let client = matches
+        .get_one::<String>(ARG_CLIENT)
+        .map(String::as_str)
+        .unwrap_or(CLIENT_CLAUDE);
+    let host = matches
+        .get_one::<String>(ARG_HOST)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_HOST);
+    let port = *matches.get_one::<u16>(ARG_PORT).unwrap_or(&8080);
+    let path = resolve_config_path(client, matches.get_one::<PathBuf>(ARG_PATH))?;
+    let dry_run = matches.get_flag(ARG_DRY_RUN);
+
+    let rendered = build_client_config(client, host, port)?;
+    let entry = rendered[MCP_SERVERS_KEY][MCP_SERVER_ALIAS].clone();
+
+    apply_to_file(&path, dry_run, |document| {
+        install_server_entry(document, entry)
+    })
+}
+
+pub fn run_config_uninstall(matches: &ArgMatches) -> Result<()> {
+    let client = matches
+        .get_one::<String>(ARG_CLIENT)
+        .map(String::as_str)
+        .unwrap_or(CLIENT_CLAUDE);
+    let path = resolve_config_path(client, matches.get_one::<PathBuf>(ARG_PATH))?;
+    let dry_run = matches.get_flag(ARG_DRY_RUN);
+
+    if !path.exists() {
+        println!("{} does not exist; nothing to uninstall", path.display());
+        return Ok(());
+    }
+    apply_to_file(&path, dry_run, uninstall_server_entry)
+}
+
+fn apply_to_file<F>(path: &Path, dry_run: bool, change: F) -> Result<()>
+where
+    F: FnOnce(&mut Value) -> Result<ConfigChange>,
+{
+    let original = read_config_text(path)?;
+    let mut document = parse_config(&original, path)?;
+    let outcome = change(&mut document)?;
+    if outcome == ConfigChange::Unchanged {
+        println!("{} is already up to date", path.display());
+        return Ok(());
+    }
+
+    let updated = format!("{}\n", serde_json::to_string_pretty(&document)?);
+    if dry_run {
+        println!("--- {}", path.display());
+        println!("+++ {} (proposed)", path.display());
+        print!("{}", line_diff(&original, &updated));
+        return Ok(());
+    }
+
+    if let Some(backup) = backup_config(path)? {
+        println!("Backed up {} to {}", path.display(), backup.display());
+    }
+    write_config(path, &updated)?;
+    let verb = match outcome {
+        ConfigChange::Added => "Added",
+        ConfigChange::Replaced => "Replaced",
+        ConfigChange::Removed => "Removed",
+        ConfigChange::Unchanged => unreachable!("unchanged configs return early"),
+    };
+    println!(
+        "{} '{}' server entry in {}",
+        verb,
+        MCP_SERVER_ALIAS,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Insert or replace `mcpServers.pcli2` in a parsed client config
+pub fn install_server_entry(document: &mut Value, entry: Value) -> Result<ConfigChange> {
+    let root = document
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Client config must be a JSON object"))?;
+    let servers = root
+        .entry(MCP_SERVERS_KEY)
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| {
+            anyhow!(
+                "'{}' in client config must be a JSON object",
+                MCP_SERVERS_KEY
+            )
+        })?;
+
+    match servers.insert(MCP_SERVER_ALIAS.to_string(), entry.clone()) {
+        None => Ok(ConfigChange::Added),
+        Some(previous) if previous == entry => Ok(ConfigChange::Unchanged),
+        Some(_) => Ok(ConfigChange::Replaced),
+    }
+}
+
+/// Remove `mcpServers.pcli2` from a parsed client config
+pub fn uninstall_server_entry(document: &mut Value) -> Result<ConfigChange> {
+    let root = document
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Client config must be a JSON object"))?;
+    let Some(servers) = root.get_mut(MCP_SERVERS_KEY) else {
+        return Ok(ConfigChange::Unchanged);
+    };
+    let servers = servers.as_object_mut().ok_or_else(|| {
+        anyhow!(
+            "'{}' in client config must be a JSON object",
+            MCP_SERVERS_KEY
+        )
+    })?;
+
+    match servers.remove(MCP_SERVER_ALIAS) {
+        Some(_) => Ok(ConfigChange::Removed),
+        None => Ok(ConfigChange::Unchanged),
+    }
+}
+
+/// Locate the config file for a client, preferring an explicit `--path`
+pub fn resolve_config_path(client: &str, explicit: Option<&PathBuf>) -> Result<PathBuf> {
+    if let Some(path) = explicit {
+        return Ok(path.clone());
+    }
+    match client {
+        CLIENT_CLAUDE => claude_desktop_config_path(),
+        CLIENT_QWEN_CODE => Ok(home_dir()?.join(".qwen").join("settings.json")),
+        CLIENT_QWEN_AGENT => Err(anyhow!(
+            "Client '{}' has no standard config file; pass --path",
+            client
+        )),
+        _ => Err(anyhow!("Unsupported client '{}'", client)),
+    }
+}
+
+fn claude_desktop_config_path() -> Result<PathBuf> {
+    let base = if cfg!(target_os = "macos") {
+        home_dir()?.join("Library").join("Application Support")
+    } else if cfg!(target_os = "windows") {
+        std::env::var("APPDATA")
+            .map(PathBuf::from)
+            .map_err(|_| anyhow!("Could not determine %APPDATA%; pass --path"))?
+    } else {
+        std::env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|_| home_dir().map(|home| home.join(".config")))?
+    };
+    Ok(base.join("Claude").join("claude_desktop_config.json"))
+}
+
+fn home_dir() -> Result<PathBuf> {
+    std::env::var("HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("USERPROFILE").map(PathBuf::from))
+        .map_err(|_| anyhow!("Could not determine home directory; pass --path"))
+}
+
+fn read_config_text(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn parse_config(text: &str, path: &Path) -> Result<Value> {
+    if text.trim().is_empty() {
+        return Ok(Value::Object(Map::new()));
+    }
+    serde_json::from_str(text).with_context(|| {
+        format!(
+            "{} is not valid JSON; fix it or pass a different --path",
+            path.display()
+        )
+    })
+}
+
+fn backup_config(path: &Path) -> Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid config file name {}", path.display()))?;
+    let stamp = Utc::now().format("%Y%m%d%H%M%S%3f").to_string();
+    let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // Never overwrite an earlier backup, even one made in the same millisecond
+    for attempt in 0.. {
+        let suffix = match attempt {
+            0 => stamp.clone(),
+            n => format!("{}-{}", stamp, n),
+        };
+        let backup = path.with_file_name(format!("{}.{}.bak", file_name, suffix));
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "Failed to back up {} to {}",
+                        path.display(),
+                        backup.display()
+                    )
+                });
+            }
+        };
+        file.write_all(&contents)
+            .with_context(|| format!("Failed to write backup {}", backup.display()))?;
+        return Ok(Some(backup));
+    }
+    unreachable!("backup attempts are unbounded")
+}
+
+fn write_config(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let temp = path.with_extension("pcli2-mcp.tmp");
+    fs::write(&temp, contents).with_context(|| format!("Failed to write {}", temp.display()))?;
+    fs::rename(&temp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Render a minimal line diff (`-` removed, `+` added, ` ` kept)
+pub fn line_diff(before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+
+    // Longest common subsequence table, filled from the end
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            out.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            out.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    for line in &old[i..] {
+        out.push_str(&format!("-{}\n", line));
+    }
+    for line in &new[j..] {
+        out.push_str(&format!("+{}\n", line));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(port: u16) -> Value {
+        build_client_config(CLIENT_CLAUDE, "localhost", port).unwrap()[MCP_SERVERS_KEY]
+            [MCP_SERVER_ALIAS]
+            .clone()
+    }
+
+    #[test]
+    fn test_install_into_empty_document() {
+        let mut document = json!({});
+        let change = install_server_entry(&mut document, entry(8080)).unwrap();
+        assert_eq!(change, ConfigChange::Added);
+        assert_eq!(document[MCP_SERVERS_KEY][MCP_SERVER_ALIAS], entry(8080));
+    }
+
+    #[test]
+    fn test_install_preserves_other_servers_and_keys() {
+        let mut document = json!({
+            "theme": "dark",
+            "mcpServers": {
+                "other": { "command": "other-server" },
+                "pcli2": { "command": "old" }
+            }
+        });
+        let change = install_server_entry(&mut document, entry(9090)).unwrap();
+        assert_eq!(change, ConfigChange::Replaced);
+        assert_eq!(document["theme"], "dark");
+        assert_eq!(
+            document[MCP_SERVERS_KEY]["other"],
+            json!({ "command": "other-server" })
+        );
+        assert_eq!(document[MCP_SERVERS_KEY][MCP_SERVER_ALIAS], entry(9090));
+
+        let again = install_server_entry(&mut document, entry(9090)).unwrap();
+        assert_eq!(again, ConfigChange::Unchanged);
+    }
+
+    #[test]
+    fn test_install_rejects_non_object_servers() {
+        let mut document = json!({ "mcpServers": [] });
+        assert!(install_server_entry(&mut document, entry(8080)).is_err());
+    }
+
+    #[test]
+    fn test_uninstall_removes_only_pcli2() {
+        let mut document = json!({
+            "mcpServers": {
+                "other": { "command": "other-server" },
+                "pcli2": { "command": "npx" }
+            }
+        });
+        let change = uninstall_server_entry(&mut document).unwrap();
+        assert_eq!(change, ConfigChange::Removed);
+        assert_eq!(
+            document,
+            json!({ "mcpServers": { "other": { "command": "other-server" } } })
+        );
+        assert_eq!(
+            uninstall_server_entry(&mut document).unwrap(),
+            ConfigChange::Unchanged
+        );
+    }
+
+    #[test]
+    fn test_resolve_config_path_prefers_explicit() {
+        let explicit = PathBuf::from("/tmp/custom.json");
+        let path = resolve_config_path(CLIENT_QWEN_AGENT, Some(&explicit)).unwrap();
+        assert_eq!(path, explicit);
+        assert!(resolve_config_path(CLIENT_QWEN_AGENT, None).is_err());
+    }
+
+    #[test]
+    fn test_resolve_config_path_qwen_code() {
+        let path = resolve_config_path(CLIENT_QWEN_CODE, None).unwrap();
+        assert!(path.ends_with(".qwen/settings.json"));
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let diff = line_diff("a\nb\nc\n", "a\nx\nc\n");
+        assert_eq!(diff, " a\n-b\n+x\n c\n");
+    }
+
+    #[test]
+    fn test_apply_to_file_writes_backup() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("pcli2-client-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.json");
+        fs::write(&path, r#"{"mcpServers":{"other":{"command":"x"}}}"#).unwrap();
+
+        apply_to_file(&path, false, |document| {
+            install_server_entry(document, entry(8080))
+        })
+        .unwrap();
+
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written[MCP_SERVERS_KEY]["other"]["command"], "x");
+        assert_eq!(written[MCP_SERVERS_KEY][MCP_SERVER_ALIAS], entry(8080));
+
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".bak"))
+            .count();
+        assert_eq!(backups, 1);
+
+        // A second backup right away gets its own name
+        let first = backup_config(&path).unwrap().unwrap();
+        let second = backup_config(&path).unwrap().unwrap();
+        assert_ne!(first, second);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
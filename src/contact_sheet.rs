@@ -0,0 +1,159 @@
+//! Contact sheets: many asset thumbnails composed into one labelled grid.
+//!
+//! Each tile holds a thumbnail scaled to fit the tile, with the asset path
+//! and an optional caption such as the match score underneath. Assets whose
+//! thumbnail could not be fetched get a grey placeholder tile so the grid
+//! still lines up with the list it was built from.
+
+use crate::raster::{LINE_HEIGHT, Rgba, fit_text, text_width};
+
+/// Tile size unless `tile_size` is given
+pub const DEFAULT_TILE_SIZE: u32 = 192;
+pub const MIN_TILE_SIZE: u32 = 64;
+pub const MAX_TILE_SIZE: u32 = 512;
+/// Most tiles on one sheet
+pub const MAX_SHEET_TILES: usize = 48;
+/// Candidates taken from a match result unless `top_k` is given
+pub const DEFAULT_SHEET_TOP_K: usize = 12;
+/// Most columns on one sheet
+pub const MAX_SHEET_COLUMNS: usize = 8;
+
+const PADDING: u32 = 8;
+const BACKGROUND: [u8; 4] = [255, 255, 255, 255];
+const BORDER: [u8; 4] = [210, 210, 210, 255];
+const PLACEHOLDER: [u8; 4] = [235, 235, 235, 255];
+const TEXT: [u8; 4] = [30, 30, 30, 255];
+const MUTED_TEXT: [u8; 4] = [110, 110, 110, 255];
+
+/// One asset on a contact sheet
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SheetTile {
+    /// First caption line, usually the asset path
+    pub label: String,
+    /// Second caption line, such as the match score
+    pub caption: Option<String>,
+    /// Thumbnail PNG, or `None` to draw a placeholder
+    pub png: Option<Vec<u8>>,
+}
+
+/// Columns used for `tiles` tiles: the requested count, or a near-square grid
+pub fn grid_columns(tiles: usize, requested: Option<usize>) -> usize {
+    let columns = requested.unwrap_or_else(|| (tiles as f64).sqrt().ceil() as usize);
+    columns.clamp(1, MAX_SHEET_COLUMNS).min(tiles.max(1))
+}
+
+/// Compose `tiles` into a grid and encode it as PNG
+pub fn compose(
+    tiles: &[SheetTile],
+    columns: Option<usize>,
+    tile_size: u32,
+) -> Result<Vec<u8>, String> {
+    if tiles.is_empty() {
+        return Err("A contact sheet needs at least one asset".to_string());
+    }
+    let tile_size = tile_size.clamp(MIN_TILE_SIZE, MAX_TILE_SIZE);
+    let columns = grid_columns(tiles.len(), columns);
+    let rows = tiles.len().div_ceil(columns);
+    let scale = if tile_size >= 320 { 2 } else { 1 };
+    let caption_height = 2 * LINE_HEIGHT * scale + PADDING / 2;
+    let cell_width = tile_size + PADDING;
+    let cell_height = tile_size + caption_height + PADDING;
+
+    let mut sheet = Rgba::new(
+        columns as u32 * cell_width + PADDING,
+        rows as u32 * cell_height + PADDING,
+        BACKGROUND,
+    );
+    let max_chars = (tile_size / text_width("x", scale)) as usize;
+    for (index, tile) in tiles.iter().enumerate() {
+        let x = PADDING + (index % columns) as u32 * cell_width;
+        let y = PADDING + (index / columns) as u32 * cell_height;
+        sheet.fill_rect(x, y, tile_size, tile_size, BORDER);
+        sheet.fill_rect(x + 1, y + 1, tile_size - 2, tile_size - 2, BACKGROUND);
+
+        match tile.png.as_deref().map(Rgba::decode_png) {
+            Some(Ok(image)) => {
+                let inner = tile_size - 4;
+                let image = image.fit_within(inner, inner);
+                let left = x + 2 + (inner - image.width) / 2;
+                let top = y + 2 + (inner - image.height) / 2;
+                sheet.draw_image(&image, left, top);
+            }
+            _ => {
+                sheet.fill_rect(x + 1, y + 1, tile_size - 2, tile_size - 2, PLACEHOLDER);
+                let text = fit_text("no thumbnail", max_chars);
+                let left = x + (tile_size - text_width(&text, scale)) / 2;
+                sheet.draw_text(
+                    &text,
+                    left,
+                    y + tile_size / 2 - 4 * scale,
+                    scale,
+                    MUTED_TEXT,
+                );
+            }
+        }
+
+        let text_top = y + tile_size + PADDING / 2;
+        sheet.draw_text(&fit_text(&tile.label, max_chars), x, text_top, scale, TEXT);
+        if let Some(caption) = &tile.caption {
+            sheet.draw_text(
+                &fit_text(caption, max_chars),
+                x,
+                text_top + LINE_HEIGHT * scale,
+                scale,
+                MUTED_TEXT,
+            );
+        }
+    }
+    sheet.encode_png()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        Rgba::new(width, height, [200, 40, 40, 255])
+            .encode_png()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_grid_columns() {
+        assert_eq!(grid_columns(1, None), 1);
+        assert_eq!(grid_columns(5, None), 3);
+        assert_eq!(grid_columns(48, None), 7);
+        assert_eq!(grid_columns(3, Some(10)), 3);
+        assert_eq!(grid_columns(30, Some(20)), MAX_SHEET_COLUMNS);
+    }
+
+    #[test]
+    fn test_compose_grid() {
+        let tiles = vec![
+            SheetTile {
+                label: "/Root/a.stl".to_string(),
+                caption: Some("score 97.5".to_string()),
+                png: Some(png(40, 20)),
+            },
+            SheetTile {
+                label: "/Root/b.stl".to_string(),
+                caption: None,
+                png: None,
+            },
+            SheetTile {
+                label: "/Root/c.stl".to_string(),
+                caption: None,
+                png: Some(b"broken".to_vec()),
+            },
+        ];
+        let sheet = Rgba::decode_png(&compose(&tiles, None, 64).unwrap()).unwrap();
+        // Two columns and two rows of 64px tiles with captions
+        assert_eq!(sheet.width, 2 * (64 + 8) + 8);
+        assert_eq!(sheet.height, 2 * (64 + 2 * 9 + 4 + 8) + 8);
+        // The middle of the first tile shows the thumbnail
+        let center = ((8 + 32) * sheet.width as usize + 8 + 32) * 4;
+        assert_eq!(&sheet.pixels[center..center + 4], [200, 40, 40, 255]);
+
+        assert!(compose(&[], None, 64).is_err());
+    }
+}
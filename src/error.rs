@@ -3,7 +3,7 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use serde_json::json;
+use serde_json::{Value, json};
 
 pub type Result<T> = std::result::Result<T, AppError>;
 
@@ -14,6 +14,249 @@ pub enum AppError {
     Rpc(super::mcp::RpcErrorResponse),
 }
 
+/// JSON-RPC error code for invalid tool arguments and generic tool failures
+pub const TOOL_ERROR_CODE: i64 = -32602;
+/// JSON-RPC error code for a tool call that exceeded its timeout
+pub const TOOL_TIMEOUT_CODE: i64 = -32001;
+/// JSON-RPC error code for a tool call rejected because the wait queue is full
+pub const SERVER_BUSY_CODE: i64 = -32002;
+
+/// Error returned from a `tools/call`, with optional structured data for clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl ToolError {
+    pub fn timeout(tool: &str, timeout: std::time::Duration, max: std::time::Duration) -> Self {
+        Self {
+            code: TOOL_TIMEOUT_CODE,
+            message: format!("{} timed out after {}s", tool, timeout.as_secs()),
+            data: Some(json!({
+                "reason": "timeout",
+                "tool": tool,
+                "timeout_seconds": timeout.as_secs(),
+                "max_timeout_seconds": max.as_secs(),
+            })),
+        }
+    }
+
+    pub fn server_busy(busy: crate::limits::ServerBusy) -> Self {
+        Self {
+            code: SERVER_BUSY_CODE,
+            message: format!(
+                "Server busy: {} pcli2 call(s) already waiting (limit {}); retry later",
+                busy.queue_depth, busy.max_queue
+            ),
+            data: Some(json!({
+                "reason": "server_busy",
+                "queue_depth": busy.queue_depth,
+                "max_queue": busy.max_queue,
+            })),
+        }
+    }
+}
+
+impl From<String> for ToolError {
+    fn from(message: String) -> Self {
+        Self {
+            code: TOOL_ERROR_CODE,
+            message,
+            data: None,
+        }
+    }
+}
+
+impl From<Pcli2Error> for ToolError {
+    fn from(error: Pcli2Error) -> Self {
+        let code = match error {
+            Pcli2Error::Timeout(_) => TOOL_TIMEOUT_CODE,
+            _ => TOOL_ERROR_CODE,
+        };
+        Self {
+            code,
+            message: format!("{}\nRemedy: {}", error, error.remedy()),
+            data: Some(json!({
+                "reason": error.code(),
+                "remedy": error.remedy(),
+                "retryable": error.is_transient(),
+            })),
+        }
+    }
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Failure of a pcli2 invocation, classified from its exit code and error output
+///
+/// Each variant carries the full human-readable message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pcli2Error {
+    /// The asset, folder or tenant does not exist
+    NotFound(String),
+    /// pcli2's credentials are missing or expired
+    AuthExpired(String),
+    /// The Physna API rejected the request with HTTP 429
+    RateLimited(String),
+    /// pcli2 did not finish within its timeout
+    Timeout(std::time::Duration),
+    /// pcli2 wrote more output than the server accepts
+    OutputTooLarge(String),
+    /// The pcli2 executable could not be started
+    SpawnFailed(String),
+    /// The tool arguments or the resulting pcli2 command line are invalid
+    InvalidArguments(String),
+    /// The Physna API failed with an HTTP 5xx status
+    Upstream5xx(String),
+    /// Any other failure
+    Failed(String),
+}
+
+/// Text that introduces an HTTP status code in pcli2's error output
+const STATUS_PREFIXES: [&str; 7] = [
+    "http ",
+    "http/1.1 ",
+    "http/2 ",
+    "status ",
+    "status: ",
+    "status code ",
+    "status code: ",
+];
+
+impl Pcli2Error {
+    /// Classify a non-zero pcli2 exit from its exit code and standard error
+    ///
+    /// Standard output holds asset names, UUIDs and counts, so it is not
+    /// consulted. Status codes only count after `HTTP` or `status`, as in
+    /// `HTTP 429` or `status: 503`.
+    pub fn from_exit(message: String, exit_code: Option<i32>, stderr: &str) -> Self {
+        let output = stderr.to_lowercase();
+        let mentions = |patterns: &[&str]| patterns.iter().any(|p| output.contains(p));
+        let has_status = |codes: &[&str]| {
+            STATUS_PREFIXES.iter().any(|prefix| {
+                output.match_indices(prefix).any(|(start, _)| {
+                    let rest = &output[start + prefix.len()..];
+                    codes.iter().any(|code| {
+                        rest.strip_prefix(code).is_some_and(|tail| {
+                            !tail.starts_with(|c: char| c.is_ascii_alphanumeric())
+                        })
+                    })
+                })
+            })
+        };
+
+        if has_status(&["429"]) || mentions(&["too many requests", "rate limit"]) {
+            Self::RateLimited(message)
+        } else if has_status(&["500", "502", "503", "504"])
+            || mentions(&[
+                "internal server error",
+                "bad gateway",
+                "service unavailable",
+                "gateway timeout",
+            ])
+        {
+            Self::Upstream5xx(message)
+        } else if has_status(&["401"])
+            || mentions(&[
+                "unauthorized",
+                "token expired",
+                "expired token",
+                "not authenticated",
+                "not logged in",
+                "auth login",
+            ])
+        {
+            Self::AuthExpired(message)
+        } else if has_status(&["404"]) || mentions(&["not found", "does not exist"]) {
+            Self::NotFound(message)
+        } else if exit_code == Some(2)
+            || mentions(&["unexpected argument", "invalid value", "usage:"])
+        {
+            Self::InvalidArguments(message)
+        } else {
+            Self::Failed(message)
+        }
+    }
+
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::AuthExpired(_) => "auth_expired",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Timeout(_) => "timeout",
+            Self::OutputTooLarge(_) => "output_too_large",
+            Self::SpawnFailed(_) => "spawn_failed",
+            Self::InvalidArguments(_) => "invalid_arguments",
+            Self::Upstream5xx(_) => "upstream_5xx",
+            Self::Failed(_) => "pcli2_failed",
+        }
+    }
+
+    /// Suggested next step for the agent or user
+    pub fn remedy(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => {
+                "Check the UUID or path, for example with pcli2_folder_resolve or the pcli2 list tool."
+            }
+            Self::AuthExpired(_) => {
+                "Run `pcli2 auth login` on the server host, then retry the call."
+            }
+            Self::RateLimited(_) => {
+                "Wait a minute before retrying, and avoid issuing calls in bulk."
+            }
+            Self::Timeout(_) => {
+                "Retry with a larger timeout_seconds, narrow the request, or use async: true for folder tools."
+            }
+            Self::OutputTooLarge(_) => {
+                "Narrow the request, for example with a higher threshold or a smaller folder."
+            }
+            Self::SpawnFailed(_) => {
+                "Install pcli2 or set PCLI2_BIN to its path on the server host."
+            }
+            Self::InvalidArguments(_) => "Fix the tool arguments and retry.",
+            Self::Upstream5xx(_) => "The Physna API is having trouble; retry in a few minutes.",
+            Self::Failed(_) => "Check the pcli2 output above; retrying is unlikely to help.",
+        }
+    }
+
+    /// Whether the failure is likely to go away on retry
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::RateLimited(_) | Self::Upstream5xx(_))
+    }
+}
+
+impl std::fmt::Display for Pcli2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout(after) => write!(f, "pcli2 timed out after {:?}", after),
+            Self::NotFound(message)
+            | Self::AuthExpired(message)
+            | Self::RateLimited(message)
+            | Self::OutputTooLarge(message)
+            | Self::SpawnFailed(message)
+            | Self::InvalidArguments(message)
+            | Self::Upstream5xx(message)
+            | Self::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Pcli2Error {}
+
+/// Argument validation failures are reported as strings
+impl From<String> for Pcli2Error {
+    fn from(message: String) -> Self {
+        Self::InvalidArguments(message)
+    }
+}
+
 impl From<anyhow::Error> for AppError {
     fn from(error: anyhow::Error) -> Self {
         AppError::Anyhow(error)
@@ -52,3 +295,68 @@ impl IntoResponse for AppError {
         (status, body).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(exit_code: i32, output: &str) -> &'static str {
+        Pcli2Error::from_exit("failed".to_string(), Some(exit_code), output).code()
+    }
+
+    #[test]
+    fn test_classify_pcli2_failures() {
+        assert_eq!(
+            classify(1, "Error: HTTP 429 Too Many Requests"),
+            "rate_limited"
+        );
+        assert_eq!(
+            classify(1, "server returned 503 Service Unavailable"),
+            "upstream_5xx"
+        );
+        assert_eq!(classify(1, "request failed, status: 502"), "upstream_5xx");
+        assert_eq!(classify(1, "Error: 401 Unauthorized"), "auth_expired");
+        assert_eq!(classify(1, "Asset not found"), "not_found");
+        assert_eq!(
+            classify(2, "error: unexpected argument '--bogus'"),
+            "invalid_arguments"
+        );
+        assert_eq!(classify(1, "something else went wrong"), "pcli2_failed");
+    }
+
+    #[test]
+    fn test_status_codes_need_a_status_prefix() {
+        // UUID fragments and counts must not look like HTTP statuses
+        assert_eq!(
+            classify(1, "failed for 5004a1c2-0429-4e5f-8401-123456789abc"),
+            "pcli2_failed"
+        );
+        assert_eq!(classify(1, "gave up after 500 assets"), "pcli2_failed");
+        assert_eq!(classify(1, "HTTP 4290 bytes"), "pcli2_failed");
+    }
+
+    #[test]
+    fn test_stdout_is_not_classified() {
+        let output = crate::backend::Pcli2Output {
+            stdout: "part-429.stl HTTP 503 fixture".to_string(),
+            stderr: "Error: upload rejected".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        let error = output.into_stdout("pcli2 asset create").unwrap_err();
+        assert_eq!(error.code(), "pcli2_failed");
+    }
+
+    #[test]
+    fn test_tool_error_from_pcli2_error() {
+        let error = ToolError::from(Pcli2Error::RateLimited("slow down".to_string()));
+        assert_eq!(error.code, TOOL_ERROR_CODE);
+        assert!(error.message.starts_with("slow down\nRemedy: "));
+        let data = error.data.expect("data");
+        assert_eq!(data["reason"], "rate_limited");
+        assert_eq!(data["retryable"], true);
+
+        let timeout = ToolError::from(Pcli2Error::Timeout(std::time::Duration::from_secs(5)));
+        assert_eq!(timeout.code, TOOL_TIMEOUT_CODE);
+    }
+}
@@ -0,0 +1,59 @@
+//! Hashing and randomness helpers shared by the caches.
+
+use sha2::{Digest, Sha256};
+
+/// Lowercase hex SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// Lowercase hex encoding of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Bytes of a hex string, or `None` if it is not valid hex
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Hex encoding of `bytes` bytes from the operating system's secure random source
+pub fn random_hex(bytes: usize) -> Result<String, String> {
+    let mut buffer = vec![0; bytes];
+    getrandom::fill(&mut buffer).map_err(|err| format!("Failed to read random bytes: {}", err))?;
+    Ok(to_hex(&buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00AB10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_random_hex() {
+        let first = random_hex(16).unwrap();
+        assert_eq!(first.len(), 32);
+        assert_ne!(first, random_hex(16).unwrap());
+    }
+}
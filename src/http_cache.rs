@@ -0,0 +1,240 @@
+//! HTTP validators, conditional requests and byte ranges for served thumbnails.
+//!
+//! Chat clients re-render conversations constantly. With an `ETag` and
+//! `Last-Modified` they can revalidate an image with `If-None-Match` or
+//! `If-Modified-Since` and get a bodyless 304 instead of downloading it again,
+//! and `Range` lets them resume or sample a large image.
+
+use chrono::{DateTime, TimeZone, Utc};
+use http::HeaderMap;
+use http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, RANGE};
+use std::ops::Range;
+
+/// Format of an HTTP date (IMF-fixdate)
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Quoted strong entity tag for a content hash
+pub fn etag(content_hash: &str) -> String {
+    format!("\"{}\"", content_hash)
+}
+
+/// Format a time as an HTTP date, dropping sub-second precision
+pub fn format_http_date(time: DateTime<Utc>) -> String {
+    time.format(HTTP_DATE_FORMAT).to_string()
+}
+
+/// Parse an HTTP date, accepting any RFC 2822 date
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Whether a GET or HEAD request's validators still match the representation
+///
+/// `If-None-Match` takes precedence; `If-Modified-Since` is only consulted
+/// without it (RFC 9110, section 13.2.2).
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: DateTime<Utc>) -> bool {
+    if let Some(value) = header(headers, IF_NONE_MATCH.as_str()) {
+        return etag_list_matches(value, etag);
+    }
+    header(headers, IF_MODIFIED_SINCE.as_str())
+        .and_then(parse_http_date)
+        .is_some_and(|since| truncate_to_seconds(last_modified) <= since)
+}
+
+/// Outcome of a request's `Range` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Send the whole representation
+    Full,
+    /// Send one slice of it with 206 Partial Content
+    Partial(Range<usize>),
+    /// No requested range overlaps it; answer 416
+    Unsatisfiable,
+}
+
+/// Byte range to send for a representation of `len` bytes
+///
+/// Only a single range is supported; multiple ranges, other units and
+/// malformed headers are ignored, as RFC 9110 allows. A stale `If-Range`
+/// also falls back to the full representation.
+pub fn byte_range(
+    headers: &HeaderMap,
+    len: usize,
+    etag: &str,
+    last_modified: DateTime<Utc>,
+) -> ByteRange {
+    let Some(value) = header(headers, RANGE.as_str()) else {
+        return ByteRange::Full;
+    };
+    if let Some(condition) = header(headers, IF_RANGE.as_str()) {
+        let current = match parse_http_date(condition) {
+            Some(date) => date == truncate_to_seconds(last_modified),
+            // Only a strong tag counts; weak tags never start with a quote
+            None => condition.trim() == etag,
+        };
+        if !current {
+            return ByteRange::Full;
+        }
+    }
+    parse_range(value, len)
+}
+
+fn parse_range(value: &str, len: usize) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let parse = |value: &str| value.trim().parse::<usize>().ok();
+    let range = match (start.trim().is_empty(), end.trim().is_empty()) {
+        // bytes=-N is the last N bytes
+        (true, false) => match parse(end) {
+            Some(0) => return ByteRange::Unsatisfiable,
+            Some(suffix) => len.saturating_sub(suffix)..len,
+            None => return ByteRange::Full,
+        },
+        // bytes=N- runs to the end
+        (false, true) => match parse(start) {
+            Some(start) => start..len,
+            None => return ByteRange::Full,
+        },
+        (false, false) => match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start <= end => start..end.saturating_add(1).min(len),
+            _ => return ByteRange::Full,
+        },
+        (true, true) => return ByteRange::Full,
+    };
+    if range.start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range)
+    }
+}
+
+/// Whether a comma-separated list of entity tags (or `*`) contains `etag`
+///
+/// Uses weak comparison, which ignores `W/` prefixes.
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    list.split(',').map(str::trim).any(|candidate| {
+        if candidate == "*" {
+            return true;
+        }
+        match candidate.strip_prefix("W/") {
+            Some(weak) => weak == etag,
+            None => candidate == etag,
+        }
+    })
+}
+
+fn truncate_to_seconds(time: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.timestamp_opt(time.timestamp(), 0)
+        .single()
+        .unwrap_or(time)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn modified() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1_700_000_000_250).unwrap()
+    }
+
+    #[test]
+    fn test_http_dates() {
+        let text = format_http_date(modified());
+        assert_eq!(text, "Tue, 14 Nov 2023 22:13:20 GMT");
+        assert_eq!(
+            parse_http_date(&text),
+            Some(truncate_to_seconds(modified()))
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let tag = etag("abc");
+        let not_modified = |pairs| is_not_modified(&headers(pairs), &tag, modified());
+
+        assert!(!not_modified(&[]));
+        assert!(not_modified(&[("if-none-match", "\"xyz\", W/\"abc\"")]));
+        assert!(not_modified(&[("if-none-match", "*")]));
+        assert!(!not_modified(&[("if-none-match", "\"xyz\"")]));
+        assert!(not_modified(&[(
+            "if-modified-since",
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        )]));
+        assert!(!not_modified(&[(
+            "if-modified-since",
+            "Tue, 14 Nov 2023 22:13:19 GMT"
+        )]));
+        // If-None-Match wins over If-Modified-Since
+        assert!(!not_modified(&[
+            ("if-none-match", "\"xyz\""),
+            ("if-modified-since", "Tue, 14 Nov 2023 22:13:20 GMT"),
+        ]));
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        let tag = etag("abc");
+        let range = |pairs| byte_range(&headers(pairs), 100, &tag, modified());
+
+        assert_eq!(range(&[]), ByteRange::Full);
+        assert_eq!(range(&[("range", "bytes=0-9")]), ByteRange::Partial(0..10));
+        assert_eq!(
+            range(&[("range", "bytes=90-")]),
+            ByteRange::Partial(90..100)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=-10")]),
+            ByteRange::Partial(90..100)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=50-500")]),
+            ByteRange::Partial(50..100)
+        );
+        assert_eq!(range(&[("range", "bytes=100-")]), ByteRange::Unsatisfiable);
+        assert_eq!(range(&[("range", "bytes=0-1,5-6")]), ByteRange::Full);
+        assert_eq!(range(&[("range", "items=0-1")]), ByteRange::Full);
+        assert_eq!(range(&[("range", "bytes=9-0")]), ByteRange::Full);
+
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", "\"abc\"")]),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", "W/\"abc\"")]),
+            ByteRange::Full
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", "*")]),
+            ByteRange::Full
+        );
+        assert_eq!(
+            range(&[
+                ("range", "bytes=0-9"),
+                ("if-range", "Tue, 14 Nov 2023 22:13:19 GMT")
+            ]),
+            ByteRange::Full
+        );
+    }
+}
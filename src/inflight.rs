@@ -0,0 +1,239 @@
+//! Merging of identical pcli2 invocations that are running at the same time.
+//!
+//! The first caller for an argv starts the command in its own task; later
+//! callers with the same argv wait for that task's result instead of
+//! spawning pcli2 again. The task is only aborted, killing the subprocess,
+//! once every caller waiting on it has gone away.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tracing::{debug, info};
+
+use crate::error::Pcli2Error;
+
+type Outcome = Result<String, Pcli2Error>;
+
+struct Flight {
+    id: u64,
+    result: watch::Receiver<Option<Outcome>>,
+    waiters: usize,
+    abort: Option<AbortHandle>,
+}
+
+/// Counters returned by the status tool
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightStats {
+    /// Distinct invocations currently running
+    pub running: usize,
+    /// Calls that joined an invocation already in flight
+    pub merged: u64,
+}
+
+/// Registry of running invocations, keyed by argv
+#[derive(Default)]
+pub struct InFlight {
+    flights: Mutex<HashMap<Vec<String>, Flight>>,
+    next_id: AtomicU64,
+    merged: AtomicU64,
+}
+
+/// Keeps a caller registered on a flight; the last one to drop aborts it
+struct Waiter {
+    registry: Arc<InFlight>,
+    key: Vec<String>,
+    id: u64,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let mut flights = self.registry.lock();
+        let Some(flight) = flights.get_mut(&self.key).filter(|f| f.id == self.id) else {
+            return;
+        };
+        flight.waiters -= 1;
+        if flight.waiters == 0 {
+            if let Some(abort) = flight.abort.take() {
+                abort.abort();
+            }
+            flights.remove(&self.key);
+            debug!("Abandoned in-flight invocation {}", self.id);
+        }
+    }
+}
+
+impl InFlight {
+    /// Run `start()` for `key`, or wait for the identical invocation already running
+    pub async fn run<F, Fut>(self: &Arc<Self>, key: Vec<String>, start: F) -> Outcome
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Outcome> + Send + 'static,
+    {
+        let (mut result, _waiter) = self.join_or_start(key, start);
+        match result.wait_for(|outcome| outcome.is_some()).await {
+            Ok(outcome) => outcome.clone().expect("wait_for only returns a result"),
+            Err(_) => Err(Pcli2Error::Failed(
+                "In-flight pcli2 invocation ended without a result".to_string(),
+            )),
+        }
+    }
+
+    pub fn stats(&self) -> InFlightStats {
+        InFlightStats {
+            running: self.lock().len(),
+            merged: self.merged.load(Ordering::SeqCst),
+        }
+    }
+
+    fn join_or_start<F, Fut>(
+        self: &Arc<Self>,
+        key: Vec<String>,
+        start: F,
+    ) -> (watch::Receiver<Option<Outcome>>, Waiter)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Outcome> + Send + 'static,
+    {
+        let mut flights = self.lock();
+        if let Some(flight) = flights.get_mut(&key) {
+            flight.waiters += 1;
+            self.merged.fetch_add(1, Ordering::SeqCst);
+            info!("Joining in-flight pcli2 {}", key.join(" "));
+            let waiter = Waiter {
+                registry: Arc::clone(self),
+                key,
+                id: flight.id,
+            };
+            return (flight.result.clone(), waiter);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = watch::channel(None);
+        let registry = Arc::clone(self);
+        let task_key = key.clone();
+        let future = start();
+        let handle = tokio::spawn(async move {
+            let outcome = future.await;
+            // Remove first so nobody joins a flight that has already finished
+            {
+                let mut flights = registry.lock();
+                if flights.get(&task_key).is_some_and(|f| f.id == id) {
+                    flights.remove(&task_key);
+                }
+            }
+            let _ = sender.send(Some(outcome));
+        });
+        flights.insert(
+            key.clone(),
+            Flight {
+                id,
+                result: receiver.clone(),
+                waiters: 1,
+                abort: Some(handle.abort_handle()),
+            },
+        );
+        let waiter = Waiter {
+            registry: Arc::clone(self),
+            key,
+            id,
+        };
+        (receiver, waiter)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Vec<String>, Flight>> {
+        self.flights
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    fn key(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_identical_calls_share_one_run() {
+        let registry = Arc::new(InFlight::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let call = || {
+            let runs = Arc::clone(&runs);
+            registry.run(key(&["folder", "part-match"]), move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok("shared".to_string())
+            })
+        };
+        let (a, b, c) = tokio::join!(call(), call(), call());
+        assert_eq!(a.as_deref(), Ok("shared"));
+        assert_eq!(b, a);
+        assert_eq!(c, a);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(registry.stats().merged, 2);
+        assert_eq!(registry.stats().running, 0);
+    }
+
+    #[tokio::test]
+    async fn test_different_argv_runs_separately() {
+        let registry = Arc::new(InFlight::default());
+        let (a, b) = tokio::join!(
+            registry.run(key(&["asset", "get", "--uuid", "a"]), || async {
+                Ok("a".to_string())
+            }),
+            registry.run(key(&["asset", "get", "--uuid", "b"]), || async {
+                Ok("b".to_string())
+            }),
+        );
+        assert_eq!((a.as_deref(), b.as_deref()), (Ok("a"), Ok("b")));
+        assert_eq!(registry.stats().merged, 0);
+    }
+
+    #[tokio::test]
+    async fn test_one_caller_leaving_keeps_shared_run() {
+        let registry = Arc::new(InFlight::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let start = |runs: Arc<AtomicUsize>| {
+            move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok("done".to_string())
+            }
+        };
+        let k = key(&["folder", "geometric-match"]);
+
+        let leaving = registry.run(k.clone(), start(Arc::clone(&runs)));
+        let timed_out = tokio::time::timeout(Duration::from_millis(20), async {
+            let staying = registry.run(k.clone(), start(Arc::clone(&runs)));
+            tokio::join!(leaving, staying)
+        });
+        // Dropping both futures at once abandons the run...
+        assert!(timed_out.await.is_err());
+        assert_eq!(registry.stats().running, 0);
+
+        // ...while dropping only one caller keeps it going for the other
+        let mut first = Box::pin(registry.run(k.clone(), start(Arc::clone(&runs))));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), &mut first)
+                .await
+                .is_err()
+        );
+        let second = {
+            let registry = Arc::clone(&registry);
+            let start = start(Arc::clone(&runs));
+            tokio::spawn(async move { registry.run(k, start).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(first);
+        assert_eq!(second.await.unwrap().as_deref(), Ok("done"));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
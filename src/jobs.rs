@@ -0,0 +1,506 @@
+//! Background jobs for long-running tool calls.
+//!
+//! A tool called with `async: true` is started as a job and returns a job ID
+//! right away. Each job record is written to `<jobs_dir>/<job_id>.json` on
+//! every state change, so status and results survive a client reconnect and
+//! a server restart.
+
+use crate::error::ToolError;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+/// How long finished jobs are kept on disk
+pub const JOB_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Most finished jobs kept; the oldest beyond this are removed
+pub const MAX_FINISHED_JOBS: usize = 256;
+
+/// File extension for persisted job records
+const JOB_EXTENSION: &str = "json";
+
+/// Lifecycle state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Running)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "running" => Some(JobStatus::Running),
+            "succeeded" => Some(JobStatus::Succeeded),
+            "failed" => Some(JobStatus::Failed),
+            "cancelled" => Some(JobStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// Error recorded for a failed job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl From<JobError> for ToolError {
+    fn from(error: JobError) -> Self {
+        ToolError {
+            code: error.code,
+            message: error.message,
+            data: error.data,
+        }
+    }
+}
+
+/// Persisted state of one job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub tool: String,
+    pub arguments: Value,
+    pub status: JobStatus,
+    /// Unix timestamps in milliseconds
+    pub created_at: i64,
+    #[serde(default)]
+    pub finished_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JobError>,
+}
+
+impl JobRecord {
+    /// Status view without the (possibly large) result payload
+    pub fn summary(&self) -> Value {
+        serde_json::json!({
+            "job_id": self.id,
+            "tool": self.tool,
+            "status": self.status,
+            "created_at": self.created_at,
+            "finished_at": self.finished_at,
+            "error": self.error,
+        })
+    }
+}
+
+struct JobEntry {
+    record: JobRecord,
+    abort: Option<AbortHandle>,
+}
+
+/// Registry of background jobs, optionally persisted to a directory
+pub struct JobManager {
+    jobs_dir: Option<PathBuf>,
+    jobs: Mutex<HashMap<String, JobEntry>>,
+    counter: AtomicU64,
+}
+
+impl JobManager {
+    /// Job manager that keeps records in memory only
+    pub fn in_memory() -> Self {
+        Self {
+            jobs_dir: None,
+            jobs: Mutex::new(HashMap::new()),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Job manager persisting records in `jobs_dir`
+    ///
+    /// Records left from a previous run are loaded; jobs that were still
+    /// running are marked failed, and finished jobs older than
+    /// [`JOB_RETENTION`] are deleted.
+    pub fn open(jobs_dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&jobs_dir)
+            .map_err(|err| format!("Failed to create jobs directory {:?}: {}", jobs_dir, err))?;
+        let manager = Self {
+            jobs_dir: Some(jobs_dir),
+            jobs: Mutex::new(HashMap::new()),
+            counter: AtomicU64::new(0),
+        };
+        manager.load_existing()?;
+        Ok(manager)
+    }
+
+    pub fn jobs_dir(&self) -> Option<&Path> {
+        self.jobs_dir.as_deref()
+    }
+
+    /// Register a new running job and return its record
+    pub fn create(&self, tool: &str, arguments: Value) -> JobRecord {
+        let record = JobRecord {
+            id: self.next_id(),
+            tool: tool.to_string(),
+            arguments,
+            status: JobStatus::Running,
+            created_at: Utc::now().timestamp_millis(),
+            finished_at: None,
+            result: None,
+            error: None,
+        };
+        self.persist(&record);
+        self.lock().insert(
+            record.id.clone(),
+            JobEntry {
+                record: record.clone(),
+                abort: None,
+            },
+        );
+        info!("Started job {} for {}", record.id, tool);
+        self.prune();
+        record
+    }
+
+    /// Remember how to abort the task running a job
+    pub fn attach(&self, job_id: &str, abort: AbortHandle) {
+        let mut jobs = self.lock();
+        if let Some(entry) = jobs.get_mut(job_id)
+            && !entry.record.status.is_finished()
+        {
+            entry.abort = Some(abort);
+        }
+    }
+
+    /// Record the outcome of a job; ignored if the job was already cancelled
+    ///
+    /// Results can be many megabytes, so the record is written on the
+    /// blocking thread pool.
+    pub async fn finish(&self, job_id: &str, outcome: Result<Value, ToolError>) {
+        let record = {
+            let mut jobs = self.lock();
+            let Some(entry) = jobs.get_mut(job_id) else {
+                return;
+            };
+            if entry.record.status.is_finished() {
+                return;
+            }
+            match outcome {
+                Ok(result) => {
+                    entry.record.status = JobStatus::Succeeded;
+                    entry.record.result = Some(result);
+                }
+                Err(err) => {
+                    entry.record.status = JobStatus::Failed;
+                    entry.record.error = Some(JobError {
+                        code: err.code,
+                        message: err.message,
+                        data: err.data,
+                    });
+                }
+            }
+            entry.record.finished_at = Some(Utc::now().timestamp_millis());
+            entry.abort = None;
+            entry.record.clone()
+        };
+        info!("Job {} finished: {:?}", record.id, record.status);
+        if let Some(path) = self.job_path(&record.id) {
+            let written = tokio::task::spawn_blocking(move || write_record(&path, &record)).await;
+            if let Err(err) = written {
+                warn!("Job persistence task failed: {}", err);
+            }
+        }
+        self.prune();
+    }
+
+    /// Cancel a running job; returns the updated record
+    pub fn cancel(&self, job_id: &str) -> Result<JobRecord, String> {
+        let record = {
+            let mut jobs = self.lock();
+            let entry = jobs
+                .get_mut(job_id)
+                .ok_or_else(|| format!("Unknown job '{}'", job_id))?;
+            if entry.record.status.is_finished() {
+                return Ok(entry.record.clone());
+            }
+            if let Some(abort) = entry.abort.take() {
+                abort.abort();
+            }
+            entry.record.status = JobStatus::Cancelled;
+            entry.record.finished_at = Some(Utc::now().timestamp_millis());
+            entry.record.clone()
+        };
+        info!("Cancelled job {}", job_id);
+        self.persist(&record);
+        Ok(record)
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<JobRecord> {
+        self.lock().get(job_id).map(|entry| entry.record.clone())
+    }
+
+    /// All jobs, newest first, optionally filtered by status
+    pub fn list(&self, status: Option<JobStatus>) -> Vec<JobRecord> {
+        let mut records: Vec<JobRecord> = self
+            .lock()
+            .values()
+            .map(|entry| entry.record.clone())
+            .filter(|record| status.is_none_or(|status| record.status == status))
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        records
+    }
+
+    /// Remove finished jobs older than [`JOB_RETENTION`] or beyond [`MAX_FINISHED_JOBS`]
+    fn prune(&self) {
+        let now = Utc::now().timestamp_millis();
+        let retention = JOB_RETENTION.as_millis() as i64;
+        let removed: Vec<String> = {
+            let mut jobs = self.lock();
+            let mut finished: Vec<(i64, String)> = jobs
+                .values()
+                .filter_map(|entry| Some((entry.record.finished_at?, entry.record.id.clone())))
+                .collect();
+            // Newest first, so everything past the cap is the oldest
+            finished.sort_by(|a, b| b.cmp(a));
+            let stale: Vec<String> = finished
+                .into_iter()
+                .enumerate()
+                .filter(|(index, (finished_at, _))| {
+                    *index >= MAX_FINISHED_JOBS || now - finished_at > retention
+                })
+                .map(|(_, (_, id))| id)
+                .collect();
+            for id in &stale {
+                jobs.remove(id);
+            }
+            stale
+        };
+        for id in &removed {
+            debug!("Removing finished job {}", id);
+            if let Some(path) = self.job_path(id) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn next_id(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::SeqCst);
+        format!(
+            "job-{:x}-{:x}-{:04x}",
+            Utc::now().timestamp_millis(),
+            std::process::id(),
+            count
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, JobEntry>> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn job_path(&self, job_id: &str) -> Option<PathBuf> {
+        self.jobs_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.{}", job_id, JOB_EXTENSION)))
+    }
+
+    fn persist(&self, record: &JobRecord) {
+        if let Some(path) = self.job_path(&record.id) {
+            write_record(&path, record);
+        }
+    }
+
+    fn load_existing(&self) -> Result<(), String> {
+        let Some(dir) = self.jobs_dir.as_ref() else {
+            return Ok(());
+        };
+        let entries = fs::read_dir(dir)
+            .map_err(|err| format!("Failed to read jobs directory {:?}: {}", dir, err))?;
+        let now = Utc::now().timestamp_millis();
+        let retention = JOB_RETENTION.as_millis() as i64;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some(JOB_EXTENSION) {
+                continue;
+            }
+            let record = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<JobRecord>(&bytes).ok());
+            let Some(mut record) = record else {
+                warn!("Ignoring unreadable job record {:?}", path);
+                continue;
+            };
+            if record
+                .finished_at
+                .is_some_and(|finished| now - finished > retention)
+            {
+                debug!("Removing expired job {}", record.id);
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if !record.status.is_finished() {
+                record.status = JobStatus::Failed;
+                record.finished_at = Some(now);
+                record.error = Some(JobError {
+                    code: crate::error::TOOL_ERROR_CODE,
+                    message: "Job was interrupted by a server restart".to_string(),
+                    data: Some(serde_json::json!({ "reason": "interrupted" })),
+                });
+                self.persist(&record);
+            }
+            self.lock().insert(
+                record.id.clone(),
+                JobEntry {
+                    record,
+                    abort: None,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Get the default jobs directory, next to the thumbnail cache
+///
+/// Uses ~/.pcli2-mcp/jobs on Unix-like systems
+pub fn default_jobs_dir() -> Result<PathBuf, String> {
+    let mut dir = crate::thumbnail::default_cache_dir()?;
+    dir.pop();
+    dir.push("jobs");
+    Ok(dir)
+}
+
+/// Write a job record to `path` through a temporary file and a rename
+fn write_record(path: &Path, record: &JobRecord) {
+    let write = serde_json::to_vec(record)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| {
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, bytes)
+                .and_then(|_| fs::rename(&temp, path))
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = write {
+        warn!("Failed to persist job {} to {:?}: {}", record.id, path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    fn temp_jobs_dir(name: &str) -> PathBuf {
+        let mut dir = env::temp_dir();
+        dir.push(format!("pcli2-jobs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_create_and_finish_job() {
+        let jobs = JobManager::in_memory();
+        let record = jobs.create("pcli2_folder_part_match", json!({"folder_path": "/A"}));
+        assert_eq!(record.status, JobStatus::Running);
+
+        jobs.finish(&record.id, Ok(json!({"content": []}))).await;
+        let finished = jobs.get(&record.id).unwrap();
+        assert_eq!(finished.status, JobStatus::Succeeded);
+        assert_eq!(finished.result, Some(json!({"content": []})));
+        assert!(finished.finished_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_keeps_error() {
+        let jobs = JobManager::in_memory();
+        let record = jobs.create("pcli2_folder_dependencies", json!({}));
+        jobs.finish(&record.id, Err(ToolError::from("boom".to_string())))
+            .await;
+        let failed = jobs.get(&record.id).unwrap();
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.unwrap().message, "boom");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_wins_over_late_finish() {
+        let jobs = JobManager::in_memory();
+        let record = jobs.create("pcli2_folder_visual_match", json!({}));
+        let cancelled = jobs.cancel(&record.id).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+
+        jobs.finish(&record.id, Ok(json!({}))).await;
+        assert_eq!(jobs.get(&record.id).unwrap().status, JobStatus::Cancelled);
+        assert!(jobs.cancel("missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_filters_by_status() {
+        let jobs = JobManager::in_memory();
+        let a = jobs.create("a", json!({}));
+        let _b = jobs.create("b", json!({}));
+        jobs.finish(&a.id, Ok(json!({}))).await;
+        assert_eq!(jobs.list(None).len(), 2);
+        assert_eq!(jobs.list(Some(JobStatus::Running)).len(), 1);
+        assert_eq!(jobs.list(Some(JobStatus::Succeeded))[0].id, a.id);
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_are_pruned_beyond_cap() {
+        let manager = JobManager::in_memory();
+        for _ in 0..MAX_FINISHED_JOBS + 5 {
+            let record = manager.create("pcli2_folder_part_match", json!({}));
+            manager.finish(&record.id, Ok(json!({ "ok": true }))).await;
+        }
+        let running = manager.create("pcli2_folder_part_match", json!({}));
+        assert_eq!(manager.list(None).len(), MAX_FINISHED_JOBS + 1);
+        assert!(manager.get(&running.id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_reopen() {
+        let dir = temp_jobs_dir("reopen");
+        let (done_id, running_id) = {
+            let jobs = JobManager::open(dir.clone()).unwrap();
+            let done = jobs.create("done", json!({}));
+            jobs.finish(
+                &done.id,
+                Ok(json!({"content": [{"type": "text", "text": "ok"}]})),
+            )
+            .await;
+            let running = jobs.create("running", json!({}));
+            (done.id, running.id)
+        };
+
+        let reopened = JobManager::open(dir.clone()).unwrap();
+        let done = reopened.get(&done_id).unwrap();
+        assert_eq!(done.status, JobStatus::Succeeded);
+        assert_eq!(done.result.unwrap()["content"][0]["text"], "ok");
+
+        let interrupted = reopened.get(&running_id).unwrap();
+        assert_eq!(interrupted.status, JobStatus::Failed);
+        assert_eq!(
+            interrupted.error.unwrap().data.unwrap()["reason"],
+            "interrupted"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_default_jobs_dir() {
+        let dir = default_jobs_dir().unwrap();
+        assert!(dir.ends_with(".pcli2-mcp/jobs"));
+    }
+}
@@ -1,16 +1,49 @@
+pub mod backend;
+pub mod cache_admin;
 pub mod cli;
+pub mod client;
+pub mod client_config;
+pub mod contact_sheet;
 pub mod error;
+pub mod hash;
+pub mod http_cache;
+pub mod inflight;
+pub mod jobs;
+pub mod limits;
+pub mod markdown;
+pub mod match_filter;
+pub mod match_report;
 pub mod mcp;
+pub mod pages;
 pub mod pcli;
+pub mod public_url;
+pub mod raster;
+pub mod result_cache;
+pub mod s3;
 pub mod server;
+pub mod signing;
+pub mod store;
 pub mod thumbnail;
+pub mod variant;
 
 use anyhow::Result;
+use backend::{Pcli2Backend, SubprocessBackend};
+use cache_admin::run_cache;
 use clap::ArgMatches;
-use cli::{ARG_LOG_LEVEL, CMD_CONFIG, CMD_HELP, CMD_SERVE, build_cli};
+use cli::{ARG_LOG_LEVEL, CMD_CACHE, CMD_CONFIG, CMD_HELP, CMD_SERVE, build_cli};
+use client::Pcli2Client;
+use inflight::InFlight;
+use jobs::JobManager;
+use limits::Pcli2Limiter;
 use mcp::run_config;
+use pages::PageStore;
+use pcli::PCLI2_TIMEOUT;
+use public_url::PublicUrl;
+use result_cache::ResultCache;
 use server::run_server;
+use std::path::PathBuf;
 use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use thumbnail::ThumbnailCache;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
@@ -18,7 +51,54 @@ use tracing_subscriber::{EnvFilter, FmtSubscriber};
 pub struct AppState {
     pub server_name: String,
     pub server_version: String,
-    pub thumbnail_cache: Arc<Option<ThumbnailCache>>,
+    pub thumbnail_cache: Option<Arc<ThumbnailCache>>,
+    /// Upper bound for the `timeout_seconds` argument of any tool call
+    pub max_tool_timeout: Duration,
+    /// Concurrency limits and wait queue for pcli2 subprocesses
+    pub limiter: Arc<Pcli2Limiter>,
+    /// Background jobs started with `async: true`
+    pub jobs: Arc<JobManager>,
+    /// Cached output of read-only pcli2 calls
+    pub results: Arc<ResultCache>,
+    /// Executes pcli2 commands; the subprocess runner unless replaced
+    pub backend: Arc<dyn Pcli2Backend>,
+    /// Identical read-only pcli2 calls currently running
+    pub in_flight: Arc<InFlight>,
+    /// Pages of results larger than the response budget
+    pub pages: Arc<PageStore>,
+    /// Where thumbnail links in tool results point
+    pub public_url: PublicUrl,
+    /// Directory that server-local upload paths must be in; without it such
+    /// uploads are refused
+    pub upload_root: Option<PathBuf>,
+}
+
+impl AppState {
+    pub fn new(
+        server_name: impl Into<String>,
+        server_version: impl Into<String>,
+        thumbnail_cache: Option<ThumbnailCache>,
+    ) -> Self {
+        Self {
+            server_name: server_name.into(),
+            server_version: server_version.into(),
+            thumbnail_cache: thumbnail_cache.map(Arc::new),
+            max_tool_timeout: PCLI2_TIMEOUT,
+            limiter: Arc::new(Pcli2Limiter::default()),
+            jobs: Arc::new(JobManager::in_memory()),
+            results: Arc::new(ResultCache::in_memory()),
+            backend: Arc::new(SubprocessBackend::from_env()),
+            in_flight: Arc::new(InFlight::default()),
+            pages: Arc::new(PageStore::default()),
+            public_url: PublicUrl::default(),
+            upload_root: None,
+        }
+    }
+
+    /// Client running pcli2 through this state's backend
+    pub fn client(&self) -> Pcli2Client {
+        Pcli2Client::new(Arc::clone(&self.backend)).with_in_flight(Arc::clone(&self.in_flight))
+    }
 }
 
 pub async fn run() -> Result<()> {
@@ -37,6 +117,7 @@ pub async fn run() -> Result<()> {
     match matches.subcommand() {
         Some((CMD_SERVE, sub_matches)) => run_server(sub_matches).await,
         Some((CMD_CONFIG, sub_matches)) => run_config(sub_matches),
+        Some((CMD_CACHE, sub_matches)) => run_cache(sub_matches),
         Some((CMD_HELP, sub_matches)) => run_help(sub_matches),
         _ => Ok(()),
     }
@@ -86,15 +167,12 @@ mod tests {
 
     #[test]
     fn test_app_state_clone() {
-        let state = AppState {
-            server_name: "test-server".to_string(),
-            server_version: "1.0.0".to_string(),
-            thumbnail_cache: Arc::new(None),
-        };
+        let state = AppState::new("test-server", "1.0.0", None);
         let cloned_state = state.clone();
 
         assert_eq!(state.server_name, cloned_state.server_name);
         assert_eq!(state.server_version, cloned_state.server_version);
+        assert_eq!(state.max_tool_timeout, cloned_state.max_tool_timeout);
     }
 
     #[test]
@@ -0,0 +1,440 @@
+//! Concurrency limits for pcli2 subprocesses.
+//!
+//! Every tool call that spawns pcli2 takes a permit from the tenant's
+//! semaphore, then the tool's semaphore, then the global one. Callers that
+//! cannot start immediately wait in a bounded queue; once the queue is full
+//! further calls are rejected with a "server busy" error.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+/// Default number of pcli2 processes allowed to run at once
+pub const DEFAULT_MAX_CONCURRENT: usize = 8;
+/// Default number of pcli2 processes allowed per tenant
+pub const DEFAULT_MAX_CONCURRENT_PER_TENANT: usize = 4;
+/// Default number of calls allowed to wait for a slot
+pub const DEFAULT_MAX_QUEUE: usize = 64;
+/// Default limit for the heavy folder-wide tools
+pub const DEFAULT_BULK_TOOL_LIMIT: usize = 2;
+
+/// Key used for calls that do not name a tenant (pcli2's active tenant)
+pub const ACTIVE_TENANT_KEY: &str = "(active)";
+
+/// Limits applied by [`Pcli2Limiter`]
+#[derive(Clone, Debug)]
+pub struct LimitsConfig {
+    pub max_concurrent: usize,
+    pub max_concurrent_per_tenant: usize,
+    pub max_queue: usize,
+    pub tool_limits: HashMap<String, usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let tool_limits = [
+            "pcli2_folder_dependencies",
+            "pcli2_folder_geometric_match",
+            "pcli2_folder_part_match",
+            "pcli2_folder_visual_match",
+        ]
+        .into_iter()
+        .map(|tool| (tool.to_string(), DEFAULT_BULK_TOOL_LIMIT))
+        .collect();
+        Self {
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            max_concurrent_per_tenant: DEFAULT_MAX_CONCURRENT_PER_TENANT,
+            max_queue: DEFAULT_MAX_QUEUE,
+            tool_limits,
+        }
+    }
+}
+
+/// Parse a `TOOL=N` per-tool limit
+pub fn parse_tool_limit(value: &str) -> Result<(String, usize), String> {
+    let (tool, limit) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid tool limit '{}': expected TOOL=N", value))?;
+    let limit: usize = limit
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid tool limit '{}': N must be a number", value))?;
+    if limit == 0 {
+        return Err(format!(
+            "Invalid tool limit '{}': N must be at least 1",
+            value
+        ));
+    }
+    Ok((tool.trim().to_string(), limit))
+}
+
+/// Reason a call could not get a slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerBusy {
+    pub queue_depth: usize,
+    pub max_queue: usize,
+}
+
+/// Snapshot of limiter activity, returned by the status tool
+#[derive(Debug, Clone, Serialize)]
+pub struct LimiterStats {
+    pub running: usize,
+    pub queue_depth: usize,
+    pub max_concurrent: usize,
+    pub max_concurrent_per_tenant: usize,
+    pub max_queue: usize,
+    pub total_started: u64,
+    pub total_rejected: u64,
+    pub average_wait_ms: u64,
+    pub max_wait_ms: u64,
+    pub running_by_tool: HashMap<String, usize>,
+    pub running_by_tenant: HashMap<String, usize>,
+}
+
+/// Global, per-tool and per-tenant limiter for pcli2 subprocesses
+pub struct Pcli2Limiter {
+    config: LimitsConfig,
+    global: Arc<Semaphore>,
+    tools: Mutex<HashMap<String, Arc<Semaphore>>>,
+    tenants: Mutex<HashMap<String, Arc<Semaphore>>>,
+    waiting: AtomicUsize,
+    running: AtomicUsize,
+    total_started: AtomicU64,
+    total_rejected: AtomicU64,
+    total_wait_ms: AtomicU64,
+    max_wait_ms: AtomicU64,
+    running_by_tool: Mutex<HashMap<String, usize>>,
+    running_by_tenant: Mutex<HashMap<String, usize>>,
+}
+
+/// Permits held while a pcli2 process runs; released on drop
+pub struct Pcli2Permit {
+    limiter: Arc<Pcli2Limiter>,
+    tool: String,
+    tenant: String,
+    _permits: [OwnedSemaphorePermit; 3],
+}
+
+impl Drop for Pcli2Permit {
+    fn drop(&mut self) {
+        self.limiter.running.fetch_sub(1, Ordering::SeqCst);
+        decrement(&self.limiter.running_by_tool, &self.tool);
+        decrement(&self.limiter.running_by_tenant, &self.tenant);
+    }
+}
+
+/// Decrements the waiting count even if the acquiring future is dropped
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Pcli2Limiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            config,
+            tools: Mutex::new(HashMap::new()),
+            tenants: Mutex::new(HashMap::new()),
+            waiting: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            total_started: AtomicU64::new(0),
+            total_rejected: AtomicU64::new(0),
+            total_wait_ms: AtomicU64::new(0),
+            max_wait_ms: AtomicU64::new(0),
+            running_by_tool: Mutex::new(HashMap::new()),
+            running_by_tenant: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &LimitsConfig {
+        &self.config
+    }
+
+    /// Wait for a slot to run `tool` for `tenant`
+    ///
+    /// A call that gets a slot at once never counts against the queue; one
+    /// that has to wait fails immediately with [`ServerBusy`] when the wait
+    /// queue is full.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        tool: &str,
+        tenant: Option<&str>,
+    ) -> Result<Pcli2Permit, ServerBusy> {
+        let tenant = tenant.unwrap_or(ACTIVE_TENANT_KEY);
+        let started = Instant::now();
+        let (tenant_semaphore, tool_semaphore) = self.semaphores(tool, tenant);
+
+        let permits = match try_acquire_all(&tenant_semaphore, &tool_semaphore, &self.global) {
+            Some(permits) => permits,
+            None => {
+                let queued = self.waiting.fetch_add(1, Ordering::SeqCst);
+                let waiting = WaitingGuard(&self.waiting);
+                if queued >= self.config.max_queue {
+                    self.total_rejected.fetch_add(1, Ordering::SeqCst);
+                    return Err(ServerBusy {
+                        queue_depth: queued,
+                        max_queue: self.config.max_queue,
+                    });
+                }
+                // Narrowest limit first, so global slots are only held by runnable calls
+                let tenant_permit = acquire_owned(tenant_semaphore).await;
+                let tool_permit = acquire_owned(tool_semaphore).await;
+                let global_permit = acquire_owned(self.global.clone()).await;
+                drop(waiting);
+                [tenant_permit, tool_permit, global_permit]
+            }
+        };
+
+        Ok(self.start(tool, tenant, permits, started.elapsed()))
+    }
+
+    /// A slot to run `tool` for `tenant` if one is free right now, without queueing
+    pub fn try_acquire(self: &Arc<Self>, tool: &str, tenant: Option<&str>) -> Option<Pcli2Permit> {
+        let tenant = tenant.unwrap_or(ACTIVE_TENANT_KEY);
+        let (tenant_semaphore, tool_semaphore) = self.semaphores(tool, tenant);
+        let permits = try_acquire_all(&tenant_semaphore, &tool_semaphore, &self.global)?;
+        Some(self.start(tool, tenant, permits, Duration::ZERO))
+    }
+
+    fn start(
+        self: &Arc<Self>,
+        tool: &str,
+        tenant: &str,
+        permits: [OwnedSemaphorePermit; 3],
+        waited: Duration,
+    ) -> Pcli2Permit {
+        self.record_start(waited);
+        increment(&self.running_by_tool, tool);
+        increment(&self.running_by_tenant, tenant);
+        debug!(
+            "pcli2 slot acquired for {} (tenant {}) after {:?}",
+            tool, tenant, waited
+        );
+        Pcli2Permit {
+            limiter: Arc::clone(self),
+            tool: tool.to_string(),
+            tenant: tenant.to_string(),
+            _permits: permits,
+        }
+    }
+
+    pub fn stats(&self) -> LimiterStats {
+        let started = self.total_started.load(Ordering::SeqCst);
+        let total_wait = self.total_wait_ms.load(Ordering::SeqCst);
+        LimiterStats {
+            running: self.running.load(Ordering::SeqCst),
+            queue_depth: self.waiting.load(Ordering::SeqCst),
+            max_concurrent: self.config.max_concurrent,
+            max_concurrent_per_tenant: self.config.max_concurrent_per_tenant,
+            max_queue: self.config.max_queue,
+            total_started: started,
+            total_rejected: self.total_rejected.load(Ordering::SeqCst),
+            average_wait_ms: total_wait.checked_div(started).unwrap_or(0),
+            max_wait_ms: self.max_wait_ms.load(Ordering::SeqCst),
+            running_by_tool: snapshot(&self.running_by_tool),
+            running_by_tenant: snapshot(&self.running_by_tenant),
+        }
+    }
+
+    fn record_start(&self, waited: Duration) {
+        let waited_ms = waited.as_millis() as u64;
+        self.running.fetch_add(1, Ordering::SeqCst);
+        self.total_started.fetch_add(1, Ordering::SeqCst);
+        self.total_wait_ms.fetch_add(waited_ms, Ordering::SeqCst);
+        self.max_wait_ms.fetch_max(waited_ms, Ordering::SeqCst);
+    }
+
+    /// The per-tenant and per-tool semaphores for a call
+    fn semaphores(&self, tool: &str, tenant: &str) -> (Arc<Semaphore>, Arc<Semaphore>) {
+        let tenant_semaphore =
+            self.semaphore_for(&self.tenants, tenant, self.config.max_concurrent_per_tenant);
+        let tool_limit = self
+            .config
+            .tool_limits
+            .get(tool)
+            .copied()
+            .unwrap_or(self.config.max_concurrent);
+        let tool_semaphore = self.semaphore_for(&self.tools, tool, tool_limit);
+        (tenant_semaphore, tool_semaphore)
+    }
+
+    fn semaphore_for(
+        &self,
+        map: &Mutex<HashMap<String, Arc<Semaphore>>>,
+        key: &str,
+        limit: usize,
+    ) -> Arc<Semaphore> {
+        let mut map = map.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        map.entry(key.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(limit.max(1))))
+            .clone()
+    }
+}
+
+impl Default for Pcli2Limiter {
+    fn default() -> Self {
+        Self::new(LimitsConfig::default())
+    }
+}
+
+/// All three permits if every limit has a free slot right now
+fn try_acquire_all(
+    tenant: &Arc<Semaphore>,
+    tool: &Arc<Semaphore>,
+    global: &Arc<Semaphore>,
+) -> Option<[OwnedSemaphorePermit; 3]> {
+    let tenant = Arc::clone(tenant).try_acquire_owned().ok()?;
+    let tool = Arc::clone(tool).try_acquire_owned().ok()?;
+    let global = Arc::clone(global).try_acquire_owned().ok()?;
+    Some([tenant, tool, global])
+}
+
+async fn acquire_owned(semaphore: Arc<Semaphore>) -> OwnedSemaphorePermit {
+    semaphore
+        .acquire_owned()
+        .await
+        .expect("limiter semaphores are never closed")
+}
+
+fn increment(map: &Mutex<HashMap<String, usize>>, key: &str) {
+    let mut map = map.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *map.entry(key.to_string()).or_insert(0) += 1;
+}
+
+fn decrement(map: &Mutex<HashMap<String, usize>>, key: &str) {
+    let mut map = map.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(count) = map.get_mut(key) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            map.remove(key);
+        }
+    }
+}
+
+fn snapshot(map: &Mutex<HashMap<String, usize>>) -> HashMap<String, usize> {
+    map.lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_concurrent: usize, per_tenant: usize, max_queue: usize) -> Arc<Pcli2Limiter> {
+        Arc::new(Pcli2Limiter::new(LimitsConfig {
+            max_concurrent,
+            max_concurrent_per_tenant: per_tenant,
+            max_queue,
+            tool_limits: HashMap::from([("bulk".to_string(), 1)]),
+        }))
+    }
+
+    #[test]
+    fn test_parse_tool_limit() {
+        assert_eq!(
+            parse_tool_limit("pcli2_folder_part_match=3").unwrap(),
+            ("pcli2_folder_part_match".to_string(), 3)
+        );
+        assert!(parse_tool_limit("pcli2_folder_part_match").is_err());
+        assert!(parse_tool_limit("tool=zero").is_err());
+        assert!(parse_tool_limit("tool=0").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_and_release_updates_stats() {
+        let limiter = limiter(2, 2, 4);
+        let permit = limiter
+            .acquire("pcli2_asset_get", Some("acme"))
+            .await
+            .unwrap();
+        let stats = limiter.stats();
+        assert_eq!(stats.running, 1);
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.running_by_tenant.get("acme"), Some(&1));
+        drop(permit);
+        let stats = limiter.stats();
+        assert_eq!(stats.running, 0);
+        assert_eq!(stats.total_started, 1);
+        assert!(stats.running_by_tool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_limit_queues_second_call() {
+        let limiter = limiter(4, 4, 4);
+        let first = limiter.acquire("bulk", None).await.unwrap();
+
+        let waiter = {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move { limiter.acquire("bulk", None).await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(limiter.stats().queue_depth, 1);
+        assert!(!waiter.is_finished());
+
+        drop(first);
+        waiter.await.unwrap().unwrap();
+        assert_eq!(limiter.stats().queue_depth, 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_with_server_busy() {
+        let limiter = limiter(1, 1, 1);
+        let _running = limiter.acquire("a", None).await.unwrap();
+        let queued = {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move { limiter.acquire("a", None).await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let busy = limiter.acquire("a", None).await.err().unwrap();
+        assert_eq!(
+            busy,
+            ServerBusy {
+                queue_depth: 1,
+                max_queue: 1
+            }
+        );
+        assert_eq!(limiter.stats().total_rejected, 1);
+        queued.abort();
+    }
+
+    #[tokio::test]
+    async fn test_free_slots_bypass_the_queue() {
+        let limiter = limiter(2, 2, 0);
+        let _first = limiter.acquire("a", None).await.unwrap();
+        let _second = limiter.acquire("a", None).await.unwrap();
+        assert_eq!(limiter.stats().queue_depth, 0);
+        // With no queue, a call that would have to wait is rejected
+        assert!(limiter.acquire("a", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_try_acquire_takes_only_free_slots() {
+        let limiter = limiter(4, 2, 4);
+        let first = limiter.try_acquire("a", Some("acme")).unwrap();
+        let _second = limiter.try_acquire("a", Some("acme")).unwrap();
+        assert!(limiter.try_acquire("a", Some("acme")).is_none());
+        assert_eq!(limiter.stats().running, 2);
+        drop(first);
+        assert!(limiter.try_acquire("a", Some("acme")).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_leaves_queue() {
+        let limiter = limiter(1, 1, 4);
+        let _running = limiter.acquire("a", None).await.unwrap();
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(20), limiter.acquire("a", None)).await;
+        assert!(cancelled.is_err());
+        assert_eq!(limiter.stats().queue_depth, 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Server-side markdown rendering of pcli2 output.
+//!
+//! The `markdown` format asks pcli2 for JSON and renders a GitHub-flavored
+//! table; `markdown_tree` asks for pcli2's `tree` output and renders it as
+//! a nested list. Chat clients display both far better than raw JSON.
+
+use serde_json::Value;
+
+use crate::match_filter::{flatten, normalize};
+
+/// Default width of a table cell before it is cut short
+pub const DEFAULT_MAX_CELL_WIDTH: usize = 60;
+
+/// Markdown rendering requested by a tool call
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkdownRender {
+    /// Table of JSON rows, optionally limited to `columns`
+    Table {
+        columns: Option<Vec<String>>,
+        max_cell_width: usize,
+    },
+    /// Nested list from pcli2's tree output
+    Tree,
+}
+
+impl MarkdownRender {
+    pub fn render(&self, output: &str) -> Result<String, String> {
+        match self {
+            Self::Table {
+                columns,
+                max_cell_width,
+            } => json_to_table(output, columns.as_deref(), *max_cell_width),
+            Self::Tree => Ok(tree_to_list(output)),
+        }
+    }
+}
+
+/// Render JSON output as a table
+///
+/// Arrays of objects, and objects holding such an array, become one row per
+/// element with nested fields as dotted columns. A single object becomes a
+/// field/value table.
+pub fn json_to_table(
+    output: &str,
+    columns: Option<&[String]>,
+    max_cell_width: usize,
+) -> Result<String, String> {
+    let document: Value =
+        serde_json::from_str(output).map_err(|err| format!("Output is not valid JSON: {}", err))?;
+    let rows = match &document {
+        Value::Array(rows) => rows.clone(),
+        Value::Object(map) => match map.values().find_map(Value::as_array) {
+            Some(rows) => rows.clone(),
+            None => return Ok(field_table(&document, columns, max_cell_width)),
+        },
+        scalar => vec![scalar.clone()],
+    };
+    if rows.is_empty() {
+        return Ok("_No results._".to_string());
+    }
+
+    let rows: Vec<Vec<(String, Value)>> = rows
+        .iter()
+        .map(|row| {
+            let mut fields = Vec::new();
+            match row {
+                Value::Object(_) => flatten("", row, &mut fields),
+                scalar => fields.push(("value".to_string(), scalar.clone())),
+            }
+            fields
+        })
+        .collect();
+
+    let mut all_columns: Vec<String> = Vec::new();
+    for row in &rows {
+        for (key, _) in row {
+            if !all_columns.contains(key) {
+                all_columns.push(key.clone());
+            }
+        }
+    }
+    let selected = select_columns(&all_columns, columns);
+
+    let mut table = table_row(selected.iter().map(|c| cell(c, max_cell_width)));
+    table.push_str(&table_row(selected.iter().map(|_| "---".to_string())));
+    for row in &rows {
+        table.push_str(&table_row(selected.iter().map(|column| {
+            let value = row.iter().find(|(key, _)| key == column).map(|(_, v)| v);
+            cell(&value.map(display).unwrap_or_default(), max_cell_width)
+        })));
+    }
+    Ok(table.trim_end().to_string())
+}
+
+fn field_table(document: &Value, columns: Option<&[String]>, max_cell_width: usize) -> String {
+    let mut fields = Vec::new();
+    flatten("", document, &mut fields);
+    let keys: Vec<String> = fields.iter().map(|(key, _)| key.clone()).collect();
+    let selected = select_columns(&keys, columns);
+
+    let mut table = String::from("| Field | Value |\n| --- | --- |\n");
+    for key in &selected {
+        if let Some((_, value)) = fields.iter().find(|(k, _)| k == key) {
+            table.push_str(&table_row(
+                [
+                    cell(key, max_cell_width),
+                    cell(&display(value), max_cell_width),
+                ]
+                .into_iter(),
+            ));
+        }
+    }
+    table.trim_end().to_string()
+}
+
+/// Requested columns that exist, matched exactly or ignoring case and separators
+fn select_columns(available: &[String], requested: Option<&[String]>) -> Vec<String> {
+    let Some(requested) = requested else {
+        return available.to_vec();
+    };
+    requested
+        .iter()
+        .filter_map(|wanted| {
+            available
+                .iter()
+                .find(|c| *c == wanted)
+                .or_else(|| available.iter().find(|c| normalize(c) == normalize(wanted)))
+                .cloned()
+        })
+        .collect()
+}
+
+fn table_row(cells: impl Iterator<Item = String>) -> String {
+    let cells: Vec<String> = cells.collect();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Escape a value for a table cell and cut it to `max_width` characters
+pub(crate) fn cell(value: &str, max_width: usize) -> String {
+    let flat = value.replace(['\r', '\n'], " ");
+    let mut text: String = flat.chars().take(max_width).collect();
+    if flat.chars().count() > max_width {
+        text.pop();
+        text.push('…');
+    }
+    text.replace('|', "\\|")
+}
+
+/// Render tree output such as `├── Child` / `│   └── Grandchild` as a nested list
+pub fn tree_to_list(output: &str) -> String {
+    let mut list = String::new();
+    for line in output.lines() {
+        let name_start = line
+            .char_indices()
+            .find(|(_, c)| !matches!(c, '│' | '├' | '└' | '─' | '|' | '`' | '-' | '+' | ' '))
+            .map(|(index, _)| index);
+        let Some(name_start) = name_start else {
+            continue;
+        };
+        // Each tree level is four columns wide
+        let depth = line[..name_start].chars().count() / 4;
+        list.push_str(&"  ".repeat(depth));
+        list.push_str("- ");
+        list.push_str(line[name_start..].trim_end());
+        list.push('\n');
+    }
+    list.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_become_table() {
+        let output = r#"[
+            {"name": "a.stl", "folder": {"path": "/Root"}, "size": 10},
+            {"name": "b|c.stl", "folder": {"path": "/Root/Sub"}, "extra": null}
+        ]"#;
+        assert_eq!(
+            json_to_table(output, None, 60).unwrap(),
+            "| name | folder.path | size | extra |\n\
+             | --- | --- | --- | --- |\n\
+             | a.stl | /Root | 10 |  |\n\
+             | b\\|c.stl | /Root/Sub |  |  |"
+        );
+    }
+
+    #[test]
+    fn test_columns_and_truncation() {
+        let output = r#"{"assets": [{"name": "a-very-long-asset-name.stl", "uuid": "u1"}]}"#;
+        let columns = vec![
+            "UUID".to_string(),
+            "name".to_string(),
+            "missing".to_string(),
+        ];
+        assert_eq!(
+            json_to_table(output, Some(&columns), 10).unwrap(),
+            "| uuid | name |\n| --- | --- |\n| u1 | a-very-lo… |"
+        );
+    }
+
+    #[test]
+    fn test_single_object_and_empty_results() {
+        assert_eq!(
+            json_to_table(r#"{"name": "acme", "id": 7}"#, None, 60).unwrap(),
+            "| Field | Value |\n| --- | --- |\n| name | acme |\n| id | 7 |"
+        );
+        assert_eq!(json_to_table("[]", None, 60).unwrap(), "_No results._");
+        assert!(json_to_table("name,uuid", None, 60).is_err());
+    }
+
+    #[test]
+    fn test_tree_to_list() {
+        let tree =
+            "Root\n├── Parts\n│   ├── a.stl\n│   └── b.stl\n└── Assemblies\n    └── top.stl\n";
+        assert_eq!(
+            tree_to_list(tree),
+            "- Root\n  - Parts\n    - a.stl\n    - b.stl\n  - Assemblies\n    - top.stl"
+        );
+    }
+}
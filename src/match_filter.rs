@@ -0,0 +1,502 @@
+//! Server-side filtering, sorting and projection of match results.
+//!
+//! Match tools accept post-processing arguments that are applied to
+//! pcli2's JSON output before the response is built, so an agent asking
+//! for the top few candidates does not pay for hundreds of rows.
+//!
+//! Rows are the elements of the top-level JSON array, or of the first
+//! array found in a top-level object. Nested objects are addressed with
+//! dotted keys such as `candidate.path`. The score, candidate path and
+//! candidate ID are found by name, ignoring case, `_` and `.`, so both
+//! `candidateAssetPath` and `candidate_asset_path` work.
+
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Tool arguments handled here
+pub const MATCH_FILTER_ARGS: &[&str] = &[
+    "limit",
+    "sort_by",
+    "min_score",
+    "exclude_path_prefix",
+    "include_path_prefix",
+    "columns",
+    "dedupe_by_candidate",
+];
+
+/// Post-processing requested by a match tool call
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchFilter {
+    pub limit: Option<usize>,
+    /// Field to sort by, descending when `descending` is set
+    pub sort_by: Option<String>,
+    pub descending: bool,
+    pub min_score: Option<f64>,
+    pub exclude_path_prefix: Vec<String>,
+    pub include_path_prefix: Vec<String>,
+    pub columns: Option<Vec<String>>,
+    pub dedupe_by_candidate: bool,
+}
+
+impl MatchFilter {
+    /// Filter described by tool arguments, or `None` when the call has none
+    pub fn from_args(args: &Value) -> Result<Option<Self>, String> {
+        if !MATCH_FILTER_ARGS.iter().any(|key| args.get(*key).is_some()) {
+            return Ok(None);
+        }
+        let mut filter = Self::default();
+        if let Some(limit) = args.get("limit") {
+            filter.limit = Some(
+                limit
+                    .as_u64()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| "'limit' must be a positive integer".to_string())?
+                    as usize,
+            );
+        }
+        if let Some(sort_by) = args.get("sort_by") {
+            let sort_by = sort_by
+                .as_str()
+                .filter(|s| !s.trim_start_matches('-').is_empty())
+                .ok_or_else(|| "'sort_by' must be a field name".to_string())?;
+            filter.descending = sort_by.starts_with('-');
+            filter.sort_by = Some(sort_by.trim_start_matches('-').to_string());
+        }
+        if let Some(min_score) = args.get("min_score") {
+            filter.min_score = Some(
+                min_score
+                    .as_f64()
+                    .ok_or_else(|| "'min_score' must be a number".to_string())?,
+            );
+        }
+        filter.exclude_path_prefix = string_list(args, "exclude_path_prefix")?;
+        filter.include_path_prefix = string_list(args, "include_path_prefix")?;
+        if args.get("columns").is_some() {
+            let columns = string_list(args, "columns")?;
+            if columns.is_empty() {
+                return Err("'columns' must name at least one field".to_string());
+            }
+            filter.columns = Some(columns);
+        }
+        if let Some(dedupe) = args.get("dedupe_by_candidate") {
+            filter.dedupe_by_candidate = dedupe
+                .as_bool()
+                .ok_or_else(|| "'dedupe_by_candidate' must be a boolean".to_string())?;
+        }
+        Ok(Some(filter))
+    }
+
+    /// Apply the filter to pcli2's JSON output, keeping its overall shape
+    pub fn apply(&self, output: &str, pretty: bool) -> Result<String, String> {
+        let mut document: Value = serde_json::from_str(output)
+            .map_err(|err| format!("Match output is not valid JSON: {}", err))?;
+        let rows = rows_mut(&mut document)
+            .ok_or_else(|| "Match output has no list of results".to_string())?;
+        let filtered = self.filter_rows(std::mem::take(rows));
+        *rows = filtered;
+        let rendered = if pretty {
+            serde_json::to_string_pretty(&document)
+        } else {
+            serde_json::to_string(&document)
+        };
+        rendered.map_err(|err| format!("Failed to render match output: {}", err))
+    }
+
+    fn filter_rows(&self, rows: Vec<Value>) -> Vec<Value> {
+        let mut rows: Vec<Row> = rows.into_iter().map(Row::new).collect();
+
+        rows.retain(|row| {
+            let path = row.candidate_path();
+            let included = self.include_path_prefix.is_empty()
+                || path.is_some_and(|path| {
+                    self.include_path_prefix
+                        .iter()
+                        .any(|prefix| path.starts_with(prefix.as_str()))
+                });
+            let excluded = path.is_some_and(|path| {
+                self.exclude_path_prefix
+                    .iter()
+                    .any(|prefix| path.starts_with(prefix.as_str()))
+            });
+            included && !excluded
+        });
+
+        if let Some(min_score) = self.min_score {
+            rows.retain(|row| row.score().is_some_and(|score| score >= min_score));
+        }
+
+        if self.dedupe_by_candidate {
+            // Keep the best-scoring row for each candidate, in first-seen order
+            let mut best: HashMap<String, usize> = HashMap::new();
+            let mut kept: Vec<Row> = Vec::new();
+            for row in rows {
+                let Some(candidate) = row.candidate_id() else {
+                    kept.push(row);
+                    continue;
+                };
+                match best.get(&candidate) {
+                    Some(&index) => {
+                        if row.score() > kept[index].score() {
+                            kept[index] = row;
+                        }
+                    }
+                    None => {
+                        best.insert(candidate, kept.len());
+                        kept.push(row);
+                    }
+                }
+            }
+            rows = kept;
+        }
+
+        match &self.sort_by {
+            Some(field) => {
+                rows.sort_by(|a, b| compare_rows(a.get(field), b.get(field), self.descending));
+            }
+            // "Top N" means the best matches first
+            None if self.limit.is_some() => {
+                rows.sort_by(|a, b| compare_rows(a.score_value(), b.score_value(), true));
+            }
+            None => {}
+        }
+
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+
+        rows.into_iter()
+            .map(|row| match &self.columns {
+                Some(columns) => row.project(columns),
+                None => row.value,
+            })
+            .collect()
+    }
+}
+
+/// Score, path and UUID of one row of a match result
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchCandidate {
+    pub score: Option<f64>,
+    pub path: Option<String>,
+    pub uuid: Option<String>,
+}
+
+/// The candidates of a match document, in row order
+pub fn match_candidates(output: &str) -> Result<Vec<MatchCandidate>, String> {
+    let mut document: Value = serde_json::from_str(output)
+        .map_err(|err| format!("Match output is not valid JSON: {}", err))?;
+    let rows =
+        rows_mut(&mut document).ok_or_else(|| "Match output has no list of results".to_string())?;
+    Ok(std::mem::take(rows)
+        .into_iter()
+        .map(|value| {
+            let row = Row::new(value);
+            MatchCandidate {
+                score: row.score(),
+                path: row.candidate_path().map(str::to_string),
+                uuid: row.candidate_uuid().map(str::to_string),
+            }
+        })
+        .collect())
+}
+
+fn string_list(args: &Value, key: &str) -> Result<Vec<String>, String> {
+    match args.get(key) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(value)) => Ok(value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("'{}' must contain only strings", key))
+            })
+            .collect(),
+        Some(_) => Err(format!("'{}' must be a string or an array of strings", key)),
+    }
+}
+
+/// The array of result rows inside a match document
+fn rows_mut(document: &mut Value) -> Option<&mut Vec<Value>> {
+    match document {
+        Value::Array(_) => document.as_array_mut(),
+        Value::Object(map) => map.values_mut().find_map(|value| value.as_array_mut()),
+        _ => None,
+    }
+}
+
+pub(crate) fn normalize(key: &str) -> String {
+    key.chars()
+        .filter(|c| !matches!(c, '_' | '.' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Dotted paths to every scalar in `value`
+pub(crate) fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&path, value, out);
+            }
+        }
+        _ => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// A result row with its fields flattened for lookups
+struct Row {
+    value: Value,
+    fields: Vec<(String, Value)>,
+}
+
+const SCORE_KEYS: &[&str] = &[
+    "score",
+    "similarity",
+    "matchpercentage",
+    "similaritypercentage",
+    "percentage",
+];
+
+impl Row {
+    fn new(value: Value) -> Self {
+        let mut fields = Vec::new();
+        flatten("", &value, &mut fields);
+        Self { value, fields }
+    }
+
+    fn find(&self, predicate: impl Fn(&str) -> bool) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(key, _)| predicate(&normalize(key)))
+            .map(|(_, value)| value)
+    }
+
+    /// A field by exact dotted key, normalized key, or the `score` and `path` aliases
+    fn get(&self, field: &str) -> Option<&Value> {
+        if let Some((_, value)) = self.fields.iter().find(|(key, _)| key == field) {
+            return Some(value);
+        }
+        let wanted = normalize(field);
+        self.find(|key| key == wanted)
+            .or_else(|| match wanted.as_str() {
+                "score" => self.score_value(),
+                "path" => self.candidate_path_value(),
+                _ => None,
+            })
+    }
+
+    fn score_value(&self) -> Option<&Value> {
+        SCORE_KEYS
+            .iter()
+            .find_map(|wanted| self.find(|key| key == *wanted || key.ends_with(wanted)))
+            .filter(|value| as_number(value).is_some())
+    }
+
+    fn score(&self) -> Option<f64> {
+        self.score_value().and_then(as_number)
+    }
+
+    fn candidate_path_value(&self) -> Option<&Value> {
+        self.find(|key| key.contains("candidate") && key.ends_with("path"))
+            .or_else(|| self.find(|key| key.contains("match") && key.ends_with("path")))
+            .or_else(|| self.find(|key| key == "path"))
+    }
+
+    fn candidate_path(&self) -> Option<&str> {
+        self.candidate_path_value().and_then(Value::as_str)
+    }
+
+    fn candidate_uuid(&self) -> Option<&str> {
+        self.find(|key| key.contains("candidate") && (key.ends_with("uuid") || key.ends_with("id")))
+            .and_then(Value::as_str)
+    }
+
+    fn candidate_id(&self) -> Option<String> {
+        self.candidate_uuid()
+            .or_else(|| self.candidate_path())
+            .map(str::to_string)
+    }
+
+    /// Only the requested columns, keyed as requested
+    fn project(&self, columns: &[String]) -> Value {
+        let mut map = Map::new();
+        for column in columns {
+            if let Some(value) = self.get(column) {
+                map.insert(column.clone(), value.clone());
+            }
+        }
+        Value::Object(map)
+    }
+}
+
+/// Numbers, including numeric strings such as `"92.5"` or `"92.5%"`
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().trim_end_matches('%').parse().ok(),
+        _ => None,
+    }
+}
+
+/// Missing values sort last either way; numbers compare numerically, the rest as text
+fn compare_rows(a: Option<&Value>, b: Option<&Value>, descending: bool) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let ordering = match (as_number(a), as_number(b)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                _ => text(a).cmp(&text(b)),
+            };
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches() -> Value {
+        json!([
+            { "referenceAssetPath": "/Root/Src/a.stl", "candidateAssetPath": "/Root/Src/b.stl", "candidateAssetUuid": "b", "matchPercentage": 99.0 },
+            { "referenceAssetPath": "/Root/Src/a.stl", "candidateAssetPath": "/Root/Lib/c.stl", "candidateAssetUuid": "c", "matchPercentage": 91.5 },
+            { "referenceAssetPath": "/Root/Src/d.stl", "candidateAssetPath": "/Root/Lib/c.stl", "candidateAssetUuid": "c", "matchPercentage": 95.0 },
+            { "referenceAssetPath": "/Root/Src/a.stl", "candidateAssetPath": "/Root/Lib/e.stl", "candidateAssetUuid": "e", "matchPercentage": 82.0 }
+        ])
+    }
+
+    fn apply(args: Value, input: &Value) -> Value {
+        let filter = MatchFilter::from_args(&args).unwrap().expect("filter");
+        serde_json::from_str(&filter.apply(&input.to_string(), false).unwrap()).unwrap()
+    }
+
+    fn candidates(output: &Value) -> Vec<&str> {
+        output
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["candidateAssetUuid"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_no_filter_args() {
+        assert_eq!(
+            MatchFilter::from_args(&json!({ "threshold": 80 })),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_limit_returns_best_matches() {
+        let output = apply(json!({ "limit": 2 }), &matches());
+        assert_eq!(candidates(&output), ["b", "c"]);
+        assert_eq!(output[1]["matchPercentage"], 95.0);
+    }
+
+    #[test]
+    fn test_path_prefixes_and_min_score() {
+        let output = apply(
+            json!({ "exclude_path_prefix": "/Root/Src", "min_score": 90 }),
+            &matches(),
+        );
+        assert_eq!(candidates(&output), ["c", "c"]);
+
+        let output = apply(
+            json!({ "include_path_prefix": ["/Root/Lib/e", "/Root/Src"] }),
+            &matches(),
+        );
+        assert_eq!(candidates(&output), ["b", "e"]);
+    }
+
+    #[test]
+    fn test_dedupe_keeps_best_row_per_candidate() {
+        let output = apply(json!({ "dedupe_by_candidate": true }), &matches());
+        assert_eq!(candidates(&output), ["b", "c", "e"]);
+        assert_eq!(output[1]["referenceAssetPath"], "/Root/Src/d.stl");
+    }
+
+    #[test]
+    fn test_sort_and_columns() {
+        let output = apply(
+            json!({ "sort_by": "candidateAssetPath", "columns": ["candidateAssetUuid", "score"] }),
+            &matches(),
+        );
+        assert_eq!(candidates(&output), ["c", "c", "e", "b"]);
+        assert_eq!(
+            output[0],
+            json!({ "candidateAssetUuid": "c", "score": 91.5 })
+        );
+
+        let output = apply(json!({ "sort_by": "-score" }), &matches());
+        assert_eq!(candidates(&output), ["b", "c", "c", "e"]);
+    }
+
+    #[test]
+    fn test_nested_rows_inside_object() {
+        let input = json!({
+            "reference": "/Root/a.stl",
+            "matches": [
+                { "candidate": { "path": "/Root/x.stl", "uuid": "x" }, "similarity": "88%" },
+                { "candidate": { "path": "/Root/y.stl", "uuid": "y" }, "similarity": "97%" }
+            ]
+        });
+        let output = apply(json!({ "limit": 1, "columns": "candidate.path" }), &input);
+        assert_eq!(
+            output,
+            json!({ "reference": "/Root/a.stl", "matches": [{ "candidate.path": "/Root/y.stl" }] })
+        );
+    }
+
+    #[test]
+    fn test_match_candidates() {
+        let candidates = match_candidates(&matches().to_string()).unwrap();
+        assert_eq!(candidates.len(), 4);
+        assert_eq!(
+            candidates[1],
+            MatchCandidate {
+                score: Some(91.5),
+                path: Some("/Root/Lib/c.stl".to_string()),
+                uuid: Some("c".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_args_and_output() {
+        assert!(MatchFilter::from_args(&json!({ "limit": 0 })).is_err());
+        assert!(MatchFilter::from_args(&json!({ "min_score": "high" })).is_err());
+        assert!(MatchFilter::from_args(&json!({ "columns": [] })).is_err());
+        let filter = MatchFilter::from_args(&json!({ "limit": 1 }))
+            .unwrap()
+            .unwrap();
+        assert!(filter.apply("a,b,c", false).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+//! Match results with thumbnails, built for `pcli2_visual_match_report`.
+//!
+//! The report runs one match command, then downloads the thumbnails of the
+//! reference asset and the top candidates concurrently and stores them in
+//! the thumbnail cache, so an agent gets scores, paths and image URLs from a
+//! single tool call instead of one call per thumbnail.
+
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::client::{AssetRef, AssetThumbnail, Pcli2Client};
+use crate::markdown::cell;
+use crate::match_filter::MatchCandidate;
+use crate::thumbnail::ThumbnailCache;
+
+/// Candidates in a report unless `top_k` is given
+pub const DEFAULT_TOP_K: usize = 5;
+/// Most candidates a report can include
+pub const MAX_TOP_K: usize = 25;
+/// Most thumbnails downloaded at once for one report, limiter slots permitting
+pub const THUMBNAIL_CONCURRENCY: usize = 4;
+
+/// An asset in a report with the URL of its cached thumbnail
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReportAsset {
+    pub uuid: Option<String>,
+    pub path: Option<String>,
+    /// `/thumbnail/:cache_key` URL, when the thumbnail was fetched
+    pub thumbnail_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_error: Option<String>,
+}
+
+impl ReportAsset {
+    fn asset_ref(&self) -> Option<AssetRef> {
+        match (&self.uuid, &self.path) {
+            (Some(uuid), _) => Some(AssetRef::uuid(uuid)),
+            (None, Some(path)) => Some(AssetRef::path(path)),
+            (None, None) => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.path
+            .as_deref()
+            .or(self.uuid.as_deref())
+            .unwrap_or("unknown")
+    }
+}
+
+/// One ranked candidate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportMatch {
+    pub rank: usize,
+    pub score: Option<f64>,
+    #[serde(flatten)]
+    pub asset: ReportAsset,
+}
+
+/// Reference asset and its best candidates, with thumbnails
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchReport {
+    pub match_type: String,
+    pub reference: ReportAsset,
+    pub matches: Vec<ReportMatch>,
+}
+
+impl MatchReport {
+    /// Report for `candidates`, in the order given
+    pub fn new(match_type: &str, reference: &AssetRef, candidates: Vec<MatchCandidate>) -> Self {
+        let reference = match reference {
+            AssetRef::Uuid(uuid) => ReportAsset {
+                uuid: Some(uuid.clone()),
+                ..ReportAsset::default()
+            },
+            AssetRef::Path(path) => ReportAsset {
+                path: Some(path.clone()),
+                ..ReportAsset::default()
+            },
+        };
+        let matches = candidates
+            .into_iter()
+            .enumerate()
+            .map(|(index, candidate)| ReportMatch {
+                rank: index + 1,
+                score: candidate.score,
+                asset: ReportAsset {
+                    uuid: candidate.uuid,
+                    path: candidate.path,
+                    ..ReportAsset::default()
+                },
+            })
+            .collect();
+        Self {
+            match_type: match_type.to_string(),
+            reference,
+            matches,
+        }
+    }
+
+    /// Download every thumbnail not already cached and store it in `cache`
+    ///
+    /// At most `concurrency` downloads run at once. Assets with a UUID are
+    /// cached under their stable key and reused by later calls. A thumbnail
+    /// that cannot be fetched is reported on its row rather than failing the
+    /// whole report.
+    pub async fn fetch_thumbnails(
+        &mut self,
+        client: &Pcli2Client,
+        tenant: Option<&str>,
+        cache: Option<&Arc<ThumbnailCache>>,
+        concurrency: usize,
+    ) {
+        let Some(cache) = cache else {
+            for asset in self.assets_mut() {
+                asset.thumbnail_error = Some("Thumbnail cache is not available".to_string());
+            }
+            return;
+        };
+
+        // Store calls may block, so they run on the blocking pool in batches
+        let keys: Vec<Option<String>> = self
+            .assets_mut()
+            .iter()
+            .map(|asset| {
+                let uuid = asset.uuid.as_deref()?;
+                Some(cache.asset_key(tenant, uuid))
+            })
+            .collect();
+        let mut cached = cache
+            .blocking(move |cache| {
+                Ok(keys
+                    .iter()
+                    .map(|key| key.as_ref().and_then(|key| cache.lookup(key)))
+                    .collect::<Vec<_>>())
+            })
+            .await
+            .unwrap_or_default()
+            .into_iter();
+
+        let mut requests = Vec::new();
+        let mut fetched = Vec::new();
+        for (index, asset) in self.assets_mut().into_iter().enumerate() {
+            let cached = cached.next().flatten();
+            let Some(asset_ref) = asset.asset_ref() else {
+                asset.thumbnail_error = Some("Match has no asset UUID or path".to_string());
+                continue;
+            };
+            if let Some(url) = cached {
+                asset.thumbnail_url = Some(url);
+                continue;
+            }
+            let mut request = AssetThumbnail::new(asset_ref);
+            request.tenant = tenant.map(str::to_string);
+            requests.push(request);
+            fetched.push(index);
+        }
+        let results = client.thumbnails(requests, concurrency).await;
+
+        let mut assets = self.assets_mut();
+        let downloads: Vec<_> = fetched
+            .into_iter()
+            .zip(results)
+            .map(|(index, result)| {
+                let asset = &assets[index];
+                let download = result.map_err(|err| err.to_string());
+                (index, asset.uuid.clone(), asset.path.clone(), download)
+            })
+            .collect();
+        let tenant = tenant.map(str::to_string);
+        let saved = cache
+            .blocking(move |cache| {
+                Ok(downloads
+                    .into_iter()
+                    .map(|(index, uuid, path, download)| {
+                        let saved = download.and_then(|bytes| match (uuid, path) {
+                            (Some(uuid), _) => {
+                                let key = cache.asset_key(tenant.as_deref(), &uuid);
+                                cache.save_thumbnail_as(&key, &uuid, &bytes)
+                            }
+                            (None, path) => cache
+                                .save_thumbnail(path.as_deref().unwrap_or("unknown"), &bytes)
+                                .map(|(_cache_key, url)| url),
+                        });
+                        (index, saved)
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await;
+        let saved = match saved {
+            Ok(saved) => saved,
+            Err(err) => {
+                for asset in assets {
+                    if asset.thumbnail_url.is_none() && asset.thumbnail_error.is_none() {
+                        asset.thumbnail_error = Some(err.clone());
+                    }
+                }
+                return;
+            }
+        };
+        for (index, saved) in saved {
+            let asset = &mut assets[index];
+            match saved {
+                Ok(url) => asset.thumbnail_url = Some(url),
+                Err(err) => asset.thumbnail_error = Some(err),
+            }
+        }
+    }
+
+    /// The reference followed by each candidate
+    fn assets_mut(&mut self) -> Vec<&mut ReportAsset> {
+        std::iter::once(&mut self.reference)
+            .chain(self.matches.iter_mut().map(|row| &mut row.asset))
+            .collect()
+    }
+
+    /// Heading, reference thumbnail and a table of ranked candidates
+    pub fn to_markdown(&self) -> String {
+        let mut text = format!(
+            "## {} matches for {}\n\n",
+            capitalize(&self.match_type),
+            cell(self.reference.name(), usize::MAX)
+        );
+        if let Some(url) = &self.reference.thumbnail_url {
+            text.push_str(&format!("![reference]({})\n\n", url));
+        }
+        if self.matches.is_empty() {
+            text.push_str("_No matches found._");
+            return text;
+        }
+        text.push_str("| # | Score | Path | Thumbnail |\n| --- | --- | --- | --- |\n");
+        for row in &self.matches {
+            let score = row.score.map(|score| score.to_string()).unwrap_or_default();
+            let thumbnail = match &row.asset.thumbnail_url {
+                Some(url) => format!("![{}]({})", row.rank, url),
+                None => "_unavailable_".to_string(),
+            };
+            text.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                row.rank,
+                score,
+                cell(row.asset.name(), usize::MAX),
+                thumbnail
+            ));
+        }
+        text.trim_end().to_string()
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(score: f64, path: &str) -> MatchCandidate {
+        MatchCandidate {
+            score: Some(score),
+            path: Some(path.to_string()),
+            uuid: None,
+        }
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let mut report = MatchReport::new(
+            "geometric",
+            &AssetRef::path("/Root/a.stl"),
+            vec![
+                candidate(97.5, "/Root/b.stl"),
+                candidate(90.0, "/Root/c|d.stl"),
+            ],
+        );
+        report.reference.thumbnail_url = Some("http://localhost:8080/thumbnail/r".to_string());
+        report.matches[0].asset.thumbnail_url =
+            Some("http://localhost:8080/thumbnail/b".to_string());
+        assert_eq!(
+            report.to_markdown(),
+            "## Geometric matches for /Root/a.stl\n\n\
+             ![reference](http://localhost:8080/thumbnail/r)\n\n\
+             | # | Score | Path | Thumbnail |\n\
+             | --- | --- | --- | --- |\n\
+             | 1 | 97.5 | /Root/b.stl | ![1](http://localhost:8080/thumbnail/b) |\n\
+             | 2 | 90 | /Root/c\\|d.stl | _unavailable_ |"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_cache_is_reported_per_asset() {
+        let mut report = MatchReport::new(
+            "part",
+            &AssetRef::uuid("ref"),
+            vec![candidate(80.0, "/Root/b.stl")],
+        );
+        report
+            .fetch_thumbnails(&Pcli2Client::default(), None, None, THUMBNAIL_CONCURRENCY)
+            .await;
+        assert!(report.reference.thumbnail_error.is_some());
+        assert!(report.matches[0].asset.thumbnail_url.is_none());
+        assert_eq!(
+            serde_json::to_value(&report.matches[0]).unwrap()["path"],
+            "/Root/b.stl"
+        );
+    }
+}
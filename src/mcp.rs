@@ -1,13 +1,19 @@
 use crate::{
     AppState,
-    cli::{ARG_CLIENT, ARG_HOST, ARG_PORT, CLIENT_CLAUDE, CLIENT_QWEN_AGENT, CLIENT_QWEN_CODE},
+    cli::{
+        ARG_CLIENT, ARG_HOST, ARG_PORT, CLIENT_CLAUDE, CLIENT_QWEN_AGENT, CLIENT_QWEN_CODE,
+        CMD_INSTALL, CMD_UNINSTALL,
+    },
+    client_config::{run_config_install, run_config_uninstall},
     pcli::*,
+    public_url::rewrite_links,
+    thumbnail::THUMBNAIL_ROUTE,
 };
 use anyhow::{Result, anyhow};
 use axum::{
     body::Bytes,
     extract::State,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Json},
 };
 use clap::ArgMatches;
@@ -45,9 +51,17 @@ pub struct RpcErrorResponse {
 pub struct RpcErrorBody {
     code: i64,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
 }
 
 pub fn run_config(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some((CMD_INSTALL, sub_matches)) => return run_config_install(sub_matches),
+        Some((CMD_UNINSTALL, sub_matches)) => return run_config_uninstall(sub_matches),
+        _ => {}
+    }
+
     let client = matches
         .get_one::<String>(ARG_CLIENT)
         .map(String::as_str)
@@ -64,7 +78,7 @@ pub fn run_config(matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
-fn build_client_config(client: &str, host: &str, port: u16) -> Result<Value> {
+pub(crate) fn build_client_config(client: &str, host: &str, port: u16) -> Result<Value> {
     let server_entry = json!({
         MCP_SERVER_ALIAS: {
             "command": MCP_REMOTE_COMMAND,
@@ -112,7 +126,11 @@ fn parse_rpc_request(value: Value) -> Result<RpcRequest, String> {
     })
 }
 
-pub async fn handle_mcp(State(state): State<AppState>, bytes: Bytes) -> impl IntoResponse {
+pub async fn handle_mcp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> impl IntoResponse {
     let value: Value = match serde_json::from_slice(&bytes) {
         Ok(value) => value,
         Err(_) => {
@@ -174,9 +192,21 @@ pub async fn handle_mcp(State(state): State<AppState>, bytes: Bytes) -> impl Int
                 .and_then(|value| value.as_str())
                 .unwrap_or("unknown");
             info!("🔧 tools/call name={}", tool_name);
-            match call_tool(params, state.thumbnail_cache.as_ref().as_ref()).await {
-                Ok(result) => json_ok(id, result).into_response(),
-                Err(message) => json_error(id, -32602, message).into_response(),
+            match call_tool(params, &state).await {
+                Ok(mut result) => {
+                    // Thumbnail links are created with the configured base URL
+                    if let (Some(origin), Some(cache)) = (
+                        state.public_url.origin(&headers),
+                        state.thumbnail_cache.as_ref(),
+                    ) {
+                        let public_base = format!("{}{}", origin, THUMBNAIL_ROUTE);
+                        rewrite_links(&mut result, cache.base_url(), &public_base);
+                    }
+                    json_ok(id, result).into_response()
+                }
+                Err(err) => {
+                    json_error_with_data(id, err.code, err.message, err.data).into_response()
+                }
             }
         }
         _ => json_error(id, -32601, format!("Method '{}' not found", method)).into_response(),
@@ -191,10 +221,23 @@ pub fn json_ok(id: Value, result: Value) -> Json<RpcResponse> {
 }
 
 pub fn json_error(id: Value, code: i64, message: String) -> Json<RpcErrorResponse> {
+    json_error_with_data(id, code, message, None)
+}
+
+pub fn json_error_with_data(
+    id: Value,
+    code: i64,
+    message: String,
+    data: Option<Value>,
+) -> Json<RpcErrorResponse> {
     Json(RpcErrorResponse {
         jsonrpc: "2.0",
         id,
-        error: RpcErrorBody { code, message },
+        error: RpcErrorBody {
+            code,
+            message,
+            data,
+        },
     })
 }
 
@@ -318,5 +361,22 @@ mod tests {
         assert_eq!(response.id, json!(1));
         assert_eq!(response.error.code, -32601);
         assert_eq!(response.error.message, "Method not found");
+        assert!(response.error.data.is_none());
+    }
+
+    #[test]
+    fn test_json_error_with_data() {
+        let response = json_error_with_data(
+            json!(7),
+            -32001,
+            "timed out".to_string(),
+            Some(json!({"reason": "timeout"})),
+        );
+        let value = serde_json::to_value(&response.0).unwrap();
+        assert_eq!(value["error"]["code"], -32001);
+        assert_eq!(value["error"]["data"]["reason"], "timeout");
+
+        let plain = serde_json::to_value(&json_error(json!(8), -1, "x".to_string()).0).unwrap();
+        assert!(plain["error"].get("data").is_none());
     }
 }
@@ -0,0 +1,364 @@
+//! Paging of tool output that exceeds the per-response budget.
+//!
+//! An oversized text result is kept server-side and replaced by its first
+//! page, a summary header and a cursor. Later pages are fetched with the
+//! `pcli2_result_page` tool until the cursor runs out. Results that embed
+//! a base64 `data:` URI are returned whole, since a cut image cannot render.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::hash::sha256_hex;
+
+/// Default budget for the text of one tool response
+pub const DEFAULT_RESPONSE_BUDGET_BYTES: usize = 64 * 1024;
+/// Rough size of a token, used to turn a token budget into bytes
+pub const BYTES_PER_TOKEN: usize = 4;
+/// How long the remaining pages of a result can be fetched
+pub const PAGED_RESULT_TTL: Duration = Duration::from_secs(30 * 60);
+/// Paged results kept at once; the oldest is dropped first
+pub const MAX_PAGED_RESULTS: usize = 32;
+/// Total text kept for paged results; the oldest are dropped first
+pub const MAX_PAGED_BYTES: usize = 16 * 1024 * 1024;
+
+struct PagedResult {
+    text: String,
+    pages: Vec<Range<usize>>,
+    created: Instant,
+}
+
+/// Counters returned by the status tool
+#[derive(Debug, Clone, Serialize)]
+pub struct PageStoreStats {
+    pub budget_bytes: usize,
+    /// Results with pages still available
+    pub stored: usize,
+    /// Total text held for those results
+    pub stored_bytes: usize,
+}
+
+/// Results split into pages, keyed by result id
+pub struct PageStore {
+    budget: usize,
+    results: Mutex<HashMap<String, PagedResult>>,
+}
+
+impl Default for PageStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESPONSE_BUDGET_BYTES)
+    }
+}
+
+impl PageStore {
+    /// Store with a budget of `budget_bytes` of text per response
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget: budget_bytes.max(1),
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn stats(&self) -> PageStoreStats {
+        let mut results = self.lock();
+        remove_expired(&mut results);
+        PageStoreStats {
+            budget_bytes: self.budget,
+            stored: results.len(),
+            stored_bytes: results.values().map(|result| result.text.len()).sum(),
+        }
+    }
+
+    /// Replace a single-text tool result larger than the budget with its first page
+    pub fn paginate(&self, result: Value) -> Value {
+        let Some(text) = result["content"][0]["text"].as_str() else {
+            return result;
+        };
+        if text.len() <= self.budget
+            || result["content"].as_array().map(Vec::len) != Some(1)
+            || embeds_data_uri(text)
+        {
+            return result;
+        }
+        let text = text.to_string();
+        let pages = split_pages(&text, self.budget);
+        let id = result_id(&text);
+        debug!(
+            "Paging {} byte result into {} page(s) as {}",
+            text.len(),
+            pages.len(),
+            id
+        );
+
+        let stored = PagedResult {
+            text,
+            pages,
+            created: Instant::now(),
+        };
+        let first = render_page(&id, &stored, 0, result);
+        let mut results = self.lock();
+        remove_expired(&mut results);
+        let mut stored_bytes: usize = results.values().map(|result| result.text.len()).sum();
+        while results.len() >= MAX_PAGED_RESULTS
+            || stored_bytes + stored.text.len() > MAX_PAGED_BYTES
+        {
+            let Some(oldest) = results
+                .iter()
+                .min_by_key(|(_, result)| result.created)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            if let Some(removed) = results.remove(&oldest) {
+                stored_bytes -= removed.text.len();
+            }
+        }
+        results.insert(id, stored);
+        first
+    }
+
+    /// The page a cursor points to, with the cursor for the page after it
+    pub fn page(&self, cursor: &str) -> Result<Value, String> {
+        let unknown = || format!("Unknown or expired cursor '{}'", cursor);
+        let (id, index) = cursor.rsplit_once('.').ok_or_else(unknown)?;
+        let index: usize = index.parse().map_err(|_| unknown())?;
+        let mut results = self.lock();
+        remove_expired(&mut results);
+        let stored = results.get(id).ok_or_else(unknown)?;
+        if index >= stored.pages.len() {
+            return Err(unknown());
+        }
+        Ok(render_page(id, stored, index, json!({})))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, PagedResult>> {
+        self.results
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn remove_expired(results: &mut HashMap<String, PagedResult>) {
+    results.retain(|_, result| result.created.elapsed() < PAGED_RESULT_TTL);
+}
+
+/// Whether `text` carries an inline base64 image that paging would cut apart
+fn embeds_data_uri(text: &str) -> bool {
+    text.match_indices("data:").any(|(start, _)| {
+        text[start..]
+            .split(['"', '\'', ' ', ')'])
+            .next()
+            .is_some_and(|uri| uri.contains(";base64,"))
+    })
+}
+
+fn result_id(text: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut seed = nanos.to_string().into_bytes();
+    seed.extend_from_slice(text.as_bytes());
+    sha256_hex(&seed)[..16].to_string()
+}
+
+/// Byte ranges of at most `budget` bytes, ending at a line break where possible
+fn split_pages(text: &str, budget: usize) -> Vec<Range<usize>> {
+    let mut pages = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = (start + budget).min(text.len());
+        if end < text.len() {
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            if let Some(newline) = text[start..end].rfind('\n') {
+                end = start + newline + 1;
+            } else if end == start {
+                // A budget smaller than one character still has to make progress
+                end = start + text[start..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        pages.push(start..end);
+        start = end;
+    }
+    pages
+}
+
+/// Tool result holding page `index`, keeping any other fields of `base`
+fn render_page(id: &str, stored: &PagedResult, index: usize, mut base: Value) -> Value {
+    let range = stored.pages[index].clone();
+    let total_pages = stored.pages.len();
+    let total_lines = stored.text.lines().count();
+    let first_line = stored.text[..range.start].matches('\n').count() + 1;
+    let last_line = first_line
+        + stored.text[range.clone()]
+            .trim_end_matches('\n')
+            .matches('\n')
+            .count();
+    let next_cursor = (index + 1 < total_pages).then(|| format!("{}.{}", id, index + 1));
+
+    let next = match &next_cursor {
+        Some(cursor) => format!(
+            "Call pcli2_result_page with cursor \"{}\" for the next page.",
+            cursor
+        ),
+        None => "This is the last page.".to_string(),
+    };
+    let header = format!(
+        "[Page {} of {}: bytes {}-{} of {}, lines {}-{} of {}. {}]",
+        index + 1,
+        total_pages,
+        range.start + 1,
+        range.end,
+        stored.text.len(),
+        first_line,
+        last_line,
+        total_lines,
+        next
+    );
+
+    base["content"] = json!([{
+        "type": "text",
+        "text": format!("{}\n{}", header, &stored.text[range]),
+    }]);
+    base["_meta"] = json!({
+        "pagination": {
+            "page": index + 1,
+            "total_pages": total_pages,
+            "total_bytes": stored.text.len(),
+            "total_lines": total_lines,
+            "next_cursor": next_cursor,
+        }
+    });
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_result(text: &str) -> Value {
+        json!({ "content": [{ "type": "text", "text": text }] })
+    }
+
+    fn page_body(page: &Value) -> String {
+        let text = page["content"][0]["text"].as_str().unwrap();
+        text.split_once('\n').unwrap().1.to_string()
+    }
+
+    #[test]
+    fn test_split_pages_prefers_line_breaks() {
+        let text = "aaaa\nbbbb\ncccccccccc\n";
+        let pages: Vec<&str> = split_pages(text, 8)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect();
+        assert_eq!(pages, ["aaaa\n", "bbbb\n", "cccccccc", "cc\n"]);
+    }
+
+    #[test]
+    fn test_split_pages_respects_char_boundaries() {
+        let text = "ééé";
+        let pages = split_pages(text, 3);
+        assert!(pages.iter().all(|range| text.is_char_boundary(range.end)));
+        assert_eq!(pages.len(), 3);
+    }
+
+    #[test]
+    fn test_small_results_are_unchanged() {
+        let store = PageStore::new(100);
+        let result = text_result("short");
+        assert_eq!(store.paginate(result.clone()), result);
+        assert_eq!(store.stats().stored, 0);
+    }
+
+    #[test]
+    fn test_cursor_walks_all_pages() {
+        let store = PageStore::new(10);
+        let text = (1..=9).map(|n| format!("line {}\n", n)).collect::<String>();
+        let mut page = store.paginate(text_result(&text));
+        assert_eq!(page["_meta"]["pagination"]["total_pages"], 9);
+        assert_eq!(page["_meta"]["pagination"]["total_lines"], 9);
+        assert!(
+            page["content"][0]["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("[Page 1 of 9: bytes 1-7 of 63, lines 1-1 of 9.")
+        );
+
+        let mut collected = page_body(&page);
+        while let Some(cursor) = page["_meta"]["pagination"]["next_cursor"].as_str() {
+            page = store.page(cursor).unwrap();
+            collected.push_str(&page_body(&page));
+        }
+        assert_eq!(collected, text);
+        assert!(
+            page["content"][0]["text"]
+                .as_str()
+                .unwrap()
+                .contains("This is the last page.")
+        );
+    }
+
+    #[test]
+    fn test_unknown_cursor_is_rejected() {
+        let store = PageStore::new(10);
+        assert!(store.page("missing.1").is_err());
+        assert!(store.page("garbage").is_err());
+
+        let page = store.paginate(text_result("0123456789abcdef"));
+        let cursor = page["_meta"]["pagination"]["next_cursor"].as_str().unwrap();
+        let (id, _) = cursor.rsplit_once('.').unwrap();
+        assert!(store.page(&format!("{}.7", id)).is_err());
+    }
+
+    #[test]
+    fn test_oldest_results_are_evicted() {
+        let store = PageStore::new(1);
+        for n in 0..MAX_PAGED_RESULTS + 3 {
+            store.paginate(text_result(&format!("result {}", n)));
+        }
+        assert_eq!(store.stats().stored, MAX_PAGED_RESULTS);
+    }
+
+    #[test]
+    fn test_stored_bytes_are_bounded() {
+        let store = PageStore::new(1024);
+        let size = MAX_PAGED_BYTES / 3 + 1;
+        let first = store.paginate(text_result(&"a".repeat(size)));
+        for _ in 0..2 {
+            store.paginate(text_result(&"b".repeat(size)));
+        }
+        let stats = store.stats();
+        assert_eq!(stats.stored, 2);
+        assert_eq!(stats.stored_bytes, 2 * size);
+        let cursor = first["_meta"]["pagination"]["next_cursor"]
+            .as_str()
+            .unwrap();
+        assert!(store.page(cursor).is_err());
+    }
+
+    #[test]
+    fn test_data_uri_results_are_not_paged() {
+        let store = PageStore::new(16);
+        let html = format!(
+            "<img src=\"data:image/png;base64,{}\" alt=\"thumbnail\" />",
+            "QUFB".repeat(64)
+        );
+        let result = text_result(&html);
+        assert_eq!(store.paginate(result.clone()), result);
+        assert_eq!(store.stats().stored, 0);
+
+        let prose = "see data: below, no image here\n".repeat(4);
+        assert!(store.paginate(text_result(&prose))["_meta"]["pagination"].is_object());
+    }
+}
@@ -1,18 +1,43 @@
 use anyhow::Result;
 use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
 use serde_json::{Map, Value, json};
-use std::{
-    env, fs,
-    path::PathBuf,
-    process::Stdio,
-    time::{SystemTime, UNIX_EPOCH},
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::AppState;
+use crate::backend::temp_upload_dir;
+use crate::client::{
+    AssetCreate, AssetCreateBatch, AssetDependencies, AssetGeometricMatch, AssetGet,
+    AssetMetadataCreate, AssetMetadataDelete, AssetPartMatch, AssetRef, AssetReprocess,
+    AssetTextMatch, AssetThumbnail, AssetVisualMatch, Concurrency, ConfigEnvironmentGet,
+    ConfigEnvironmentList, ConfigGet, ConfigGetPath, FolderDependencies, FolderGet, FolderMatch,
+    FolderRef, FolderResolve, List, ListResource, MatchKind, MetadataType, Output, OutputFormat,
+    Pcli2Client, Pcli2Request, TenantGet, TenantList, TenantState, TenantUse, Threshold, Version,
 };
-use tokio::io::{AsyncRead, AsyncReadExt};
-use tracing::info;
-
+use crate::contact_sheet::{
+    DEFAULT_SHEET_TOP_K, DEFAULT_TILE_SIZE, MAX_SHEET_COLUMNS, MAX_SHEET_TILES, MAX_TILE_SIZE,
+    MIN_TILE_SIZE, SheetTile, compose,
+};
+use crate::error::{Pcli2Error, TOOL_ERROR_CODE, ToolError};
+use crate::jobs::JobStatus;
+use crate::limits::Pcli2Permit;
+use crate::markdown::{DEFAULT_MAX_CELL_WIDTH, MarkdownRender};
+use crate::match_filter::{MatchFilter, flatten, match_candidates, normalize};
+use crate::match_report::{DEFAULT_TOP_K, MAX_TOP_K, MatchReport, THUMBNAIL_CONCURRENCY};
+use crate::result_cache::{CacheScope, Mutation, cache_ttl};
 use crate::thumbnail::ThumbnailCache;
-
-pub const PCLI2_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+use crate::variant::{MAX_VARIANT_DIMENSION, MIN_VARIANT_DIMENSION, VariantSpec};
+
+pub const PCLI2_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Default timeout for quick lookups such as `pcli2 --version` or `tenant list`
+pub const QUICK_TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default timeout for single-asset and single-folder operations
+pub const STANDARD_TOOL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Default timeout for bulk folder operations over many assets
+pub const BULK_TOOL_TIMEOUT: Duration = PCLI2_TIMEOUT;
 pub const MAX_PCLI2_OUTPUT_BYTES: usize = 200 * 1024 * 1024;
 pub const PCLI2_BIN_ENV: &str = "PCLI2_BIN";
 
@@ -47,9 +72,58 @@ fn define_tool<F>(
 {
     let mut props = Props::new();
     build(&mut props);
+    add_timeout(&mut props, default_tool_timeout(name));
+    if let Some(ttl) = cache_ttl(name) {
+        add_cache_control(&mut props, ttl);
+    }
     push_tool(tools, name, description, props, required);
 }
 
+/// Default timeout for a tool, used when the call has no `timeout_seconds`
+pub fn default_tool_timeout(name: &str) -> Duration {
+    match name {
+        "pcli2_version"
+        | "pcli2_config_get"
+        | "pcli2_config_get_path"
+        | "pcli2_config_environment_list"
+        | "pcli2_config_environment_get"
+        | "pcli2_tenant_list"
+        | "pcli2_tenant_get"
+        | "pcli2_tenant_use"
+        | "pcli2_folder_resolve"
+        | "pcli2_thumbnail_cache_cleanup"
+        | "pcli2_server_status"
+        | "pcli2_job_status"
+        | "pcli2_job_result"
+        | "pcli2_job_cancel"
+        | "pcli2_job_list" => QUICK_TOOL_TIMEOUT,
+        "pcli2_folder_dependencies"
+        | "pcli2_folder_geometric_match"
+        | "pcli2_folder_part_match"
+        | "pcli2_folder_visual_match"
+        | "pcli2_asset_create_batch" => BULK_TOOL_TIMEOUT,
+        _ => STANDARD_TOOL_TIMEOUT,
+    }
+}
+
+/// Resolve the timeout for one call from its `timeout_seconds` argument
+pub fn resolve_tool_timeout(name: &str, args: &Value, max: Duration) -> Result<Duration, String> {
+    validate_range_u64(args, "timeout_seconds", 1, max.as_secs())?;
+    if let Some(value) = args.get("timeout_seconds")
+        && value.as_u64().is_none()
+    {
+        return Err(format!(
+            "Invalid argument 'timeout_seconds': expected a positive integer, got {}",
+            value
+        ));
+    }
+    Ok(args
+        .get("timeout_seconds")
+        .and_then(|v| v.as_u64())
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| default_tool_timeout(name).min(max)))
+}
+
 fn add_prop(props: &mut Props, key: &str, value: Value) {
     props.insert(key.to_string(), value);
 }
@@ -86,11 +160,42 @@ fn add_metadata(props: &mut Props) {
     );
 }
 
+/// Output formats offered by pcli2, plus the markdown formats rendered by the server
 fn add_format(props: &mut Props, values: &[&str]) {
+    let mut values = values.to_vec();
+    if values.contains(&"csv") {
+        values.push("markdown");
+    }
+    if values.contains(&"tree") {
+        values.push("markdown_tree");
+    }
     add_prop(
         props,
         "format",
-        json!({ "type": "string", "enum": values, "description": "Output format." }),
+        json!({ "type": "string", "enum": values, "description": "Output format. 'markdown' renders a table from JSON output; 'markdown_tree' renders the tree as a nested list." }),
+    );
+    if values.contains(&"markdown") {
+        add_columns(props);
+        add_prop(
+            props,
+            "max_cell_width",
+            json!({ "type": "integer", "minimum": 4, "description": format!("Longest markdown table cell before it is cut short (default {}).", DEFAULT_MAX_CELL_WIDTH) }),
+        );
+    }
+}
+
+/// `columns`, shared by markdown tables and match filters so neither schema replaces the other
+fn add_columns(props: &mut Props) {
+    add_prop(
+        props,
+        "columns",
+        json!({
+            "oneOf": [
+                { "type": "string" },
+                { "type": "array", "items": { "type": "string" } }
+            ],
+            "description": "Fields to show, e.g. 'name,uuid'; nested fields use dotted names. Picks the columns of a markdown table and, on match tools, the fields returned for each match. Comma-separated string or array."
+        }),
     );
 }
 
@@ -200,6 +305,118 @@ fn add_metadata_name_value(props: &mut Props) {
     );
 }
 
+fn add_match_filters(props: &mut Props) {
+    add_prop(
+        props,
+        "limit",
+        json!({ "type": "integer", "minimum": 1, "description": "Return at most this many matches, best first unless sort_by is given." }),
+    );
+    add_prop(
+        props,
+        "sort_by",
+        json!({ "type": "string", "description": "Field to sort matches by; prefix with '-' for descending, e.g. '-score'. 'score' and 'path' refer to the match score and candidate path." }),
+    );
+    add_prop(
+        props,
+        "min_score",
+        json!({ "type": "number", "description": "Drop matches scoring below this value." }),
+    );
+    for (key, description) in [
+        (
+            "exclude_path_prefix",
+            "Drop matches whose candidate path starts with any of these prefixes.",
+        ),
+        (
+            "include_path_prefix",
+            "Keep only matches whose candidate path starts with one of these prefixes.",
+        ),
+    ] {
+        add_prop(
+            props,
+            key,
+            json!({
+                "oneOf": [
+                    { "type": "string" },
+                    { "type": "array", "items": { "type": "string" } }
+                ],
+                "description": format!("{} Comma-separated string or array.", description)
+            }),
+        );
+    }
+    add_prop(
+        props,
+        "dedupe_by_candidate",
+        json!({ "type": "boolean", "description": "Keep only the best match for each candidate asset." }),
+    );
+    add_columns(props);
+}
+
+fn add_async(props: &mut Props) {
+    add_prop(
+        props,
+        "async",
+        json!({ "type": "boolean", "description": "Run as a background job and return a job ID immediately. Use pcli2_job_status and pcli2_job_result to follow up." }),
+    );
+}
+
+fn add_upload_folder(props: &mut Props) {
+    add_prop(
+        props,
+        "folder_path",
+        json!({ "type": "string", "description": "Folder to create the asset(s) in, e.g. /Root/Parts." }),
+    );
+}
+
+fn add_wait(props: &mut Props) {
+    add_prop(
+        props,
+        "wait",
+        json!({ "type": "boolean", "description": "Poll `pcli2 tenant state` until the new asset(s) finish indexing, within the call's timeout." }),
+    );
+}
+
+fn add_job_id(props: &mut Props) {
+    add_prop(
+        props,
+        "job_id",
+        json!({ "type": "string", "description": "Job ID returned by an `async: true` call." }),
+    );
+}
+
+fn add_cache_control(props: &mut Props, ttl: Duration) {
+    add_prop(
+        props,
+        "no_cache",
+        json!({
+            "type": "boolean",
+            "description": format!(
+                "Skip the result cache for this call. Results are otherwise reused for {} seconds.",
+                ttl.as_secs()
+            )
+        }),
+    );
+    add_prop(
+        props,
+        "refresh",
+        json!({ "type": "boolean", "description": "Run pcli2 even if a cached result exists and cache the new output." }),
+    );
+}
+
+fn add_timeout(props: &mut Props, default: Duration) {
+    add_prop(
+        props,
+        "timeout_seconds",
+        json!({
+            "type": "integer",
+            "minimum": 1,
+            "description": format!(
+                "Timeout for this call in seconds. Defaults to {}; capped by the server maximum.",
+                default.as_secs()
+            )
+        }),
+    );
+}
+
 fn add_metadata_name(props: &mut Props) {
     add_prop(
         props,
@@ -433,6 +650,7 @@ pub fn tool_list() -> Vec<Value> {
             add_pretty(props);
             add_format(props, &["json", "csv", "tree"]);
             add_progress(props);
+            add_async(props);
         },
     );
 
@@ -450,8 +668,10 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
             add_concurrent(props);
             add_progress(props);
+            add_async(props);
         },
     );
 
@@ -469,8 +689,10 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
             add_concurrent(props);
             add_progress(props);
+            add_async(props);
         },
     );
 
@@ -487,8 +709,10 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
             add_concurrent(props);
             add_progress(props);
+            add_async(props);
         },
     );
 
@@ -540,6 +764,46 @@ pub fn tool_list() -> Vec<Value> {
                     "description": "Output format: 'url' returns an HTTP URL (efficient for LLM context, ~200 tokens), 'data_url' returns a base64 data URI (self-contained image, ~50K tokens but renders immediately in markdown without HTTP fetch). Use 'data_url' when the client cannot make HTTP requests or when you need the image to display immediately."
                 }),
             );
+            add_prop(
+                props,
+                "refresh",
+                json!({ "type": "boolean", "description": "Download the thumbnail again even if a cached copy exists." }),
+            );
+            for (key, description) in [
+                (
+                    "width",
+                    "Scale the thumbnail down to at most this many pixels wide, keeping its aspect ratio.",
+                ),
+                (
+                    "height",
+                    "Scale the thumbnail down to at most this many pixels high, keeping its aspect ratio.",
+                ),
+                (
+                    "max_dimension",
+                    "Scale the thumbnail down so neither side exceeds this many pixels. A 128px JPEG keeps 'data_url' responses small.",
+                ),
+            ] {
+                add_prop(
+                    props,
+                    key,
+                    json!({
+                        "type": "integer",
+                        "minimum": MIN_VARIANT_DIMENSION,
+                        "maximum": MAX_VARIANT_DIMENSION,
+                        "description": description
+                    }),
+                );
+            }
+            add_prop(
+                props,
+                "format",
+                json!({
+                    "type": "string",
+                    "enum": ["png", "jpeg", "webp"],
+                    "default": "png",
+                    "description": "Image encoding. 'jpeg' is the most compact; 'webp' is lossless."
+                }),
+            );
         },
     );
 
@@ -567,6 +831,7 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
         },
     );
 
@@ -583,6 +848,7 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
         },
     );
 
@@ -598,6 +864,7 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
         },
     );
 
@@ -614,6 +881,96 @@ pub fn tool_list() -> Vec<Value> {
             add_metadata(props);
             add_pretty(props);
             add_format(props, &["json", "csv"]);
+            add_match_filters(props);
+        },
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_visual_match_report",
+        "Runs a geometric, part or visual match for an asset and returns the best candidates with their scores, paths and thumbnail URLs in one response. Thumbnails for the reference and each candidate are fetched concurrently and cached.",
+        &[],
+        |props| {
+            add_tenant(props);
+            add_uuid_path(props);
+            add_prop(
+                props,
+                "match_type",
+                json!({ "type": "string", "enum": ["geometric", "part", "visual"], "default": "geometric", "description": "Match command to run." }),
+            );
+            add_threshold(props);
+            add_prop(
+                props,
+                "top_k",
+                json!({ "type": "integer", "minimum": 1, "maximum": MAX_TOP_K, "default": DEFAULT_TOP_K, "description": "Number of best candidates to include." }),
+            );
+            add_match_filters(props);
+            for key in ["limit", "sort_by", "columns"] {
+                props.remove(key);
+            }
+            add_prop(
+                props,
+                "format",
+                json!({ "type": "string", "enum": ["markdown", "json"], "default": "markdown", "description": "'markdown' returns a table with embedded thumbnails; 'json' returns the report as structured data." }),
+            );
+        },
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_contact_sheet",
+        "Composes the thumbnails of several assets into one labelled grid image, captioned with each asset's path and score. Give either `assets` or the JSON output of a match tool as `match_result`. The sheet is cached and returned as a URL or as an image block.",
+        &[],
+        |props| {
+            add_tenant(props);
+            add_prop(
+                props,
+                "assets",
+                json!({
+                    "type": "array",
+                    "items": {
+                        "oneOf": [
+                            { "type": "string" },
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "uuid": { "type": "string" },
+                                    "path": { "type": "string" },
+                                    "label": { "type": "string" },
+                                    "score": { "type": "number" }
+                                }
+                            }
+                        ]
+                    },
+                    "maxItems": MAX_SHEET_TILES,
+                    "description": "Assets to show, as UUIDs, paths (starting with '/') or objects with uuid/path and an optional label and score."
+                }),
+            );
+            add_prop(
+                props,
+                "match_result",
+                json!({ "description": "JSON output of a match tool, as a string or object. The best `top_k` candidates are shown with their scores." }),
+            );
+            add_prop(
+                props,
+                "top_k",
+                json!({ "type": "integer", "minimum": 1, "maximum": MAX_SHEET_TILES, "default": DEFAULT_SHEET_TOP_K, "description": "Number of candidates taken from `match_result`." }),
+            );
+            add_prop(
+                props,
+                "grid_columns",
+                json!({ "type": "integer", "minimum": 1, "maximum": MAX_SHEET_COLUMNS, "description": "Tiles per row. Defaults to a near-square grid." }),
+            );
+            add_prop(
+                props,
+                "tile_size",
+                json!({ "type": "integer", "minimum": MIN_TILE_SIZE, "maximum": MAX_TILE_SIZE, "default": DEFAULT_TILE_SIZE, "description": "Width and height of each thumbnail tile in pixels." }),
+            );
+            add_prop(
+                props,
+                "response_mode",
+                json!({ "type": "string", "enum": ["url", "image"], "default": "url", "description": "'url' returns the cached sheet's HTTP URL; 'image' returns the PNG as an MCP image block." }),
+            );
         },
     );
 
@@ -641,10 +998,128 @@ pub fn tool_list() -> Vec<Value> {
         },
     );
 
+    define_tool(
+        &mut tools,
+        "pcli2_asset_create",
+        "Uploads one file as a new asset with `pcli2 asset create`. Give `file_path` for a file on the server, inside its upload root, or `file_base64` and `file_name` for the file contents.",
+        &["folder_path"],
+        |props| {
+            add_tenant(props);
+            add_prop(
+                props,
+                "file_path",
+                json!({ "type": "string", "description": "Server-local file to upload; relative paths are taken from the upload root." }),
+            );
+            add_prop(
+                props,
+                "file_base64",
+                json!({ "type": "string", "description": "Base64 contents of the file to upload." }),
+            );
+            add_prop(
+                props,
+                "file_name",
+                json!({ "type": "string", "description": "Asset file name for `file_base64`, e.g. bracket.stl." }),
+            );
+            add_upload_folder(props);
+            add_wait(props);
+        },
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_asset_create_batch",
+        "Uploads the files in a server directory, inside its upload root, as new assets with `pcli2 asset create-batch`.",
+        &["directory", "folder_path"],
+        |props| {
+            add_tenant(props);
+            add_prop(
+                props,
+                "directory",
+                json!({ "type": "string", "description": "Server-local directory to upload from; relative paths are taken from the upload root." }),
+            );
+            add_prop(
+                props,
+                "pattern",
+                json!({ "type": "string", "default": "*", "description": "Glob selecting the files in `directory`, e.g. *.stl." }),
+            );
+            add_upload_folder(props);
+            add_concurrent(props);
+            add_wait(props);
+            add_async(props);
+        },
+    );
+
     define_tool(
         &mut tools,
         "pcli2_thumbnail_cache_cleanup",
-        "Removes expired thumbnails from the cache to free up disk space.",
+        "Removes expired thumbnails from the cache, evicts least recently used ones beyond the cache limits, and reports cache statistics.",
+        &[],
+        |_| {},
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_job_status",
+        "Reports the status of a background job started with `async: true`.",
+        &["job_id"],
+        add_job_id,
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_job_result",
+        "Returns the output of a finished background job.",
+        &["job_id"],
+        add_job_id,
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_job_cancel",
+        "Cancels a running background job and stops its pcli2 process.",
+        &["job_id"],
+        add_job_id,
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_job_list",
+        "Lists background jobs, newest first.",
+        &[],
+        |props| {
+            add_prop(
+                props,
+                "status",
+                json!({
+                    "type": "string",
+                    "enum": ["running", "succeeded", "failed", "cancelled"],
+                    "description": "Only list jobs in this state."
+                }),
+            );
+        },
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_result_page",
+        "Fetches the next page of a tool result that exceeded the response budget. Pass the `next_cursor` from the previous page.",
+        &["cursor"],
+        |props| {
+            add_prop(
+                props,
+                "cursor",
+                json!({
+                    "type": "string",
+                    "description": "Cursor from the summary header or `_meta.pagination.next_cursor` of the previous page."
+                }),
+            );
+        },
+    );
+
+    define_tool(
+        &mut tools,
+        "pcli2_server_status",
+        "Reports server status: running pcli2 processes, queue depth and wait times.",
         &[],
         |_| {},
     );
@@ -652,10 +1127,7 @@ pub fn tool_list() -> Vec<Value> {
     tools
 }
 
-pub async fn call_tool(
-    params: Value,
-    thumbnail_cache: Option<&ThumbnailCache>,
-) -> Result<Value, String> {
+pub async fn call_tool(params: Value, state: &AppState) -> Result<Value, ToolError> {
     let name = params
         .get("name")
         .and_then(|v| v.as_str())
@@ -664,73 +1136,315 @@ pub async fn call_tool(
         .get("arguments")
         .cloned()
         .unwrap_or_else(|| json!({}));
-
-    match name {
-        "pcli2" => {
-            let output = run_pcli2_list(args).await?;
-            Ok(json!({
-                "content": [{
-                    "type": "text",
-                    "text": output
-                }]
-            }))
-        }
-        "pcli2_tenant_list" => {
-            run_simple_tool("pcli2 tenant list", run_pcli2_tenant_list(args).await)
-        }
-        "pcli2_version" => run_simple_tool("pcli2 --version", run_pcli2_version().await),
-        "pcli2_config_get" => run_simple_tool("pcli2 config get", run_pcli2_config_get(args).await),
-        "pcli2_config_get_path" => run_simple_tool(
-            "pcli2 config get path",
-            run_pcli2_config_get_path(args).await,
-        ),
-        "pcli2_config_environment_list" => run_simple_tool(
-            "pcli2 config environment list",
-            run_pcli2_config_environment_list(args).await,
-        ),
-        "pcli2_config_environment_get" => run_simple_tool(
-            "pcli2 config environment get",
-            run_pcli2_config_environment_get(args).await,
-        ),
-        "pcli2_tenant_get" => run_simple_tool("pcli2 tenant get", run_pcli2_tenant_get(args).await),
-        "pcli2_tenant_state" => {
-            run_simple_tool("pcli2 tenant state", run_pcli2_tenant_state(args).await)
+    if !is_known_tool(name) {
+        return Err(format!("Unknown tool '{}'", name).into());
+    }
+    if name == "pcli2_result_page" {
+        let cursor = args
+            .get("cursor")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "Missing required argument: 'cursor'".to_string())?;
+        return Ok(state.pages.page(cursor)?);
+    }
+    // Job results are paged like any other output
+    let result = match call_job_tool(name, &args, state) {
+        Some(result) => result,
+        None => {
+            let timeout = resolve_tool_timeout(name, &args, state.max_tool_timeout)?;
+            if args.get("async").and_then(|v| v.as_bool()).unwrap_or(false) {
+                if !ASYNC_TOOLS.contains(&name) {
+                    return Err(format!("Tool '{}' does not support 'async'", name).into());
+                }
+                return Ok(start_job(name, args, timeout, state));
+            }
+            execute_tool(name, args, timeout, state).await
         }
-        "pcli2_tenant_use" => run_simple_tool("pcli2 tenant use", run_pcli2_tenant_use(args).await),
-        "pcli2_folder_get" => run_simple_tool("pcli2 folder get", run_pcli2_folder_get(args).await),
-        "pcli2_folder_resolve" => {
-            run_simple_tool("pcli2 folder resolve", run_pcli2_folder_resolve(args).await)
+    };
+    result.map(|value| state.pages.paginate(value))
+}
+
+/// Tools whose JSON output can be filtered with [`crate::match_filter::MATCH_FILTER_ARGS`]
+pub const MATCH_TOOLS: &[&str] = &[
+    "pcli2_folder_geometric_match",
+    "pcli2_folder_part_match",
+    "pcli2_folder_visual_match",
+    "pcli2_geometric_match",
+    "pcli2_asset_part_match",
+    "pcli2_asset_visual_match",
+    "pcli2_asset_text_match",
+];
+
+/// Tools that accept `async: true` and run as background jobs
+pub const ASYNC_TOOLS: &[&str] = &[
+    "pcli2_folder_dependencies",
+    "pcli2_folder_geometric_match",
+    "pcli2_folder_part_match",
+    "pcli2_folder_visual_match",
+    "pcli2_asset_create_batch",
+];
+
+fn start_job(name: &str, args: Value, timeout: Duration, state: &AppState) -> Value {
+    let record = state.jobs.create(name, args.clone());
+    let job_state = state.clone();
+    let tool = name.to_string();
+    let job_id = record.id.clone();
+    let handle = tokio::spawn(async move {
+        let outcome = execute_tool(&tool, args, timeout, &job_state).await;
+        job_state.jobs.finish(&job_id, outcome).await;
+    });
+    state.jobs.attach(&record.id, handle.abort_handle());
+
+    let mut summary = record.summary();
+    summary["message"] = json!(format!(
+        "Started in the background. Poll pcli2_job_status with job_id '{}' and fetch the output with pcli2_job_result.",
+        record.id
+    ));
+    json_text_result(&summary)
+}
+
+fn call_job_tool(name: &str, args: &Value, state: &AppState) -> Option<Result<Value, ToolError>> {
+    let job_id = || {
+        args.get("job_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ToolError::from("Missing required argument: 'job_id'".to_string()))
+    };
+    let unknown = |id: &str| ToolError::from(format!("Unknown job '{}'", id));
+
+    let result = match name {
+        "pcli2_job_status" => job_id().and_then(|id| {
+            state
+                .jobs
+                .get(id)
+                .map(|record| json_text_result(&record.summary()))
+                .ok_or_else(|| unknown(id))
+        }),
+        "pcli2_job_result" => job_id().and_then(|id| {
+            let record = state.jobs.get(id).ok_or_else(|| unknown(id))?;
+            match (record.status, record.result, record.error) {
+                (JobStatus::Succeeded, Some(result), _) => Ok(result),
+                (JobStatus::Failed, _, Some(error)) => Err(error.into()),
+                (status, _, _) => Err(ToolError {
+                    code: TOOL_ERROR_CODE,
+                    message: format!("Job '{}' has no result (status: {:?})", id, status),
+                    data: Some(json!({ "reason": "job_not_finished", "status": status })),
+                }),
+            }
+        }),
+        "pcli2_job_cancel" => job_id().and_then(|id| {
+            state
+                .jobs
+                .cancel(id)
+                .map(|record| json_text_result(&record.summary()))
+                .map_err(ToolError::from)
+        }),
+        "pcli2_job_list" => {
+            let status = match args.get("status").and_then(|v| v.as_str()) {
+                Some(value) => match JobStatus::parse(value) {
+                    Some(status) => Some(status),
+                    None => {
+                        return Some(Err(format!("Invalid job status '{}'", value).into()));
+                    }
+                },
+                None => None,
+            };
+            let jobs: Vec<Value> = state
+                .jobs
+                .list(status)
+                .iter()
+                .map(|record| record.summary())
+                .collect();
+            Ok(json_text_result(&json!({ "jobs": jobs })))
         }
-        "pcli2_folder_dependencies" => run_simple_tool(
-            "pcli2 folder dependencies",
-            run_pcli2_folder_dependencies(args).await,
-        ),
-        "pcli2_folder_geometric_match" => run_simple_tool(
-            "pcli2 folder geometric-match",
-            run_pcli2_folder_geometric_match(args).await,
-        ),
-        "pcli2_folder_part_match" => run_simple_tool(
-            "pcli2 folder part-match",
-            run_pcli2_folder_part_match(args).await,
-        ),
-        "pcli2_folder_visual_match" => run_simple_tool(
-            "pcli2 folder visual-match",
-            run_pcli2_folder_visual_match(args).await,
-        ),
-        "pcli2_asset_get" => run_simple_tool("pcli2 asset get", run_pcli2_asset_get(args).await),
-        "pcli2_asset_dependencies" => run_simple_tool(
-            "pcli2 asset dependencies",
-            run_pcli2_asset_dependencies(args).await,
-        ),
-        "pcli2_asset_thumbnail" => {
-            let src = run_pcli2_asset_thumbnail(args, thumbnail_cache).await?;
-            // Return HTML that embeds the thumbnail image
-            // src can be either an HTTP URL (response_mode=url) or a data URI (response_mode=data_url)
-            let html = format!(
-                r#"<!DOCTYPE html>
-<html>
-<head><title>Asset Thumbnail</title></head>
-<body>
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn json_text_result(value: &Value) -> Value {
+    let text = serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string());
+    json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }]
+    })
+}
+
+/// Run a tool in the current task, honouring concurrency limits and `timeout`
+async fn execute_tool(
+    name: &str,
+    args: Value,
+    timeout: Duration,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    let tenant = args
+        .get("tenant")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+
+    let flag = |key: &str| args.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+    let (no_cache, refresh) = (flag("no_cache"), flag("refresh"));
+    let cached = cacheable_invocation(name, &args);
+    if let Some((argv, _)) = &cached
+        && !no_cache
+        && !refresh
+        && let Some(output) = state.results.get(name, argv, tenant.as_deref())
+    {
+        return Ok(text_result(output));
+    }
+    let mutation = mutation_for(name, &args);
+
+    // Queue wait counts against the call's timeout
+    let deadline = Instant::now() + timeout;
+    let call = async {
+        let _permit = if spawns_pcli2(name) && !takes_slot_per_run(name) {
+            let permit = state
+                .limiter
+                .acquire(name, tenant.as_deref())
+                .await
+                .map_err(ToolError::server_busy)?;
+            Some(permit)
+        } else {
+            None
+        };
+        dispatch_tool(name, args, deadline, state).await
+    };
+    let result = match tokio::time::timeout(timeout, call).await {
+        Ok(result) => result,
+        Err(_) => Err(ToolError::timeout(name, timeout, state.max_tool_timeout)),
+    };
+
+    // A failed or timed-out mutation may still have reached the API
+    if let Some(mutation) = mutation {
+        state.results.invalidate(&mutation);
+    }
+    if let (Ok(value), Some((argv, scope))) = (&result, cached)
+        && !no_cache
+        && let Some(output) = value["content"][0]["text"].as_str()
+    {
+        state
+            .results
+            .put(name, &argv, tenant.as_deref(), scope, output);
+    }
+    result
+}
+
+/// The pcli2 argv and scope of a call whose output may be cached
+///
+/// Invalid arguments yield `None` so the tool reports its usual error.
+fn cacheable_invocation(name: &str, args: &Value) -> Option<(Vec<String>, CacheScope)> {
+    let scope = match name {
+        "pcli2" => CacheScope::Folder {
+            uuid: string_arg(args, "folder_uuid"),
+            path: string_arg(args, "folder_path"),
+        },
+        "pcli2_tenant_get" => CacheScope::Tenant,
+        "pcli2_folder_get" => {
+            let (uuid, path) = require_folder_uuid_or_path(args).ok()?;
+            CacheScope::Folder { uuid, path }
+        }
+        "pcli2_asset_get" => {
+            let (uuid, path) = require_uuid_or_path(args).ok()?;
+            CacheScope::Asset { uuid, path }
+        }
+        _ => return None,
+    };
+    let request = tool_request(name, args)?.ok()?;
+    Some((request.argv(), scope))
+}
+
+/// What a mutating tool changes, for result cache invalidation
+fn mutation_for(name: &str, args: &Value) -> Option<Mutation> {
+    let string = |key: &str| args.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    match name {
+        "pcli2_asset_metadata_create" | "pcli2_asset_metadata_delete" | "pcli2_asset_reprocess" => {
+            Some(Mutation::Asset {
+                tenant: string("tenant"),
+                uuid: string("uuid"),
+                path: string("path"),
+            })
+        }
+        "pcli2_asset_create" | "pcli2_asset_create_batch" => {
+            let folder = string("folder_path")?;
+            let file_name = string("file_name").or_else(|| {
+                let path = string("file_path")?;
+                Some(Path::new(&path).file_name()?.to_string_lossy().into_owned())
+            });
+            // Without a file name, every listing of the folder is stale
+            let name = match name {
+                "pcli2_asset_create" => file_name.unwrap_or_default(),
+                _ => String::new(),
+            };
+            Some(Mutation::Asset {
+                tenant: string("tenant"),
+                uuid: None,
+                path: Some(format!("{}/{}", folder.trim_end_matches('/'), name)),
+            })
+        }
+        "pcli2_tenant_use" => Some(Mutation::ActiveTenant),
+        _ => None,
+    }
+}
+
+fn text_result(text: String) -> Value {
+    json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }]
+    })
+}
+
+fn is_known_tool(name: &str) -> bool {
+    tool_list().iter().any(|tool| tool["name"] == name)
+}
+
+/// Whether a tool runs pcli2 several times, taking a slot for each run
+///
+/// Uploads that wait for indexing would otherwise hold a slot while sleeping
+/// between polls.
+fn takes_slot_per_run(name: &str) -> bool {
+    matches!(name, "pcli2_asset_create" | "pcli2_asset_create_batch")
+}
+
+/// Whether a tool runs a pcli2 subprocess and so needs a concurrency slot
+fn spawns_pcli2(name: &str) -> bool {
+    !matches!(
+        name,
+        "pcli2_thumbnail_cache_cleanup"
+            | "pcli2_server_status"
+            | "pcli2_result_page"
+            | "pcli2_job_status"
+            | "pcli2_job_result"
+            | "pcli2_job_cancel"
+            | "pcli2_job_list"
+    )
+}
+
+async fn dispatch_tool(
+    name: &str,
+    args: Value,
+    deadline: Instant,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    if tool_request(name, &args).is_some() {
+        return run_command_tool(name, args, state).await;
+    }
+    let thumbnail_cache = state.thumbnail_cache.as_ref();
+    match name {
+        "pcli2_asset_create" => run_asset_create(name, args, deadline, state).await,
+        "pcli2_asset_create_batch" => run_asset_create_batch(name, args, deadline, state).await,
+        "pcli2_visual_match_report" => run_visual_match_report(args, state, thumbnail_cache).await,
+        "pcli2_contact_sheet" => run_contact_sheet(args, state, thumbnail_cache).await,
+        "pcli2_asset_thumbnail" => {
+            let src = run_pcli2_asset_thumbnail(state, args, thumbnail_cache).await?;
+            // Return HTML that embeds the thumbnail image
+            // src can be either an HTTP URL (response_mode=url) or a data URI (response_mode=data_url)
+            let html = format!(
+                r#"<!DOCTYPE html>
+<html>
+<head><title>Asset Thumbnail</title></head>
+<body>
 <img src="{}" alt="Asset Thumbnail" style="max-width: 100%; height: auto;">
 </body>
 </html>"#,
@@ -743,39 +1457,6 @@ pub async fn call_tool(
                 }]
             }))
         }
-        "pcli2_asset_reprocess" => run_simple_tool(
-            "pcli2 asset reprocess",
-            run_pcli2_asset_reprocess(args).await,
-        ),
-        "pcli2_geometric_match" => {
-            let output = run_pcli2_asset_geometric_match(args).await?;
-            Ok(json!({
-                "content": [{
-                    "type": "text",
-                    "text": output
-                }]
-            }))
-        }
-        "pcli2_asset_part_match" => run_simple_tool(
-            "pcli2 asset part-match",
-            run_pcli2_asset_part_match(args).await,
-        ),
-        "pcli2_asset_visual_match" => run_simple_tool(
-            "pcli2 asset visual-match",
-            run_pcli2_asset_visual_match(args).await,
-        ),
-        "pcli2_asset_text_match" => run_simple_tool(
-            "pcli2 asset text-match",
-            run_pcli2_asset_text_match(args).await,
-        ),
-        "pcli2_asset_metadata_create" => run_simple_tool(
-            "pcli2 asset metadata create",
-            run_pcli2_asset_metadata_create(args).await,
-        ),
-        "pcli2_asset_metadata_delete" => run_simple_tool(
-            "pcli2 asset metadata delete",
-            run_pcli2_asset_metadata_delete(args).await,
-        ),
         "pcli2_thumbnail_cache_cleanup" => {
             let Some(cache) = thumbnail_cache else {
                 return Ok(json!({
@@ -785,21 +1466,89 @@ pub async fn call_tool(
                     }]
                 }));
             };
-            match cache.cleanup_expired() {
-                Ok(count) => Ok(json!({
+            let swept = cache
+                .blocking(|cache| cache.sweep().map(|result| (result, cache.stats())))
+                .await;
+            match swept {
+                Ok((result, stats)) => Ok(json!({
                     "content": [{
                         "type": "text",
-                        "text": format!("Cleaned up {} expired thumbnail(s)", count)
+                        "text": format!(
+                            "Cleaned up {} expired thumbnail(s) and evicted {} least recently used thumbnail(s)\n\n{}",
+                            result.expired,
+                            result.evicted,
+                            serde_json::to_string_pretty(&stats).unwrap_or_default()
+                        )
                     }]
                 })),
-                Err(err) => Err(format!("Thumbnail cache cleanup failed: {}", err)),
+                Err(err) => Err(format!("Thumbnail cache cleanup failed: {}", err).into()),
+            }
+        }
+        "pcli2_server_status" => {
+            let status = json!({
+                "server": {
+                    "name": state.server_name,
+                    "version": state.server_version,
+                },
+                "pcli2": state.limiter.stats(),
+                "backend": state.backend.name(),
+                "in_flight": state.in_flight.stats(),
+                "jobs": {
+                    "running": state.jobs.list(Some(JobStatus::Running)).len(),
+                    "total": state.jobs.list(None).len(),
+                },
+                "result_cache": state.results.stats(),
+                "thumbnail_cache": state.thumbnail_cache.as_deref().map(ThumbnailCache::stats),
+                "pages": state.pages.stats(),
+            });
+            let text = serde_json::to_string_pretty(&status)
+                .map_err(|err| format!("Failed to render server status: {}", err))?;
+            Ok(json!({
+                "content": [{
+                    "type": "text",
+                    "text": text
+                }]
+            }))
+        }
+        _ => Err(format!("Unknown tool '{}'", name).into()),
+    }
+}
+
+/// Run a tool backed by one pcli2 command, applying match filters and markdown rendering
+async fn run_command_tool(
+    name: &str,
+    mut args: Value,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    let markdown = markdown_arg(&mut args);
+    let filter = match_filter_arg(name, &mut args);
+    let request = tool_request(name, &args).unwrap_or_else(|| {
+        Err(Pcli2Error::Failed(format!(
+            "No pcli2 command for '{}'",
+            name
+        )))
+    });
+    match (request, markdown, filter) {
+        (Ok(request), Ok(markdown), Ok(filter)) => {
+            let mut result = state.client().run(request.as_ref()).await;
+            if let Some(filter) = filter {
+                let pretty = bool_arg(&args, "pretty");
+                result = result
+                    .and_then(|output| filter.apply(&output, pretty).map_err(Pcli2Error::Failed));
             }
+            if let Some(markdown) = markdown {
+                result =
+                    result.and_then(|output| markdown.render(&output).map_err(Pcli2Error::Failed));
+            }
+            run_simple_tool(&request.label(), result)
+        }
+        (Err(error), _, _) | (_, Err(error), _) | (_, _, Err(error)) => {
+            run_simple_tool(name, Err(error))
         }
-        _ => Err(format!("Unknown tool '{}'", name)),
     }
 }
 
-fn run_simple_tool(label: &str, result: Result<String, String>) -> Result<Value, String> {
+fn run_simple_tool(label: &str, result: Result<String, Pcli2Error>) -> Result<Value, ToolError> {
     match result {
         Ok(output) => Ok(json!({
             "content": [{
@@ -807,602 +1556,209 @@ fn run_simple_tool(label: &str, result: Result<String, String>) -> Result<Value,
                 "text": output
             }]
         })),
-        Err(message) => Err(format!("{} failed: {}", label, message)),
+        Err(error) => {
+            let mut error = ToolError::from(error);
+            error.message = format!("{} failed: {}", label, error.message);
+            Err(error)
+        }
     }
 }
 
-async fn run_pcli2_list(args: Value) -> Result<String, String> {
-    let resource = args
-        .get("resource")
-        .and_then(|v| v.as_str())
-        .unwrap_or("folder");
-    let mut cmd_args: Vec<String> = vec![resource.to_string(), "list".to_string()];
+/// Build the typed request for a tool that maps onto a single pcli2 command
+fn tool_request(name: &str, args: &Value) -> Option<Result<BoxedRequest, Pcli2Error>> {
+    let request = match name {
+        "pcli2" => list_request(args).map(boxed),
+        "pcli2_version" => Ok(boxed(Version)),
+        "pcli2_config_get" => output_arg(args).map(|output| boxed(ConfigGet { output })),
+        "pcli2_config_get_path" => output_arg(args).map(|output| boxed(ConfigGetPath { output })),
+        "pcli2_config_environment_list" => {
+            output_arg(args).map(|output| boxed(ConfigEnvironmentList { output }))
+        }
+        "pcli2_config_environment_get" => output_arg(args).map(|output| {
+            boxed(ConfigEnvironmentGet {
+                name: string_arg(args, "name"),
+                output,
+            })
+        }),
+        "pcli2_tenant_list" => output_arg(args).map(|output| boxed(TenantList { output })),
+        "pcli2_tenant_get" => tenant_get_request(args).map(boxed),
+        "pcli2_tenant_state" => output_arg(args).map(|output| {
+            boxed(TenantState {
+                tenant: string_arg(args, "tenant"),
+                state_type: string_arg(args, "type"),
+                output,
+            })
+        }),
+        "pcli2_tenant_use" => tenant_use_request(args).map(boxed),
+        "pcli2_folder_get" => folder_get_request(args).map(boxed),
+        "pcli2_folder_resolve" => string_arg(args, "folder_path")
+            .ok_or_else(|| missing_argument("folder_path"))
+            .map(|folder_path| {
+                boxed(FolderResolve {
+                    folder_path,
+                    tenant: string_arg(args, "tenant"),
+                })
+            }),
+        "pcli2_folder_dependencies" => folder_dependencies_request(args).map(boxed),
+        "pcli2_folder_geometric_match" => {
+            folder_match_request(MatchKind::Geometric, args).map(boxed)
+        }
+        "pcli2_folder_part_match" => folder_match_request(MatchKind::Part, args).map(boxed),
+        "pcli2_folder_visual_match" => folder_match_request(MatchKind::Visual, args).map(boxed),
+        "pcli2_asset_get" => asset_get_request(args).map(boxed),
+        "pcli2_asset_dependencies" => asset_dependencies_request(args).map(boxed),
+        "pcli2_asset_reprocess" => asset_ref_arg(args).map(|asset| {
+            boxed(AssetReprocess {
+                asset,
+                tenant: string_arg(args, "tenant"),
+            })
+        }),
+        "pcli2_geometric_match" => asset_geometric_match_request(args).map(boxed),
+        "pcli2_asset_part_match" => asset_part_match_request(args).map(boxed),
+        "pcli2_asset_visual_match" => asset_visual_match_request(args).map(boxed),
+        "pcli2_asset_text_match" => asset_text_match_request(args).map(boxed),
+        "pcli2_asset_metadata_create" => asset_metadata_create_request(args).map(boxed),
+        "pcli2_asset_metadata_delete" => asset_metadata_delete_request(args).map(boxed),
+        _ => return None,
+    };
+    Some(request)
+}
 
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    if args
-        .get("metadata")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        cmd_args.push("--metadata".to_string());
-    }
-    if args
-        .get("headers")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        cmd_args.push("--headers".to_string());
-    }
-    if args
-        .get("pretty")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        cmd_args.push("--pretty".to_string());
-    }
-    if let Some(format) = args.get("format").and_then(|v| v.as_str()) {
-        cmd_args.push("-f".to_string());
-        cmd_args.push(format.to_string());
-    }
-    if let Some(folder_uuid) = args.get("folder_uuid").and_then(|v| v.as_str()) {
-        cmd_args.push("--folder-uuid".to_string());
-        cmd_args.push(folder_uuid.to_string());
-    }
-    if let Some(folder_path) = args.get("folder_path").and_then(|v| v.as_str()) {
-        cmd_args.push("--folder-path".to_string());
-        cmd_args.push(folder_path.to_string());
-    }
-    if args
-        .get("reload")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false)
-    {
-        cmd_args.push("--reload".to_string());
-    }
+type BoxedRequest = Box<dyn Pcli2Request + Send + Sync>;
 
-    run_pcli2_command(cmd_args, &format!("pcli2 {} list", resource)).await
+fn boxed<R: Pcli2Request + Send + Sync + 'static>(request: R) -> BoxedRequest {
+    Box::new(request)
 }
 
-async fn run_pcli2_asset_geometric_match(args: Value) -> Result<String, String> {
-    validate_range_f64(&args, "threshold", 0.0, 100.0)?;
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "geometric-match".to_string()];
-
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
+fn list_request(args: &Value) -> Result<List, Pcli2Error> {
+    let resource = match string_arg(args, "resource") {
+        Some(resource) => ListResource::parse(&resource)?,
+        None => ListResource::Folder,
+    };
+    Ok(List {
+        resource,
+        tenant: string_arg(args, "tenant"),
+        folder_uuid: string_arg(args, "folder_uuid"),
+        folder_path: string_arg(args, "folder_path"),
+        reload: bool_arg(args, "reload"),
+        output: output_arg(args)?,
+    })
+}
 
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
+fn tenant_get_request(args: &Value) -> Result<TenantGet, Pcli2Error> {
+    Ok(TenantGet {
+        output: output_arg(args)?,
+    })
+}
 
-    run_pcli2_command(cmd_args, "pcli2 asset geometric-match").await
+fn tenant_use_request(args: &Value) -> Result<TenantUse, Pcli2Error> {
+    let name = string_arg(args, "tenant_name")
+        .or_else(|| string_arg(args, "name"))
+        .ok_or_else(|| {
+            Pcli2Error::InvalidArguments(
+                "Missing required argument: provide 'tenant_name' or 'name'".to_string(),
+            )
+        })?;
+    Ok(TenantUse {
+        name,
+        refresh: bool_arg(args, "refresh"),
+        output: output_arg(args)?,
+    })
 }
 
-pub async fn run_pcli2_tenant_list(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["tenant".to_string(), "list".to_string()];
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 tenant list").await
+fn folder_get_request(args: &Value) -> Result<FolderGet, Pcli2Error> {
+    Ok(FolderGet {
+        folder: folder_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        output: output_arg(args)?,
+    })
 }
 
-pub async fn run_pcli2_version() -> Result<String, String> {
-    let cmd_args: Vec<String> = vec!["--version".to_string()];
-    run_pcli2_command(cmd_args, "pcli2 --version").await
+fn folder_dependencies_request(args: &Value) -> Result<FolderDependencies, Pcli2Error> {
+    let mut request = FolderDependencies::new(parse_string_list(args, "folder_path"))?
+        .progress(bool_arg(args, "progress"));
+    request.tenant = string_arg(args, "tenant");
+    request.output = output_arg(args)?;
+    Ok(request)
 }
 
-async fn run_pcli2_config_get(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["config".to_string(), "get".to_string()];
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 config get").await
+fn folder_match_request(kind: MatchKind, args: &Value) -> Result<FolderMatch, Pcli2Error> {
+    let mut request = FolderMatch::new(kind, parse_string_list(args, "folder_path"))?
+        .exclusive(bool_arg(args, "exclusive"))
+        .progress(bool_arg(args, "progress"));
+    request.tenant = string_arg(args, "tenant");
+    request.threshold = threshold_arg(args)?;
+    request.concurrent = concurrency_arg(args)?;
+    request.output = output_arg(args)?;
+    Ok(request)
 }
 
-async fn run_pcli2_config_get_path(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> =
-        vec!["config".to_string(), "get".to_string(), "path".to_string()];
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 config get path").await
+fn asset_get_request(args: &Value) -> Result<AssetGet, Pcli2Error> {
+    Ok(AssetGet {
+        asset: asset_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        output: output_arg(args)?,
+    })
 }
 
-async fn run_pcli2_config_environment_list(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec![
-        "config".to_string(),
-        "environment".to_string(),
-        "list".to_string(),
-    ];
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 config environment list").await
+fn asset_dependencies_request(args: &Value) -> Result<AssetDependencies, Pcli2Error> {
+    Ok(AssetDependencies {
+        asset: asset_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        output: output_arg(args)?,
+    })
 }
 
-async fn run_pcli2_config_environment_get(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec![
-        "config".to_string(),
-        "environment".to_string(),
-        "get".to_string(),
-    ];
-    push_opt_string(
-        &mut cmd_args,
-        "-n",
-        args.get("name").and_then(|v| v.as_str()),
-    );
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 config environment get").await
-}
-
-async fn run_pcli2_tenant_get(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["tenant".to_string(), "get".to_string()];
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 tenant get").await
-}
-
-async fn run_pcli2_tenant_state(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["tenant".to_string(), "state".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    push_opt_string(
-        &mut cmd_args,
-        "--type",
-        args.get("type").and_then(|v| v.as_str()),
-    );
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 tenant state").await
+fn asset_geometric_match_request(args: &Value) -> Result<AssetGeometricMatch, Pcli2Error> {
+    Ok(AssetGeometricMatch {
+        target: asset_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        threshold: threshold_arg(args)?,
+        output: output_arg(args)?,
+    })
 }
-
-async fn run_pcli2_tenant_use(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["tenant".to_string(), "use".to_string()];
-    let name = args
-        .get("tenant_name")
-        .and_then(|v| v.as_str())
-        .or_else(|| args.get("name").and_then(|v| v.as_str()))
-        .ok_or_else(|| "Missing required argument: provide 'tenant_name' or 'name'".to_string())?;
-    cmd_args.push("--name".to_string());
-    cmd_args.push(name.to_string());
-    push_flag_if(&mut cmd_args, &args, "refresh", "--refresh");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 tenant use").await
-}
-
-async fn run_pcli2_folder_get(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "get".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (folder_uuid, folder_path) = require_folder_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--folder-uuid", folder_uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--folder-path", folder_path.as_deref());
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 folder get").await
-}
-
-async fn run_pcli2_folder_resolve(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "resolve".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let folder_path = args
-        .get("folder_path")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Missing required argument: 'folder_path'".to_string())?;
-    cmd_args.push("--folder-path".to_string());
-    cmd_args.push(folder_path.to_string());
-    run_pcli2_command(cmd_args, "pcli2 folder resolve").await
-}
-
-async fn run_pcli2_folder_dependencies(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "dependencies".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let folder_paths = parse_string_list(&args, "folder_path");
-    if folder_paths.is_empty() {
-        return Err("Missing required argument: 'folder_path'".to_string());
-    }
-    for path in folder_paths {
-        cmd_args.push("--folder-path".to_string());
-        cmd_args.push(path);
-    }
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    push_flag_if(&mut cmd_args, &args, "progress", "--progress");
-    run_pcli2_command(cmd_args, "pcli2 folder dependencies").await
-}
-
-async fn run_pcli2_folder_geometric_match(args: Value) -> Result<String, String> {
-    validate_range_f64(&args, "threshold", 0.0, 100.0)?;
-    validate_range_u64(&args, "concurrent", 1, 10)?;
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "geometric-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let folder_paths = parse_string_list(&args, "folder_path");
-    if folder_paths.is_empty() {
-        return Err("Missing required argument: 'folder_path'".to_string());
-    }
-    for path in folder_paths {
-        cmd_args.push("--folder-path".to_string());
-        cmd_args.push(path);
-    }
-    push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
-    push_flag_if(&mut cmd_args, &args, "exclusive", "--exclusive");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    push_opt_u64(&mut cmd_args, &args, "concurrent", "--concurrent");
-    push_flag_if(&mut cmd_args, &args, "progress", "--progress");
-    run_pcli2_command(cmd_args, "pcli2 folder geometric-match").await
-}
-
-async fn run_pcli2_folder_part_match(args: Value) -> Result<String, String> {
-    validate_range_f64(&args, "threshold", 0.0, 100.0)?;
-    validate_range_u64(&args, "concurrent", 1, 10)?;
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "part-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let folder_paths = parse_string_list(&args, "folder_path");
-    if folder_paths.is_empty() {
-        return Err("Missing required argument: 'folder_path'".to_string());
-    }
-    for path in folder_paths {
-        cmd_args.push("--folder-path".to_string());
-        cmd_args.push(path);
-    }
-    push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
-    push_flag_if(&mut cmd_args, &args, "exclusive", "--exclusive");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    push_opt_u64(&mut cmd_args, &args, "concurrent", "--concurrent");
-    push_flag_if(&mut cmd_args, &args, "progress", "--progress");
-    run_pcli2_command(cmd_args, "pcli2 folder part-match").await
-}
-
-async fn run_pcli2_folder_visual_match(args: Value) -> Result<String, String> {
-    validate_range_u64(&args, "concurrent", 1, 10)?;
-    let mut cmd_args: Vec<String> = vec!["folder".to_string(), "visual-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let folder_paths = parse_string_list(&args, "folder_path");
-    if folder_paths.is_empty() {
-        return Err("Missing required argument: 'folder_path'".to_string());
-    }
-    for path in folder_paths {
-        cmd_args.push("--folder-path".to_string());
-        cmd_args.push(path);
-    }
-    push_flag_if(&mut cmd_args, &args, "exclusive", "--exclusive");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    push_opt_u64(&mut cmd_args, &args, "concurrent", "--concurrent");
-    push_flag_if(&mut cmd_args, &args, "progress", "--progress");
-    run_pcli2_command(cmd_args, "pcli2 folder visual-match").await
-}
-
-async fn run_pcli2_asset_get(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "get".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset get").await
-}
-
-async fn run_pcli2_asset_dependencies(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "dependencies".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset dependencies").await
+
+fn asset_part_match_request(args: &Value) -> Result<AssetPartMatch, Pcli2Error> {
+    Ok(AssetPartMatch {
+        target: asset_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        threshold: threshold_arg(args)?,
+        output: output_arg(args)?,
+    })
 }
 
-async fn run_pcli2_asset_thumbnail(
-    args: Value,
-    thumbnail_cache: Option<&ThumbnailCache>,
-) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "thumbnail".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    let temp_path = temp_thumbnail_path()?;
-    let temp_path_str = temp_path
-        .to_str()
-        .ok_or_else(|| "Failed to build temporary thumbnail path".to_string())?;
-    push_opt_string(&mut cmd_args, "--file", Some(temp_path_str));
-    run_pcli2_command(cmd_args, "pcli2 asset thumbnail").await?;
-
-    let bytes_result =
-        fs::read(&temp_path).map_err(|err| format!("Failed to read thumbnail output: {}", err));
-    let _ = fs::remove_file(&temp_path);
-    let bytes = bytes_result?;
-    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
-        return Err("Thumbnail output was not a valid PNG file.".to_string());
-    }
+fn asset_visual_match_request(args: &Value) -> Result<AssetVisualMatch, Pcli2Error> {
+    Ok(AssetVisualMatch {
+        target: asset_ref_arg(args)?,
+        tenant: string_arg(args, "tenant"),
+        output: output_arg(args)?,
+    })
+}
 
-    // Determine response mode (default to "url" for efficiency)
-    let response_mode = args
-        .get("response_mode")
-        .and_then(|v| v.as_str())
-        .unwrap_or("url");
+fn asset_text_match_request(args: &Value) -> Result<AssetTextMatch, Pcli2Error> {
+    let text = string_arg(args, "text").ok_or_else(|| missing_argument("text"))?;
+    Ok(AssetTextMatch {
+        text,
+        tenant: string_arg(args, "tenant"),
+        fuzzy: bool_arg(args, "fuzzy"),
+        output: output_arg(args)?,
+    })
+}
 
-    match response_mode {
-        "data_url" => {
-            // Return base64-encoded data URI (self-contained, uses more tokens)
-            let encoded = BASE64_STANDARD.encode(bytes);
-            Ok(format!("data:image/png;base64,{}", encoded))
-        }
-        "url" => {
-            // Save to cache and return HTTP URL (efficient for LLM context)
-            if let Some(cache) = thumbnail_cache {
-                let source = uuid.or(path).unwrap_or_else(|| "unknown".to_string());
-                let (_cache_key, url) = cache.save_thumbnail(&source, &bytes)?;
-                Ok(url)
-            } else {
-                // Fallback to data URL if cache is not available
-                let encoded = BASE64_STANDARD.encode(bytes);
-                Ok(format!("data:image/png;base64,{}", encoded))
-            }
-        }
-        _ => {
-            // Unknown mode, default to URL behavior
-            if let Some(cache) = thumbnail_cache {
-                let source = uuid.or(path).unwrap_or_else(|| "unknown".to_string());
-                let (_cache_key, url) = cache.save_thumbnail(&source, &bytes)?;
-                Ok(url)
-            } else {
-                let encoded = BASE64_STANDARD.encode(bytes);
-                Ok(format!("data:image/png;base64,{}", encoded))
-            }
-        }
+fn asset_metadata_create_request(args: &Value) -> Result<AssetMetadataCreate, Pcli2Error> {
+    let asset = asset_ref_arg(args)?;
+    let name = string_arg(args, "name").ok_or_else(|| missing_argument("name"))?;
+    let value = string_arg(args, "value").ok_or_else(|| missing_argument("value"))?;
+    let mut request = AssetMetadataCreate::new(asset, name, value);
+    request.tenant = string_arg(args, "tenant");
+    if let Some(value_type) = string_arg(args, "type") {
+        request.value_type = Some(MetadataType::parse(&value_type)?);
     }
+    Ok(request)
 }
 
-async fn run_pcli2_asset_reprocess(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "reprocess".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    run_pcli2_command(cmd_args, "pcli2 asset reprocess").await
-}
-
-async fn run_pcli2_asset_part_match(args: Value) -> Result<String, String> {
-    validate_range_f64(&args, "threshold", 0.0, 100.0)?;
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "part-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset part-match").await
-}
-
-async fn run_pcli2_asset_visual_match(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "visual-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset visual-match").await
-}
-
-async fn run_pcli2_asset_text_match(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec!["asset".to_string(), "text-match".to_string()];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let text = args
-        .get("text")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Missing required argument: 'text'".to_string())?;
-    cmd_args.push("--text".to_string());
-    cmd_args.push(text.to_string());
-    push_flag_if(&mut cmd_args, &args, "fuzzy", "--fuzzy");
-    push_flag_if(&mut cmd_args, &args, "headers", "--headers");
-    push_flag_if(&mut cmd_args, &args, "metadata", "--metadata");
-    push_flag_if(&mut cmd_args, &args, "pretty", "--pretty");
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset text-match").await
-}
-
-async fn run_pcli2_asset_metadata_create(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec![
-        "asset".to_string(),
-        "metadata".to_string(),
-        "create".to_string(),
-    ];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
-
-    let name = args
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Missing required argument: 'name'".to_string())?;
-    let value = args
-        .get("value")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Missing required argument: 'value'".to_string())?;
-    cmd_args.push("--name".to_string());
-    cmd_args.push(name.to_string());
-    cmd_args.push("--value".to_string());
-    cmd_args.push(value.to_string());
-    push_opt_string(
-        &mut cmd_args,
-        "--type",
-        args.get("type").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset metadata create").await
-}
-
-async fn run_pcli2_asset_metadata_delete(args: Value) -> Result<String, String> {
-    let mut cmd_args: Vec<String> = vec![
-        "asset".to_string(),
-        "metadata".to_string(),
-        "delete".to_string(),
-    ];
-    if let Some(tenant) = args.get("tenant").and_then(|v| v.as_str()) {
-        cmd_args.push("-t".to_string());
-        cmd_args.push(tenant.to_string());
-    }
-    let (uuid, path) = require_uuid_or_path(&args)?;
-    push_opt_string(&mut cmd_args, "--uuid", uuid.as_deref());
-    push_opt_string(&mut cmd_args, "--path", path.as_deref());
+fn asset_metadata_delete_request(args: &Value) -> Result<AssetMetadataDelete, Pcli2Error> {
+    let asset = asset_ref_arg(args)?;
     let names: Vec<String> = match args.get("name") {
         Some(Value::Array(values)) => values
             .iter()
@@ -1420,19 +1776,927 @@ async fn run_pcli2_asset_metadata_delete(args: Value) -> Result<String, String>
             .collect(),
         _ => Vec::new(),
     };
-    if names.is_empty() {
-        return Err("Missing required argument: 'name'".to_string());
+    let mut request = AssetMetadataDelete::new(asset, names)?;
+    request.tenant = string_arg(args, "tenant");
+    request.output = output_arg(args)?;
+    Ok(request)
+}
+
+pub async fn run_pcli2_tenant_list(
+    client: &Pcli2Client,
+    args: Value,
+) -> Result<String, Pcli2Error> {
+    let request = TenantList {
+        output: output_arg(&args)?,
+    };
+    client.run(&request).await
+}
+
+pub async fn run_pcli2_version(client: &Pcli2Client) -> Result<String, Pcli2Error> {
+    client.run(&Version).await
+}
+
+async fn run_pcli2_asset_thumbnail(
+    state: &AppState,
+    args: Value,
+    thumbnail_cache: Option<&Arc<ThumbnailCache>>,
+) -> Result<String, Pcli2Error> {
+    let asset = asset_ref_arg(&args)?;
+    let tenant = string_arg(&args, "tenant");
+    let variant = VariantSpec::from_args(&args).map_err(Pcli2Error::InvalidArguments)?;
+
+    // Determine response mode (default to "url" for efficiency)
+    let data_url = args.get("response_mode").and_then(|v| v.as_str()) == Some("data_url");
+    let encode = |bytes: &[u8]| {
+        format!(
+            "data:{};base64,{}",
+            variant.format.mime_type(),
+            BASE64_STANDARD.encode(bytes)
+        )
+    };
+
+    let Some(cache) = thumbnail_cache else {
+        // Fallback to data URL if cache is not available
+        let mut request = AssetThumbnail::new(asset);
+        request.tenant = tenant;
+        let bytes = state.client().thumbnail(&request).await?;
+        let bytes = if variant.is_original() {
+            bytes
+        } else {
+            let spec = variant.clone();
+            tokio::task::spawn_blocking(move || spec.render(&bytes))
+                .await
+                .map_err(|err| Pcli2Error::Failed(format!("Thumbnail task failed: {}", err)))?
+                .map_err(Pcli2Error::Failed)?
+        };
+        return Ok(encode(&bytes));
+    };
+
+    // Thumbnails are cached per tenant and asset UUID, so paths are resolved first
+    let (uuid, source) = match asset {
+        AssetRef::Uuid(uuid) => (uuid.clone(), uuid),
+        AssetRef::Path(path) => (
+            resolve_asset_uuid(state, tenant.as_deref(), &path).await?,
+            path,
+        ),
+    };
+    let cache_key = cache.asset_key(tenant.as_deref(), &uuid);
+    let cached = if bool_arg(&args, "refresh") {
+        false
+    } else {
+        let key = cache_key.clone();
+        cache
+            .blocking(move |cache| Ok(cache.lookup(&key).is_some()))
+            .await
+            .map_err(Pcli2Error::Failed)?
+    };
+    if !cached {
+        let mut request = AssetThumbnail::new(AssetRef::Uuid(uuid));
+        request.tenant = tenant;
+        let bytes = state.client().thumbnail(&request).await?;
+        let key = cache_key.clone();
+        cache
+            .blocking(move |cache| cache.save_thumbnail_as(&key, &source, &bytes))
+            .await
+            .map_err(Pcli2Error::Failed)?;
     }
-    for name in names {
-        cmd_args.push("--name".to_string());
-        cmd_args.push(name);
+
+    // Variants are rendered now, so fetching the URL does not wait for it;
+    // the lookup above already counted this request
+    let bytes = if data_url || !variant.is_original() {
+        let (key, spec) = (cache_key.clone(), variant.clone());
+        cache
+            .blocking(move |cache| cache.peek_variant(&key, &spec))
+            .await
+            .map_err(Pcli2Error::Failed)?
+    } else {
+        Vec::new()
+    };
+    Ok(if data_url {
+        encode(&bytes)
+    } else {
+        cache.variant_url(&cache_key, &variant)
+    })
+}
+
+/// UUID of the asset at `path`, from the result cache or `pcli2 asset get`
+async fn resolve_asset_uuid(
+    state: &AppState,
+    tenant: Option<&str>,
+    path: &str,
+) -> Result<String, Pcli2Error> {
+    let tool = "pcli2_asset_get";
+    let mut args = json!({ "path": path, "format": "json" });
+    if let Some(tenant) = tenant {
+        args["tenant"] = json!(tenant);
+    }
+    let (argv, scope) = cacheable_invocation(tool, &args)
+        .ok_or_else(|| Pcli2Error::InvalidArguments(format!("Invalid asset path '{}'", path)))?;
+    let output = match state.results.get(tool, &argv, tenant) {
+        Some(output) => output,
+        None => {
+            let output = state
+                .client()
+                .run_argv(argv.clone(), "pcli2 asset get")
+                .await?;
+            state.results.put(tool, &argv, tenant, scope, &output);
+            output
+        }
+    };
+    asset_uuid(&output).ok_or_else(|| {
+        Pcli2Error::Failed(format!("pcli2 asset get returned no UUID for '{}'", path))
+    })
+}
+
+/// The `uuid` field of `pcli2 asset get` JSON output
+fn asset_uuid(output: &str) -> Option<String> {
+    let document: Value = serde_json::from_str(output).ok()?;
+    let asset = match &document {
+        Value::Array(assets) => assets.first()?,
+        other => other,
+    };
+    value_uuid(asset)
+}
+
+/// The UUID field of one asset in pcli2 JSON output
+fn value_uuid(asset: &Value) -> Option<String> {
+    let mut fields = Vec::new();
+    flatten("", asset, &mut fields);
+    let uuid = |exact: bool| {
+        fields.iter().find_map(|(key, value)| {
+            let key = normalize(key);
+            let matches = if exact {
+                key == "uuid"
+            } else {
+                key.ends_with("uuid")
+            };
+            matches.then(|| value.as_str()).flatten()
+        })
+    };
+    uuid(true).or_else(|| uuid(false)).map(str::to_string)
+}
+
+/// Time between `pcli2 tenant state` polls while waiting for uploads to be indexed
+const INDEXING_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Part of the call's timeout kept back from waiting, so the upload result is still returned
+const INDEXING_WAIT_GRACE: Duration = Duration::from_secs(10);
+
+/// A base64 upload written to a temporary directory, removed on drop
+struct StagedUpload {
+    dir: PathBuf,
+    file: PathBuf,
+}
+
+impl StagedUpload {
+    fn write(file_name: &str, data: &[u8]) -> Result<Self, Pcli2Error> {
+        let dir = temp_upload_dir()?;
+        let staged = Self {
+            file: dir.join(file_name),
+            dir,
+        };
+        fs::write(&staged.file, data).map_err(|err| {
+            Pcli2Error::Failed(format!("Failed to stage upload {:?}: {}", staged.file, err))
+        })?;
+        Ok(staged)
     }
-    push_opt_string(
-        &mut cmd_args,
-        "-f",
-        args.get("format").and_then(|v| v.as_str()),
-    );
-    run_pcli2_command(cmd_args, "pcli2 asset metadata delete").await
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Upload one file given as a server-local path or base64 contents
+async fn run_asset_create(
+    name: &str,
+    args: Value,
+    deadline: Instant,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    let folder_path =
+        string_arg(&args, "folder_path").ok_or_else(|| missing_argument("folder_path"))?;
+    let (file, _staged) = match (
+        string_arg(&args, "file_path"),
+        string_arg(&args, "file_base64"),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(Pcli2Error::InvalidArguments(
+                "Provide either 'file_path' or 'file_base64', not both".to_string(),
+            )
+            .into());
+        }
+        (Some(path), None) => (resolve_upload_path(state, &path)?, None),
+        (None, Some(encoded)) => {
+            let file_name =
+                string_arg(&args, "file_name").ok_or_else(|| missing_argument("file_name"))?;
+            validate_file_name(&file_name)?;
+            let data = BASE64_STANDARD.decode(encoded.trim()).map_err(|err| {
+                Pcli2Error::InvalidArguments(format!("Invalid argument 'file_base64': {}", err))
+            })?;
+            let staged = StagedUpload::write(&file_name, &data)?;
+            (staged.file.clone(), Some(staged))
+        }
+        (None, None) => {
+            return Err(Pcli2Error::InvalidArguments(
+                "Missing required argument: provide either 'file_path' or 'file_base64'"
+                    .to_string(),
+            )
+            .into());
+        }
+    };
+    let mut request =
+        AssetCreate::new(file.to_string_lossy(), folder_path).format(OutputFormat::Json);
+    request.tenant = string_arg(&args, "tenant");
+    finish_upload(name, &request, &args, deadline, state).await
+}
+
+/// Upload the files matching `pattern` in a server-local directory
+async fn run_asset_create_batch(
+    name: &str,
+    args: Value,
+    deadline: Instant,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    let folder_path =
+        string_arg(&args, "folder_path").ok_or_else(|| missing_argument("folder_path"))?;
+    let directory = string_arg(&args, "directory").ok_or_else(|| missing_argument("directory"))?;
+    let directory = resolve_upload_path(state, &directory)?;
+    if !directory.is_dir() {
+        return Err(Pcli2Error::InvalidArguments(format!(
+            "'{}' is not a directory",
+            directory.display()
+        ))
+        .into());
+    }
+    let pattern = string_arg(&args, "pattern").unwrap_or_else(|| "*".to_string());
+    if pattern.is_empty() || pattern.contains(['/', '\\']) || pattern.contains("..") {
+        return Err(Pcli2Error::InvalidArguments(format!(
+            "Invalid argument 'pattern': '{}' must match file names in 'directory'",
+            pattern
+        ))
+        .into());
+    }
+    check_links_stay_in_root(state, &directory)?;
+
+    let files = directory.join(&pattern);
+    let mut request =
+        AssetCreateBatch::new(files.to_string_lossy(), folder_path).format(OutputFormat::Json);
+    request.tenant = string_arg(&args, "tenant");
+    request.concurrent = concurrency_arg(&args)?;
+    finish_upload(name, &request, &args, deadline, state).await
+}
+
+/// Run an upload and, with `wait`, poll until its assets are indexed
+///
+/// The upload and each poll take their own limiter slot, so none is held
+/// while sleeping. Waiting stops short of `deadline`, the end of the call's
+/// timeout, so the upload result is still returned.
+async fn finish_upload<R: Pcli2Request + Sync>(
+    name: &str,
+    request: &R,
+    args: &Value,
+    deadline: Instant,
+    state: &AppState,
+) -> Result<Value, ToolError> {
+    let started = Instant::now();
+    let tenant = string_arg(args, "tenant");
+    let output = {
+        let _permit = state
+            .limiter
+            .acquire(name, tenant.as_deref())
+            .await
+            .map_err(ToolError::server_busy)?;
+        state.client().run(request).await
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => return run_simple_tool(&request.label(), Err(error)),
+    };
+    if !bool_arg(args, "wait") {
+        return Ok(text_result(output));
+    }
+
+    let document: Value = serde_json::from_str(&output).unwrap_or(Value::String(output));
+    let uuids: Vec<String> = match &document {
+        Value::Array(assets) => assets.iter().filter_map(value_uuid).collect(),
+        asset => value_uuid(asset).into_iter().collect(),
+    };
+    let deadline = deadline.checked_sub(INDEXING_WAIT_GRACE).unwrap_or(started);
+    let indexing = wait_for_indexing(name, state, tenant, &uuids, deadline)
+        .await
+        .map_err(|mut error| {
+            error.message = format!(
+                "Upload succeeded but polling pcli2 tenant state failed: {}",
+                error.message
+            );
+            error
+        })?;
+    Ok(json_text_result(&json!({
+        "assets": document,
+        "indexing": indexing,
+        "waited_seconds": started.elapsed().as_secs(),
+    })))
+}
+
+/// Poll `pcli2 tenant state` until all of `uuids` have indexed, or `deadline`
+///
+/// An asset counts as indexed once it has been listed as indexing and is no
+/// longer listed; one that was never listed may not have been queued yet.
+/// Returns `finished`, `failed` when pcli2 lists one of them as failed, or
+/// `pending` when the deadline passed first. Without UUIDs there is nothing
+/// to track, so it returns `unknown` without polling.
+async fn wait_for_indexing(
+    name: &str,
+    state: &AppState,
+    tenant: Option<String>,
+    uuids: &[String],
+    deadline: Instant,
+) -> Result<&'static str, ToolError> {
+    if uuids.is_empty() {
+        return Ok("unknown");
+    }
+    let poll = |state_type: &'static str| {
+        let mut request = TenantState::default()
+            .state_type(state_type)
+            .format(OutputFormat::Json);
+        request.tenant = tenant.clone();
+        async move {
+            let _permit = state
+                .limiter
+                .acquire(name, request.tenant.as_deref())
+                .await
+                .map_err(ToolError::server_busy)?;
+            let output = state.client().run(&request).await?;
+            Ok::<_, ToolError>(listed_assets(&output, uuids))
+        }
+    };
+    let tracked: HashSet<&str> = uuids.iter().map(String::as_str).collect();
+    let mut seen = HashSet::new();
+    loop {
+        let indexing = poll("indexing").await?;
+        let idle = indexing.is_empty();
+        seen.extend(indexing);
+        if idle {
+            if !poll("failed").await?.is_empty() {
+                return Ok("failed");
+            }
+            if seen == tracked {
+                return Ok("finished");
+            }
+        }
+        if Instant::now() + INDEXING_POLL_INTERVAL > deadline {
+            return Ok("pending");
+        }
+        tokio::time::sleep(INDEXING_POLL_INTERVAL).await;
+    }
+}
+
+/// Those of `uuids` that `tenant state` output lists
+fn listed_assets<'a>(output: &str, uuids: &'a [String]) -> HashSet<&'a str> {
+    uuids
+        .iter()
+        .map(String::as_str)
+        .filter(|uuid| output.contains(uuid))
+        .collect()
+}
+
+/// Resolve a server-local upload path, refusing anything outside the upload root
+///
+/// Symlinks and `..` are resolved before the check, so neither can lead out
+/// of the root.
+fn resolve_upload_path(state: &AppState, path: &str) -> Result<PathBuf, Pcli2Error> {
+    let root = state.upload_root.as_ref().ok_or_else(|| {
+        Pcli2Error::InvalidArguments(
+            "Uploading server-local files is disabled; start the server with --upload-root"
+                .to_string(),
+        )
+    })?;
+    let resolved = root.join(path).canonicalize().map_err(|err| {
+        Pcli2Error::InvalidArguments(format!("Cannot read upload path '{}': {}", path, err))
+    })?;
+    if !resolved.starts_with(root) {
+        return Err(Pcli2Error::InvalidArguments(format!(
+            "Upload path '{}' is outside the upload root",
+            path
+        )));
+    }
+    Ok(resolved)
+}
+
+/// Refuse a batch directory whose entries link outside the upload root
+fn check_links_stay_in_root(state: &AppState, directory: &Path) -> Result<(), Pcli2Error> {
+    let entries = fs::read_dir(directory).map_err(|err| {
+        Pcli2Error::InvalidArguments(format!(
+            "Cannot read directory '{}': {}",
+            directory.display(),
+            err
+        ))
+    })?;
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|kind| kind.is_symlink()) {
+            let target = entry.path();
+            resolve_upload_path(state, &target.to_string_lossy())?;
+        }
+    }
+    Ok(())
+}
+
+/// A file name for a staged upload, without any directory part
+fn validate_file_name(file_name: &str) -> Result<(), Pcli2Error> {
+    if file_name.is_empty()
+        || file_name == "."
+        || file_name == ".."
+        || file_name.contains(['/', '\\', '\0'])
+    {
+        return Err(Pcli2Error::InvalidArguments(format!(
+            "Invalid argument 'file_name': '{}' must be a plain file name",
+            file_name
+        )));
+    }
+    Ok(())
+}
+
+/// Run the requested match, keep the best `top_k` candidates and attach thumbnails
+async fn run_visual_match_report(
+    args: Value,
+    state: &AppState,
+    thumbnail_cache: Option<&Arc<ThumbnailCache>>,
+) -> Result<Value, ToolError> {
+    let label = "pcli2_visual_match_report";
+    let match_type = string_arg(&args, "match_type").unwrap_or_else(|| "geometric".to_string());
+    let match_tool = match match_type.as_str() {
+        "geometric" => "pcli2_geometric_match",
+        "part" => "pcli2_asset_part_match",
+        "visual" => "pcli2_asset_visual_match",
+        other => {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'match_type': expected geometric, part or visual, got '{}'",
+                other
+            ))
+            .into());
+        }
+    };
+    let markdown = match string_arg(&args, "format").as_deref() {
+        None | Some("markdown") => true,
+        Some("json") => false,
+        Some(other) => {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'format': unsupported format '{}'",
+                other
+            ))
+            .into());
+        }
+    };
+    validate_range_u64(&args, "top_k", 1, MAX_TOP_K as u64)
+        .map_err(Pcli2Error::InvalidArguments)?;
+    let top_k = args
+        .get("top_k")
+        .and_then(|v| v.as_u64())
+        .map_or(DEFAULT_TOP_K, |top_k| top_k as usize);
+    if match_type == "visual" && args.get("threshold").is_some() {
+        return Err(Pcli2Error::InvalidArguments(
+            "'threshold' does not apply to visual matches; use 'min_score' instead".to_string(),
+        )
+        .into());
+    }
+    let reference = asset_ref_arg(&args)?;
+    let tenant = string_arg(&args, "tenant");
+
+    // Best first; the top K are taken after the reference itself is dropped
+    let mut match_args = args.clone();
+    for key in [
+        "limit", "columns", "format", "headers", "metadata", "pretty",
+    ] {
+        if let Some(map) = match_args.as_object_mut() {
+            map.remove(key);
+        }
+    }
+    match_args["sort_by"] = json!("-score");
+    match_args["format"] = json!("json");
+    let filter = MatchFilter::from_args(&match_args)
+        .map_err(Pcli2Error::InvalidArguments)?
+        .unwrap_or_default();
+    let request = match tool_request(match_tool, &match_args) {
+        Some(request) => request?,
+        None => return Err(format!("No request for '{}'", match_tool).into()),
+    };
+
+    let client = state.client();
+    let output = client.run(request.as_ref()).await;
+    let candidates = output.and_then(|output| {
+        filter
+            .apply(&output, false)
+            .and_then(|filtered| match_candidates(&filtered))
+            .map_err(Pcli2Error::Failed)
+    });
+    let mut candidates = match candidates {
+        Ok(candidates) => candidates,
+        Err(error) => return run_simple_tool(&request.label(), Err(error)),
+    };
+    candidates.retain(|candidate| match &reference {
+        AssetRef::Uuid(uuid) => candidate.uuid.as_ref() != Some(uuid),
+        AssetRef::Path(path) => candidate.path.as_ref() != Some(path),
+    });
+    candidates.truncate(top_k);
+
+    let mut report = MatchReport::new(&match_type, &reference, candidates);
+    let permits = thumbnail_permits(state, label, tenant.as_deref());
+    report
+        .fetch_thumbnails(
+            &client,
+            tenant.as_deref(),
+            thumbnail_cache,
+            permits.len() + 1,
+        )
+        .await;
+    drop(permits);
+    let text = if markdown {
+        Ok(report.to_markdown())
+    } else {
+        serde_json::to_string_pretty(&report)
+            .map_err(|err| Pcli2Error::Failed(format!("Failed to render report: {}", err)))
+    };
+    run_simple_tool(label, text)
+}
+
+/// Limiter slots for parallel thumbnail downloads, beyond the one the call holds
+///
+/// Only slots that are free right now are taken, so the fan-out stays within
+/// the concurrency limits without queueing behind calls that wait for it.
+fn thumbnail_permits(state: &AppState, tool: &str, tenant: Option<&str>) -> Vec<Pcli2Permit> {
+    (1..THUMBNAIL_CONCURRENCY)
+        .map_while(|_| state.limiter.try_acquire(tool, tenant))
+        .collect()
+}
+
+/// Fetch the thumbnails of the requested assets and compose them into one sheet
+async fn run_contact_sheet(
+    args: Value,
+    state: &AppState,
+    thumbnail_cache: Option<&Arc<ThumbnailCache>>,
+) -> Result<Value, ToolError> {
+    let label = "pcli2_contact_sheet";
+    for (key, min, max) in [
+        ("top_k", 1, MAX_SHEET_TILES as u64),
+        ("grid_columns", 1, MAX_SHEET_COLUMNS as u64),
+        ("tile_size", MIN_TILE_SIZE as u64, MAX_TILE_SIZE as u64),
+    ] {
+        validate_range_u64(&args, key, min, max).map_err(Pcli2Error::InvalidArguments)?;
+    }
+    let as_image = match string_arg(&args, "response_mode").as_deref() {
+        None | Some("url") => false,
+        Some("image") => true,
+        Some(other) => {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Invalid argument 'response_mode': expected url or image, got '{}'",
+                other
+            ))
+            .into());
+        }
+    };
+    let mut entries = contact_sheet_entries(&args)?;
+    let tenant = string_arg(&args, "tenant");
+
+    // Thumbnails cached under an asset's stable key are reused
+    let cache_key = |asset: &AssetRef| match (asset, thumbnail_cache) {
+        (AssetRef::Uuid(uuid), Some(cache)) => Some(cache.asset_key(tenant.as_deref(), uuid)),
+        _ => None,
+    };
+    let keys: Vec<Option<String>> = entries
+        .iter()
+        .map(|(asset, _, _)| asset.as_ref().and_then(&cache_key))
+        .collect();
+    let mut cached = match thumbnail_cache {
+        Some(cache) => cache
+            .blocking(move |cache| {
+                Ok(keys
+                    .iter()
+                    .map(|key| key.as_ref().and_then(|key| cache.load_thumbnail(key).ok()))
+                    .collect())
+            })
+            .await
+            .unwrap_or_default(),
+        None => Vec::new(),
+    }
+    .into_iter();
+    let mut requests = Vec::new();
+    for (asset, tile, error) in &mut entries {
+        let cached = cached.next().flatten();
+        let Some(asset) = asset else {
+            *error = Some("no asset UUID or path".to_string());
+            continue;
+        };
+        if let Some(png) = cached {
+            tile.png = Some(png);
+            continue;
+        }
+        let mut request = AssetThumbnail::new(asset.clone());
+        request.tenant = tenant.clone();
+        requests.push(request);
+    }
+    let downloaded = requests
+        .iter()
+        .map(|request| request.asset.clone())
+        .collect::<Vec<_>>();
+    let permits = thumbnail_permits(state, label, tenant.as_deref());
+    let mut results = state
+        .client()
+        .thumbnails(requests, permits.len() + 1)
+        .await
+        .into_iter()
+        .zip(downloaded);
+    drop(permits);
+    let mut to_cache = Vec::new();
+    for (asset, tile, error) in &mut entries {
+        if asset.is_none() || tile.png.is_some() {
+            continue;
+        }
+        match results.next() {
+            Some((Ok(png), asset)) => {
+                if let (Some(key), AssetRef::Uuid(uuid)) = (cache_key(&asset), &asset) {
+                    to_cache.push((key, uuid.clone(), png.clone()));
+                }
+                tile.png = Some(png);
+            }
+            Some((Err(err), _)) => *error = Some(err.to_string()),
+            None => {}
+        }
+    }
+    if let Some(cache) = thumbnail_cache.filter(|_| !to_cache.is_empty()) {
+        // Caching is an optimisation; the sheet is built either way
+        let _ = cache
+            .blocking(move |cache| {
+                for (key, uuid, png) in &to_cache {
+                    let _ = cache.save_thumbnail_as(key, uuid, png);
+                }
+                Ok(())
+            })
+            .await;
+    }
+
+    let tiles: Vec<SheetTile> = entries.iter().map(|(_, tile, _)| tile.clone()).collect();
+    let columns = args
+        .get("grid_columns")
+        .and_then(|v| v.as_u64())
+        .map(|columns| columns as usize);
+    let tile_size = args
+        .get("tile_size")
+        .and_then(|v| v.as_u64())
+        .map_or(DEFAULT_TILE_SIZE, |size| size as u32);
+    let sheet = tokio::task::spawn_blocking(move || compose(&tiles, columns, tile_size))
+        .await
+        .map_err(|err| Pcli2Error::Failed(format!("Contact sheet task failed: {}", err)))?
+        .map_err(Pcli2Error::Failed)?;
+
+    let legend = entries
+        .iter()
+        .enumerate()
+        .map(|(index, (_, tile, error))| {
+            let mut line = format!("{}. {}", index + 1, tile.label);
+            if let Some(caption) = &tile.caption {
+                line.push_str(&format!(" ({})", caption));
+            }
+            if let Some(error) = error {
+                line.push_str(&format!(" - thumbnail unavailable: {}", error));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    match thumbnail_cache.filter(|_| !as_image) {
+        Some(cache) => {
+            let (_cache_key, url) = cache
+                .blocking(move |cache| cache.save_thumbnail("contact-sheet", &sheet))
+                .await
+                .map_err(Pcli2Error::Failed)?;
+            run_simple_tool(
+                label,
+                Ok(format!("![Contact sheet]({})\n\n{}", url, legend)),
+            )
+        }
+        None => Ok(json!({
+            "content": [
+                {
+                    "type": "image",
+                    "data": BASE64_STANDARD.encode(&sheet),
+                    "mimeType": "image/png"
+                },
+                {
+                    "type": "text",
+                    "text": legend
+                }
+            ]
+        })),
+    }
+}
+
+/// An asset on a contact sheet, its tile and why its thumbnail is missing
+type SheetEntry = (Option<AssetRef>, SheetTile, Option<String>);
+
+/// Assets for a contact sheet with their tiles, from `assets` or `match_result`
+fn contact_sheet_entries(args: &Value) -> Result<Vec<SheetEntry>, Pcli2Error> {
+    let invalid = |message: &str| Pcli2Error::InvalidArguments(message.to_string());
+    let entry =
+        |uuid: Option<&str>, path: Option<&str>, label: Option<&str>, score: Option<f64>| {
+            let asset = match (uuid, path) {
+                (Some(uuid), _) => Some(AssetRef::uuid(uuid)),
+                (None, Some(path)) => Some(AssetRef::path(path)),
+                (None, None) => None,
+            };
+            let tile = SheetTile {
+                label: label.or(path).or(uuid).unwrap_or("unknown").to_string(),
+                caption: score.map(|score| format!("score {}", score)),
+                png: None,
+            };
+            (asset, tile, None)
+        };
+
+    match (args.get("assets"), args.get("match_result")) {
+        (Some(_), Some(_)) => Err(invalid(
+            "Provide either 'assets' or 'match_result', not both",
+        )),
+        (Some(Value::Array(assets)), None) => {
+            if assets.is_empty() || assets.len() > MAX_SHEET_TILES {
+                return Err(Pcli2Error::InvalidArguments(format!(
+                    "'assets' must list between 1 and {} assets",
+                    MAX_SHEET_TILES
+                )));
+            }
+            assets
+                .iter()
+                .map(|asset| match asset {
+                    Value::String(id) if id.starts_with('/') => {
+                        Ok(entry(None, Some(id), None, None))
+                    }
+                    Value::String(id) => Ok(entry(Some(id), None, None, None)),
+                    Value::Object(map) => {
+                        let text = |key: &str| map.get(key).and_then(Value::as_str);
+                        Ok(entry(
+                            text("uuid"),
+                            text("path"),
+                            text("label"),
+                            map.get("score").and_then(Value::as_f64),
+                        ))
+                    }
+                    _ => Err(invalid(
+                        "'assets' entries must be UUIDs, paths or objects with a uuid or path",
+                    )),
+                })
+                .collect()
+        }
+        (Some(_), None) => Err(invalid("'assets' must be an array")),
+        (None, Some(result)) => {
+            let output = match result {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            let mut candidates = match_candidates(&output).map_err(Pcli2Error::InvalidArguments)?;
+            candidates.sort_by(|a, b| match (a.score, b.score) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            });
+            let top_k = args
+                .get("top_k")
+                .and_then(|v| v.as_u64())
+                .map_or(DEFAULT_SHEET_TOP_K, |top_k| top_k as usize);
+            candidates.truncate(top_k);
+            if candidates.is_empty() {
+                return Err(invalid("'match_result' has no matches"));
+            }
+            Ok(candidates
+                .iter()
+                .map(|candidate| {
+                    entry(
+                        candidate.uuid.as_deref(),
+                        candidate.path.as_deref(),
+                        None,
+                        candidate.score,
+                    )
+                })
+                .collect())
+        }
+        (None, None) => Err(invalid(
+            "Missing required argument: provide 'assets' or 'match_result'",
+        )),
+    }
+}
+
+/// Rendering for the `markdown` formats, asking pcli2 for the output it renders from
+fn markdown_arg(args: &mut Value) -> Result<Option<MarkdownRender>, Pcli2Error> {
+    let render = match string_arg(args, "format").as_deref() {
+        Some("markdown") => {
+            validate_range_u64(args, "max_cell_width", 4, u64::MAX)
+                .map_err(Pcli2Error::InvalidArguments)?;
+            let columns: Vec<String> = parse_string_list(args, "columns")
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|column| !column.is_empty())
+                .map(str::to_string)
+                .collect();
+            let max_cell_width = args
+                .get("max_cell_width")
+                .and_then(|v| v.as_u64())
+                .map_or(DEFAULT_MAX_CELL_WIDTH, |width| width as usize);
+            args["format"] = json!("json");
+            MarkdownRender::Table {
+                columns: (!columns.is_empty()).then_some(columns),
+                max_cell_width,
+            }
+        }
+        Some("markdown_tree") => {
+            args["format"] = json!("tree");
+            MarkdownRender::Tree
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(render))
+}
+
+/// Post-processing for a match tool call, switching pcli2 to JSON output when needed
+fn match_filter_arg(name: &str, args: &mut Value) -> Result<Option<MatchFilter>, Pcli2Error> {
+    if !MATCH_TOOLS.contains(&name) {
+        return Ok(None);
+    }
+    let Some(filter) = MatchFilter::from_args(args)? else {
+        return Ok(None);
+    };
+    match string_arg(args, "format").as_deref() {
+        None | Some("json") => {}
+        Some(format) => {
+            return Err(Pcli2Error::InvalidArguments(format!(
+                "Post-processing arguments need JSON output, but format is '{}'",
+                format
+            )));
+        }
+    }
+    args["format"] = json!("json");
+    Ok(Some(filter))
+}
+
+fn string_arg(args: &Value, key: &str) -> Option<String> {
+    args.get(key).and_then(|v| v.as_str()).map(str::to_string)
+}
+
+fn bool_arg(args: &Value, key: &str) -> bool {
+    args.get(key).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+fn missing_argument(key: &str) -> Pcli2Error {
+    Pcli2Error::InvalidArguments(format!("Missing required argument: '{}'", key))
+}
+
+fn output_arg(args: &Value) -> Result<Output, Pcli2Error> {
+    let format = match string_arg(args, "format") {
+        Some(format) => Some(OutputFormat::parse(&format)?),
+        None => None,
+    };
+    Ok(Output {
+        headers: bool_arg(args, "headers"),
+        metadata: bool_arg(args, "metadata"),
+        pretty: bool_arg(args, "pretty"),
+        format,
+    })
+}
+
+/// The asset named by exactly one of `uuid` and `path`
+fn asset_ref_arg(args: &Value) -> Result<AssetRef, Pcli2Error> {
+    match require_uuid_or_path(args)? {
+        (Some(_), Some(_)) => Err(Pcli2Error::InvalidArguments(
+            "Provide either 'uuid' or 'path', not both".to_string(),
+        )),
+        (Some(uuid), None) => Ok(AssetRef::Uuid(uuid)),
+        (None, path) => Ok(AssetRef::Path(path.unwrap_or_default())),
+    }
+}
+
+/// The folder named by exactly one of `folder_uuid` and `folder_path`
+fn folder_ref_arg(args: &Value) -> Result<FolderRef, Pcli2Error> {
+    match require_folder_uuid_or_path(args)? {
+        (Some(_), Some(_)) => Err(Pcli2Error::InvalidArguments(
+            "Provide either 'folder_uuid' or 'folder_path', not both".to_string(),
+        )),
+        (Some(uuid), None) => Ok(FolderRef::Uuid(uuid)),
+        (None, path) => Ok(FolderRef::Path(path.unwrap_or_default())),
+    }
+}
+
+fn threshold_arg(args: &Value) -> Result<Option<Threshold>, Pcli2Error> {
+    args.get("threshold")
+        .and_then(|v| v.as_f64())
+        .map(Threshold::new)
+        .transpose()
+}
+
+fn concurrency_arg(args: &Value) -> Result<Option<Concurrency>, Pcli2Error> {
+    args.get("concurrent")
+        .and_then(|v| v.as_u64())
+        .map(Concurrency::new)
+        .transpose()
 }
 
 fn parse_string_list(args: &Value, key: &str) -> Vec<String> {
@@ -1446,17 +2710,6 @@ fn parse_string_list(args: &Value, key: &str) -> Vec<String> {
     }
 }
 
-fn temp_thumbnail_path() -> Result<PathBuf, String> {
-    let mut path = env::temp_dir();
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|err| format!("Failed to read system time: {}", err))?
-        .as_millis();
-    let pid = std::process::id();
-    path.push(format!("pcli2-thumbnail-{}-{}.png", pid, timestamp));
-    Ok(path)
-}
-
 fn require_uuid_or_path(args: &Value) -> Result<(Option<String>, Option<String>), String> {
     let uuid = args
         .get("uuid")
@@ -1489,18 +2742,6 @@ fn require_folder_uuid_or_path(args: &Value) -> Result<(Option<String>, Option<S
     Ok((uuid, path))
 }
 
-fn validate_range_f64(args: &Value, key: &str, min: f64, max: f64) -> Result<(), String> {
-    if let Some(value) = args.get(key).and_then(|v| v.as_f64())
-        && (value < min || value > max)
-    {
-        return Err(format!(
-            "Invalid argument '{}': value {} must be between {} and {}",
-            key, value, min, max
-        ));
-    }
-    Ok(())
-}
-
 fn validate_range_u64(args: &Value, key: &str, min: u64, max: u64) -> Result<(), String> {
     if let Some(value) = args.get(key).and_then(|v| v.as_u64())
         && (value < min || value > max)
@@ -1513,32 +2754,6 @@ fn validate_range_u64(args: &Value, key: &str, min: u64, max: u64) -> Result<(),
     Ok(())
 }
 
-fn push_flag_if(cmd_args: &mut Vec<String>, args: &Value, key: &str, flag: &str) {
-    if args.get(key).and_then(|v| v.as_bool()).unwrap_or(false) {
-        cmd_args.push(flag.to_string());
-    }
-}
-
-fn push_opt_string(cmd_args: &mut Vec<String>, flag: &str, value: Option<&str>) {
-    if let Some(value) = value {
-        cmd_args.push(flag.to_string());
-        cmd_args.push(value.to_string());
-    }
-}
-
-fn push_opt_f64(cmd_args: &mut Vec<String>, args: &Value, key: &str, flag: &str) {
-    if let Some(value) = args.get(key).and_then(|v| v.as_f64()) {
-        cmd_args.push(flag.to_string());
-        cmd_args.push(value.to_string());
-    }
-}
-
-fn push_opt_u64(cmd_args: &mut Vec<String>, args: &Value, key: &str, flag: &str) {
-    if let Some(value) = args.get(key).and_then(|v| v.as_u64()) {
-        cmd_args.push(flag.to_string());
-        cmd_args.push(value.to_string());
-    }
-}
 pub fn shell_escape_arg(arg: &str) -> String {
     let safe = arg
         .chars()
@@ -1563,220 +2778,107 @@ pub fn shell_escape_arg(arg: &str) -> String {
     escaped
 }
 
-pub async fn read_limited<R: AsyncRead + Unpin>(
-    mut reader: R,
-    limit: usize,
-    label: &str,
-) -> Result<Vec<u8>, String> {
-    let mut buf = Vec::new();
-    let mut chunk = [0u8; 8192];
-    loop {
-        let read = reader
-            .read(&mut chunk)
-            .await
-            .map_err(|err| format!("Failed to read pcli2 {}: {}", label, err))?;
-        if read == 0 {
-            break;
-        }
-        if buf.len() + read > limit {
-            return Err(format!(
-                "pcli2 {} exceeded maximum output size of {} bytes",
-                label, limit
-            ));
-        }
-        buf.extend_from_slice(&chunk[..read]);
-    }
-    Ok(buf)
-}
-
-pub async fn run_pcli2_command(cmd_args: Vec<String>, label: &str) -> Result<String, String> {
-    let rendered = cmd_args
-        .iter()
-        .map(|arg| shell_escape_arg(arg))
-        .collect::<Vec<_>>()
-        .join(" ");
-    info!("▶ pcli2 {}", rendered);
-    let mut child = tokio::process::Command::new(pcli2_executable())
-        .args(&cmd_args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to execute pcli2: {}", e))?;
-
-    let stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| "Failed to capture pcli2 stdout".to_string())?;
-    let stderr = child
-        .stderr
-        .take()
-        .ok_or_else(|| "Failed to capture pcli2 stderr".to_string())?;
-
-    let stdout_task = tokio::spawn(read_limited(stdout, MAX_PCLI2_OUTPUT_BYTES, "stdout"));
-    let stderr_task = tokio::spawn(read_limited(stderr, MAX_PCLI2_OUTPUT_BYTES, "stderr"));
-
-    let output = tokio::time::timeout(PCLI2_TIMEOUT, async {
-        let status = child
-            .wait()
-            .await
-            .map_err(|err| format!("Failed waiting for pcli2: {}", err))?;
-        let stdout = stdout_task
-            .await
-            .map_err(|err| format!("Failed to read pcli2 stdout: {}", err))??;
-        let stderr = stderr_task
-            .await
-            .map_err(|err| format!("Failed to read pcli2 stderr: {}", err))??;
-        Ok((status, stdout, stderr))
-    })
-    .await;
-
-    let (status, stdout, stderr) = match output {
-        Ok(Ok(output)) => output,
-        Ok(Err(message)) => {
-            let _ = child.kill().await;
-            return Err(message);
-        }
-        Err(_) => {
-            let _ = child.kill().await;
-            return Err(format!(
-                "{} failed: timed out after {:?}",
-                label, PCLI2_TIMEOUT
-            ));
-        }
-    };
-
-    let stdout = String::from_utf8_lossy(&stdout);
-    let stderr = String::from_utf8_lossy(&stderr);
-
-    if status.success() {
-        Ok(stdout.trim_end().to_string())
-    } else {
-        Err(format!(
-            "{} failed (code {}):\n{}\n{}",
-            label,
-            status,
-            stdout.trim_end(),
-            stderr.trim_end()
-        ))
-    }
-}
-
-pub fn pcli2_executable() -> String {
-    env::var(PCLI2_BIN_ENV).unwrap_or_else(|_| "pcli2".to_string())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
 
     #[test]
-    fn test_shell_escape_arg() {
-        assert_eq!(shell_escape_arg("simple"), "simple");
-        assert_eq!(shell_escape_arg(""), "''");
-        assert_eq!(shell_escape_arg("with space"), "'with space'");
-        assert_eq!(shell_escape_arg("with'quote"), "'with'\"'\"'quote'");
-    }
-
-    #[test]
-    fn test_push_flag_if() {
-        let mut cmd_args = vec![];
-        let args = json!({"flag": true});
-        push_flag_if(&mut cmd_args, &args, "flag", "--flag");
-        assert_eq!(cmd_args, vec!["--flag".to_string()]);
-    }
-
-    #[test]
-    fn test_push_flag_if_false() {
-        let mut cmd_args: Vec<String> = vec![];
-        let args = json!({"flag": false});
-        push_flag_if(&mut cmd_args, &args, "flag", "--flag");
-        let expected: Vec<String> = vec![];
-        assert_eq!(cmd_args, expected);
-    }
-
-    #[test]
-    fn test_push_flag_if_missing() {
-        let mut cmd_args: Vec<String> = vec![];
-        let args = json!({});
-        push_flag_if(&mut cmd_args, &args, "flag", "--flag");
-        let expected: Vec<String> = vec![];
-        assert_eq!(cmd_args, expected);
-    }
-
-    #[test]
-    fn test_push_opt_string_some() {
-        let mut cmd_args: Vec<String> = vec![];
-        push_opt_string(&mut cmd_args, "--opt", Some("value"));
-        assert_eq!(cmd_args, vec!["--opt".to_string(), "value".to_string()]);
-    }
-
-    #[test]
-    fn test_push_opt_string_none() {
-        let mut cmd_args: Vec<String> = vec![];
-        push_opt_string(&mut cmd_args, "--opt", None);
-        let expected: Vec<String> = vec![];
-        assert_eq!(cmd_args, expected);
-    }
-
-    #[test]
-    fn test_push_opt_f64() {
-        let mut cmd_args = vec![];
-        let args = json!({"threshold": 80.5});
-        push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
+    fn test_tool_request_builds_argv() {
+        let args = json!({
+            "tenant": "acme",
+            "uuid": "u1",
+            "threshold": 90,
+            "format": "json"
+        });
+        let request = tool_request("pcli2_asset_part_match", &args)
+            .expect("known tool")
+            .expect("valid args");
+        assert_eq!(request.label(), "pcli2 asset part-match");
         assert_eq!(
-            cmd_args,
-            vec!["--threshold".to_string(), "80.5".to_string()]
+            request.argv(),
+            vec![
+                "asset",
+                "part-match",
+                "-t",
+                "acme",
+                "--uuid",
+                "u1",
+                "--threshold",
+                "90",
+                "-f",
+                "json"
+            ]
         );
+        assert!(tool_request("pcli2_server_status", &json!({})).is_none());
     }
 
     #[test]
-    fn test_push_opt_f64_missing() {
-        let mut cmd_args: Vec<String> = vec![];
-        let args = json!({});
-        push_opt_f64(&mut cmd_args, &args, "threshold", "--threshold");
-        let expected: Vec<String> = vec![];
-        assert_eq!(cmd_args, expected);
-    }
-
-    #[test]
-    fn test_push_opt_u64() {
-        let mut cmd_args = vec![];
-        let args = json!({"count": 5});
-        push_opt_u64(&mut cmd_args, &args, "count", "--count");
-        assert_eq!(cmd_args, vec!["--count".to_string(), "5".to_string()]);
-    }
-
-    #[test]
-    fn test_push_opt_u64_missing() {
-        let mut cmd_args: Vec<String> = vec![];
-        let args = json!({});
-        push_opt_u64(&mut cmd_args, &args, "count", "--count");
-        let expected: Vec<String> = vec![];
-        assert_eq!(cmd_args, expected);
+    fn test_match_tools_share_columns_definition() {
+        let tools = tool_list();
+        let columns = |name: &str| {
+            tools.iter().find(|tool| tool["name"] == name).unwrap()["inputSchema"]["properties"]
+                ["columns"]
+                .clone()
+        };
+        assert_eq!(
+            columns("pcli2_asset_part_match"),
+            columns("pcli2_asset_get")
+        );
     }
 
     #[test]
-    fn test_validate_range_f64_valid() {
-        let args = json!({"threshold": 80.5});
-        let result = validate_range_f64(&args, "threshold", 0.0, 100.0);
-        assert!(result.is_ok());
+    fn test_metadata_flag_only_for_commands_that_accept_it() {
+        let argv = |name: &str, args: Value| {
+            tool_request(name, &args)
+                .expect("known tool")
+                .expect("valid args")
+                .argv()
+        };
+        let metadata = json!({ "uuid": "u1", "metadata": true });
+        assert!(argv("pcli2_asset_get", metadata.clone()).contains(&"--metadata".to_string()));
+        assert!(!argv("pcli2_tenant_list", metadata).contains(&"--metadata".to_string()));
     }
 
     #[test]
-    fn test_validate_range_f64_invalid_low() {
-        let args = json!({"threshold": -1.0});
-        let result = validate_range_f64(&args, "threshold", 0.0, 100.0);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("must be between"));
+    fn test_tool_request_rejects_invalid_args() {
+        let invalid = |name: &str, args: Value| {
+            tool_request(name, &args)
+                .expect("known tool")
+                .err()
+                .expect("invalid args")
+                .code()
+        };
+        assert_eq!(
+            invalid(
+                "pcli2_folder_part_match",
+                json!({ "folder_path": "/Root", "threshold": 120 })
+            ),
+            "invalid_arguments"
+        );
+        assert_eq!(
+            invalid(
+                "pcli2_asset_get",
+                json!({ "path": "/Root/a.stl", "format": "xml" })
+            ),
+            "invalid_arguments"
+        );
+        assert_eq!(invalid("pcli2_asset_get", json!({})), "invalid_arguments");
+        assert_eq!(
+            invalid(
+                "pcli2_asset_get",
+                json!({ "uuid": "u1", "path": "/Root/a.stl" })
+            ),
+            "invalid_arguments"
+        );
     }
 
     #[test]
-    fn test_validate_range_f64_invalid_high() {
-        let args = json!({"threshold": 101.0});
-        let result = validate_range_f64(&args, "threshold", 0.0, 100.0);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("must be between"));
+    fn test_shell_escape_arg() {
+        assert_eq!(shell_escape_arg("simple"), "simple");
+        assert_eq!(shell_escape_arg(""), "''");
+        assert_eq!(shell_escape_arg("with space"), "'with space'");
+        assert_eq!(shell_escape_arg("with'quote"), "'with'\"'\"'quote'");
     }
 
     #[test]
@@ -1880,6 +2982,48 @@ mod tests {
         assert_eq!(result, vec!["single-item"]);
     }
 
+    #[test]
+    fn test_default_tool_timeout_classes() {
+        assert_eq!(default_tool_timeout("pcli2_version"), QUICK_TOOL_TIMEOUT);
+        assert_eq!(
+            default_tool_timeout("pcli2_asset_get"),
+            STANDARD_TOOL_TIMEOUT
+        );
+        assert_eq!(
+            default_tool_timeout("pcli2_folder_visual_match"),
+            BULK_TOOL_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn test_resolve_tool_timeout() {
+        let max = Duration::from_secs(600);
+        let default = resolve_tool_timeout("pcli2_folder_part_match", &json!({}), max).unwrap();
+        assert_eq!(default, max);
+
+        let explicit =
+            resolve_tool_timeout("pcli2_version", &json!({"timeout_seconds": 5}), max).unwrap();
+        assert_eq!(explicit, Duration::from_secs(5));
+
+        let too_long = resolve_tool_timeout("pcli2_version", &json!({"timeout_seconds": 601}), max);
+        assert!(too_long.unwrap_err().contains("must be between"));
+
+        let not_integer =
+            resolve_tool_timeout("pcli2_version", &json!({"timeout_seconds": "10"}), max);
+        assert!(not_integer.is_err());
+    }
+
+    #[test]
+    fn test_tool_list_includes_timeout_seconds() {
+        for tool in tool_list() {
+            assert!(
+                tool["inputSchema"]["properties"]["timeout_seconds"].is_object(),
+                "{} is missing timeout_seconds",
+                tool["name"]
+            );
+        }
+    }
+
     #[test]
     fn test_parse_string_list_empty() {
         let args = json!({});
@@ -1887,4 +3031,71 @@ mod tests {
         let expected: Vec<String> = vec![];
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_upload_paths_stay_in_root() {
+        let base = std::env::temp_dir().join(format!("pcli2-upload-root-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("parts")).unwrap();
+        fs::write(root.join("parts/a.stl"), b"solid").unwrap();
+        fs::write(base.join("secret.txt"), b"secret").unwrap();
+
+        let mut state = AppState::new("test", "0.0.0", None);
+        assert!(resolve_upload_path(&state, "parts/a.stl").is_err());
+        state.upload_root = Some(root.canonicalize().unwrap());
+
+        let resolved = resolve_upload_path(&state, "parts/a.stl").unwrap();
+        assert!(resolved.ends_with("parts/a.stl"));
+        assert!(resolve_upload_path(&state, "../secret.txt").is_err());
+        assert!(resolve_upload_path(&state, &base.join("secret.txt").to_string_lossy()).is_err());
+        assert!(resolve_upload_path(&state, "parts/missing.stl").is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("secret.txt"), root.join("parts/link.stl"))
+                .unwrap();
+            assert!(resolve_upload_path(&state, "parts/link.stl").is_err());
+            assert!(check_links_stay_in_root(&state, &root.join("parts")).is_err());
+        }
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_validate_file_name() {
+        assert!(validate_file_name("bracket.stl").is_ok());
+        for name in ["", ".", "..", "../a.stl", "dir/a.stl", "dir\\a.stl"] {
+            assert!(validate_file_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_listed_assets() {
+        let uuids = vec!["u1".to_string(), "u3".to_string()];
+        let listed = listed_assets(r#"[{"uuid": "u1"}, {"uuid": "u2"}]"#, &uuids);
+        assert_eq!(listed, HashSet::from(["u1"]));
+        assert!(listed_assets(r#"[{"uuid": "u2"}]"#, &uuids).is_empty());
+        assert!(listed_assets(r#"[{"uuid": "u1"}]"#, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_upload_invalidates_target_folder() {
+        let mutation = mutation_for(
+            "pcli2_asset_create",
+            &json!({ "file_path": "parts/a.stl", "folder_path": "/Root/Parts/" }),
+        );
+        assert!(matches!(
+            mutation,
+            Some(Mutation::Asset { tenant: None, uuid: None, path: Some(path) })
+                if path == "/Root/Parts/a.stl"
+        ));
+        let mutation = mutation_for(
+            "pcli2_asset_create_batch",
+            &json!({ "directory": "parts", "folder_path": "/Root/Parts" }),
+        );
+        assert!(matches!(
+            mutation,
+            Some(Mutation::Asset { path: Some(path), .. }) if path == "/Root/Parts/"
+        ));
+    }
 }
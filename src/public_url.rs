@@ -0,0 +1,258 @@
+//! Externally reachable origin for links handed out by the server.
+//!
+//! Thumbnail URLs point back at this server, but the bind address is often
+//! not what a client can reach: `0.0.0.0` is not an address at all, and
+//! behind a reverse proxy the server sees its own port. Links can use a fixed
+//! `--public-url`, or the origin each MCP request was sent to, taken from the
+//! `Forwarded`, `X-Forwarded-*` and `Host` headers.
+
+use http::HeaderMap;
+use serde_json::Value;
+
+/// `--public-url` value that builds links from each request
+pub const PUBLIC_URL_REQUEST: &str = "request";
+
+/// Where links in tool results point
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum PublicUrl {
+    /// The bind host and port, or the proxy's origin when forwarded headers are present
+    #[default]
+    Bind,
+    /// A fixed origin, with an optional path prefix
+    Fixed(String),
+    /// The origin of each MCP request
+    Request,
+}
+
+impl PublicUrl {
+    /// Default for a server bound to `host`
+    ///
+    /// A wildcard bind address is never reachable as such, so links follow
+    /// the request instead.
+    pub fn for_bind_host(host: &str) -> Self {
+        match host {
+            "0.0.0.0" | "::" | "[::]" => Self::Request,
+            _ => Self::Bind,
+        }
+    }
+
+    /// Origin for links in the response to a request with `headers`
+    ///
+    /// `None` keeps the base URL the thumbnail cache was configured with.
+    pub fn origin(&self, headers: &HeaderMap) -> Option<String> {
+        match self {
+            Self::Fixed(_) => None,
+            Self::Bind if !is_forwarded(headers) => None,
+            Self::Bind | Self::Request => request_origin(headers),
+        }
+    }
+}
+
+/// Parse a `--public-url` value: `request` or an `http(s)://` URL
+pub fn parse_public_url(value: &str) -> Result<PublicUrl, String> {
+    if value.eq_ignore_ascii_case(PUBLIC_URL_REQUEST) {
+        return Ok(PublicUrl::Request);
+    }
+    let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))
+        .ok_or_else(|| format!("expected an http(s) URL or '{}'", PUBLIC_URL_REQUEST))?;
+    let host = rest.split('/').next().unwrap_or_default();
+    if !is_valid_host(host) || rest.contains(['?', '#']) {
+        return Err(format!("'{}' is not a valid public URL", value));
+    }
+    Ok(PublicUrl::Fixed(value.trim_end_matches('/').to_string()))
+}
+
+/// Replace `from` with `to` in every string of a tool result
+pub fn rewrite_links(value: &mut Value, from: &str, to: &str) {
+    if from == to {
+        return;
+    }
+    match value {
+        Value::String(text) if text.contains(from) => *text = text.replace(from, to),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| rewrite_links(item, from, to)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|item| rewrite_links(item, from, to)),
+        _ => {}
+    }
+}
+
+fn is_forwarded(headers: &HeaderMap) -> bool {
+    ["forwarded", "x-forwarded-host", "x-forwarded-proto"]
+        .iter()
+        .any(|name| headers.contains_key(*name))
+}
+
+/// Origin from the forwarded headers, falling back to `Host`
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    let (forwarded_host, forwarded_proto) = header(headers, "forwarded")
+        .map(parse_forwarded)
+        .unwrap_or_default();
+    let host = forwarded_host
+        .or_else(|| first_value(headers, "x-forwarded-host"))
+        .or_else(|| header(headers, "host").map(str::to_string))?;
+    let proto = forwarded_proto
+        .or_else(|| first_value(headers, "x-forwarded-proto"))
+        .unwrap_or_else(|| "http".to_string())
+        .to_ascii_lowercase();
+    if !is_valid_host(&host) || !matches!(proto.as_str(), "http" | "https") {
+        return None;
+    }
+
+    let mut origin = format!("{}://{}", proto, host);
+    let default_port = if proto == "https" { "443" } else { "80" };
+    if let Some(port) = first_value(headers, "x-forwarded-port")
+        && !has_port(&host)
+        && port != default_port
+        && port.chars().all(|c| c.is_ascii_digit())
+    {
+        origin.push(':');
+        origin.push_str(&port);
+    }
+    if let Some(prefix) = first_value(headers, "x-forwarded-prefix") {
+        let prefix = prefix.trim_matches('/');
+        if !prefix.is_empty() && prefix.chars().all(is_path_char) {
+            origin.push('/');
+            origin.push_str(prefix);
+        }
+    }
+    Some(origin)
+}
+
+/// `host` and `proto` of the first element of a `Forwarded` header (RFC 7239)
+fn parse_forwarded(value: &str) -> (Option<String>, Option<String>) {
+    let mut host = None;
+    let mut proto = None;
+    let first = value.split(',').next().unwrap_or_default();
+    for pair in first.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "host" => host = Some(value),
+            "proto" => proto = Some(value),
+            _ => {}
+        }
+    }
+    (host, proto)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// First of several comma-separated values, as appended by chained proxies
+fn first_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = header(headers, name)?.split(',').next()?.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '[' | ']'))
+}
+
+fn has_port(host: &str) -> bool {
+    host.rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'))
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '-' | '_' | '~')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_public_url() {
+        assert_eq!(parse_public_url("request"), Ok(PublicUrl::Request));
+        assert_eq!(
+            parse_public_url("https://pcli2.example.com/mcp/"),
+            Ok(PublicUrl::Fixed(
+                "https://pcli2.example.com/mcp".to_string()
+            ))
+        );
+        assert!(parse_public_url("pcli2.example.com").is_err());
+        assert!(parse_public_url("http://user@host").is_err());
+        assert!(parse_public_url("http://host/?q=1").is_err());
+    }
+
+    #[test]
+    fn test_request_origin_from_headers() {
+        let host = headers(&[("host", "mcp.internal:8080")]);
+        assert_eq!(PublicUrl::Bind.origin(&host), None);
+        assert_eq!(
+            PublicUrl::Request.origin(&host).as_deref(),
+            Some("http://mcp.internal:8080")
+        );
+
+        let forwarded = headers(&[
+            ("host", "127.0.0.1:8080"),
+            (
+                "forwarded",
+                "for=10.0.0.1;proto=https;host=\"pcli2.example.com\", for=10.0.0.2",
+            ),
+            ("x-forwarded-prefix", "/tools/"),
+        ]);
+        assert_eq!(
+            PublicUrl::Bind.origin(&forwarded).as_deref(),
+            Some("https://pcli2.example.com/tools")
+        );
+
+        let x_forwarded = headers(&[
+            ("host", "127.0.0.1:8080"),
+            ("x-forwarded-host", "pcli2.example.com, proxy.local"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-port", "8443"),
+        ]);
+        assert_eq!(
+            PublicUrl::Bind.origin(&x_forwarded).as_deref(),
+            Some("https://pcli2.example.com:8443")
+        );
+
+        let spoofed = headers(&[("host", "evil.example/\"><script>")]);
+        assert_eq!(PublicUrl::Request.origin(&spoofed), None);
+        assert_eq!(
+            PublicUrl::Fixed("https://x".to_string()).origin(&forwarded),
+            None
+        );
+        assert_eq!(PublicUrl::for_bind_host("0.0.0.0"), PublicUrl::Request);
+        assert_eq!(PublicUrl::for_bind_host("localhost"), PublicUrl::Bind);
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let mut result = json!({
+            "content": [{ "type": "text", "text": "<img src=\"http://0.0.0.0:8080/thumbnail/abc\">" }],
+            "count": 1
+        });
+        rewrite_links(
+            &mut result,
+            "http://0.0.0.0:8080/thumbnail",
+            "https://pcli2.example.com/thumbnail",
+        );
+        assert_eq!(
+            result["content"][0]["text"],
+            "<img src=\"https://pcli2.example.com/thumbnail/abc\">"
+        );
+        assert_eq!(result["count"], 1);
+    }
+}
@@ -0,0 +1,439 @@
+//! Minimal RGBA raster operations for composing thumbnail images.
+//!
+//! Thumbnails arrive as PNG files. This module decodes them into 8-bit RGBA
+//! pixels, scales and places them on a canvas, draws short ASCII captions
+//! with a built-in 5x7 bitmap font and encodes the result as PNG, JPEG or
+//! lossless WebP.
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+/// Largest width or height accepted when decoding
+pub const MAX_DIMENSION: u32 = 8192;
+/// Most pixels accepted when decoding, so one image stays within 16 MiB of RGBA
+pub const MAX_PIXELS: u64 = 2048 * 2048;
+/// Width of one character cell at scale 1, including spacing
+pub const GLYPH_ADVANCE: u32 = 6;
+/// Height of one text line at scale 1, including spacing
+pub const LINE_HEIGHT: u32 = 9;
+
+/// An 8-bit RGBA image stored row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rgba {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Rgba {
+    /// Image filled with `color`
+    pub fn new(width: u32, height: u32, color: [u8; 4]) -> Self {
+        let pixels = color
+            .iter()
+            .copied()
+            .cycle()
+            .take(width as usize * height as usize * 4)
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Decode a PNG file of any color type and bit depth
+    pub fn decode_png(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = Decoder::new(bytes);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .map_err(|err| format!("Failed to read PNG header: {}", err))?;
+        let (width, height) = {
+            let info = reader.info();
+            (info.width, info.height)
+        };
+        if width == 0
+            || height == 0
+            || width > MAX_DIMENSION
+            || height > MAX_DIMENSION
+            || width as u64 * height as u64 > MAX_PIXELS
+        {
+            return Err(format!("Unsupported PNG size {}x{}", width, height));
+        }
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader
+            .next_frame(&mut buffer)
+            .map_err(|err| format!("Failed to decode PNG: {}", err))?;
+
+        let channels = match frame.color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+            ColorType::Indexed => return Err("Indexed PNG was not expanded".to_string()),
+        };
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in buffer.chunks(frame.line_size).take(height as usize) {
+            for pixel in row[..width as usize * channels].chunks(channels) {
+                pixels.extend_from_slice(&match pixel {
+                    [g] => [*g, *g, *g, 255],
+                    [g, a] => [*g, *g, *g, *a],
+                    [r, g, b] => [*r, *g, *b, 255],
+                    [r, g, b, a] => [*r, *g, *b, *a],
+                    _ => unreachable!("channel count is 1 to 4"),
+                });
+            }
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Encode as an 8-bit RGBA PNG
+    pub fn encode_png(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|err| format!("Failed to write PNG header: {}", err))?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(|err| format!("Failed to encode PNG: {}", err))?;
+        writer
+            .finish()
+            .map_err(|err| format!("Failed to finish PNG: {}", err))?;
+        Ok(bytes)
+    }
+
+    /// Encode as a baseline JPEG, with transparent areas flattened onto white
+    pub fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, String> {
+        let (width, height) = (
+            u16::try_from(self.width).map_err(|_| "Image is too wide for JPEG".to_string())?,
+            u16::try_from(self.height).map_err(|_| "Image is too tall for JPEG".to_string())?,
+        );
+        let rgb: Vec<u8> = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let alpha = pixel[3] as u32;
+                let over_white = move |value: u8| {
+                    ((value as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+                };
+                [
+                    over_white(pixel[0]),
+                    over_white(pixel[1]),
+                    over_white(pixel[2]),
+                ]
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        jpeg_encoder::Encoder::new(&mut bytes, quality)
+            .encode(&rgb, width, height, jpeg_encoder::ColorType::Rgb)
+            .map_err(|err| format!("Failed to encode JPEG: {}", err))?;
+        Ok(bytes)
+    }
+
+    /// Encode as a lossless WebP
+    pub fn encode_webp(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        image_webp::WebPEncoder::new(&mut bytes)
+            .encode(
+                &self.pixels,
+                self.width,
+                self.height,
+                image_webp::ColorType::Rgba8,
+            )
+            .map_err(|err| format!("Failed to encode WebP: {}", err))?;
+        Ok(bytes)
+    }
+
+    /// Copy scaled to `width` x `height`, averaging the source pixels under each target pixel
+    pub fn resize(&self, width: u32, height: u32) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        let span = |target: u32, target_len: u32, source_len: u32| {
+            let start = (target as u64 * source_len as u64 / target_len as u64) as u32;
+            let end = ((target as u64 + 1) * source_len as u64 / target_len as u64) as u32;
+            start..end.max(start + 1).min(source_len)
+        };
+        for y in 0..height {
+            let rows = span(y, height, self.height);
+            for x in 0..width {
+                let columns = span(x, width, self.width);
+                let mut sum = [0u64; 4];
+                let mut count = 0u64;
+                for source_y in rows.clone() {
+                    for source_x in columns.clone() {
+                        let index = self.index(source_x, source_y);
+                        for (total, value) in sum.iter_mut().zip(&self.pixels[index..index + 4]) {
+                            *total += *value as u64;
+                        }
+                        count += 1;
+                    }
+                }
+                pixels.extend(sum.iter().map(|total| (total / count.max(1)) as u8));
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Copy scaled to fit within `max_width` x `max_height`, keeping the aspect ratio
+    pub fn fit_within(&self, max_width: u32, max_height: u32) -> Self {
+        let scale = f64::min(
+            max_width as f64 / self.width as f64,
+            max_height as f64 / self.height as f64,
+        );
+        let width = ((self.width as f64 * scale).round() as u32).clamp(1, max_width.max(1));
+        let height = ((self.height as f64 * scale).round() as u32).clamp(1, max_height.max(1));
+        self.resize(width, height)
+    }
+
+    /// Paint `color` over a rectangle, clipped to the image
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 4]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.blend(column, row, color);
+            }
+        }
+    }
+
+    /// Draw `image` with its top-left corner at `x`, `y`, blending by alpha
+    pub fn draw_image(&mut self, image: &Rgba, x: u32, y: u32) {
+        for row in 0..image.height.min(self.height.saturating_sub(y)) {
+            for column in 0..image.width.min(self.width.saturating_sub(x)) {
+                let index = image.index(column, row);
+                let mut color = [0; 4];
+                color.copy_from_slice(&image.pixels[index..index + 4]);
+                self.blend(x + column, y + row, color);
+            }
+        }
+    }
+
+    /// Draw ASCII text; other characters are drawn as `?`
+    pub fn draw_text(&mut self, text: &str, x: u32, y: u32, scale: u32, color: [u8; 4]) {
+        let scale = scale.max(1);
+        for (position, c) in text.chars().enumerate() {
+            let glyph = glyph(c);
+            let left = x + position as u32 * GLYPH_ADVANCE * scale;
+            for (column, bits) in glyph.iter().enumerate() {
+                for row in 0..7 {
+                    if bits & (1 << row) != 0 {
+                        self.fill_rect(
+                            left + column as u32 * scale,
+                            y + row * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+
+    fn blend(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let index = self.index(x, y);
+        let alpha = color[3] as u32;
+        for (under, over) in self.pixels[index..index + 3].iter_mut().zip(color) {
+            *under = ((over as u32 * alpha + *under as u32 * (255 - alpha)) / 255) as u8;
+        }
+        let under = self.pixels[index + 3] as u32;
+        self.pixels[index + 3] = (alpha + under * (255 - alpha) / 255) as u8;
+    }
+}
+
+/// Width in pixels of `text` drawn at `scale`
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * GLYPH_ADVANCE * scale.max(1)
+}
+
+/// `text` shortened from the left with `...` so it fits in `max_chars`
+pub fn fit_text(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.to_string();
+    }
+    if max_chars <= 3 {
+        return ".".repeat(max_chars);
+    }
+    let tail: String = text.chars().skip(count - (max_chars - 3)).collect();
+    format!("...{}", tail)
+}
+
+/// Column bitmaps of a printable ASCII character, least significant bit at the top
+fn glyph(c: char) -> [u8; 5] {
+    let code = c as u32;
+    if (0x20..=0x7e).contains(&code) {
+        FONT[(code - 0x20) as usize]
+    } else {
+        FONT[('?' as u32 - 0x20) as usize]
+    }
+}
+
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut image = Rgba::new(3, 2, [255, 255, 255, 255]);
+        image.fill_rect(1, 0, 1, 2, [255, 0, 0, 255]);
+        let decoded = Rgba::decode_png(&image.encode_png().unwrap()).unwrap();
+        assert_eq!(decoded, image);
+        assert!(Rgba::decode_png(b"not a png").is_err());
+        // Checked from the header, before any pixel buffer is allocated
+        let wide = Rgba::new(MAX_DIMENSION, 1024, [0, 0, 0, 255]);
+        assert!(Rgba::decode_png(&wide.encode_png().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_jpeg_and_webp_encoding() {
+        let mut image = Rgba::new(20, 10, [0, 0, 0, 0]);
+        image.fill_rect(0, 0, 10, 10, [200, 30, 30, 255]);
+        let jpeg = image.encode_jpeg(80).unwrap();
+        assert!(jpeg.starts_with(&[0xff, 0xd8, 0xff]));
+        assert!(jpeg.ends_with(&[0xff, 0xd9]));
+
+        let webp = image.encode_webp().unwrap();
+        assert_eq!(&webp[..4], b"RIFF");
+        assert_eq!(&webp[8..12], b"WEBP");
+    }
+
+    #[test]
+    fn test_resize_averages_pixels() {
+        let mut image = Rgba::new(4, 2, [0, 0, 0, 255]);
+        image.fill_rect(0, 0, 1, 2, [200, 200, 200, 255]);
+        let small = image.resize(2, 1);
+        assert_eq!(small.pixels, [100, 100, 100, 255, 0, 0, 0, 255]);
+
+        let fitted = Rgba::new(400, 100, [0, 0, 0, 255]).fit_within(100, 100);
+        assert_eq!((fitted.width, fitted.height), (100, 25));
+    }
+
+    #[test]
+    fn test_draw_text_and_fit() {
+        let mut image = Rgba::new(12, 8, [255, 255, 255, 255]);
+        image.draw_text("|", 0, 0, 1, [0, 0, 0, 255]);
+        // The bar of '|' is the third column of its cell
+        assert_eq!(
+            &image.pixels[image.index(2, 3)..image.index(2, 3) + 3],
+            [0, 0, 0]
+        );
+        assert_eq!(
+            &image.pixels[image.index(0, 3)..image.index(0, 3) + 3],
+            [255, 255, 255]
+        );
+        assert_eq!(text_width("abc", 2), 36);
+        assert_eq!(fit_text("/Root/Parts/bracket.stl", 10), "...ket.stl");
+        assert_eq!(fit_text("short", 10), "short");
+    }
+}
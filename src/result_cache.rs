@@ -0,0 +1,556 @@
+//! Cache for the output of read-only pcli2 calls.
+//!
+//! Entries are keyed by tool name, the pcli2 argv and the tenant, and expire
+//! after a per-tool TTL. Entries live in memory and, when a cache directory
+//! is configured, are also written to `<cache_dir>/<key>.json` so they
+//! survive a restart. Mutating tools invalidate every entry they might have
+//! made stale.
+
+use crate::hash::sha256_hex;
+use crate::limits::ACTIVE_TENANT_KEY;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// File extension for persisted cache entries
+const ENTRY_EXTENSION: &str = "json";
+
+/// TTL for a cacheable tool, or `None` if the tool is never cached
+pub fn cache_ttl(tool: &str) -> Option<Duration> {
+    match tool {
+        "pcli2" => Some(Duration::from_secs(60)),
+        "pcli2_folder_get" | "pcli2_asset_get" => Some(Duration::from_secs(2 * 60)),
+        "pcli2_tenant_get" => Some(Duration::from_secs(5 * 60)),
+        _ => None,
+    }
+}
+
+/// What a cached result describes, used to decide invalidation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CacheScope {
+    /// Tenant-wide data such as `tenant get`
+    Tenant,
+    /// A folder or folder listing; both `None` means the root listing
+    Folder {
+        uuid: Option<String>,
+        path: Option<String>,
+    },
+    /// A single asset
+    Asset {
+        uuid: Option<String>,
+        path: Option<String>,
+    },
+}
+
+/// A change made by a mutating tool
+#[derive(Debug, Clone)]
+pub enum Mutation {
+    /// Asset metadata or processing state changed
+    Asset {
+        tenant: Option<String>,
+        uuid: Option<String>,
+        path: Option<String>,
+    },
+    /// The active tenant changed
+    ActiveTenant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    tool: String,
+    argv: Vec<String>,
+    tenant: String,
+    scope: CacheScope,
+    /// Unix timestamp in milliseconds
+    cached_at: i64,
+    output: String,
+}
+
+/// Hit and miss counters, returned by the status tool
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+/// In-memory and on-disk cache of read-only pcli2 output
+pub struct ResultCache {
+    cache_dir: Option<PathBuf>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl ResultCache {
+    /// Cache that keeps entries in memory only
+    pub fn in_memory() -> Self {
+        Self {
+            cache_dir: None,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Cache persisting entries in `cache_dir`, loading unexpired ones
+    pub fn open(cache_dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&cache_dir).map_err(|err| {
+            format!(
+                "Failed to create result cache directory {:?}: {}",
+                cache_dir, err
+            )
+        })?;
+        let cache = Self {
+            cache_dir: Some(cache_dir),
+            ..Self::in_memory()
+        };
+        cache.load_existing()?;
+        Ok(cache)
+    }
+
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    /// Cache key for a tool invocation
+    pub fn key(tool: &str, argv: &[String], tenant: Option<&str>) -> String {
+        let tenant = tenant.unwrap_or(ACTIVE_TENANT_KEY);
+        let mut material = format!("{}\0{}", tool, tenant);
+        for arg in argv {
+            material.push('\0');
+            material.push_str(arg);
+        }
+        sha256_hex(material.as_bytes())
+    }
+
+    /// Cached output for an invocation, if present and younger than the tool's TTL
+    pub fn get(&self, tool: &str, argv: &[String], tenant: Option<&str>) -> Option<String> {
+        let ttl = cache_ttl(tool)?;
+        let key = Self::key(tool, argv, tenant);
+        let mut entries = self.lock();
+        let fresh = entries
+            .get(&key)
+            .filter(|entry| !is_expired(entry, ttl))
+            .map(|entry| entry.output.clone());
+        match fresh {
+            Some(output) => {
+                self.hits.fetch_add(1, Ordering::SeqCst);
+                debug!("Result cache hit for {}", tool);
+                Some(output)
+            }
+            None => {
+                if entries.remove(&key).is_some() {
+                    self.remove_file(&key);
+                }
+                self.misses.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+
+    /// Store the output of a successful invocation
+    pub fn put(
+        &self,
+        tool: &str,
+        argv: &[String],
+        tenant: Option<&str>,
+        scope: CacheScope,
+        output: &str,
+    ) {
+        if cache_ttl(tool).is_none() {
+            return;
+        }
+        let key = Self::key(tool, argv, tenant);
+        let entry = CacheEntry {
+            tool: tool.to_string(),
+            argv: argv.to_vec(),
+            tenant: tenant.unwrap_or(ACTIVE_TENANT_KEY).to_string(),
+            scope,
+            cached_at: Utc::now().timestamp_millis(),
+            output: output.to_string(),
+        };
+        self.persist(&key, &entry);
+        self.lock().insert(key, entry);
+    }
+
+    /// Drop every entry the mutation might have made stale
+    ///
+    /// Returns the number of entries removed.
+    pub fn invalidate(&self, mutation: &Mutation) -> usize {
+        let removed: Vec<String> = {
+            let mut entries = self.lock();
+            let stale: Vec<String> = entries
+                .iter()
+                .filter(|(_, entry)| may_affect(entry, mutation))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &stale {
+                entries.remove(key);
+            }
+            stale
+        };
+        for key in &removed {
+            self.remove_file(key);
+        }
+        if !removed.is_empty() {
+            debug!("Invalidated {} cached result(s)", removed.len());
+            self.invalidations
+                .fetch_add(removed.len() as u64, Ordering::SeqCst);
+        }
+        removed.len()
+    }
+
+    pub fn stats(&self) -> ResultCacheStats {
+        ResultCacheStats {
+            entries: self.lock().len(),
+            hits: self.hits.load(Ordering::SeqCst),
+            misses: self.misses.load(Ordering::SeqCst),
+            invalidations: self.invalidations.load(Ordering::SeqCst),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CacheEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn entry_path(&self, key: &str) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.{}", key, ENTRY_EXTENSION)))
+    }
+
+    fn persist(&self, key: &str, entry: &CacheEntry) {
+        let Some(path) = self.entry_path(key) else {
+            return;
+        };
+        let write = serde_json::to_vec(entry)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| {
+                let temp = path.with_extension("tmp");
+                fs::write(&temp, bytes)
+                    .and_then(|_| fs::rename(&temp, &path))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = write {
+            warn!("Failed to persist cached result to {:?}: {}", path, err);
+        }
+    }
+
+    fn remove_file(&self, key: &str) {
+        if let Some(path) = self.entry_path(key)
+            && path.exists()
+            && let Err(err) = fs::remove_file(&path)
+        {
+            warn!("Failed to remove cached result {:?}: {}", path, err);
+        }
+    }
+
+    fn load_existing(&self) -> Result<(), String> {
+        let Some(dir) = self.cache_dir.as_ref() else {
+            return Ok(());
+        };
+        let entries = fs::read_dir(dir)
+            .map_err(|err| format!("Failed to read result cache directory {:?}: {}", dir, err))?;
+        for file in entries.flatten() {
+            let path = file.path();
+            if path.extension().and_then(|s| s.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let Some(key) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            let entry = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<CacheEntry>(&bytes).ok());
+            match entry {
+                Some(entry)
+                    if cache_ttl(&entry.tool).is_some_and(|ttl| !is_expired(&entry, ttl)) =>
+                {
+                    self.lock().insert(key, entry);
+                }
+                _ => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_expired(entry: &CacheEntry, ttl: Duration) -> bool {
+    let age_millis = Utc::now().timestamp_millis() - entry.cached_at;
+    age_millis > ttl.as_millis() as i64
+}
+
+/// Whether a mutation might change the cached output; errs on the side of `true`
+fn may_affect(entry: &CacheEntry, mutation: &Mutation) -> bool {
+    match mutation {
+        Mutation::ActiveTenant => entry.tenant == ACTIVE_TENANT_KEY,
+        Mutation::Asset { tenant, uuid, path } => {
+            let tenant = tenant.as_deref().unwrap_or(ACTIVE_TENANT_KEY);
+            // An explicit tenant may still be the active one, so only two
+            // different explicit tenants rule each other out
+            if entry.tenant != tenant
+                && entry.tenant != ACTIVE_TENANT_KEY
+                && tenant != ACTIVE_TENANT_KEY
+            {
+                return false;
+            }
+            match &entry.scope {
+                CacheScope::Tenant => false,
+                CacheScope::Asset {
+                    uuid: entry_uuid,
+                    path: entry_path,
+                } => {
+                    let different_uuid = matches!((uuid, entry_uuid), (Some(a), Some(b)) if a != b);
+                    let different_path = matches!((path, entry_path), (Some(a), Some(b)) if a != b);
+                    !(different_uuid || different_path)
+                }
+                CacheScope::Folder {
+                    path: Some(folder), ..
+                } => match path {
+                    Some(asset_path) => is_ancestor(folder, asset_path),
+                    None => true,
+                },
+                CacheScope::Folder { .. } => true,
+            }
+        }
+    }
+}
+
+fn is_ancestor(folder: &str, asset_path: &str) -> bool {
+    let folder = folder.trim_end_matches('/');
+    folder.is_empty()
+        || asset_path
+            .strip_prefix(folder)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Get the default result cache directory, next to the thumbnail cache
+///
+/// Uses ~/.pcli2-mcp/results on Unix-like systems
+pub fn default_result_cache_dir() -> Result<PathBuf, String> {
+    let mut dir = crate::thumbnail::default_cache_dir()?;
+    dir.pop();
+    dir.push("results");
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn argv(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn asset_scope(uuid: Option<&str>, path: Option<&str>) -> CacheScope {
+        CacheScope::Asset {
+            uuid: uuid.map(str::to_string),
+            path: path.map(str::to_string),
+        }
+    }
+
+    fn asset_mutation(uuid: Option<&str>, path: Option<&str>) -> Mutation {
+        Mutation::Asset {
+            tenant: None,
+            uuid: uuid.map(str::to_string),
+            path: path.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_key_depends_on_tool_argv_and_tenant() {
+        let a = ResultCache::key("pcli2_asset_get", &argv(&["asset", "get"]), None);
+        let b = ResultCache::key("pcli2_asset_get", &argv(&["asset", "get"]), Some("acme"));
+        let c = ResultCache::key("pcli2_folder_get", &argv(&["asset", "get"]), None);
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let cache = ResultCache::in_memory();
+        let args = argv(&["asset", "get", "--uuid", "u1"]);
+        assert!(cache.get("pcli2_asset_get", &args, None).is_none());
+        cache.put(
+            "pcli2_asset_get",
+            &args,
+            None,
+            asset_scope(Some("u1"), None),
+            "output",
+        );
+        assert_eq!(
+            cache.get("pcli2_asset_get", &args, None).as_deref(),
+            Some("output")
+        );
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_uncacheable_tool_is_ignored() {
+        let cache = ResultCache::in_memory();
+        let args = argv(&["asset", "reprocess"]);
+        cache.put(
+            "pcli2_asset_reprocess",
+            &args,
+            None,
+            CacheScope::Tenant,
+            "x",
+        );
+        assert!(cache.get("pcli2_asset_reprocess", &args, None).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_asset_mutation_invalidates_matching_entries() {
+        let cache = ResultCache::in_memory();
+        let part = argv(&["asset", "get", "--path", "/Root/A/part.stl"]);
+        let other = argv(&["asset", "get", "--path", "/Root/B/other.stl"]);
+        let folder = argv(&["folder", "get", "--folder-path", "/Root/A"]);
+        let sibling = argv(&["folder", "get", "--folder-path", "/Root/B"]);
+        let tenant = argv(&["tenant", "get"]);
+        cache.put(
+            "pcli2_asset_get",
+            &part,
+            None,
+            asset_scope(None, Some("/Root/A/part.stl")),
+            "part",
+        );
+        cache.put(
+            "pcli2_asset_get",
+            &other,
+            None,
+            asset_scope(None, Some("/Root/B/other.stl")),
+            "other",
+        );
+        let folder_scope = |path: &str| CacheScope::Folder {
+            uuid: None,
+            path: Some(path.to_string()),
+        };
+        cache.put(
+            "pcli2_folder_get",
+            &folder,
+            None,
+            folder_scope("/Root/A"),
+            "a",
+        );
+        cache.put(
+            "pcli2_folder_get",
+            &sibling,
+            None,
+            folder_scope("/Root/B"),
+            "b",
+        );
+        cache.put("pcli2_tenant_get", &tenant, None, CacheScope::Tenant, "t");
+
+        let removed = cache.invalidate(&asset_mutation(None, Some("/Root/A/part.stl")));
+        assert_eq!(removed, 2);
+        assert!(cache.get("pcli2_asset_get", &part, None).is_none());
+        assert!(cache.get("pcli2_folder_get", &folder, None).is_none());
+        assert!(cache.get("pcli2_asset_get", &other, None).is_some());
+        assert!(cache.get("pcli2_folder_get", &sibling, None).is_some());
+        assert!(cache.get("pcli2_tenant_get", &tenant, None).is_some());
+    }
+
+    #[test]
+    fn test_uuid_mutation_is_conservative_for_path_entries() {
+        let cache = ResultCache::in_memory();
+        let by_path = argv(&["asset", "get", "--path", "/Root/part.stl"]);
+        let by_other_uuid = argv(&["asset", "get", "--uuid", "u2"]);
+        cache.put(
+            "pcli2_asset_get",
+            &by_path,
+            None,
+            asset_scope(None, Some("/Root/part.stl")),
+            "p",
+        );
+        cache.put(
+            "pcli2_asset_get",
+            &by_other_uuid,
+            None,
+            asset_scope(Some("u2"), None),
+            "u",
+        );
+
+        cache.invalidate(&asset_mutation(Some("u1"), None));
+        assert!(cache.get("pcli2_asset_get", &by_path, None).is_none());
+        assert!(cache.get("pcli2_asset_get", &by_other_uuid, None).is_some());
+    }
+
+    #[test]
+    fn test_active_tenant_change_keeps_explicit_tenant_entries() {
+        let cache = ResultCache::in_memory();
+        let args = argv(&["tenant", "get"]);
+        cache.put(
+            "pcli2_tenant_get",
+            &args,
+            None,
+            CacheScope::Tenant,
+            "active",
+        );
+        cache.put(
+            "pcli2_tenant_get",
+            &args,
+            Some("acme"),
+            CacheScope::Tenant,
+            "acme",
+        );
+        assert_eq!(cache.invalidate(&Mutation::ActiveTenant), 1);
+        assert!(cache.get("pcli2_tenant_get", &args, Some("acme")).is_some());
+    }
+
+    #[test]
+    fn test_entries_survive_reopen() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("pcli2-result-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let args = argv(&["tenant", "get"]);
+        {
+            let cache = ResultCache::open(dir.clone()).unwrap();
+            cache.put(
+                "pcli2_tenant_get",
+                &args,
+                None,
+                CacheScope::Tenant,
+                "persisted",
+            );
+        }
+        let reopened = ResultCache::open(dir.clone()).unwrap();
+        assert_eq!(
+            reopened.get("pcli2_tenant_get", &args, None).as_deref(),
+            Some("persisted")
+        );
+        reopened.invalidate(&Mutation::ActiveTenant);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_is_ancestor() {
+        assert!(is_ancestor("/Root", "/Root/A/part.stl"));
+        assert!(is_ancestor("/Root/", "/Root/part.stl"));
+        assert!(is_ancestor("/", "/part.stl"));
+        assert!(!is_ancestor("/Ro", "/Root/part.stl"));
+    }
+}
@@ -1,22 +1,47 @@
 use crate::AppState;
-use crate::cli::{ARG_HOST, ARG_PORT, DEFAULT_HOST};
+use crate::backend::{FixtureBackend, Pcli2Backend, RecordingBackend, SubprocessBackend};
+use crate::cli::{
+    ARG_FIXTURES, ARG_HOST, ARG_MAX_CONCURRENT, ARG_MAX_CONCURRENT_PER_TENANT, ARG_MAX_QUEUE,
+    ARG_MAX_RESPONSE_BYTES, ARG_MAX_RESPONSE_TOKENS, ARG_MAX_TOOL_TIMEOUT, ARG_PORT,
+    ARG_PUBLIC_URL, ARG_RECORD, ARG_REPLAY, ARG_S3_BUCKET, ARG_S3_ENDPOINT, ARG_S3_PREFIX,
+    ARG_S3_PRESIGN, ARG_S3_REGION, ARG_SIGNING_KEY_FILE, ARG_THUMBNAIL_CACHE_MAX_ENTRIES,
+    ARG_THUMBNAIL_CACHE_MAX_MB, ARG_THUMBNAIL_STORE, ARG_THUMBNAIL_SWEEP_INTERVAL,
+    ARG_THUMBNAIL_URL_TTL, ARG_TOOL_LIMIT, ARG_UPLOAD_ROOT, DEFAULT_HOST,
+};
+use crate::http_cache::{self, ByteRange};
+use crate::jobs::{JobManager, default_jobs_dir};
+use crate::limits::{LimitsConfig, Pcli2Limiter};
 use crate::mcp::handle_mcp;
-use crate::thumbnail::{ThumbnailCache, ThumbnailCacheConfig, default_cache_dir};
+use crate::pages::{BYTES_PER_TOKEN, DEFAULT_RESPONSE_BUDGET_BYTES, PageStore};
+use crate::pcli::PCLI2_TIMEOUT;
+use crate::public_url::PublicUrl;
+use crate::result_cache::{ResultCache, default_result_cache_dir};
+use crate::s3::{DEFAULT_REGION, S3Config, S3Store};
+use crate::signing::{DEFAULT_URL_TTL, LinkError, UrlSigner, default_signing_key_path};
+use crate::store::{MemoryStore, STORE_MEMORY, STORE_S3, ThumbnailStore};
+use crate::thumbnail::{
+    DEFAULT_SWEEP_INTERVAL, THUMBNAIL_ROUTE, ThumbnailCache, ThumbnailCacheConfig,
+    default_cache_dir,
+};
+use crate::variant::VariantSpec;
 use anyhow::{Result, anyhow};
-use axum::body::Body;
-use axum::response::Response;
 use axum::{
     BoxError, Router,
     error_handling::HandleErrorLayer,
-    extract::{DefaultBodyLimit, Path, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     routing::get,
 };
 use chrono::Utc;
 use clap::ArgMatches;
-use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION,
+};
+use http::{HeaderMap, HeaderValue};
+use std::collections::HashMap;
 use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tower::{ServiceBuilder, timeout::TimeoutLayer};
@@ -25,7 +50,9 @@ use tracing::{debug, info, warn};
 const SERVER_NAME: &str = "mcp-http-server";
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 const MAX_REQUEST_BYTES: usize = 1_048_576;
-const REQUEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Extra time the HTTP layer allows beyond the longest tool timeout, so the
+/// tool's own structured timeout error reaches the client first
+const REQUEST_TIMEOUT_GRACE: Duration = Duration::from_secs(30);
 const THUMBNAIL_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
 
 pub async fn run_server(matches: &ArgMatches) -> Result<()> {
@@ -36,16 +63,49 @@ pub async fn run_server(matches: &ArgMatches) -> Result<()> {
     let port = *matches
         .get_one::<u16>(ARG_PORT)
         .ok_or_else(|| anyhow!("missing port"))?;
+    let max_tool_timeout = matches
+        .get_one::<u64>(ARG_MAX_TOOL_TIMEOUT)
+        .map(|secs| Duration::from_secs(*secs))
+        .unwrap_or(PCLI2_TIMEOUT);
 
     print_banner();
 
+    let public_url = matches
+        .get_one::<PublicUrl>(ARG_PUBLIC_URL)
+        .cloned()
+        .unwrap_or_else(|| PublicUrl::for_bind_host(host));
+    match &public_url {
+        PublicUrl::Fixed(url) => info!("Thumbnail links use {}", url),
+        PublicUrl::Request => info!("Thumbnail links use the origin of each MCP request"),
+        PublicUrl::Bind => {}
+    }
+
     // Initialize thumbnail cache
-    let thumbnail_cache = match default_cache_dir() {
-        Ok(cache_dir) => {
-            let config = ThumbnailCacheConfig::new(cache_dir, THUMBNAIL_TTL, host, port);
+    let thumbnail_cache = match thumbnail_cache_dir(matches) {
+        Ok((cache_dir, store)) => {
+            let max_bytes = matches
+                .get_one::<u64>(ARG_THUMBNAIL_CACHE_MAX_MB)
+                .map(|mb| mb * 1024 * 1024);
+            let max_entries = matches
+                .get_one::<usize>(ARG_THUMBNAIL_CACHE_MAX_ENTRIES)
+                .copied();
+            let mut config = ThumbnailCacheConfig::new(cache_dir, THUMBNAIL_TTL, host, port)
+                .with_limits(max_bytes, max_entries);
+            if let PublicUrl::Fixed(url) = &public_url {
+                config = config.with_public_url(url);
+            }
+            if let Some(signer) = url_signer(matches)? {
+                config = config.with_signer(signer);
+            }
+            if let Some(store) = store {
+                config = config.with_store(store);
+            }
             match ThumbnailCache::new(config) {
                 Ok(cache) => {
-                    info!("Thumbnail cache initialized at {:?}", cache.cache_dir());
+                    info!(
+                        "Thumbnail cache initialized at {}",
+                        cache.store().describe()
+                    );
                     Some(cache)
                 }
                 Err(err) => {
@@ -55,21 +115,92 @@ pub async fn run_server(matches: &ArgMatches) -> Result<()> {
             }
         }
         Err(err) => {
-            warn!("Could not determine thumbnail cache directory: {}", err);
+            warn!("Could not set up the thumbnail store: {}", err);
             None
         }
     };
 
-    let state = AppState {
-        server_name: SERVER_NAME.to_string(),
-        server_version: APP_VERSION.to_string(),
-        thumbnail_cache: Arc::new(thumbnail_cache),
+    let limits = limits_config(matches);
+    info!(
+        "pcli2 concurrency: {} global, {} per tenant, queue of {}",
+        limits.max_concurrent, limits.max_concurrent_per_tenant, limits.max_queue
+    );
+
+    let jobs = match default_jobs_dir().and_then(JobManager::open) {
+        Ok(jobs) => {
+            info!("Job store initialized at {:?}", jobs.jobs_dir());
+            jobs
+        }
+        Err(err) => {
+            warn!(
+                "Failed to initialize job store, keeping jobs in memory: {}",
+                err
+            );
+            JobManager::in_memory()
+        }
+    };
+
+    let backend = pcli2_backend(matches)?;
+    let upload_root = upload_root(matches)?;
+
+    // A token budget, when given, overrides the byte budget
+    let response_budget = matches
+        .get_one::<usize>(ARG_MAX_RESPONSE_TOKENS)
+        .map(|tokens| tokens * BYTES_PER_TOKEN)
+        .or_else(|| matches.get_one::<usize>(ARG_MAX_RESPONSE_BYTES).copied())
+        .unwrap_or(DEFAULT_RESPONSE_BUDGET_BYTES);
+    info!("Tool output longer than {} bytes is paged", response_budget);
+
+    // Recorded or canned answers must not mix with the on-disk cache of real
+    // runs, and cache hits would keep calls out of a recording
+    let results = if backend.is_some() {
+        ResultCache::in_memory()
+    } else {
+        match default_result_cache_dir().and_then(ResultCache::open) {
+            Ok(results) => {
+                info!("Result cache initialized at {:?}", results.cache_dir());
+                results
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to initialize result cache, keeping results in memory: {}",
+                    err
+                );
+                ResultCache::in_memory()
+            }
+        }
     };
 
+    let mut state = AppState::new(SERVER_NAME, APP_VERSION, thumbnail_cache);
+    state.max_tool_timeout = max_tool_timeout;
+    state.limiter = Arc::new(Pcli2Limiter::new(limits));
+    state.jobs = Arc::new(jobs);
+    state.results = Arc::new(results);
+    state.pages = Arc::new(PageStore::new(response_budget));
+    state.public_url = public_url;
+    state.upload_root = upload_root;
+    if let Some(backend) = backend {
+        state.backend = backend;
+    }
+
+    let sweep_interval = matches
+        .get_one::<u64>(ARG_THUMBNAIL_SWEEP_INTERVAL)
+        .map(|secs| Duration::from_secs(*secs))
+        .unwrap_or(DEFAULT_SWEEP_INTERVAL);
+    if let Some(cache) = state.thumbnail_cache.clone()
+        && !sweep_interval.is_zero()
+    {
+        info!("Sweeping the thumbnail cache every {:?}", sweep_interval);
+        tokio::spawn(sweep_thumbnails(cache, sweep_interval));
+    }
+
     let app = Router::new()
         .route("/health", get(health))
         .route("/mcp", axum::routing::post(handle_mcp))
-        .route("/thumbnail/:cache_key", get(serve_thumbnail))
+        .route(
+            &format!("{}/:cache_key", THUMBNAIL_ROUTE),
+            get(serve_thumbnail),
+        )
         .with_state(state)
         .layer(
             ServiceBuilder::new()
@@ -83,7 +214,7 @@ pub async fn run_server(matches: &ArgMatches) -> Result<()> {
                         )
                     }
                 }))
-                .layer(TimeoutLayer::new(REQUEST_TIMEOUT))
+                .layer(TimeoutLayer::new(max_tool_timeout + REQUEST_TIMEOUT_GRACE))
                 .layer(DefaultBodyLimit::max(MAX_REQUEST_BYTES)),
         );
 
@@ -107,12 +238,171 @@ pub async fn run_server(matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// Cache directory and the store chosen with `--thumbnail-store`
+///
+/// The store is `None` for the cache directory itself.
+fn thumbnail_cache_dir(
+    matches: &ArgMatches,
+) -> Result<(PathBuf, Option<Arc<dyn ThumbnailStore>>), String> {
+    let store: Arc<dyn ThumbnailStore> = match matches
+        .get_one::<String>(ARG_THUMBNAIL_STORE)
+        .map(String::as_str)
+    {
+        Some(STORE_MEMORY) => Arc::new(MemoryStore::new()),
+        Some(STORE_S3) => Arc::new(S3Store::new(s3_config(matches)?)?),
+        _ => return Ok((default_cache_dir()?, None)),
+    };
+    // The directory is unused, and a missing home directory must not matter
+    Ok((default_cache_dir().unwrap_or_default(), Some(store)))
+}
+
+/// S3 store settings from the `--s3-*` options and the AWS credential variables
+fn s3_config(matches: &ArgMatches) -> Result<S3Config, String> {
+    let option = |id: &str| matches.get_one::<String>(id).map(String::as_str);
+    let bucket = option(ARG_S3_BUCKET).ok_or("--s3-bucket is required for the S3 store")?;
+    let region = option(ARG_S3_REGION).unwrap_or(DEFAULT_REGION);
+    let endpoint = match option(ARG_S3_ENDPOINT) {
+        Some(endpoint) => endpoint.to_string(),
+        None => format!("https://s3.{}.amazonaws.com", region),
+    };
+    let mut config = S3Config::new(&endpoint, bucket)
+        .with_region(region)
+        .with_presign(matches.get_flag(ARG_S3_PRESIGN))
+        .with_env_credentials()?;
+    if let Some(prefix) = option(ARG_S3_PREFIX) {
+        config = config.with_prefix(prefix);
+    }
+    Ok(config)
+}
+
+/// Signer for thumbnail links, keyed from `--signing-key-file` or the default key file
+///
+/// A key file given with `--signing-key-file` must load, so servers meant to
+/// share it never silently diverge. When the default file cannot be used the
+/// signer falls back to a key only this process knows, so links stop working
+/// after a restart but are never served unsigned.
+fn url_signer(matches: &ArgMatches) -> Result<Option<UrlSigner>> {
+    let ttl = matches
+        .get_one::<u64>(ARG_THUMBNAIL_URL_TTL)
+        .map(|secs| Duration::from_secs(*secs))
+        .unwrap_or(DEFAULT_URL_TTL);
+    if let Some(path) = matches.get_one::<PathBuf>(ARG_SIGNING_KEY_FILE) {
+        let signer = UrlSigner::load_or_create(path, ttl).map_err(|err| anyhow!(err))?;
+        info!("Thumbnail links are signed and valid for {:?}", ttl);
+        return Ok(Some(signer));
+    }
+    match default_signing_key_path().and_then(|path| UrlSigner::load_or_create(&path, ttl)) {
+        Ok(signer) => {
+            info!("Thumbnail links are signed and valid for {:?}", ttl);
+            Ok(Some(signer))
+        }
+        Err(err) => {
+            warn!(
+                "Failed to load signing key, signing thumbnail links with a temporary key: {}",
+                err
+            );
+            match UrlSigner::random(ttl) {
+                Ok(signer) => Ok(Some(signer)),
+                Err(err) => {
+                    warn!("Thumbnail links cannot be signed: {}", err);
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// Directory given with `--upload-root`, resolved so paths can be compared with it
+fn upload_root(matches: &ArgMatches) -> Result<Option<PathBuf>> {
+    let Some(dir) = matches.get_one::<PathBuf>(ARG_UPLOAD_ROOT) else {
+        return Ok(None);
+    };
+    let root = dir
+        .canonicalize()
+        .map_err(|err| anyhow!("Invalid upload root {:?}: {}", dir, err))?;
+    if !root.is_dir() {
+        return Err(anyhow!("Upload root {:?} is not a directory", dir));
+    }
+    info!("Asset uploads may read files under {:?}", root);
+    Ok(Some(root))
+}
+
+/// Backend chosen with `--fixtures`, `--record` or `--replay`, if any
+fn pcli2_backend(matches: &ArgMatches) -> Result<Option<Arc<dyn Pcli2Backend>>> {
+    if let Some(path) = matches.get_one::<PathBuf>(ARG_FIXTURES) {
+        let backend = FixtureBackend::load(path).map_err(|err| anyhow!(err))?;
+        info!(
+            "Answering pcli2 calls from {} fixture(s) in {:?}",
+            backend.fixtures().len(),
+            path
+        );
+        return Ok(Some(Arc::new(backend)));
+    }
+    if let Some(dir) = matches.get_one::<PathBuf>(ARG_REPLAY) {
+        let backend = FixtureBackend::load_cassette(dir).map_err(|err| anyhow!(err))?;
+        info!(
+            "Replaying {} recorded pcli2 call(s) from {:?}",
+            backend.fixtures().len(),
+            dir
+        );
+        return Ok(Some(Arc::new(backend)));
+    }
+    if let Some(dir) = matches.get_one::<PathBuf>(ARG_RECORD) {
+        let subprocess = Arc::new(SubprocessBackend::from_env());
+        let backend =
+            RecordingBackend::with_cassette(subprocess, dir).map_err(|err| anyhow!(err))?;
+        info!("Recording pcli2 calls into {:?}", dir);
+        return Ok(Some(Arc::new(backend)));
+    }
+    Ok(None)
+}
+
+fn limits_config(matches: &ArgMatches) -> LimitsConfig {
+    let mut limits = LimitsConfig::default();
+    if let Some(value) = matches.get_one::<usize>(ARG_MAX_CONCURRENT) {
+        limits.max_concurrent = *value;
+    }
+    if let Some(value) = matches.get_one::<usize>(ARG_MAX_CONCURRENT_PER_TENANT) {
+        limits.max_concurrent_per_tenant = *value;
+    }
+    if let Some(value) = matches.get_one::<usize>(ARG_MAX_QUEUE) {
+        limits.max_queue = *value;
+    }
+    if let Some(tool_limits) = matches.get_many::<(String, usize)>(ARG_TOOL_LIMIT) {
+        for (tool, limit) in tool_limits {
+            limits.tool_limits.insert(tool.clone(), *limit);
+        }
+    }
+    limits
+}
+
+/// Periodically remove expired thumbnails and evict down to the cache bounds
+async fn sweep_thumbnails(cache: Arc<ThumbnailCache>, interval: Duration) {
+    // The first tick completes immediately, trimming a cache left by an earlier run
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let cache = cache.clone();
+        let swept = tokio::task::spawn_blocking(move || cache.sweep()).await;
+        match swept {
+            Ok(Ok(result)) => debug!(
+                "Thumbnail sweep removed {} expired and {} evicted thumbnail(s)",
+                result.expired, result.evicted
+            ),
+            Ok(Err(err)) => warn!("Thumbnail sweep failed: {}", err),
+            Err(err) => warn!("Thumbnail sweep task failed: {}", err),
+        }
+    }
+}
+
 async fn health() -> impl IntoResponse {
     (StatusCode::OK, "ok")
 }
 
 async fn serve_thumbnail(
     Path(cache_key): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     state: State<AppState>,
 ) -> impl IntoResponse {
     let Some(cache) = state.thumbnail_cache.as_ref() else {
@@ -123,28 +413,126 @@ async fn serve_thumbnail(
             .into_response();
     };
 
-    match cache.load_thumbnail(&cache_key) {
-        Ok(data) => {
-            let mut response = Response::new(Body::from(data));
-            response
-                .headers_mut()
-                .insert(CONTENT_TYPE, "image/png".parse().unwrap());
-            response
-                .headers_mut()
-                .insert(CACHE_CONTROL, "public, max-age=3600".parse().unwrap());
-            response
+    let variant = match VariantSpec::from_query(&params) {
+        Ok(variant) => variant,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let valid_for = match cache.verify_link(
+        &cache_key,
+        &variant,
+        params.get("expires").map(String::as_str),
+        params.get("sig").map(String::as_str),
+    ) {
+        Ok(valid_for) => valid_for,
+        Err(err) => {
+            debug!("Refused thumbnail link for {}: {}", cache_key, err);
+            let status = match err {
+                LinkError::Expired => StatusCode::GONE,
+                LinkError::Unsigned | LinkError::BadSignature => StatusCode::FORBIDDEN,
+            };
+            return (status, err.to_string()).into_response();
+        }
+    };
+
+    // Let the store serve the image when it hands out presigned URLs; the
+    // redirect must not outlive the link that led to it. Store calls and
+    // rendering block, so they run off the async workers.
+    let max_ttl = valid_for.map(Duration::from_secs);
+    let (key, spec) = (cache_key.clone(), variant.clone());
+    let presigned = cache
+        .blocking(move |cache| cache.presigned_url(&key, &spec, max_ttl))
+        .await;
+    match presigned {
+        Ok(Some(url)) => {
+            let mut response_headers = HeaderMap::new();
+            response_headers.insert(LOCATION, header_value(&url));
+            response_headers.insert(CACHE_CONTROL, header_value("private, no-cache"));
+            return (StatusCode::TEMPORARY_REDIRECT, response_headers).into_response();
         }
+        Ok(None) => {}
+        Err(err) => {
+            debug!("Failed to presign thumbnail {}: {}", cache_key, err);
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Thumbnail not found: {}", err),
+            )
+                .into_response();
+        }
+    }
+
+    let (key, spec) = (cache_key.clone(), variant.clone());
+    let loaded = cache
+        .blocking(move |cache| {
+            let data = cache.load_variant(&key, &spec)?;
+            let freshness = cache.freshness(&key, &spec, &data);
+            Ok((data, freshness))
+        })
+        .await;
+    let (data, freshness) = match loaded {
+        Ok(loaded) => loaded,
         Err(err) => {
             debug!("Failed to serve thumbnail {}: {}", cache_key, err);
-            (
+            return (
                 StatusCode::NOT_FOUND,
                 format!("Thumbnail not found: {}", err),
+            )
+                .into_response();
+        }
+    };
+
+    let etag = http_cache::etag(&freshness.content_hash);
+    // Clients may keep the image until the entry expires; signed links are
+    // private and must not be cached beyond their own expiry either
+    let max_age = freshness.expires_in.as_secs();
+    let cache_control = match valid_for {
+        Some(secs) => format!("private, max-age={}", secs.min(max_age)),
+        None => format!("public, max-age={}", max_age),
+    };
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(ETAG, header_value(&etag));
+    response_headers.insert(
+        LAST_MODIFIED,
+        header_value(&http_cache::format_http_date(freshness.last_modified)),
+    );
+    response_headers.insert(CACHE_CONTROL, header_value(&cache_control));
+    response_headers.insert(ACCEPT_RANGES, header_value("bytes"));
+
+    if http_cache::is_not_modified(&headers, &etag, freshness.last_modified) {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    let len = data.len();
+    match http_cache::byte_range(&headers, len, &etag, freshness.last_modified) {
+        ByteRange::Full => {
+            response_headers.insert(CONTENT_TYPE, header_value(variant.format.mime_type()));
+            (StatusCode::OK, response_headers, data).into_response()
+        }
+        ByteRange::Partial(range) => {
+            response_headers.insert(CONTENT_TYPE, header_value(variant.format.mime_type()));
+            response_headers.insert(
+                CONTENT_RANGE,
+                header_value(&format!("bytes {}-{}/{}", range.start, range.end - 1, len)),
+            );
+            (
+                StatusCode::PARTIAL_CONTENT,
+                response_headers,
+                data[range].to_vec(),
             )
                 .into_response()
         }
+        ByteRange::Unsatisfiable => {
+            response_headers.insert(CONTENT_RANGE, header_value(&format!("bytes */{}", len)));
+            (StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response()
+        }
     }
 }
 
+/// Header value for text built from validated parts
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).expect("header values are visible ASCII")
+}
+
 fn print_banner() {
     let ascii = [
         "██████╗  ██████╗██╗     ██╗██████╗     ███╗   ███╗ ██████╗██████╗ ",
@@ -229,7 +617,7 @@ mod tests {
     use super::*;
     use axum::{
         body::Body,
-        http::{Request, StatusCode},
+        http::{Request, Response, StatusCode},
     };
     use tower::ServiceExt; // for `oneshot`
 
@@ -255,6 +643,182 @@ mod tests {
         assert_eq!(&body[..], b"ok");
     }
 
+    #[tokio::test]
+    async fn test_thumbnail_conditional_and_range_requests() {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "pcli2-mcp-thumbnail-http-test-{}",
+            std::process::id()
+        ));
+        let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+            cache_dir.clone(),
+            Duration::from_secs(600),
+            "localhost",
+            8080,
+        ))
+        .unwrap();
+        let png = crate::raster::Rgba::new(40, 20, [1, 2, 3, 255])
+            .encode_png()
+            .unwrap();
+        cache.save_thumbnail_as("k", "asset", &png).unwrap();
+        let app = Router::new()
+            .route(
+                &format!("{}/:cache_key", THUMBNAIL_ROUTE),
+                get(serve_thumbnail),
+            )
+            .with_state(AppState::new("test", "0.0.0", Some(cache)));
+        let request = |method: &str, headers: &[(&str, &str)]| {
+            let mut builder = Request::builder()
+                .method(method)
+                .uri(format!("{}/k", THUMBNAIL_ROUTE));
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            app.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+        let body = |response: Response<Body>| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let full = request("GET", &[]).await.unwrap();
+        assert_eq!(full.status(), StatusCode::OK);
+        let etag = full.headers()[ETAG].to_str().unwrap().to_string();
+        let last_modified = full.headers()[LAST_MODIFIED].to_str().unwrap().to_string();
+        let max_age: u64 = full.headers()[CACHE_CONTROL]
+            .to_str()
+            .unwrap()
+            .strip_prefix("public, max-age=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(max_age > 590 && max_age <= 600);
+        assert_eq!(&body(full).await[..], &png[..]);
+
+        let revalidated = request("GET", &[("if-none-match", &etag)]).await.unwrap();
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(revalidated.headers()[ETAG], etag.as_str());
+        assert!(body(revalidated).await.is_empty());
+        let since = request("GET", &[("if-modified-since", &last_modified)])
+            .await
+            .unwrap();
+        assert_eq!(since.status(), StatusCode::NOT_MODIFIED);
+
+        let head = request("HEAD", &[]).await.unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()[ETAG], etag.as_str());
+        assert_eq!(head.headers()[CONTENT_TYPE], "image/png");
+        assert!(body(head).await.is_empty());
+
+        let partial = request("GET", &[("range", "bytes=0-7")]).await.unwrap();
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            partial.headers()[CONTENT_RANGE],
+            format!("bytes 0-7/{}", png.len()).as_str()
+        );
+        assert_eq!(&body(partial).await[..], &png[..8]);
+        let beyond = request("GET", &[("range", "bytes=100000-")]).await.unwrap();
+        assert_eq!(beyond.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    /// Memory store that presigns like a bucket would
+    #[derive(Debug, Default)]
+    struct PresigningStore(MemoryStore);
+
+    impl ThumbnailStore for PresigningStore {
+        fn describe(&self) -> String {
+            self.0.describe()
+        }
+        fn get(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+            self.0.get(name)
+        }
+        fn put(&self, name: &str, data: &[u8]) -> Result<(), String> {
+            self.0.put(name, data)
+        }
+        fn delete(&self, name: &str) -> Result<(), String> {
+            self.0.delete(name)
+        }
+        fn size(&self, name: &str) -> Result<Option<u64>, String> {
+            self.0.size(name)
+        }
+        fn list(&self) -> Result<Vec<crate::store::StoredObject>, String> {
+            self.0.list()
+        }
+        fn can_presign(&self) -> bool {
+            true
+        }
+        fn presigned_url(&self, name: &str, ttl: Duration) -> Option<String> {
+            Some(format!(
+                "https://bucket.example.com/{}?ttl={}",
+                name,
+                ttl.as_secs()
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_thumbnail_redirects_to_presigned_url() {
+        let store = Arc::new(PresigningStore::default());
+        let signer = UrlSigner::new(b"key", Duration::from_secs(300));
+        let cache = ThumbnailCache::new(
+            ThumbnailCacheConfig::new(PathBuf::new(), Duration::from_secs(600), "localhost", 8080)
+                .with_signer(signer)
+                .with_store(store.clone()),
+        )
+        .unwrap();
+        let png = crate::raster::Rgba::new(40, 20, [1, 2, 3, 255])
+            .encode_png()
+            .unwrap();
+        cache.save_thumbnail_as("k", "asset", &png).unwrap();
+        let link = cache.variant_url(
+            "k",
+            &VariantSpec::from_query(&HashMap::from([
+                ("w".to_string(), "10".to_string()),
+                ("format".to_string(), "jpeg".to_string()),
+            ]))
+            .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                &format!("{}/:cache_key", THUMBNAIL_ROUTE),
+                get(serve_thumbnail),
+            )
+            .with_state(AppState::new("test", "0.0.0", Some(cache)));
+        let get_path = |path: String| {
+            app.clone()
+                .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        };
+
+        // The variant is rendered into the store, then served from there
+        let path = link
+            .strip_prefix("http://localhost:8080")
+            .unwrap()
+            .to_string();
+        let redirect = get_path(path.clone()).await.unwrap();
+        assert_eq!(redirect.status(), StatusCode::TEMPORARY_REDIRECT);
+        let location = redirect.headers()[LOCATION].to_str().unwrap();
+        let (name, ttl) = location
+            .strip_prefix("https://bucket.example.com/")
+            .unwrap()
+            .split_once("?ttl=")
+            .unwrap();
+        assert!(name.starts_with("k_") && name.ends_with(".jpeg"));
+        assert!(store.get(name).unwrap().is_some());
+        // Limited by the signed link (rounded up to a quarter TTL) rather than the entry
+        assert!(ttl.parse::<u64>().unwrap() <= 375);
+
+        let unsigned = get_path(format!("{}/k", THUMBNAIL_ROUTE)).await.unwrap();
+        assert_eq!(unsigned.status(), StatusCode::FORBIDDEN);
+
+        // The signature covers the variant, so the link cannot pick another one
+        let other_variant = path.replace("format=jpeg", "format=webp");
+        assert_ne!(other_variant, path);
+        let refused = get_path(other_variant).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    }
+
     #[test]
     fn test_lerp() {
         // Test edge cases
@@ -0,0 +1,362 @@
+//! Signed, expiring thumbnail links.
+//!
+//! A thumbnail URL carries `expires` (Unix seconds) and `sig`, an HMAC-SHA256
+//! of the cache key and expiry under a server secret. `/thumbnail/:cache_key`
+//! only serves an image for a link that is signed and unexpired, so links work
+//! without bearer auth but cannot be guessed, forged or used indefinitely. The
+//! secret is kept in `~/.pcli2-mcp/signing.key`, so links survive a restart
+//! and servers sharing the file accept each other's links.
+
+use crate::hash::{from_hex, random_hex, to_hex};
+use chrono::Utc;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a thumbnail link stays valid unless configured otherwise
+pub const DEFAULT_URL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Length of a generated secret
+const KEY_BYTES: usize = 32;
+/// Signature bytes kept in a link; 128 bits keep URLs short
+const SIGNATURE_BYTES: usize = 16;
+/// Reads of a key file that is still empty or incomplete before giving up
+const LOAD_ATTEMPTS: u32 = 5;
+/// Pause between those reads
+const LOAD_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Why a thumbnail link was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    /// The link has no `expires` or `sig` parameter
+    Unsigned,
+    /// The signature does not match the cache key and expiry
+    BadSignature,
+    /// The link's expiry has passed
+    Expired,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "Thumbnail link is not signed"),
+            Self::BadSignature => write!(f, "Thumbnail link signature is invalid"),
+            Self::Expired => write!(f, "Thumbnail link has expired"),
+        }
+    }
+}
+
+/// Signs and checks thumbnail links
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlSigner")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    /// Signer using `key`, issuing links valid for `ttl`
+    pub fn new(key: &[u8], ttl: Duration) -> Self {
+        Self {
+            key: key.to_vec(),
+            ttl,
+        }
+    }
+
+    /// Signer with a fresh secret that only this process knows
+    pub fn random(ttl: Duration) -> Result<Self, String> {
+        let key = random_hex(KEY_BYTES)?;
+        Ok(Self::new(&from_hex(&key).unwrap_or_default(), ttl))
+    }
+
+    /// Signer using the hex secret in `path`, creating the file if it is missing
+    ///
+    /// The key is written to a temporary file and then hard-linked into
+    /// place, so `path` never exists half-written. When another server
+    /// creates the file first, its key is read back so both sign with the
+    /// same secret.
+    pub fn load_or_create(path: &Path, ttl: Duration) -> Result<Self, String> {
+        if path.exists() {
+            return Self::load(path, ttl);
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| format!("Failed to create directory {:?}: {}", dir, err))?;
+        }
+        let key = random_hex(KEY_BYTES)?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", random_hex(8)?));
+        let temp = PathBuf::from(temp);
+        write_private(&temp, &key)
+            .map_err(|err| format!("Failed to write signing key {:?}: {}", temp, err))?;
+        let linked = fs::hard_link(&temp, path);
+        let _ = fs::remove_file(&temp);
+        match linked {
+            Ok(()) => Ok(Self::new(&from_hex(&key).unwrap_or_default(), ttl)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Self::load(path, ttl),
+            Err(err) => Err(format!("Failed to create signing key {:?}: {}", path, err)),
+        }
+    }
+
+    /// Signer using the hex secret in the existing file `path`
+    ///
+    /// A file that is empty or not a valid key is read again a few times,
+    /// in case a server that does not link its key into place is writing it.
+    fn load(path: &Path, ttl: Duration) -> Result<Self, String> {
+        let mut attempt = 1;
+        loop {
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read signing key {:?}: {}", path, err))?;
+            match from_hex(text.trim()).filter(|key| key.len() >= KEY_BYTES / 2) {
+                Some(key) => return Ok(Self::new(&key, ttl)),
+                None if attempt < LOAD_ATTEMPTS => {
+                    std::thread::sleep(LOAD_RETRY_DELAY);
+                    attempt += 1;
+                }
+                None => {
+                    return Err(format!(
+                        "Signing key {:?} is not at least 16 hex bytes",
+                        path
+                    ));
+                }
+            }
+        }
+    }
+
+    /// How long issued links stay valid
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Query string signing `cache_key` until at least the TTL has passed
+    ///
+    /// The expiry is rounded up to a quarter of the TTL, so links issued for
+    /// the same key within that window are identical and stay cacheable.
+    pub fn sign(&self, cache_key: &str) -> String {
+        let ttl = self.ttl.as_secs() as i64;
+        let bucket = (ttl / 4).max(1);
+        let expires = Utc::now().timestamp() + ttl;
+        let expires = (expires + bucket - 1) / bucket * bucket;
+        self.sign_until(cache_key, expires)
+    }
+
+    /// Query string signing `cache_key` until `expires` (Unix seconds)
+    pub fn sign_until(&self, cache_key: &str, expires: i64) -> String {
+        let signature = self.mac(cache_key, expires).finalize().into_bytes();
+        format!(
+            "expires={}&sig={}",
+            expires,
+            to_hex(&signature[..SIGNATURE_BYTES])
+        )
+    }
+
+    /// Check a link's `expires` and `sig` parameters
+    ///
+    /// Returns the seconds left before the link expires.
+    pub fn verify(
+        &self,
+        cache_key: &str,
+        expires: Option<&str>,
+        signature: Option<&str>,
+    ) -> Result<u64, LinkError> {
+        let (Some(expires), Some(signature)) = (expires, signature) else {
+            return Err(LinkError::Unsigned);
+        };
+        let expires: i64 = expires.parse().map_err(|_| LinkError::BadSignature)?;
+        let signature = from_hex(signature)
+            .filter(|signature| signature.len() == SIGNATURE_BYTES)
+            .ok_or(LinkError::BadSignature)?;
+        self.mac(cache_key, expires)
+            .verify_truncated_left(&signature)
+            .map_err(|_| LinkError::BadSignature)?;
+
+        let remaining = expires - Utc::now().timestamp();
+        if remaining <= 0 {
+            return Err(LinkError::Expired);
+        }
+        Ok(remaining as u64)
+    }
+
+    /// Stable identifier for `material` that only holders of the secret can compute
+    ///
+    /// Used for cache keys that must be found again without being guessable.
+    pub fn derive_key(&self, material: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(format!("key\0{}", material).as_bytes());
+        to_hex(&mac.finalize().into_bytes()[..SIGNATURE_BYTES])
+    }
+
+    fn mac(&self, cache_key: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(format!("thumbnail\0{}\0{}", cache_key, expires).as_bytes());
+        mac
+    }
+}
+
+/// Write `contents` to a new file only the current user can read
+///
+/// Fails with `AlreadyExists` when the file is already there.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    if let Err(err) = file
+        .write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+    {
+        let _ = fs::remove_file(path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Get the default signing key path
+///
+/// Uses ~/.pcli2-mcp/signing.key, next to the thumbnail cache
+pub fn default_signing_key_path() -> Result<PathBuf, String> {
+    let mut path = crate::thumbnail::default_cache_dir()?;
+    path.pop();
+    path.push("signing.key");
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn query(signed: &str) -> (String, String) {
+        let mut expires = String::new();
+        let mut signature = String::new();
+        for pair in signed.split('&') {
+            match pair.split_once('=') {
+                Some(("expires", value)) => expires = value.to_string(),
+                Some(("sig", value)) => signature = value.to_string(),
+                _ => {}
+            }
+        }
+        (expires, signature)
+    }
+
+    #[test]
+    fn test_signed_links_verify() {
+        let signer = UrlSigner::new(b"secret", Duration::from_secs(60));
+        let (expires, signature) = query(&signer.sign("abc"));
+        let remaining = signer
+            .verify("abc", Some(&expires), Some(&signature))
+            .unwrap();
+        assert!((59..=75).contains(&remaining));
+
+        assert_eq!(
+            signer.verify("abd", Some(&expires), Some(&signature)),
+            Err(LinkError::BadSignature)
+        );
+        let later = (expires.parse::<i64>().unwrap() + 1).to_string();
+        assert_eq!(
+            signer.verify("abc", Some(&later), Some(&signature)),
+            Err(LinkError::BadSignature)
+        );
+        let other = UrlSigner::new(b"other", Duration::from_secs(60));
+        assert_eq!(
+            other.verify("abc", Some(&expires), Some(&signature)),
+            Err(LinkError::BadSignature)
+        );
+        assert_eq!(
+            signer.verify("abc", None, Some(&signature)),
+            Err(LinkError::Unsigned)
+        );
+    }
+
+    #[test]
+    fn test_repeated_links_are_identical() {
+        let signer = UrlSigner::new(b"secret", Duration::from_secs(3600));
+        let first = signer.sign("abc");
+        assert_eq!(first, signer.sign("abc"));
+        let (expires, _) = query(&first);
+        assert_eq!(expires.parse::<i64>().unwrap() % 900, 0);
+    }
+
+    #[test]
+    fn test_expired_links_are_refused() {
+        let signer = UrlSigner::new(b"secret", Duration::from_secs(60));
+        let expires = Utc::now().timestamp() - 1;
+        let (expires, signature) = query(&signer.sign_until("abc", expires));
+        assert_eq!(
+            signer.verify("abc", Some(&expires), Some(&signature)),
+            Err(LinkError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_key_file_is_created_once() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("pcli2-signing-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("signing.key");
+
+        let first = UrlSigner::load_or_create(&path, DEFAULT_URL_TTL).unwrap();
+        let second = UrlSigner::load_or_create(&path, DEFAULT_URL_TTL).unwrap();
+        let (expires, signature) = query(&first.sign("abc"));
+        assert!(
+            second
+                .verify("abc", Some(&expires), Some(&signature))
+                .is_ok()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // No temporary files are left next to the key
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::write(&path, "not hex").unwrap();
+        assert!(UrlSigner::load_or_create(&path, DEFAULT_URL_TTL).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_key_file_being_written_is_read_again() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("pcli2-signing-retry-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("signing.key");
+        fs::write(&path, "").unwrap();
+
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(LOAD_RETRY_DELAY);
+                fs::write(&path, "00".repeat(KEY_BYTES)).unwrap();
+            })
+        };
+        let signer = UrlSigner::load_or_create(&path, DEFAULT_URL_TTL).unwrap();
+        writer.join().unwrap();
+        assert_eq!(signer.key, vec![0; KEY_BYTES]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,379 @@
+//! Storage backends for the thumbnail cache.
+//!
+//! [`ThumbnailCache`](crate::thumbnail::ThumbnailCache) keeps each thumbnail
+//! as two named objects, the image and its `.meta` sidecar, in a
+//! [`ThumbnailStore`]. [`FsStore`] keeps them in a local directory,
+//! [`MemoryStore`] in process memory for tests and ephemeral containers, and
+//! [`S3Store`](crate::s3::S3Store) in an S3-compatible bucket that several
+//! replicas can share.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// `--thumbnail-store` value for [`FsStore`]
+pub const STORE_FS: &str = "fs";
+/// `--thumbnail-store` value for [`MemoryStore`]
+pub const STORE_MEMORY: &str = "memory";
+/// `--thumbnail-store` value for [`S3Store`](crate::s3::S3Store)
+pub const STORE_S3: &str = "s3";
+
+/// File in a cache directory locked while the cache is changed
+const LOCK_FILE: &str = ".lock";
+
+/// Extension appended to files while they are being written
+const TEMP_EXTENSION: &str = "tmp";
+
+/// An object in a store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub name: String,
+    pub bytes: u64,
+    /// Last write (Unix timestamp in milliseconds), when the store knows it
+    pub modified: Option<i64>,
+}
+
+/// Held while the cache is changed; the lock is released on drop
+#[derive(Debug, Default)]
+pub struct StoreLock {
+    _file: Option<File>,
+}
+
+/// Where the thumbnail cache keeps its objects
+///
+/// Writes replace an object as a whole, so readers never see part of one.
+pub trait ThumbnailStore: fmt::Debug + Send + Sync {
+    /// Where the objects are, for logs
+    fn describe(&self) -> String;
+
+    /// Contents of an object, `None` when it does not exist
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Create or replace an object
+    fn put(&self, name: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Delete an object; deleting a missing object succeeds
+    fn delete(&self, name: &str) -> Result<(), String>;
+
+    /// Size of an object, `None` when it does not exist
+    fn size(&self, name: &str) -> Result<Option<u64>, String>;
+
+    /// Every object
+    fn list(&self) -> Result<Vec<StoredObject>, String>;
+
+    /// Serialize changes with other processes using the store
+    ///
+    /// Stores that cannot lock return a guard that holds nothing.
+    fn lock(&self, _exclusive: bool) -> Result<StoreLock, String> {
+        Ok(StoreLock::default())
+    }
+
+    /// Whether [`lock`](Self::lock) serializes every process using the store
+    ///
+    /// When it does not, another process may be halfway through writing a
+    /// thumbnail, so recovery and cleanup leave recent images alone.
+    fn can_lock(&self) -> bool {
+        false
+    }
+
+    /// Remove what interrupted writes left behind, returning how many were removed
+    fn remove_incomplete(&self) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    /// Path of an object on the local disk, for stores that have one
+    fn local_path(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether [`presigned_url`](Self::presigned_url) hands out URLs
+    fn can_presign(&self) -> bool {
+        false
+    }
+
+    /// URL that fetches an object straight from the store for `ttl`
+    fn presigned_url(&self, _name: &str, _ttl: Duration) -> Option<String> {
+        None
+    }
+}
+
+/// Objects as files in a local directory
+///
+/// Writes go through a synced temporary file and a rename, and changes take
+/// an advisory lock on the directory's `.lock` file, so several processes
+/// can share the directory.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    dir: PathBuf,
+}
+
+impl FsStore {
+    /// Store in `dir`, creating it if needed
+    pub fn new(dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|err| {
+            format!(
+                "Failed to create thumbnail cache directory {:?}: {}",
+                dir, err
+            )
+        })?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn read_dir(&self) -> Result<fs::ReadDir, String> {
+        fs::read_dir(&self.dir)
+            .map_err(|err| format!("Failed to read cache directory {:?}: {}", self.dir, err))
+    }
+}
+
+impl ThumbnailStore for FsStore {
+    fn describe(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path(name);
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("Failed to read {:?}: {}", path, err)),
+        }
+    }
+
+    fn put(&self, name: &str, data: &[u8]) -> Result<(), String> {
+        write_atomic(&self.path(name), data)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        let path = self.path(name);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Failed to remove {:?}: {}", path, err))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn size(&self, name: &str) -> Result<Option<u64>, String> {
+        Ok(fs::metadata(self.path(name)).ok().map(|meta| meta.len()))
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = Vec::new();
+        for entry in self.read_dir()?.flatten() {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name == LOCK_FILE || is_temp_name(&name) {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            objects.push(StoredObject {
+                name,
+                bytes: meta.len(),
+                modified: meta
+                    .modified()
+                    .ok()
+                    .map(|time| DateTime::<Utc>::from(time).timestamp_millis()),
+            });
+        }
+        Ok(objects)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<StoreLock, String> {
+        let path = self.path(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|err| format!("Failed to open cache lock {:?}: {}", path, err))?;
+        let locked = if exclusive {
+            file.lock()
+        } else {
+            file.lock_shared()
+        };
+        locked.map_err(|err| format!("Failed to lock cache {:?}: {}", path, err))?;
+        Ok(StoreLock { _file: Some(file) })
+    }
+
+    fn can_lock(&self) -> bool {
+        true
+    }
+
+    fn remove_incomplete(&self) -> Result<usize, String> {
+        let mut removed = 0;
+        for entry in self.read_dir()?.flatten() {
+            if entry.file_name().to_str().is_some_and(is_temp_name) {
+                self.delete(&entry.file_name().to_string_lossy())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn local_path(&self, name: &str) -> Option<PathBuf> {
+        Some(self.path(name))
+    }
+}
+
+/// Objects in process memory, lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    objects: Mutex<BTreeMap<String, (Vec<u8>, i64)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn objects(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, (Vec<u8>, i64)>> {
+        self.objects
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ThumbnailStore for MemoryStore {
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.objects().get(name).map(|(data, _)| data.clone()))
+    }
+
+    fn put(&self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.objects().insert(
+            name.to_string(),
+            (data.to_vec(), Utc::now().timestamp_millis()),
+        );
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        self.objects().remove(name);
+        Ok(())
+    }
+
+    fn size(&self, name: &str) -> Result<Option<u64>, String> {
+        Ok(self.objects().get(name).map(|(data, _)| data.len() as u64))
+    }
+
+    /// Only this process sees the objects, so the cache's own lock is enough
+    fn can_lock(&self) -> bool {
+        true
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        Ok(self
+            .objects()
+            .iter()
+            .map(|(name, (data, modified))| StoredObject {
+                name: name.clone(),
+                bytes: data.len() as u64,
+                modified: Some(*modified),
+            })
+            .collect())
+    }
+}
+
+/// Replace `path` with `data` through a synced temporary file and a rename
+///
+/// Callers hold the cache lock, so the temporary name cannot collide.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".");
+    temp.push(TEMP_EXTENSION);
+    let temp = PathBuf::from(temp);
+
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        // Persist the rename itself, so a crash cannot bring back the old file
+        #[cfg(unix)]
+        if let Some(dir) = path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    };
+    write().map_err(|err| {
+        let _ = fs::remove_file(&temp);
+        format!("Failed to write {:?}: {}", path, err)
+    })
+}
+
+fn is_temp_name(name: &str) -> bool {
+    name.ends_with(&format!(".{}", TEMP_EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn exercise(store: &dyn ThumbnailStore) {
+        assert_eq!(store.get("a.png").unwrap(), None);
+        store.put("a.png", b"first").unwrap();
+        store.put("a.png", b"second").unwrap();
+        store.put("a.meta", b"{}").unwrap();
+        assert_eq!(store.get("a.png").unwrap().as_deref(), Some(&b"second"[..]));
+        assert_eq!(store.size("a.png").unwrap(), Some(6));
+
+        let mut names: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|object| object.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.meta", "a.png"]);
+
+        store.delete("a.png").unwrap();
+        store.delete("a.png").unwrap();
+        assert_eq!(store.size("a.png").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        exercise(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_fs_store() {
+        let dir = env::temp_dir().join(format!("pcli2-store-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = FsStore::new(dir.clone()).unwrap();
+        let _lock = store.lock(false).unwrap();
+        exercise(&store);
+
+        // Leftovers of an interrupted write are not objects
+        fs::write(dir.join("b.png.tmp"), b"partial").unwrap();
+        assert!(store.list().unwrap().iter().all(|o| o.name == "a.meta"));
+        assert_eq!(store.remove_incomplete().unwrap(), 1);
+        assert_eq!(store.local_path("a.meta"), Some(dir.join("a.meta")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -3,24 +3,76 @@
 //! This module provides functionality to cache thumbnail images on disk
 //! and serve them via HTTP URLs, avoiding the need to transmit large
 //! base64-encoded images in MCP responses.
-
-use chrono::Utc;
+//!
+//! The cache can be bounded by total size and entry count. When a bound is
+//! exceeded, the least recently used thumbnails are evicted; the access time
+//! is kept in each thumbnail's metadata file. Sizes and access times are
+//! also indexed in memory, so enforcing the bounds and reporting statistics
+//! do not list the store. The index is rebuilt from the store when the cache
+//! is opened and on every sweep, which picks up changes made by other
+//! processes sharing the store.
+//!
+//! Resized or re-encoded variants of a thumbnail are cached as entries of
+//! their own, under the original's key followed by `_` and the variant tag,
+//! and are dropped when the original is replaced.
+//!
+//! With a [`UrlSigner`], every URL handed out is signed and expires; the
+//! HTTP endpoint checks the link with [`ThumbnailCache::verify_link`].
+//!
+//! Images and metadata are kept in a [`ThumbnailStore`], by default the
+//! cache directory ([`FsStore`]). Changes are serialized by a lock in the
+//! cache itself and by the store's lock, for the cache directory an advisory
+//! lock on its `.lock` file, so `pcli2-mcp cache` and several servers can
+//! share one cache. Stores that cannot lock across processes may be written
+//! by another replica at any moment, so recovery and cleanup leave images
+//! younger than [`UNLOCKED_WRITE_GRACE`] alone. Each object is replaced
+//! as a whole, the image before its metadata, so a crash never leaves a
+//! half-written file under a cache key. Opening the cache removes leftovers
+//! of interrupted writes and repairs or deletes images and metadata that
+//! lost their counterpart.
+
+use crate::hash::{random_hex, sha256_hex};
+use crate::limits::ACTIVE_TENANT_KEY;
+use crate::signing::{LinkError, UrlSigner};
+use crate::store::{FsStore, StoreLock, StoredObject, ThumbnailStore};
+use crate::variant::{ImageFormat, VariantSpec};
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
-use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::Duration;
 use tracing::{debug, info, warn};
 
 /// Default TTL for cached thumbnails
 pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
 
+/// Route serving cached thumbnails, relative to the server's origin
+pub const THUMBNAIL_ROUTE: &str = "/thumbnail";
+
+/// Default time between background sweeps of the cache
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 /// File extension for cached thumbnails
 const THUMBNAIL_EXTENSION: &str = "png";
 
+/// File extensions of cached thumbnails and their variants
+const IMAGE_EXTENSIONS: [&str; 3] = [THUMBNAIL_EXTENSION, "jpeg", "webp"];
+
+/// Separates an original's cache key from a variant tag
+const VARIANT_SEPARATOR: char = '_';
+
 /// Metadata file extension
 const METADATA_EXTENSION: &str = "meta";
 
+/// Source recorded for an image whose metadata had to be recreated
+const RECOVERED_SOURCE: &str = "(recovered)";
+
+/// Age below which an image in a store without locks may still be getting its metadata
+pub const UNLOCKED_WRITE_GRACE: Duration = Duration::from_secs(60);
+
 /// Thumbnail cache configuration
 #[derive(Clone, Debug)]
 pub struct ThumbnailCacheConfig {
@@ -30,48 +82,347 @@ pub struct ThumbnailCacheConfig {
     pub ttl: Duration,
     /// Base URL for serving thumbnails
     pub base_url: String,
+    /// Largest total size of the cached thumbnails in bytes, if bounded
+    pub max_bytes: Option<u64>,
+    /// Largest number of cached thumbnails, if bounded
+    pub max_entries: Option<usize>,
+    /// Signs thumbnail URLs; without one, URLs are plain and never expire
+    pub signer: Option<UrlSigner>,
+    /// Where thumbnails are kept; `None` keeps them in `cache_dir`
+    pub store: Option<Arc<dyn ThumbnailStore>>,
 }
 
 impl ThumbnailCacheConfig {
     pub fn new(cache_dir: PathBuf, ttl: Duration, host: &str, port: u16) -> Self {
-        let base_url = format!("http://{}:{}/thumbnail", host, port);
+        let base_url = format!("http://{}:{}{}", host, port, THUMBNAIL_ROUTE);
         Self {
             cache_dir,
             ttl,
             base_url,
+            max_bytes: None,
+            max_entries: None,
+            signer: None,
+            store: None,
         }
     }
+
+    /// Sign every thumbnail URL with `signer`
+    pub fn with_signer(mut self, signer: UrlSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Serve thumbnails from `public_url` instead of the bind host and port
+    pub fn with_public_url(mut self, public_url: &str) -> Self {
+        self.base_url = format!("{}{}", public_url.trim_end_matches('/'), THUMBNAIL_ROUTE);
+        self
+    }
+
+    /// Bound the cache by total size and entry count
+    pub fn with_limits(mut self, max_bytes: Option<u64>, max_entries: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Keep thumbnails in `store` instead of the cache directory
+    pub fn with_store(mut self, store: Arc<dyn ThumbnailStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
 }
 
 /// Metadata stored alongside each cached thumbnail
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ThumbnailMetadata {
     /// When the thumbnail was cached (as Unix timestamp in milliseconds)
     pub cached_at: i64,
     /// Original asset path or UUID (for reference)
     pub source: String,
-    /// Content hash (optional, for deduplication)
+    /// SHA-256 of the PNG data, hex encoded
     pub content_hash: Option<String>,
+    /// When the thumbnail was last served or reused (Unix timestamp in milliseconds)
+    #[serde(default)]
+    pub last_accessed: Option<i64>,
+}
+
+impl ThumbnailMetadata {
+    /// Time used to order thumbnails for eviction
+    fn last_used(&self) -> i64 {
+        self.last_accessed.unwrap_or(self.cached_at)
+    }
+}
+
+/// Validators and remaining lifetime of a cached thumbnail, for HTTP caching
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThumbnailFreshness {
+    /// SHA-256 of the image data, hex encoded
+    pub content_hash: String,
+    /// When the image was cached
+    pub last_modified: DateTime<Utc>,
+    /// Time left before the entry expires
+    pub expires_in: Duration,
+}
+
+/// Size and usage counters, returned by the status and cleanup tools
+#[derive(Debug, Clone, Serialize)]
+pub struct ThumbnailCacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups answered from the cache, between 0 and 1
+    pub hit_rate: f64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+/// Thumbnails removed by one sweep
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SweepResult {
+    pub expired: usize,
+    pub evicted: usize,
+}
+
+/// A cached thumbnail or variant, as listed by `pcli2-mcp cache`
+#[derive(Debug, Clone, Serialize)]
+pub struct ThumbnailEntry {
+    pub key: String,
+    /// Image file, for stores on the local disk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub bytes: u64,
+    /// `None` when the metadata file is missing or unreadable
+    pub metadata: Option<ThumbnailMetadata>,
+    pub expired: bool,
+}
+
+/// Which thumbnails [`ThumbnailCache::purge`] removes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PurgeFilter {
+    /// Thumbnails past their TTL
+    Expired,
+    /// Every thumbnail and metadata file
+    All,
+    /// Thumbnails of an asset path or UUID, with their variants
+    Source(String),
+}
+
+/// What opening the cache repaired, as counted by [`ThumbnailCache::recover`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RecoveryResult {
+    /// Leftovers of interrupted writes, such as temporary files
+    pub temp_files: usize,
+    /// Images given new metadata
+    pub repaired: usize,
+    /// Unreadable images and metadata without an image
+    pub removed: usize,
+}
+
+/// A stored thumbnail, as seen when enforcing the cache bounds
+struct CacheEntry {
+    key: String,
+    bytes: u64,
+    last_used: i64,
+}
+
+/// Held while the cache is read or changed; released on drop
+struct CacheLock<'a> {
+    _shared: Option<RwLockReadGuard<'a, ()>>,
+    _exclusive: Option<RwLockWriteGuard<'a, ()>>,
+    _store: StoreLock,
+}
+
+/// Size and last use of every stored thumbnail, ordered for eviction
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, (u64, i64)>,
+    by_use: BTreeSet<(i64, String)>,
+    bytes: u64,
+    /// Access times not yet written to the metadata, persisted by the sweep
+    accessed: HashMap<String, i64>,
+}
+
+impl CacheIndex {
+    fn from_entries(entries: Vec<CacheEntry>) -> Self {
+        let mut index = Self::default();
+        for entry in entries {
+            index.insert(&entry.key, entry.bytes, entry.last_used);
+        }
+        index
+    }
+
+    fn insert(&mut self, key: &str, bytes: u64, last_used: i64) {
+        self.remove(key);
+        self.entries.insert(key.to_string(), (bytes, last_used));
+        self.by_use.insert((last_used, key.to_string()));
+        self.bytes += bytes;
+    }
+
+    fn touch(&mut self, key: &str, last_used: i64) {
+        if let Some(&(bytes, _)) = self.entries.get(key) {
+            self.insert(key, bytes, last_used);
+            self.accessed.insert(key.to_string(), last_used);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.accessed.remove(key);
+        if let Some((bytes, last_used)) = self.entries.remove(key) {
+            self.by_use.remove(&(last_used, key.to_string()));
+            self.bytes -= bytes;
+        }
+    }
+
+    /// Least recently used key that is neither `keep` nor in `skip`
+    fn oldest(&self, keep: Option<&str>, skip: &HashSet<String>) -> Option<String> {
+        self.by_use
+            .iter()
+            .map(|(_, key)| key)
+            .find(|key| keep != Some(key.as_str()) && !skip.contains(*key))
+            .cloned()
+    }
 }
 
 /// Thumbnail cache for storing and retrieving cached thumbnails
 pub struct ThumbnailCache {
     config: ThumbnailCacheConfig,
+    store: Arc<dyn ThumbnailStore>,
+    /// Serializes this process's changes, whether or not the store can lock
+    lock: RwLock<()>,
+    index: Mutex<CacheIndex>,
+    /// Keys asset keys; the link signer, or a key only this process knows
+    asset_keys: Option<UrlSigner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
 }
 
 impl ThumbnailCache {
     /// Create a new thumbnail cache with the given configuration
     pub fn new(config: ThumbnailCacheConfig) -> Result<Self, String> {
-        let cache = Self { config };
-        cache.ensure_cache_dir()?;
+        let store = match &config.store {
+            Some(store) => store.clone(),
+            None => Arc::new(FsStore::new(config.cache_dir.clone())?),
+        };
+        debug!("Thumbnail cache store ready: {}", store.describe());
+        let asset_keys = config
+            .signer
+            .clone()
+            .or_else(|| match UrlSigner::random(config.ttl) {
+                Ok(signer) => Some(signer),
+                Err(err) => {
+                    warn!("{}; deriving asset thumbnail keys without a secret", err);
+                    None
+                }
+            });
+        let cache = Self {
+            config,
+            store,
+            lock: RwLock::new(()),
+            index: Mutex::new(CacheIndex::default()),
+            asset_keys,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+        };
+        match cache.recover() {
+            Ok(result) if result != RecoveryResult::default() => info!(
+                "Recovered thumbnail cache: removed {} temporary file(s) and {} orphan(s), repaired {} thumbnail(s)",
+                result.temp_files, result.removed, result.repaired
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Failed to recover thumbnail cache: {}", err),
+        }
+        if let Err(err) = cache.reindex() {
+            warn!("Failed to index thumbnail cache: {}", err);
+        }
         Ok(cache)
     }
 
-    /// Get the cache directory path
+    /// Clean up after interrupted writes and removals
+    ///
+    /// Removes what interrupted writes left behind, deletes metadata whose
+    /// image is gone, and gives images without readable metadata new metadata
+    /// dated from the object, or deletes them when they do not look like an
+    /// image of their format.
+    pub fn recover(&self) -> Result<RecoveryResult, String> {
+        let _lock = self.lock(true)?;
+        let mut result = RecoveryResult {
+            temp_files: self.store.remove_incomplete()?,
+            ..RecoveryResult::default()
+        };
+
+        let objects = self.store.list()?;
+        let names: HashSet<&str> = objects.iter().map(|object| object.name.as_str()).collect();
+        for object in &objects {
+            let Some((cache_key, extension)) = object.name.rsplit_once('.') else {
+                continue;
+            };
+            if extension == METADATA_EXTENSION {
+                if !names.contains(Self::image_name(cache_key).as_str()) {
+                    self.remove_logged(&object.name);
+                    result.removed += 1;
+                }
+            } else if IMAGE_EXTENSIONS.contains(&extension)
+                && !self.may_be_in_flight(object)
+                && self.metadata(cache_key).is_none()
+            {
+                match self.repair_metadata(cache_key, object) {
+                    Ok(()) => result.repaired += 1,
+                    Err(err) => {
+                        debug!("Removing unrecoverable thumbnail {}: {}", object.name, err);
+                        self.remove_logged(&object.name);
+                        self.remove_logged(&Self::metadata_name(cache_key));
+                        result.removed += 1;
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Recreate the metadata of an image whose metadata is missing or unreadable
+    fn repair_metadata(&self, cache_key: &str, object: &StoredObject) -> Result<(), String> {
+        let data = self
+            .store
+            .get(&object.name)?
+            .ok_or_else(|| "removed while recovering".to_string())?;
+        if !has_image_signature(&object.name, &data) {
+            return Err("not a valid image".to_string());
+        }
+        let cached_at = object
+            .modified
+            .unwrap_or_else(|| Utc::now().timestamp_millis());
+        // Variants record their original's key as their source
+        let source = cache_key
+            .split_once(VARIANT_SEPARATOR)
+            .map_or(RECOVERED_SOURCE, |(original, _)| original);
+        self.write_metadata(
+            cache_key,
+            &ThumbnailMetadata {
+                cached_at,
+                source: source.to_string(),
+                content_hash: Some(sha256_hex(&data)),
+                last_accessed: None,
+            },
+        )
+    }
+
+    /// Get the cache directory path, used when no other store is configured
     pub fn cache_dir(&self) -> &Path {
         &self.config.cache_dir
     }
 
+    /// Where thumbnails are kept
+    pub fn store(&self) -> &dyn ThumbnailStore {
+        self.store.as_ref()
+    }
+
     /// Get the base URL for serving thumbnails
     pub fn base_url(&self) -> &str {
         &self.config.base_url
@@ -82,39 +433,202 @@ impl ThumbnailCache {
         self.config.ttl
     }
 
-    /// Ensure the cache directory exists
-    fn ensure_cache_dir(&self) -> Result<(), String> {
-        fs::create_dir_all(&self.config.cache_dir).map_err(|err| {
-            format!(
-                "Failed to create thumbnail cache directory {:?}: {}",
-                self.config.cache_dir, err
-            )
-        })?;
-        debug!(
-            "Thumbnail cache directory ready: {:?}",
-            self.config.cache_dir
-        );
-        Ok(())
-    }
-
     /// Generate a unique cache key for a thumbnail
     ///
-    /// The cache key is based on the source identifier (path or UUID) and a timestamp
-    /// to allow multiple versions of the same asset to be cached.
+    /// The key is 128 random bits, so it cannot be guessed from the source
+    /// identifier (path or UUID) or the time it was cached.
     pub fn generate_cache_key(&self, source: &str) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        use std::time::{SystemTime, UNIX_EPOCH};
+        random_hex(16).unwrap_or_else(|err| {
+            warn!("{}; deriving thumbnail cache key from time", err);
+            let material = format!(
+                "{}\0{}",
+                source,
+                Utc::now().timestamp_nanos_opt().unwrap_or(0)
+            );
+            sha256_hex(material.as_bytes())[..32].to_string()
+        })
+    }
+
+    /// Stable cache key for the thumbnail of an asset
+    ///
+    /// The key depends only on the tenant and the asset UUID, so the same
+    /// asset is found again by later calls instead of being downloaded anew.
+    /// It is an HMAC under the link signing secret, so knowing an asset's
+    /// UUID is not enough to fetch its thumbnail.
+    pub fn asset_key(&self, tenant: Option<&str>, asset_uuid: &str) -> String {
+        let tenant = tenant.unwrap_or(ACTIVE_TENANT_KEY);
+        let material = format!("thumbnail\0{}\0{}", tenant, asset_uuid);
+        match &self.asset_keys {
+            Some(signer) => signer.derive_key(&material),
+            None => sha256_hex(material.as_bytes())[..32].to_string(),
+        }
+    }
+
+    /// URL serving the thumbnail stored under `cache_key`, signed when a signer is configured
+    pub fn url_for(&self, cache_key: &str) -> String {
+        self.variant_url(cache_key, &VariantSpec::default())
+    }
+
+    /// URL serving `spec`'s variant of the thumbnail stored under `cache_key`
+    ///
+    /// The signature covers the variant, so a link cannot be reused to
+    /// render other sizes or formats.
+    pub fn variant_url(&self, cache_key: &str, spec: &VariantSpec) -> String {
+        let mut query = spec.query();
+        if let Some(signer) = &self.config.signer {
+            if !query.is_empty() {
+                query.push('&');
+            }
+            query.push_str(&signer.sign(&Self::entry_key(cache_key, spec)));
+        }
+        if query.is_empty() {
+            format!("{}/{}", self.config.base_url, cache_key)
+        } else {
+            format!("{}/{}?{}", self.config.base_url, cache_key, query)
+        }
+    }
 
-        let mut hasher = DefaultHasher::new();
-        source.hash(&mut hasher);
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::ZERO)
-            .as_millis();
-        timestamp.hash(&mut hasher);
+    /// Cache key of `spec`'s variant of the thumbnail stored under `cache_key`
+    pub fn variant_key(cache_key: &str, spec: &VariantSpec) -> String {
+        format!("{}{}{}", cache_key, VARIANT_SEPARATOR, spec.tag())
+    }
+
+    /// Load `spec`'s variant of a cached thumbnail, rendering and caching it if needed
+    pub fn load_variant(&self, cache_key: &str, spec: &VariantSpec) -> Result<Vec<u8>, String> {
+        self.read_variant(cache_key, spec, true)
+    }
+
+    /// Like [`Self::load_variant`], without counting a hit or miss
+    ///
+    /// For callers that already counted the request with [`Self::lookup`].
+    pub fn peek_variant(&self, cache_key: &str, spec: &VariantSpec) -> Result<Vec<u8>, String> {
+        self.read_variant(cache_key, spec, false)
+    }
+
+    fn read_variant(
+        &self,
+        cache_key: &str,
+        spec: &VariantSpec,
+        record: bool,
+    ) -> Result<Vec<u8>, String> {
+        if spec.is_original() {
+            return self.read_thumbnail(cache_key, record);
+        }
+        let variant_key = Self::variant_key(cache_key, spec);
+        if self.exists(&variant_key) && self.is_current_variant(cache_key, &variant_key) {
+            return self.read_thumbnail(&variant_key, record);
+        }
+        let original = self.read_thumbnail(cache_key, record)?;
+        let data = spec.render(&original)?;
+        self.save_thumbnail_as(&variant_key, cache_key, &data)?;
+        Ok(data)
+    }
+
+    /// Validators and remaining lifetime of `spec`'s variant, as loaded into `data`
+    pub fn freshness(
+        &self,
+        cache_key: &str,
+        spec: &VariantSpec,
+        data: &[u8],
+    ) -> ThumbnailFreshness {
+        let metadata = self.metadata(&Self::entry_key(cache_key, spec));
+        let now = Utc::now().timestamp_millis();
+        let cached_at = metadata.as_ref().map_or(now, |metadata| metadata.cached_at);
+        let age = Duration::from_millis(now.saturating_sub(cached_at).max(0) as u64);
+        ThumbnailFreshness {
+            content_hash: metadata
+                .and_then(|metadata| metadata.content_hash)
+                .unwrap_or_else(|| sha256_hex(data)),
+            last_modified: Utc
+                .timestamp_millis_opt(cached_at)
+                .single()
+                .unwrap_or_else(Utc::now),
+            expires_in: self.config.ttl.saturating_sub(age),
+        }
+    }
+
+    /// Presigned store URL of `spec`'s variant, rendering and caching the variant if needed
+    ///
+    /// Returns `None` when the store does not presign. The URL expires with
+    /// the entry, or after `max_ttl` if that is sooner.
+    pub fn presigned_url(
+        &self,
+        cache_key: &str,
+        spec: &VariantSpec,
+        max_ttl: Option<Duration>,
+    ) -> Result<Option<String>, String> {
+        if !self.store.can_presign() {
+            return Ok(None);
+        }
+        let key = Self::entry_key(cache_key, spec);
+        let current = spec.is_original() || self.is_current_variant(cache_key, &key);
+        let metadata = self.metadata(&key).filter(|_| current);
+        let expires_in = match metadata.and_then(|m| self.expires_in(&m)) {
+            Some(expires_in) => {
+                self.record_access(&key, true);
+                expires_in
+            }
+            None if !spec.is_original() => {
+                self.load_variant(cache_key, spec)?;
+                self.config.ttl
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::SeqCst);
+                return Err(format!("Thumbnail not found: {}", cache_key));
+            }
+        };
+        let ttl = max_ttl.map_or(expires_in, |max_ttl| max_ttl.min(expires_in));
+        Ok(self.store.presigned_url(&Self::image_name(&key), ttl))
+    }
+
+    /// Check the `expires` and `sig` query parameters of a link to `spec`'s variant
+    ///
+    /// Returns the seconds the link stays valid, or `None` when links are not signed.
+    pub fn verify_link(
+        &self,
+        cache_key: &str,
+        spec: &VariantSpec,
+        expires: Option<&str>,
+        signature: Option<&str>,
+    ) -> Result<Option<u64>, LinkError> {
+        match &self.config.signer {
+            Some(signer) => signer
+                .verify(&Self::entry_key(cache_key, spec), expires, signature)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Run `f` on the blocking thread pool
+    ///
+    /// Store calls may wait on the disk or the network and rendering a
+    /// variant is CPU-bound, so async callers must not run them in place.
+    pub async fn blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&ThumbnailCache) -> Result<T, String> + Send + 'static,
+        T: Send + 'static,
+    {
+        let cache = Arc::clone(self);
+        tokio::task::spawn_blocking(move || f(&cache))
+            .await
+            .map_err(|err| format!("Thumbnail cache task failed: {}", err))?
+    }
 
-        format!("{:016x}", hasher.finish())
+    /// URL of an unexpired thumbnail stored under `cache_key`
+    pub fn lookup(&self, cache_key: &str) -> Option<String> {
+        let fresh = self.exists(cache_key) && !self.is_expired(cache_key);
+        self.record_access(cache_key, fresh);
+        fresh.then(|| self.url_for(cache_key))
+    }
+
+    /// Metadata of the thumbnail stored under `cache_key`
+    pub fn metadata(&self, cache_key: &str) -> Option<ThumbnailMetadata> {
+        let content = self
+            .store
+            .get(&Self::metadata_name(cache_key))
+            .ok()
+            .flatten()?;
+        serde_json::from_slice(&content).ok()
     }
 
     /// Save a thumbnail to the cache
@@ -122,107 +636,207 @@ impl ThumbnailCache {
     /// Returns the cache key and the full URL for accessing the thumbnail
     pub fn save_thumbnail(&self, source: &str, data: &[u8]) -> Result<(String, String), String> {
         let cache_key = self.generate_cache_key(source);
-        let file_path = self.cache_path(&cache_key);
-        let meta_path = self.metadata_path(&cache_key);
+        let url = self.save_thumbnail_as(&cache_key, source, data)?;
+        Ok((cache_key, url))
+    }
 
-        // Write the thumbnail data
-        let mut file = File::create(&file_path)
-            .map_err(|err| format!("Failed to create thumbnail file {:?}: {}", file_path, err))?;
-        file.write_all(data)
-            .map_err(|err| format!("Failed to write thumbnail data to {:?}: {}", file_path, err))?;
+    /// Save a thumbnail under a given cache key, replacing any previous one
+    ///
+    /// Returns the full URL for accessing the thumbnail
+    pub fn save_thumbnail_as(
+        &self,
+        cache_key: &str,
+        source: &str,
+        data: &[u8],
+    ) -> Result<String, String> {
+        let _lock = self.lock(true)?;
+
+        // Variants of the previous thumbnail would no longer match it
+        if !cache_key.contains(VARIANT_SEPARATOR) {
+            self.remove_variants(cache_key);
+        }
+
+        // Write the image before its metadata, so an interrupted save leaves
+        // an image that recovery can repair rather than metadata for nothing
+        self.store.put(&Self::image_name(cache_key), data)?;
 
         // Write the metadata
         let metadata = ThumbnailMetadata {
             cached_at: Utc::now().timestamp_millis(),
             source: source.to_string(),
-            content_hash: None,
+            content_hash: Some(sha256_hex(data)),
+            last_accessed: None,
         };
-        let meta_json = serde_json::to_string_pretty(&metadata)
-            .map_err(|err| format!("Failed to serialize thumbnail metadata: {}", err))?;
-        fs::write(&meta_path, meta_json)
-            .map_err(|err| format!("Failed to write metadata file {:?}: {}", meta_path, err))?;
+        self.write_metadata(cache_key, &metadata)?;
+        self.index()
+            .insert(cache_key, data.len() as u64, metadata.cached_at);
 
-        let url = format!("{}/{}", self.config.base_url, cache_key);
+        let url = self.url_for(cache_key);
         info!(
             "Cached thumbnail for '{}' at {} (expires in {:?})",
             source, url, self.config.ttl
         );
 
-        Ok((cache_key, url))
+        // Make room without evicting the thumbnail whose URL is returned
+        if let Err(err) = self.evict(Some(cache_key)) {
+            warn!("Failed to enforce thumbnail cache limits: {}", err);
+        }
+
+        Ok(url)
     }
 
     /// Load a thumbnail from the cache
     ///
     /// Returns the thumbnail data if found and not expired
     pub fn load_thumbnail(&self, cache_key: &str) -> Result<Vec<u8>, String> {
-        let file_path = self.cache_path(cache_key);
+        self.read_thumbnail(cache_key, true)
+    }
 
-        // Check if file exists
-        if !file_path.exists() {
+    /// Load a thumbnail, counting the hit or miss when `record` is set
+    fn read_thumbnail(&self, cache_key: &str, record: bool) -> Result<Vec<u8>, String> {
+        // Read the thumbnail data
+        let Some(data) = self.store.get(&Self::image_name(cache_key))? else {
+            if record {
+                self.record_access(cache_key, false);
+            }
             return Err(format!("Thumbnail not found: {}", cache_key));
-        }
+        };
 
         // Check if expired
         if self.is_expired(cache_key) {
             // Clean up expired thumbnail
-            let _ = self.remove_thumbnail(cache_key);
+            if record {
+                self.record_access(cache_key, false);
+            }
+            if self.remove_expired(cache_key).unwrap_or(false) {
+                self.expirations.fetch_add(1, Ordering::SeqCst);
+            }
             return Err(format!("Thumbnail expired and removed: {}", cache_key));
         }
 
-        // Read the thumbnail data
-        let mut file = File::open(&file_path)
-            .map_err(|err| format!("Failed to open thumbnail file {:?}: {}", file_path, err))?;
-        let mut data = Vec::new();
-        file.read_to_end(&mut data).map_err(|err| {
-            format!(
-                "Failed to read thumbnail data from {:?}: {}",
-                file_path, err
-            )
-        })?;
-
+        if record {
+            self.record_access(cache_key, true);
+        }
         debug!("Loaded thumbnail from cache: {}", cache_key);
         Ok(data)
     }
 
-    /// Check if a thumbnail is expired
-    fn is_expired(&self, cache_key: &str) -> bool {
-        let meta_path = self.metadata_path(cache_key);
-        if !meta_path.exists() {
-            return true;
-        }
-
-        // Read metadata and check age
-        match fs::read_to_string(&meta_path) {
-            Ok(content) => {
-                if let Ok(metadata) = serde_json::from_str::<ThumbnailMetadata>(&content) {
-                    let now = Utc::now().timestamp_millis();
-                    let age_millis = now - metadata.cached_at;
-                    let ttl_millis = self.config.ttl.as_millis() as i64;
-                    age_millis > ttl_millis
-                } else {
-                    true // Invalid metadata means expired
-                }
+    /// Remove every indexed variant of the thumbnail stored under `cache_key`
+    ///
+    /// Variants the index does not know yet are never served, being older
+    /// than the new original, and go with the next eviction or sweep.
+    fn remove_variants(&self, cache_key: &str) {
+        let prefix = format!("{}{}", cache_key, VARIANT_SEPARATOR);
+        let variants: Vec<String> = self
+            .index()
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in variants {
+            if let Err(err) = self.remove_files(&key) {
+                warn!("Failed to remove thumbnail variant {}: {}", key, err);
             }
-            Err(_) => true,
         }
     }
 
-    /// Remove a thumbnail and its metadata from the cache
-    pub fn remove_thumbnail(&self, cache_key: &str) -> Result<(), String> {
-        let file_path = self.cache_path(cache_key);
-        let meta_path = self.metadata_path(cache_key);
+    /// Whether the variant under `variant_key` is unexpired and rendered from the current original
+    fn is_current_variant(&self, cache_key: &str, variant_key: &str) -> bool {
+        let Some(variant) = self
+            .metadata(variant_key)
+            .filter(|metadata| self.expires_in(metadata).is_some())
+        else {
+            return false;
+        };
+        self.metadata(cache_key)
+            .is_some_and(|original| variant.cached_at >= original.cached_at)
+    }
 
-        if file_path.exists() {
-            fs::remove_file(&file_path).map_err(|err| {
-                format!("Failed to remove thumbnail file {:?}: {}", file_path, err)
-            })?;
+    /// Count a hit or miss, and on a hit mark the thumbnail as recently used
+    ///
+    /// Only the in-memory index changes; [`Self::sweep`] writes the access
+    /// times to the metadata in one batch.
+    fn record_access(&self, cache_key: &str, hit: bool) {
+        if !hit {
+            self.misses.fetch_add(1, Ordering::SeqCst);
+            return;
         }
-        if meta_path.exists() {
-            fs::remove_file(&meta_path).map_err(|err| {
-                format!("Failed to remove metadata file {:?}: {}", meta_path, err)
-            })?;
+        self.hits.fetch_add(1, Ordering::SeqCst);
+        self.index().touch(cache_key, Utc::now().timestamp_millis());
+    }
+
+    /// Write the access times recorded since the last sweep to the metadata
+    fn persist_access_times(&self) -> Result<usize, String> {
+        let _lock = self.lock(true)?;
+        let accessed = std::mem::take(&mut self.index().accessed);
+        let mut written = 0;
+        for (cache_key, last_accessed) in accessed {
+            // Re-read under the lock so a purged thumbnail is not given metadata again
+            let Some(mut metadata) = self.metadata(&cache_key) else {
+                continue;
+            };
+            if metadata.last_accessed >= Some(last_accessed) {
+                continue;
+            }
+            metadata.last_accessed = Some(last_accessed);
+            match self.write_metadata(&cache_key, &metadata) {
+                Ok(()) => written += 1,
+                Err(err) => debug!("Failed to record thumbnail access: {}", err),
+            }
+        }
+        Ok(written)
+    }
+
+    fn write_metadata(&self, cache_key: &str, metadata: &ThumbnailMetadata) -> Result<(), String> {
+        let meta_json = serde_json::to_string_pretty(metadata)
+            .map_err(|err| format!("Failed to serialize thumbnail metadata: {}", err))?;
+        self.store
+            .put(&Self::metadata_name(cache_key), meta_json.as_bytes())
+    }
+
+    /// Remove a thumbnail that is still expired once the lock is held
+    ///
+    /// Another process may have been writing it, its metadata not yet in
+    /// place. Without a store lock that cannot be ruled out, so an image
+    /// without metadata is left for the sweep.
+    fn remove_expired(&self, cache_key: &str) -> Result<bool, String> {
+        let _lock = self.lock(true)?;
+        if !self.is_expired(cache_key)
+            || (!self.store.can_lock() && self.metadata(cache_key).is_none())
+        {
+            return Ok(false);
         }
+        self.remove_files(cache_key)?;
+        Ok(true)
+    }
+
+    /// Check if a thumbnail is expired; missing or invalid metadata means expired
+    fn is_expired(&self, cache_key: &str) -> bool {
+        self.metadata(cache_key)
+            .and_then(|metadata| self.expires_in(&metadata))
+            .is_none()
+    }
+
+    /// Time left before a thumbnail with `metadata` expires, `None` once it has
+    fn expires_in(&self, metadata: &ThumbnailMetadata) -> Option<Duration> {
+        let age_millis = Utc::now().timestamp_millis() - metadata.cached_at;
+        let ttl_millis = self.config.ttl.as_millis() as i64;
+        (age_millis <= ttl_millis)
+            .then(|| Duration::from_millis((ttl_millis - age_millis.max(0)).max(0) as u64))
+    }
 
+    /// Remove a thumbnail and its metadata from the cache
+    pub fn remove_thumbnail(&self, cache_key: &str) -> Result<(), String> {
+        let _lock = self.lock(true)?;
+        self.remove_files(cache_key)
+    }
+
+    /// Remove a thumbnail's image and metadata; the caller holds the lock
+    fn remove_files(&self, cache_key: &str) -> Result<(), String> {
+        self.store.delete(&Self::image_name(cache_key))?;
+        self.index().remove(cache_key);
+        self.store.delete(&Self::metadata_name(cache_key))?;
         debug!("Removed thumbnail from cache: {}", cache_key);
         Ok(())
     }
@@ -231,28 +845,16 @@ impl ThumbnailCache {
     ///
     /// Returns the number of thumbnails removed
     pub fn cleanup_expired(&self) -> Result<usize, String> {
+        let _lock = self.lock(true)?;
         let mut removed = 0;
 
-        let entries = fs::read_dir(&self.config.cache_dir).map_err(|err| {
-            format!(
-                "Failed to read cache directory {:?}: {}",
-                self.config.cache_dir, err
-            )
-        })?;
-
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) != Some(THUMBNAIL_EXTENSION) {
+        for object in self.store.list()? {
+            let Some(cache_key) = image_key(&object.name) else {
                 continue;
-            }
-
-            let cache_key = match path.file_stem().and_then(|s| s.to_str()) {
-                Some(key) => key,
-                None => continue,
             };
 
-            if self.is_expired(cache_key) {
-                if let Err(err) = self.remove_thumbnail(cache_key) {
+            if !self.may_be_in_flight(&object) && self.is_expired(cache_key) {
+                if let Err(err) = self.remove_files(cache_key) {
                     warn!("Failed to remove expired thumbnail {}: {}", cache_key, err);
                 } else {
                     removed += 1;
@@ -263,24 +865,346 @@ impl ThumbnailCache {
         if removed > 0 {
             info!("Cleaned up {} expired thumbnail(s)", removed);
         }
+        self.expirations.fetch_add(removed as u64, Ordering::SeqCst);
         Ok(removed)
     }
 
-    /// Get the file path for a cached thumbnail
-    fn cache_path(&self, cache_key: &str) -> PathBuf {
-        let mut path = self.config.cache_dir.clone();
-        path.push(format!("{}.{}", cache_key, THUMBNAIL_EXTENSION));
-        path
+    /// Evict least recently used thumbnails until the cache is within its bounds
+    ///
+    /// Returns the number of thumbnails evicted
+    pub fn evict_lru(&self) -> Result<usize, String> {
+        let _lock = self.lock(true)?;
+        self.evict(None)
     }
 
-    /// Get the metadata file path for a cached thumbnail
-    fn metadata_path(&self, cache_key: &str) -> PathBuf {
-        let mut path = self.config.cache_dir.clone();
-        path.push(format!("{}.{}", cache_key, METADATA_EXTENSION));
-        path
+    /// Every thumbnail and variant in the cache, ordered by key
+    pub fn list(&self) -> Result<Vec<ThumbnailEntry>, String> {
+        let _lock = self.lock(false)?;
+        let mut entries: Vec<ThumbnailEntry> = self
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| self.entry(&entry.key))
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    /// The thumbnail or variant stored under `cache_key`, if any
+    pub fn entry(&self, cache_key: &str) -> Option<ThumbnailEntry> {
+        if cache_key.is_empty()
+            || !cache_key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+        {
+            return None;
+        }
+        let name = Self::image_name(cache_key);
+        let bytes = self.store.size(&name).ok().flatten()?;
+        Some(ThumbnailEntry {
+            key: cache_key.to_string(),
+            path: self.store.local_path(&name),
+            bytes,
+            metadata: self.metadata(cache_key),
+            expired: self.is_expired(cache_key),
+        })
+    }
+
+    /// Copy the image stored under `cache_key` to `destination`, even if expired
+    ///
+    /// Unlike loading, this does not count as a use of the thumbnail. Returns
+    /// the number of bytes copied.
+    pub fn export(&self, cache_key: &str, destination: &Path) -> Result<u64, String> {
+        let _lock = self.lock(false)?;
+        let data = self
+            .entry(cache_key)
+            .map(|entry| self.store.get(&Self::image_name(&entry.key)))
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| format!("Thumbnail not found: {}", cache_key))?;
+        fs::write(destination, &data)
+            .map_err(|err| format!("Failed to write {:?}: {}", destination, err))?;
+        Ok(data.len() as u64)
+    }
+
+    /// Remove the thumbnails selected by `filter`
+    ///
+    /// Returns the number of thumbnails and variants removed
+    pub fn purge(&self, filter: &PurgeFilter) -> Result<usize, String> {
+        let source = match filter {
+            PurgeFilter::Expired => return self.cleanup_expired(),
+            PurgeFilter::All => return self.purge_all(),
+            PurgeFilter::Source(source) => source,
+        };
+
+        let _lock = self.lock(true)?;
+        let entries = self.entries()?;
+        // Keys of assets fetched in the active tenant also match a bare UUID
+        let asset_key = self.asset_key(None, source);
+        let originals: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .filter(|key| !key.contains(VARIANT_SEPARATOR))
+            .filter(|key| {
+                *key == asset_key
+                    || self
+                        .metadata(key)
+                        .is_some_and(|metadata| metadata.source == *source)
+            })
+            .collect();
+
+        let mut removed = 0;
+        for entry in &entries {
+            let original = entry
+                .key
+                .split_once(VARIANT_SEPARATOR)
+                .map_or(entry.key.as_str(), |(original, _)| original);
+            if !originals.contains(&original) {
+                continue;
+            }
+            match self.remove_files(&entry.key) {
+                Ok(()) => removed += 1,
+                Err(err) => warn!("Failed to purge thumbnail {}: {}", entry.key, err),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove every thumbnail and metadata file, including orphaned metadata
+    fn purge_all(&self) -> Result<usize, String> {
+        let _lock = self.lock(true)?;
+        let mut removed = 0;
+        for object in self.store.list()? {
+            let is_image = image_key(&object.name).is_some();
+            let is_metadata = object
+                .name
+                .rsplit_once('.')
+                .is_some_and(|(_, extension)| extension == METADATA_EXTENSION);
+            if !is_image && !is_metadata {
+                continue;
+            }
+            self.store.delete(&object.name)?;
+            if is_image {
+                removed += 1;
+            }
+        }
+        *self.index() = CacheIndex::default();
+        Ok(removed)
+    }
+
+    /// Lock the cache, exclusively to change it or shared to read it
+    ///
+    /// The lock is released when the returned guard is dropped. It is taken
+    /// in this process first, then in the store; for the cache directory the
+    /// store's lock is advisory and shared with other processes using it.
+    fn lock(&self, exclusive: bool) -> Result<CacheLock<'_>, String> {
+        let (shared, exclusive_guard) = if exclusive {
+            let guard = self.lock.write().unwrap_or_else(|err| err.into_inner());
+            (None, Some(guard))
+        } else {
+            let guard = self.lock.read().unwrap_or_else(|err| err.into_inner());
+            (Some(guard), None)
+        };
+        Ok(CacheLock {
+            _shared: shared,
+            _exclusive: exclusive_guard,
+            _store: self.store.lock(exclusive)?,
+        })
+    }
+
+    /// Whether another process may still be writing `object`, in a store without locks
+    fn may_be_in_flight(&self, object: &StoredObject) -> bool {
+        let grace = UNLOCKED_WRITE_GRACE.as_millis() as i64;
+        !self.store.can_lock()
+            && object
+                .modified
+                .is_some_and(|modified| Utc::now().timestamp_millis() - modified < grace)
+    }
+
+    /// Remove expired thumbnails, record access times, re-index the store,
+    /// then evict down to the cache bounds
+    pub fn sweep(&self) -> Result<SweepResult, String> {
+        let expired = self.cleanup_expired()?;
+        let persisted = self.persist_access_times()?;
+        if persisted > 0 {
+            debug!("Recorded access times of {} thumbnail(s)", persisted);
+        }
+        self.reindex()?;
+        let evicted = self.evict_lru()?;
+        Ok(SweepResult { expired, evicted })
+    }
+
+    /// Rebuild the in-memory index from the store
+    ///
+    /// Access times not yet persisted are carried over, so hits during the
+    /// rebuild still count for eviction.
+    fn reindex(&self) -> Result<(), String> {
+        let _lock = self.lock(false)?;
+        let mut index = CacheIndex::from_entries(self.entries()?);
+        let mut current = self.index();
+        for (key, last_used) in std::mem::take(&mut current.accessed) {
+            if index
+                .entries
+                .get(key.as_str())
+                .is_some_and(|&(_, used)| used < last_used)
+            {
+                index.touch(&key, last_used);
+            }
+        }
+        *current = index;
+        Ok(())
+    }
+
+    fn index(&self) -> MutexGuard<'_, CacheIndex> {
+        self.index.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Current size of the cache and usage since the server started
+    pub fn stats(&self) -> ThumbnailCacheStats {
+        let (entries, bytes) = {
+            let index = self.index();
+            (index.entries.len(), index.bytes)
+        };
+        let hits = self.hits.load(Ordering::SeqCst);
+        let misses = self.misses.load(Ordering::SeqCst);
+        let lookups = hits + misses;
+        ThumbnailCacheStats {
+            entries,
+            bytes,
+            max_entries: self.config.max_entries,
+            max_bytes: self.config.max_bytes,
+            hits,
+            misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+            evictions: self.evictions.load(Ordering::SeqCst),
+            expirations: self.expirations.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Evict least recently used thumbnails other than `keep`; the caller holds the lock
+    fn evict(&self, keep: Option<&str>) -> Result<usize, String> {
+        if self.config.max_bytes.is_none() && self.config.max_entries.is_none() {
+            return Ok(0);
+        }
+        let max_entries = self.config.max_entries.unwrap_or(usize::MAX);
+        let max_bytes = self.config.max_bytes.unwrap_or(u64::MAX);
+        let mut failed = HashSet::new();
+        let mut evicted = 0;
+        loop {
+            let next = {
+                let index = self.index();
+                if index.entries.len() <= max_entries && index.bytes <= max_bytes {
+                    break;
+                }
+                index.oldest(keep, &failed)
+            };
+            let Some(key) = next else {
+                break;
+            };
+            match self.remove_files(&key) {
+                Ok(()) => evicted += 1,
+                Err(err) => {
+                    warn!("Failed to evict thumbnail {}: {}", key, err);
+                    failed.insert(key);
+                }
+            }
+        }
+
+        if evicted > 0 {
+            let index = self.index();
+            info!(
+                "Evicted {} least recently used thumbnail(s), {} left using {} bytes",
+                evicted,
+                index.entries.len(),
+                index.bytes
+            );
+        }
+        self.evictions.fetch_add(evicted as u64, Ordering::SeqCst);
+        Ok(evicted)
+    }
+
+    /// Every stored thumbnail with its size and last use
+    fn entries(&self) -> Result<Vec<CacheEntry>, String> {
+        let mut entries = Vec::new();
+        for object in self.store.list()? {
+            let Some(cache_key) = image_key(&object.name) else {
+                continue;
+            };
+            // Thumbnails without readable metadata go first, unless they may
+            // still be getting it
+            let last_used = match self.metadata(cache_key) {
+                Some(metadata) => metadata.last_used(),
+                None if self.may_be_in_flight(&object) => object.modified.unwrap_or(i64::MIN),
+                None => i64::MIN,
+            };
+            entries.push(CacheEntry {
+                key: cache_key.to_string(),
+                bytes: object.bytes,
+                last_used,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Whether the image of a thumbnail is stored
+    fn exists(&self, cache_key: &str) -> bool {
+        self.store
+            .size(&Self::image_name(cache_key))
+            .is_ok_and(|size| size.is_some())
+    }
+
+    /// Cache key of `spec`'s variant, the original's key for the original
+    fn entry_key(cache_key: &str, spec: &VariantSpec) -> String {
+        if spec.is_original() {
+            cache_key.to_string()
+        } else {
+            Self::variant_key(cache_key, spec)
+        }
+    }
+
+    /// Get the object name of a cached thumbnail
+    ///
+    /// Originals are PNG files; a variant's extension is its format, the end of its tag.
+    fn image_name(cache_key: &str) -> String {
+        let extension = cache_key
+            .split_once(VARIANT_SEPARATOR)
+            .and_then(|(_, tag)| tag.rsplit('-').next())
+            .and_then(|format| ImageFormat::parse(format).ok())
+            .map_or(THUMBNAIL_EXTENSION, |format| format.name());
+        format!("{}.{}", cache_key, extension)
+    }
+
+    /// Get the metadata object name of a cached thumbnail
+    fn metadata_name(cache_key: &str) -> String {
+        format!("{}.{}", cache_key, METADATA_EXTENSION)
+    }
+
+    fn remove_logged(&self, name: &str) {
+        if let Err(err) = self.store.delete(name) {
+            warn!("{}", err);
+        }
+    }
+}
+
+/// Whether `data` starts like an image of the format named by `name`'s extension
+fn has_image_signature(name: &str, data: &[u8]) -> bool {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some(THUMBNAIL_EXTENSION) => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+        Some("jpeg") => data.starts_with(&[0xff, 0xd8, 0xff]),
+        Some("webp") => data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP",
+        _ => false,
     }
 }
 
+/// Cache key of an image object, `None` for other objects
+fn image_key(name: &str) -> Option<&str> {
+    let (cache_key, extension) = name.rsplit_once('.')?;
+    IMAGE_EXTENSIONS.contains(&extension).then_some(cache_key)
+}
+
 /// Get the default cache directory path
 ///
 /// Uses ~/.pcli2-mcp/thumbnails on Unix-like systems
@@ -309,6 +1233,10 @@ mod tests {
             cache_dir: temp_dir.clone(),
             ttl: DEFAULT_TTL,
             base_url: "http://localhost:8080/thumbnail".to_string(),
+            max_bytes: None,
+            max_entries: None,
+            signer: None,
+            store: None,
         };
 
         let cache = ThumbnailCache::new(config).unwrap();
@@ -325,7 +1253,7 @@ mod tests {
         assert_ne!(key1, key2);
 
         // Keys should be hex strings of consistent length
-        assert_eq!(key1.len(), 16);
+        assert_eq!(key1.len(), 32);
         assert!(key1.chars().all(|c| c.is_ascii_hexdigit()));
     }
 
@@ -346,6 +1274,45 @@ mod tests {
         assert_eq!(loaded, data);
     }
 
+    #[test]
+    fn test_asset_keys_are_stable() {
+        let (cache, _temp_dir) = create_test_cache();
+        let key = cache.asset_key(Some("acme"), "asset-1");
+        assert_eq!(key, cache.asset_key(Some("acme"), "asset-1"));
+        assert_ne!(key, cache.asset_key(Some("other"), "asset-1"));
+        assert_ne!(key, cache.asset_key(None, "asset-1"));
+        assert_eq!(key.len(), 32);
+
+        // Keys come from the signing secret, not the UUID alone
+        let material = "thumbnail\0acme\0asset-1";
+        assert_ne!(key, sha256_hex(material.as_bytes())[..32]);
+        let keyed = |secret: &[u8]| {
+            let config = ThumbnailCacheConfig::new(PathBuf::new(), DEFAULT_TTL, "localhost", 8080)
+                .with_store(Arc::new(crate::store::MemoryStore::default()))
+                .with_signer(UrlSigner::new(secret, DEFAULT_TTL));
+            ThumbnailCache::new(config)
+                .unwrap()
+                .asset_key(Some("acme"), "asset-1")
+        };
+        assert_eq!(keyed(b"one"), keyed(b"one"));
+        assert_ne!(keyed(b"one"), keyed(b"two"));
+
+        assert_eq!(cache.lookup(&key), None);
+        let url = cache.save_thumbnail_as(&key, "asset-1", b"png v1").unwrap();
+        assert_eq!(cache.lookup(&key), Some(url.clone()));
+        assert_eq!(
+            cache.metadata(&key).unwrap().content_hash,
+            Some(sha256_hex(b"png v1"))
+        );
+
+        // Saving again replaces the entry under the same key
+        assert_eq!(
+            cache.save_thumbnail_as(&key, "asset-1", b"png v2").unwrap(),
+            url
+        );
+        assert_eq!(cache.load_thumbnail(&key).unwrap(), b"png v2");
+    }
+
     #[test]
     fn test_load_nonexistent_thumbnail() {
         let (cache, _temp_dir) = create_test_cache();
@@ -384,6 +1351,10 @@ mod tests {
             cache_dir: temp_dir.clone(),
             ttl: Duration::from_millis(100), // 100ms TTL
             base_url: "http://localhost:8080/thumbnail".to_string(),
+            max_bytes: None,
+            max_entries: None,
+            signer: None,
+            store: None,
         };
 
         let cache = ThumbnailCache::new(config).unwrap();
@@ -407,6 +1378,472 @@ mod tests {
         assert_eq!(cleaned, 0);
     }
 
+    #[test]
+    fn test_signed_urls() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!(
+            "pcli2-thumbnail-signed-test-{}",
+            std::process::id()
+        ));
+        let signer = UrlSigner::new(b"secret", Duration::from_secs(60));
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080)
+            .with_signer(signer);
+        let cache = ThumbnailCache::new(config).unwrap();
+
+        let (cache_key, url) = cache.save_thumbnail("asset", b"png").unwrap();
+        let query = url
+            .strip_prefix(&format!("http://localhost:8080/thumbnail/{}?", cache_key))
+            .unwrap();
+        let (expires, signature) = query.split_once("&sig=").unwrap();
+        let expires = expires.strip_prefix("expires=");
+        assert!(
+            cache
+                .verify_link(
+                    &cache_key,
+                    &VariantSpec::default(),
+                    expires,
+                    Some(signature)
+                )
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            cache.verify_link("other", &VariantSpec::default(), expires, Some(signature)),
+            Err(LinkError::BadSignature)
+        );
+        assert_eq!(
+            cache.verify_link(&cache_key, &VariantSpec::default(), None, None),
+            Err(LinkError::Unsigned)
+        );
+
+        // A variant link is signed for that variant only
+        let spec = VariantSpec::from_args(&serde_json::json!({ "width": 32 })).unwrap();
+        let url = cache.variant_url(&cache_key, &spec);
+        let query = url.split_once("?width=32&expires=").unwrap().1;
+        let (expires, signature) = query.split_once("&sig=").unwrap();
+        assert!(
+            cache
+                .verify_link(&cache_key, &spec, Some(expires), Some(signature))
+                .is_ok()
+        );
+        let larger = VariantSpec::from_args(&serde_json::json!({ "width": 64 })).unwrap();
+        assert_eq!(
+            cache.verify_link(&cache_key, &larger, Some(expires), Some(signature)),
+            Err(LinkError::BadSignature)
+        );
+        assert_eq!(
+            cache.verify_link(
+                &cache_key,
+                &VariantSpec::default(),
+                Some(expires),
+                Some(signature)
+            ),
+            Err(LinkError::BadSignature)
+        );
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_variants_are_cached_until_replaced() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!(
+            "pcli2-thumbnail-variant-test-{}",
+            std::process::id()
+        ));
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080);
+        let cache = ThumbnailCache::new(config).unwrap();
+        let png = crate::raster::Rgba::new(64, 32, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+        cache.save_thumbnail_as("k", "asset", &png).unwrap();
+
+        let spec = VariantSpec::from_args(&serde_json::json!({
+            "max_dimension": 16,
+            "format": "jpeg"
+        }))
+        .unwrap();
+        let jpeg = cache.load_variant("k", &spec).unwrap();
+        assert!(jpeg.starts_with(&[0xff, 0xd8]));
+        let variant_file = temp_dir.join("k_w0-h0-m16-jpeg.jpeg");
+        assert!(variant_file.exists());
+        assert_eq!(cache.load_variant("k", &spec).unwrap(), jpeg);
+        assert_eq!(
+            cache.variant_url("k", &spec),
+            "http://localhost:8080/thumbnail/k?max_dimension=16&format=jpeg"
+        );
+        assert_eq!(cache.stats().entries, 2);
+
+        let freshness = cache.freshness("k", &spec, &jpeg);
+        assert_eq!(freshness.content_hash, sha256_hex(&jpeg));
+        assert!(freshness.expires_in <= DEFAULT_TTL);
+        assert!(freshness.expires_in > DEFAULT_TTL - Duration::from_secs(60));
+        assert_eq!(
+            cache
+                .freshness("k", &VariantSpec::default(), &png)
+                .content_hash,
+            sha256_hex(&png)
+        );
+
+        // A new original drops variants rendered from the old one
+        cache.save_thumbnail_as("k", "asset", &png).unwrap();
+        assert!(!variant_file.exists());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_lru_eviction_and_stats() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!("pcli2-thumbnail-lru-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080)
+            .with_limits(Some(25), Some(2));
+        let cache = ThumbnailCache::new(config).unwrap();
+
+        cache
+            .save_thumbnail_as("a", "asset-a", b"0123456789")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        cache
+            .save_thumbnail_as("b", "asset-b", b"0123456789")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        // Using `a` makes `b` the least recently used
+        assert!(cache.lookup("a").is_some());
+        std::thread::sleep(Duration::from_millis(5));
+        cache
+            .save_thumbnail_as("c", "asset-c", b"0123456789")
+            .unwrap();
+
+        assert!(cache.lookup("b").is_none());
+        assert_eq!(cache.load_thumbnail("a").unwrap(), b"0123456789");
+        assert!(cache.lookup("c").is_some());
+
+        // A thumbnail larger than the whole cache is kept until the next one
+        cache.save_thumbnail_as("d", "asset-d", &[0; 40]).unwrap();
+        assert!(cache.lookup("d").is_some());
+        assert_eq!(cache.sweep().unwrap().evicted, 1);
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.evictions, 4);
+        assert_eq!((stats.hits, stats.misses), (4, 1));
+        assert_eq!(stats.hit_rate, 0.8);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_access_times_are_persisted_by_sweep() {
+        let temp_dir = std::env::temp_dir().join(format!(
+            "pcli2-mcp-thumbnail-access-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080)
+            .with_limits(None, Some(2));
+        let cache = ThumbnailCache::new(config).unwrap();
+
+        cache.save_thumbnail_as("a", "asset-a", b"a").unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        cache.save_thumbnail_as("b", "asset-b", b"b").unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.lookup("a").is_some());
+
+        // Hits only change the index until the sweep writes them out
+        assert_eq!(cache.metadata("a").unwrap().last_accessed, None);
+        assert_eq!(cache.sweep().unwrap(), SweepResult::default());
+        assert!(cache.metadata("a").unwrap().last_accessed.is_some());
+        assert_eq!(cache.metadata("b").unwrap().last_accessed, None);
+
+        // The re-indexed order still has `b` as the least recently used
+        cache.save_thumbnail_as("c", "asset-c", b"c").unwrap();
+        assert!(cache.lookup("a").is_some());
+        assert!(cache.lookup("b").is_none());
+
+        // Peeking at a variant counts neither a hit nor a miss
+        let stats = cache.stats();
+        let spec = VariantSpec::default();
+        assert_eq!(cache.peek_variant("a", &spec).unwrap(), b"a");
+        assert!(cache.peek_variant("b", &spec).is_err());
+        let after = cache.stats();
+        assert_eq!((after.hits, after.misses), (stats.hits, stats.misses));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_index_picks_up_other_processes_on_sweep() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!("pcli2-thumbnail-index-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let open = || {
+            ThumbnailCache::new(ThumbnailCacheConfig::new(
+                temp_dir.clone(),
+                DEFAULT_TTL,
+                "localhost",
+                8080,
+            ))
+            .unwrap()
+        };
+        let (cache, other) = (open(), open());
+        let png = crate::raster::Rgba::new(32, 32, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+
+        other.save_thumbnail_as("k", "asset", &png).unwrap();
+        assert_eq!(cache.stats().entries, 0);
+        cache.sweep().unwrap();
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().bytes, png.len() as u64);
+
+        // A variant left behind by a replaced original is rendered again
+        let spec = VariantSpec {
+            max_dimension: Some(16),
+            ..VariantSpec::default()
+        };
+        cache.load_variant("k", &spec).unwrap();
+        let variant_key = ThumbnailCache::variant_key("k", &spec);
+        let rendered = cache.metadata(&variant_key).unwrap().cached_at;
+        std::thread::sleep(Duration::from_millis(5));
+        other.save_thumbnail_as("k", "asset", &png).unwrap();
+        assert!(!cache.is_current_variant("k", &variant_key));
+        cache.load_variant("k", &spec).unwrap();
+        assert!(cache.metadata(&variant_key).unwrap().cached_at > rendered);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_list_and_purge() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!("pcli2-thumbnail-purge-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080);
+        let cache = ThumbnailCache::new(config).unwrap();
+        let png = crate::raster::Rgba::new(32, 32, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+
+        let uuid_key = cache.asset_key(None, "uuid-a");
+        cache
+            .save_thumbnail_as(&uuid_key, "/Root/a.stl", &png)
+            .unwrap();
+        cache.save_thumbnail_as("b", "/Root/b.stl", &png).unwrap();
+        cache.save_thumbnail_as("c", "/Root/c.stl", &png).unwrap();
+        let spec = VariantSpec {
+            max_dimension: Some(16),
+            ..VariantSpec::default()
+        };
+        cache.load_variant("b", &spec).unwrap();
+
+        let entries = cache.list().unwrap();
+        let keys: Vec<&str> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        let mut expected = ["b", "b_w0-h0-m16-png", "c", uuid_key.as_str()];
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+        let b = entries.iter().find(|entry| entry.key == "b").unwrap();
+        assert_eq!(b.metadata.as_ref().unwrap().source, "/Root/b.stl");
+        assert!(!b.expired);
+        assert!(cache.entry("../b").is_none());
+
+        // A path matches the recorded source, a UUID the active tenant's key
+        let source = |s: &str| PurgeFilter::Source(s.to_string());
+        assert_eq!(cache.purge(&source("/Root/b.stl")).unwrap(), 2);
+        assert_eq!(cache.purge(&source("uuid-a")).unwrap(), 1);
+        assert_eq!(cache.purge(&source("/Root/missing.stl")).unwrap(), 0);
+        assert_eq!(cache.purge(&PurgeFilter::Expired).unwrap(), 0);
+
+        fs::write(temp_dir.join("orphan.meta"), "{}").unwrap();
+        assert_eq!(cache.purge(&PurgeFilter::All).unwrap(), 1);
+        assert!(cache.list().unwrap().is_empty());
+        assert!(!temp_dir.join("orphan.meta").exists());
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_changes_wait_for_the_lock() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!("pcli2-thumbnail-lock-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080);
+        let cache = std::sync::Arc::new(ThumbnailCache::new(config).unwrap());
+
+        // Another process holding the lock has its own open file, as here
+        let held = cache.lock(true).unwrap();
+        let writer = {
+            let cache = cache.clone();
+            std::thread::spawn(move || cache.save_thumbnail_as("k", "asset", b"data"))
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(cache.entry("k").is_none());
+
+        drop(held);
+        writer.join().unwrap().unwrap();
+        assert_eq!(cache.entry("k").unwrap().bytes, 4);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_opening_recovers_interrupted_writes() {
+        let mut temp_dir = env::temp_dir();
+        temp_dir.push(format!(
+            "pcli2-thumbnail-recover-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let config = ThumbnailCacheConfig::new(temp_dir.clone(), DEFAULT_TTL, "localhost", 8080);
+        let png = crate::raster::Rgba::new(8, 8, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+        {
+            let cache = ThumbnailCache::new(config.clone()).unwrap();
+            cache.save_thumbnail_as("kept", "asset", &png).unwrap();
+            cache.save_thumbnail_as("orphan", "asset", &png).unwrap();
+        }
+        // Only finished files are left behind by a save
+        let names = |dir: &Path| {
+            let mut names: Vec<String> = fs::read_dir(dir)
+                .unwrap()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(&temp_dir),
+            [
+                ".lock",
+                "kept.meta",
+                "kept.png",
+                "orphan.meta",
+                "orphan.png"
+            ]
+        );
+
+        // Simulate a crash mid-save, a lost image, a lost sidecar and a torn file
+        fs::write(temp_dir.join("partial.png.tmp"), &png[..10]).unwrap();
+        fs::remove_file(temp_dir.join("orphan.png")).unwrap();
+        fs::write(temp_dir.join("bare.png"), &png).unwrap();
+        fs::write(temp_dir.join("bare_w0-h0-m16-png.png"), &png).unwrap();
+        fs::write(temp_dir.join("torn.png"), b"\x89PN").unwrap();
+        fs::write(temp_dir.join("kept.meta.tmp"), "{").unwrap();
+
+        let cache = ThumbnailCache::new(config).unwrap();
+        assert_eq!(cache.recover().unwrap(), RecoveryResult::default());
+        assert_eq!(
+            names(&temp_dir),
+            [
+                ".lock",
+                "bare.meta",
+                "bare.png",
+                "bare_w0-h0-m16-png.meta",
+                "bare_w0-h0-m16-png.png",
+                "kept.meta",
+                "kept.png"
+            ]
+        );
+        let repaired = cache.metadata("bare").unwrap();
+        assert_eq!(repaired.source, RECOVERED_SOURCE);
+        assert_eq!(repaired.content_hash, Some(sha256_hex(&png)));
+        assert_eq!(cache.metadata("bare_w0-h0-m16-png").unwrap().source, "bare");
+        assert_eq!(cache.load_thumbnail("bare").unwrap(), png);
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = Arc::new(crate::store::MemoryStore::new());
+        let config = ThumbnailCacheConfig::new(PathBuf::new(), DEFAULT_TTL, "localhost", 8080)
+            .with_store(store.clone());
+        let png = crate::raster::Rgba::new(8, 8, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+
+        let cache = ThumbnailCache::new(config.clone()).unwrap();
+        cache.save_thumbnail_as("a", "/Root/a.stl", &png).unwrap();
+        assert_eq!(cache.load_thumbnail("a").unwrap(), png);
+        assert!(cache.lookup("a").is_some());
+        let entry = cache.entry("a").unwrap();
+        assert_eq!(entry.path, None);
+        assert_eq!(entry.bytes, png.len() as u64);
+        // Nothing presigns in memory
+        assert_eq!(
+            cache
+                .presigned_url("a", &VariantSpec::default(), None)
+                .unwrap(),
+            None
+        );
+
+        // A second cache on the same store sees the thumbnail, and recovers
+        // one whose metadata was lost
+        store.put("b.png", &png).unwrap();
+        let other = ThumbnailCache::new(config).unwrap();
+        assert_eq!(other.metadata("b").unwrap().source, RECOVERED_SOURCE);
+        assert_eq!(other.list().unwrap().len(), 2);
+        assert_eq!(other.purge(&PurgeFilter::All).unwrap(), 2);
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    /// A shared store without locks, such as a bucket used by several replicas
+    #[derive(Debug, Default)]
+    struct UnlockedStore(crate::store::MemoryStore);
+
+    impl ThumbnailStore for UnlockedStore {
+        fn describe(&self) -> String {
+            "unlocked".to_string()
+        }
+        fn get(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+            self.0.get(name)
+        }
+        fn put(&self, name: &str, data: &[u8]) -> Result<(), String> {
+            self.0.put(name, data)
+        }
+        fn delete(&self, name: &str) -> Result<(), String> {
+            self.0.delete(name)
+        }
+        fn size(&self, name: &str) -> Result<Option<u64>, String> {
+            self.0.size(name)
+        }
+        fn list(&self) -> Result<Vec<StoredObject>, String> {
+            self.0.list()
+        }
+    }
+
+    #[test]
+    fn test_recent_images_are_kept_without_store_lock() {
+        let store = Arc::new(UnlockedStore::default());
+        let config = ThumbnailCacheConfig::new(PathBuf::new(), DEFAULT_TTL, "localhost", 8080)
+            .with_store(store.clone());
+        let png = crate::raster::Rgba::new(8, 8, [0, 0, 0, 255])
+            .encode_png()
+            .unwrap();
+
+        // Another replica has written the image but not yet its metadata
+        store.put("b.png", &png).unwrap();
+        let cache = ThumbnailCache::new(config).unwrap();
+        assert_eq!(cache.recover().unwrap(), RecoveryResult::default());
+        assert!(cache.metadata("b").is_none());
+        assert!(cache.load_thumbnail("b").is_err());
+        assert_eq!(cache.cleanup_expired().unwrap(), 0);
+        assert!(store.get("b.png").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_metadata_without_access_time() {
+        let metadata: ThumbnailMetadata =
+            serde_json::from_str(r#"{"cached_at": 5, "source": "x", "content_hash": null}"#)
+                .unwrap();
+        assert_eq!(metadata.last_accessed, None);
+        assert_eq!(metadata.last_used(), 5);
+    }
+
     #[test]
     fn test_default_cache_dir() {
         let result = default_cache_dir();
@@ -0,0 +1,239 @@
+//! Resized and re-encoded thumbnail variants.
+//!
+//! pcli2 returns full-size PNG thumbnails. A variant scales one down to a
+//! `width`, `height` or `max_dimension` and re-encodes it as PNG, JPEG or
+//! WebP; a 128px JPEG is small enough to inline as a data URI. The same
+//! options are accepted as tool arguments and as query parameters on
+//! `/thumbnail/:cache_key`, and each variant is cached under its own key.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::raster::Rgba;
+
+/// Smallest width, height or `max_dimension` of a variant
+pub const MIN_VARIANT_DIMENSION: u32 = 16;
+/// Largest width, height or `max_dimension` of a variant
+pub const MAX_VARIANT_DIMENSION: u32 = 2048;
+/// JPEG quality used for variants
+const JPEG_QUALITY: u8 = 80;
+
+/// Encoding of a thumbnail variant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// Lossless WebP
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "png" => Ok(Self::Png),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "webp" => Ok(Self::Webp),
+            other => Err(format!(
+                "Invalid argument 'format': '{}' must be one of png, jpeg, webp",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpeg",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// Size and encoding requested for a thumbnail
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariantSpec {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Bound on both width and height
+    pub max_dimension: Option<u32>,
+    pub format: ImageFormat,
+}
+
+impl VariantSpec {
+    /// Options given as tool arguments
+    pub fn from_args(args: &Value) -> Result<Self, String> {
+        let dimension = |key: &str| match args.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .ok_or_else(|| format!("Invalid argument '{}': must be an integer", key))
+                .and_then(|value| checked_dimension(key, value).map(Some)),
+        };
+        let format = match args.get("format").and_then(Value::as_str) {
+            Some(format) => ImageFormat::parse(format)?,
+            None => ImageFormat::Png,
+        };
+        Ok(Self {
+            width: dimension("width")?,
+            height: dimension("height")?,
+            max_dimension: dimension("max_dimension")?,
+            format,
+        })
+    }
+
+    /// Options given as query parameters; other parameters are ignored
+    pub fn from_query(params: &HashMap<String, String>) -> Result<Self, String> {
+        let dimension = |key: &str| match params.get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid parameter '{}': must be an integer", key))
+                .and_then(|value| checked_dimension(key, value).map(Some)),
+        };
+        let format = match params.get("format") {
+            Some(format) => ImageFormat::parse(format)?,
+            None => ImageFormat::Png,
+        };
+        Ok(Self {
+            width: dimension("width")?,
+            height: dimension("height")?,
+            max_dimension: dimension("max_dimension")?,
+            format,
+        })
+    }
+
+    /// Whether this is pcli2's thumbnail as downloaded
+    pub fn is_original(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Query parameters selecting this variant, empty for the original
+    pub fn query(&self) -> String {
+        let mut params = Vec::new();
+        for (key, value) in [
+            ("width", self.width),
+            ("height", self.height),
+            ("max_dimension", self.max_dimension),
+        ] {
+            if let Some(value) = value {
+                params.push(format!("{}={}", key, value));
+            }
+        }
+        if self.format != ImageFormat::Png {
+            params.push(format!("format={}", self.format.name()));
+        }
+        params.join("&")
+    }
+
+    /// Short name distinguishing this variant in cache keys, ending in the format
+    pub fn tag(&self) -> String {
+        format!(
+            "w{}-h{}-m{}-{}",
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.max_dimension.unwrap_or(0),
+            self.format.name()
+        )
+    }
+
+    /// Scale a PNG thumbnail down to this variant's bounds and encode it
+    ///
+    /// The aspect ratio is kept and images are never enlarged.
+    pub fn render(&self, png: &[u8]) -> Result<Vec<u8>, String> {
+        let image = Rgba::decode_png(png)?;
+        let limit = self.max_dimension.unwrap_or(u32::MAX);
+        let max_width = self.width.unwrap_or(u32::MAX).min(limit).min(image.width);
+        let max_height = self.height.unwrap_or(u32::MAX).min(limit).min(image.height);
+        let image = if (max_width, max_height) == (image.width, image.height) {
+            image
+        } else {
+            image.fit_within(max_width, max_height)
+        };
+        match self.format {
+            ImageFormat::Png => image.encode_png(),
+            ImageFormat::Jpeg => image.encode_jpeg(JPEG_QUALITY),
+            ImageFormat::Webp => image.encode_webp(),
+        }
+    }
+}
+
+fn checked_dimension(key: &str, value: u64) -> Result<u32, String> {
+    if !(MIN_VARIANT_DIMENSION as u64..=MAX_VARIANT_DIMENSION as u64).contains(&value) {
+        return Err(format!(
+            "Invalid argument '{}': value {} must be between {} and {}",
+            key, value, MIN_VARIANT_DIMENSION, MAX_VARIANT_DIMENSION
+        ));
+    }
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_spec_from_args_and_query() {
+        let spec =
+            VariantSpec::from_args(&json!({ "max_dimension": 128, "format": "jpg" })).unwrap();
+        assert_eq!(
+            spec,
+            VariantSpec {
+                max_dimension: Some(128),
+                format: ImageFormat::Jpeg,
+                ..VariantSpec::default()
+            }
+        );
+        assert_eq!(spec.query(), "max_dimension=128&format=jpeg");
+        assert_eq!(spec.tag(), "w0-h0-m128-jpeg");
+
+        let params: HashMap<String, String> = [
+            ("max_dimension", "128"),
+            ("format", "jpeg"),
+            ("sig", "ignored"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(VariantSpec::from_query(&params).unwrap(), spec);
+
+        assert!(VariantSpec::from_args(&json!({})).unwrap().is_original());
+        assert!(VariantSpec::from_args(&json!({ "width": 4 })).is_err());
+        assert!(VariantSpec::from_args(&json!({ "format": "gif" })).is_err());
+    }
+
+    #[test]
+    fn test_render_scales_down_only() {
+        let png = Rgba::new(400, 200, [10, 20, 30, 255]).encode_png().unwrap();
+
+        let spec = VariantSpec {
+            max_dimension: Some(100),
+            ..VariantSpec::default()
+        };
+        let small = Rgba::decode_png(&spec.render(&png).unwrap()).unwrap();
+        assert_eq!((small.width, small.height), (100, 50));
+
+        let spec = VariantSpec {
+            width: Some(1000),
+            ..VariantSpec::default()
+        };
+        let same = Rgba::decode_png(&spec.render(&png).unwrap()).unwrap();
+        assert_eq!((same.width, same.height), (400, 200));
+
+        let spec = VariantSpec {
+            height: Some(20),
+            format: ImageFormat::Jpeg,
+            ..VariantSpec::default()
+        };
+        assert!(spec.render(&png).unwrap().starts_with(&[0xff, 0xd8]));
+    }
+}
@@ -1,15 +1,26 @@
 use axum::body::Bytes;
-use axum::{body::to_bytes, extract::State, http::StatusCode, response::IntoResponse};
+use axum::{
+    body::to_bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
 use pcli2_mcp::{
     AppState,
+    backend::{Fixture, FixtureBackend, RecordingBackend, SubprocessBackend},
+    client::Pcli2Client,
     mcp::handle_mcp,
-    pcli::{PCLI2_BIN_ENV, run_pcli2_command, run_pcli2_tenant_list, run_pcli2_version},
+    pages::PageStore,
+    pcli::{PCLI2_BIN_ENV, run_pcli2_tenant_list, run_pcli2_version},
+    public_url::PublicUrl,
+    raster::Rgba,
+    thumbnail::{ThumbnailCache, ThumbnailCacheConfig},
 };
 use serde_json::{Value, json};
 use std::{
     fs,
     path::PathBuf,
-    sync::OnceLock,
+    sync::{Arc, OnceLock},
     time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::Mutex;
@@ -64,6 +75,54 @@ if [ "$1" = "tenant" ] && [ "$2" = "list" ]; then
   echo "tenant list ok"
   exit 0
 fi
+if [ "$1" = "folder" ] && [ "$2" = "part-match" ]; then
+  echo "part-match ok"
+  exit 0
+fi
+if [ "$1" = "folder" ] && [ "$2" = "geometric-match" ]; then
+  sleep 5
+  echo "geometric-match ok"
+  exit 0
+fi
+if [ "$1" = "asset" ] && [ "$2" = "get" ]; then
+  counter="$(dirname "$0")/asset-get-count"
+  count=$(( $(cat "$counter" 2>/dev/null || echo 0) + 1 ))
+  echo "$count" > "$counter"
+  echo "asset get $count"
+  exit 0
+fi
+if [ "$1" = "folder" ] && [ "$2" = "dependencies" ]; then
+  sleep 1
+  counter="$(dirname "$0")/dependencies-count"
+  count=$(( $(cat "$counter" 2>/dev/null || echo 0) + 1 ))
+  echo "$count" > "$counter"
+  echo "dependencies $count"
+  exit 0
+fi
+if [ "$1" = "tenant" ] && [ "$2" = "state" ]; then
+  counter="$(dirname "$0")/tenant-state-count"
+  count=$(( $(cat "$counter" 2>/dev/null || echo 0) + 1 ))
+  echo "$count" > "$counter"
+  if [ "$count" = "1" ]; then
+    echo "Error: HTTP 429 Too Many Requests" >&2
+    exit 1
+  fi
+  echo "tenant state ok"
+  exit 0
+fi
+if [ "$1" = "asset" ] && [ "$2" = "dependencies" ]; then
+  echo "Error: Asset not found" >&2
+  exit 1
+fi
+if [ "$1" = "asset" ] && [ "$2" = "metadata" ] && [ "$3" = "create" ]; then
+  echo "metadata create ok"
+  exit 0
+fi
+if [ "$1" = "tenant" ] && [ "$2" = "get" ]; then
+  sleep 5
+  echo "tenant get ok"
+  exit 0
+fi
 echo "unknown args" >&2
 exit 1
 "#;
@@ -84,7 +143,8 @@ async fn mock_pcli2_version_and_tenant_list() {
     let script_path = make_mock_pcli2();
     let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
 
-    let version = run_pcli2_version().await.expect("version");
+    let client = Pcli2Client::default();
+    let version = run_pcli2_version(&client).await.expect("version");
     assert_eq!(version.trim(), "pcli2 9.9.9");
 
     let args = json!({
@@ -92,7 +152,9 @@ async fn mock_pcli2_version_and_tenant_list() {
         "pretty": false,
         "headers": false
     });
-    let list = run_pcli2_tenant_list(args).await.expect("tenant list");
+    let list = run_pcli2_tenant_list(&client, args)
+        .await
+        .expect("tenant list");
     assert_eq!(list.trim(), "tenant list ok");
 }
 
@@ -102,20 +164,17 @@ async fn mock_pcli2_error_includes_label() {
     let script_path = make_mock_pcli2();
     let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
 
-    let err = run_pcli2_command(vec!["oops".to_string()], "pcli2 oops")
+    let err = Pcli2Client::default()
+        .run_argv(vec!["oops".to_string()], "pcli2 oops")
         .await
         .expect_err("expected error");
-    assert!(err.contains("pcli2 oops failed"));
+    assert!(err.to_string().contains("pcli2 oops failed"));
 }
 
 #[tokio::test]
 async fn jsonrpc_parse_error_returns_32700() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
-    let response = handle_mcp(State(state), Bytes::from("{bad json"))
+    let state = AppState::new("test", "0.0.0", None);
+    let response = handle_mcp(State(state), HeaderMap::new(), Bytes::from("{bad json"))
         .await
         .into_response();
     assert_eq!(response.status(), StatusCode::OK);
@@ -128,14 +187,14 @@ async fn jsonrpc_parse_error_returns_32700() {
 
 #[tokio::test]
 async fn jsonrpc_invalid_request_returns_32600() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
-    let response = handle_mcp(State(state), Bytes::from(r#"{"jsonrpc":"2.0","id":1}"#))
-        .await
-        .into_response();
+    let state = AppState::new("test", "0.0.0", None);
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(r#"{"jsonrpc":"2.0","id":1}"#),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), usize::MAX)
         .await
@@ -146,13 +205,10 @@ async fn jsonrpc_invalid_request_returns_32600() {
 
 #[tokio::test]
 async fn jsonrpc_notification_returns_no_content() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("test", "0.0.0", None);
     let response = handle_mcp(
         State(state),
+        HeaderMap::new(),
         Bytes::from(r#"{"jsonrpc":"2.0","method":"tools/list"}"#),
     )
     .await
@@ -171,11 +227,7 @@ async fn logging_on_tools_call_outputs_command() {
     let script_path = make_mock_pcli2();
     let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
 
-    let state = AppState {
-        server_name: "mock".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("mock", "0.0.0", None);
 
     let request = json!({
         "jsonrpc": "2.0",
@@ -186,19 +238,19 @@ async fn logging_on_tools_call_outputs_command() {
             "arguments": {}
         }
     });
-    let response = handle_mcp(State(state), Bytes::from(request.to_string()))
-        .await
-        .into_response();
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
 }
 
 #[tokio::test]
 async fn test_initialize_method() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("test", "0.0.0", None);
 
     let request = json!({
         "jsonrpc": "2.0",
@@ -206,9 +258,13 @@ async fn test_initialize_method() {
         "method": "initialize",
         "params": {}
     });
-    let response = handle_mcp(State(state), Bytes::from(request.to_string()))
-        .await
-        .into_response();
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
 
     let body = to_bytes(response.into_body(), usize::MAX)
@@ -223,11 +279,7 @@ async fn test_initialize_method() {
 
 #[tokio::test]
 async fn test_tools_list_method() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("test", "0.0.0", None);
 
     let request = json!({
         "jsonrpc": "2.0",
@@ -235,9 +287,13 @@ async fn test_tools_list_method() {
         "method": "tools/list",
         "params": {}
     });
-    let response = handle_mcp(State(state), Bytes::from(request.to_string()))
-        .await
-        .into_response();
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
 
     let body = to_bytes(response.into_body(), usize::MAX)
@@ -251,11 +307,7 @@ async fn test_tools_list_method() {
 
 #[tokio::test]
 async fn test_unknown_method_returns_error() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("test", "0.0.0", None);
 
     let request = json!({
         "jsonrpc": "2.0",
@@ -263,9 +315,13 @@ async fn test_unknown_method_returns_error() {
         "method": "unknown/method",
         "params": {}
     });
-    let response = handle_mcp(State(state), Bytes::from(request.to_string()))
-        .await
-        .into_response();
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
 
     let body = to_bytes(response.into_body(), usize::MAX)
@@ -278,11 +334,7 @@ async fn test_unknown_method_returns_error() {
 
 #[tokio::test]
 async fn test_jsonrpc_wrong_version() {
-    let state = AppState {
-        server_name: "test".to_string(),
-        server_version: "0.0.0".to_string(),
-        thumbnail_cache: std::sync::Arc::new(None),
-    };
+    let state = AppState::new("test", "0.0.0", None);
 
     let request = json!({
         "jsonrpc": "1.0",
@@ -290,9 +342,13 @@ async fn test_jsonrpc_wrong_version() {
         "method": "tools/list",
         "params": {}
     });
-    let response = handle_mcp(State(state), Bytes::from(request.to_string()))
-        .await
-        .into_response();
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
     assert_eq!(response.status(), StatusCode::OK);
 
     let body = to_bytes(response.into_body(), usize::MAX)
@@ -302,3 +358,1193 @@ async fn test_jsonrpc_wrong_version() {
 
     assert_eq!(value["error"]["code"], -32600);
 }
+
+#[tokio::test]
+async fn tools_call_timeout_returns_structured_error() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+
+    let state = AppState::new("test", "0.0.0", None);
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {
+            "name": "pcli2_tenant_get",
+            "arguments": { "timeout_seconds": 1 }
+        }
+    });
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    let value: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(value["error"]["code"], -32001);
+    assert_eq!(value["error"]["data"]["reason"], "timeout");
+    assert_eq!(value["error"]["data"]["tool"], "pcli2_tenant_get");
+    assert_eq!(value["error"]["data"]["timeout_seconds"], 1);
+}
+
+#[tokio::test]
+async fn tools_call_rejects_timeout_above_server_maximum() {
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.max_tool_timeout = std::time::Duration::from_secs(10);
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {
+            "name": "pcli2_version",
+            "arguments": { "timeout_seconds": 11 }
+        }
+    });
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    let value: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(value["error"]["code"], -32602);
+    assert!(
+        value["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("timeout_seconds")
+    );
+}
+
+#[tokio::test]
+async fn server_status_reports_limiter_stats() {
+    let state = AppState::new("test", "0.0.0", None);
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "pcli2_server_status", "arguments": {} }
+    });
+    let response = handle_mcp(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    let value: Value = serde_json::from_slice(&body).expect("json");
+    let text = value["result"]["content"][0]["text"]
+        .as_str()
+        .expect("text");
+    let status: Value = serde_json::from_str(text).expect("status json");
+
+    assert_eq!(status["pcli2"]["running"], 0);
+    assert_eq!(status["pcli2"]["queue_depth"], 0);
+    assert_eq!(status["pcli2"]["max_queue"], 64);
+}
+
+async fn call_tool_json(state: &AppState, name: &str, arguments: Value) -> Value {
+    call_tool_with_headers(state, HeaderMap::new(), name, arguments).await
+}
+
+async fn call_tool_with_headers(
+    state: &AppState,
+    headers: HeaderMap,
+    name: &str,
+    arguments: Value,
+) -> Value {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments }
+    });
+    let response = handle_mcp(
+        State(state.clone()),
+        headers,
+        Bytes::from(request.to_string()),
+    )
+    .await
+    .into_response();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    serde_json::from_slice(&body).expect("json")
+}
+
+fn result_text(value: &Value) -> &str {
+    value["result"]["content"][0]["text"]
+        .as_str()
+        .expect("text content")
+}
+
+#[tokio::test]
+async fn async_folder_match_runs_as_job() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+
+    let started = call_tool_json(
+        &state,
+        "pcli2_folder_part_match",
+        json!({ "folder_path": "/Root", "async": true }),
+    )
+    .await;
+    let started: Value = serde_json::from_str(result_text(&started)).expect("job json");
+    let job_id = started["job_id"].as_str().expect("job id").to_string();
+
+    let mut status = Value::Null;
+    for _ in 0..50 {
+        let response =
+            call_tool_json(&state, "pcli2_job_status", json!({ "job_id": job_id })).await;
+        status = serde_json::from_str(result_text(&response)).expect("status json");
+        if status["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(status["status"], "succeeded");
+
+    let result = call_tool_json(&state, "pcli2_job_result", json!({ "job_id": job_id })).await;
+    assert_eq!(result_text(&result), "part-match ok");
+
+    let listed = call_tool_json(&state, "pcli2_job_list", json!({ "status": "succeeded" })).await;
+    let listed: Value = serde_json::from_str(result_text(&listed)).expect("list json");
+    assert_eq!(listed["jobs"][0]["job_id"], job_id.as_str());
+}
+
+#[tokio::test]
+async fn async_job_can_be_cancelled() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+
+    // The mock sleeps for `tenant get`, but only folder tools accept `async`
+    let rejected = call_tool_json(&state, "pcli2_tenant_get", json!({ "async": true })).await;
+    assert_eq!(rejected["error"]["code"], -32602);
+
+    let started = call_tool_json(
+        &state,
+        "pcli2_folder_geometric_match",
+        json!({ "folder_path": "/Root", "async": true }),
+    )
+    .await;
+    let started: Value = serde_json::from_str(result_text(&started)).expect("job json");
+    let job_id = started["job_id"].as_str().expect("job id");
+
+    let cancelled = call_tool_json(&state, "pcli2_job_cancel", json!({ "job_id": job_id })).await;
+    let cancelled: Value = serde_json::from_str(result_text(&cancelled)).expect("cancel json");
+    assert_eq!(cancelled["status"], "cancelled");
+
+    let result = call_tool_json(&state, "pcli2_job_result", json!({ "job_id": job_id })).await;
+    assert_eq!(result["error"]["data"]["reason"], "job_not_finished");
+}
+
+#[tokio::test]
+async fn read_only_results_are_cached_until_invalidated() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+    let asset = json!({ "path": "/Root/part.stl" });
+
+    let first = call_tool_json(&state, "pcli2_asset_get", asset.clone()).await;
+    assert_eq!(result_text(&first), "asset get 1");
+    let cached = call_tool_json(&state, "pcli2_asset_get", asset.clone()).await;
+    assert_eq!(result_text(&cached), "asset get 1");
+
+    let bypassed = call_tool_json(
+        &state,
+        "pcli2_asset_get",
+        json!({ "path": "/Root/part.stl", "no_cache": true }),
+    )
+    .await;
+    assert_eq!(result_text(&bypassed), "asset get 2");
+    let still_cached = call_tool_json(&state, "pcli2_asset_get", asset.clone()).await;
+    assert_eq!(result_text(&still_cached), "asset get 1");
+
+    let refreshed = call_tool_json(
+        &state,
+        "pcli2_asset_get",
+        json!({ "path": "/Root/part.stl", "refresh": true }),
+    )
+    .await;
+    assert_eq!(result_text(&refreshed), "asset get 3");
+    let after_refresh = call_tool_json(&state, "pcli2_asset_get", asset.clone()).await;
+    assert_eq!(result_text(&after_refresh), "asset get 3");
+
+    call_tool_json(
+        &state,
+        "pcli2_asset_metadata_create",
+        json!({ "path": "/Root/part.stl", "name": "material", "value": "steel" }),
+    )
+    .await;
+    let after_mutation = call_tool_json(&state, "pcli2_asset_get", asset).await;
+    assert_eq!(result_text(&after_mutation), "asset get 4");
+
+    let status = call_tool_json(&state, "pcli2_server_status", json!({})).await;
+    let status: Value = serde_json::from_str(result_text(&status)).expect("status json");
+    assert_eq!(status["result_cache"]["hits"], 3);
+    assert_eq!(status["result_cache"]["invalidations"], 1);
+}
+
+#[tokio::test]
+async fn identical_concurrent_calls_share_one_pcli2_run() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+    let args = json!({ "folder_path": "/Root" });
+
+    let (first, second) = tokio::join!(
+        call_tool_json(&state, "pcli2_folder_dependencies", args.clone()),
+        call_tool_json(&state, "pcli2_folder_dependencies", args.clone()),
+    );
+    assert_eq!(result_text(&first), "dependencies 1");
+    assert_eq!(result_text(&second), "dependencies 1");
+
+    let later = call_tool_json(&state, "pcli2_folder_dependencies", args).await;
+    assert_eq!(result_text(&later), "dependencies 2");
+}
+
+#[tokio::test]
+async fn rate_limited_calls_are_retried() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+
+    let response = call_tool_json(&state, "pcli2_tenant_state", json!({})).await;
+    assert_eq!(result_text(&response), "tenant state ok");
+}
+
+#[tokio::test]
+async fn classified_errors_include_code_and_remedy() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+    let state = AppState::new("test", "0.0.0", None);
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_dependencies",
+        json!({ "path": "/Root/missing.stl" }),
+    )
+    .await;
+    let error = &response["error"];
+    assert_eq!(error["code"], -32602);
+    assert_eq!(error["data"]["reason"], "not_found");
+    assert_eq!(error["data"]["retryable"], false);
+    assert!(error["message"].as_str().unwrap().contains("Remedy:"));
+}
+
+#[tokio::test]
+async fn fixture_backend_answers_tool_calls() {
+    let fixture = |args: &[&str], stdout: &str| Fixture {
+        args: args.iter().map(|s| s.to_string()).collect(),
+        stdout: stdout.to_string(),
+        ..Fixture::default()
+    };
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::new(vec![
+        fixture(&["tenant", "list"], "acme\nglobex"),
+        fixture(
+            &["asset", "get", "--uuid", "*"],
+            "{\"name\": \"bracket.stl\"}",
+        ),
+    ]));
+
+    let response = call_tool_json(&state, "pcli2_tenant_list", json!({})).await;
+    assert_eq!(result_text(&response), "acme\nglobex");
+
+    let response = call_tool_json(&state, "pcli2_asset_get", json!({ "uuid": "a1" })).await;
+    assert_eq!(result_text(&response), "{\"name\": \"bracket.stl\"}");
+
+    let response = call_tool_json(&state, "pcli2_version", json!({})).await;
+    assert!(
+        response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("no fixture matches")
+    );
+
+    let status = call_tool_json(&state, "pcli2_server_status", json!({})).await;
+    let status: Value = serde_json::from_str(result_text(&status)).expect("status json");
+    assert_eq!(status["backend"], "fixture");
+}
+
+#[tokio::test]
+async fn recorded_session_replays_without_pcli2() {
+    let _lock = test_env_lock().lock().await;
+    let script_path = make_mock_pcli2();
+    let cassette = script_path.with_file_name("cassette");
+
+    {
+        let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, script_path.to_string_lossy().as_ref());
+        let mut state = AppState::new("test", "0.0.0", None);
+        state.backend = Arc::new(
+            RecordingBackend::with_cassette(Arc::new(SubprocessBackend::from_env()), &cassette)
+                .expect("cassette"),
+        );
+        for uuid in ["a", "b", "a"] {
+            call_tool_json(
+                &state,
+                "pcli2_asset_get",
+                json!({ "uuid": uuid, "no_cache": true }),
+            )
+            .await;
+        }
+        call_tool_json(&state, "pcli2_asset_dependencies", json!({ "uuid": "x" })).await;
+    }
+
+    let entry: Value =
+        serde_json::from_str(&fs::read_to_string(cassette.join("0000000001.json")).expect("entry"))
+            .expect("entry json");
+    assert_eq!(entry["args"], json!(["asset", "get", "--uuid", "a"]));
+    assert_eq!(entry["stdout"], "asset get 1\n");
+    assert!(entry["duration_ms"].is_u64());
+
+    let _guard = EnvVarGuard::set(PCLI2_BIN_ENV, "/nonexistent/pcli2");
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::load_cassette(&cassette).expect("replay"));
+    let mut answers = Vec::new();
+    for uuid in ["a", "b", "a"] {
+        let response = call_tool_json(
+            &state,
+            "pcli2_asset_get",
+            json!({ "uuid": uuid, "no_cache": true }),
+        )
+        .await;
+        answers.push(result_text(&response).to_string());
+    }
+    assert_eq!(answers, ["asset get 1", "asset get 2", "asset get 3"]);
+
+    let response = call_tool_json(&state, "pcli2_asset_dependencies", json!({ "uuid": "x" })).await;
+    assert_eq!(response["error"]["data"]["reason"], "not_found");
+}
+
+#[tokio::test]
+async fn large_results_are_paged_with_cursor() {
+    let listing = (1..=20)
+        .map(|n| format!("asset-{:02}.stl", n))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::new(vec![Fixture {
+        args: vec![
+            "folder".to_string(),
+            "dependencies".to_string(),
+            "*".to_string(),
+            "*".to_string(),
+        ],
+        stdout: listing.clone(),
+        ..Fixture::default()
+    }]));
+    state.pages = Arc::new(PageStore::new(100));
+
+    let mut response = call_tool_json(
+        &state,
+        "pcli2_folder_dependencies",
+        json!({ "folder_path": "/Root" }),
+    )
+    .await;
+    let header = result_text(&response).lines().next().unwrap().to_string();
+    assert!(header.starts_with("[Page 1 of 3: bytes 1-91 of 259, lines 1-7 of 20."));
+
+    let mut pages = Vec::new();
+    loop {
+        let text = result_text(&response);
+        pages.push(text.split_once('\n').unwrap().1.to_string());
+        let Some(cursor) = response["result"]["_meta"]["pagination"]["next_cursor"].as_str() else {
+            break;
+        };
+        response = call_tool_json(&state, "pcli2_result_page", json!({ "cursor": cursor })).await;
+    }
+    assert_eq!(pages.concat(), listing);
+
+    let response = call_tool_json(&state, "pcli2_result_page", json!({ "cursor": "nope.1" })).await;
+    assert!(
+        response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Unknown or expired cursor")
+    );
+}
+
+#[tokio::test]
+async fn data_url_thumbnails_are_never_paged() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let png = Rgba::new(64, 64, [20, 90, 200, 255])
+        .encode_png()
+        .expect("png");
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::new(vec![Fixture {
+        args: ["asset", "thumbnail", "--uuid", "a-uuid"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        file_base64: Some(BASE64_STANDARD.encode(&png)),
+        ..Fixture::default()
+    }]));
+    state.pages = Arc::new(PageStore::new(32));
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({ "uuid": "a-uuid", "response_mode": "data_url" }),
+    )
+    .await;
+    assert!(response["result"]["_meta"]["pagination"].is_null());
+    let html = result_text(&response);
+    assert!(html.len() > 32);
+    let start = html.find("data:image/png;base64,").expect("png data uri") + 22;
+    let encoded = &html[start..start + html[start..].find('"').unwrap()];
+    assert_eq!(BASE64_STANDARD.decode(encoded).expect("base64"), png);
+}
+
+#[tokio::test]
+async fn match_results_are_filtered_server_side() {
+    let rows = json!([
+        { "candidateAssetPath": "/Root/Src/b.stl", "matchPercentage": 99.0 },
+        { "candidateAssetPath": "/Root/Lib/c.stl", "matchPercentage": 91.5 },
+        { "candidateAssetPath": "/Root/Lib/d.stl", "matchPercentage": 95.0 }
+    ]);
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::new(vec![Fixture {
+        args: [
+            "asset",
+            "part-match",
+            "--path",
+            "/Root/Src/a.stl",
+            "-f",
+            "json",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+        stdout: rows.to_string(),
+        ..Fixture::default()
+    }]));
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_part_match",
+        json!({
+            "path": "/Root/Src/a.stl",
+            "limit": 1,
+            "exclude_path_prefix": "/Root/Src/",
+            "columns": ["path", "score"]
+        }),
+    )
+    .await;
+    let filtered: Value = serde_json::from_str(result_text(&response)).expect("json");
+    assert_eq!(
+        filtered,
+        json!([{ "path": "/Root/Lib/d.stl", "score": 95.0 }])
+    );
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_part_match",
+        json!({ "path": "/Root/Src/a.stl", "format": "csv", "limit": 1 }),
+    )
+    .await;
+    assert_eq!(response["error"]["data"]["reason"], "invalid_arguments");
+}
+
+#[tokio::test]
+async fn markdown_formats_are_rendered_server_side() {
+    let fixture = |args: &[&str], stdout: &str| Fixture {
+        args: args.iter().map(|s| s.to_string()).collect(),
+        stdout: stdout.to_string(),
+        ..Fixture::default()
+    };
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = Arc::new(FixtureBackend::new(vec![
+        fixture(
+            &["folder", "list", "-f", "json"],
+            r#"[{"name":"Parts","uuid":"f1","assets":3},{"name":"A|B","uuid":"f2","assets":0}]"#,
+        ),
+        fixture(
+            &["folder", "list", "-f", "tree"],
+            "Root\n├── Parts\n│   └── Bolts\n└── A|B\n",
+        ),
+    ]));
+
+    let response = call_tool_json(
+        &state,
+        "pcli2",
+        json!({ "format": "markdown", "columns": "name,assets" }),
+    )
+    .await;
+    assert_eq!(
+        result_text(&response),
+        "| name | assets |\n| --- | --- |\n| Parts | 3 |\n| A\\|B | 0 |"
+    );
+
+    let response = call_tool_json(&state, "pcli2", json!({ "format": "markdown_tree" })).await;
+    assert_eq!(
+        result_text(&response),
+        "- Root\n  - Parts\n    - Bolts\n  - A|B"
+    );
+
+    let response = call_tool_json(
+        &state,
+        "pcli2",
+        json!({ "format": "markdown", "max_cell_width": 1 }),
+    )
+    .await;
+    assert_eq!(response["error"]["data"]["reason"], "invalid_arguments");
+}
+
+#[tokio::test]
+async fn visual_match_report_includes_thumbnails() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let thumbnail = |uuid: &str| Fixture {
+        args: args(&["asset", "thumbnail", "--uuid", uuid]),
+        file_base64: Some(BASE64_STANDARD.encode(b"\x89PNG\r\n\x1a\nimage")),
+        ..Fixture::default()
+    };
+    let rows = json!([
+        { "candidateAssetPath": "/Root/a.stl", "candidateAssetUuid": "ref", "matchPercentage": 100.0 },
+        { "candidateAssetPath": "/Root/c.stl", "candidateAssetUuid": "c", "matchPercentage": 91.5 },
+        { "candidateAssetPath": "/Root/b.stl", "candidateAssetUuid": "b", "matchPercentage": 97.0 },
+        { "candidateAssetPath": "/Root/d.stl", "candidateAssetUuid": "d", "matchPercentage": 85.0 }
+    ]);
+    let cache_dir =
+        std::env::temp_dir().join(format!("pcli2-mcp-report-test-{}", std::process::id()));
+    let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+        cache_dir.clone(),
+        std::time::Duration::from_secs(60),
+        "localhost",
+        8080,
+    ))
+    .expect("thumbnail cache");
+    let mut state = AppState::new("test", "0.0.0", Some(cache));
+    state.backend = Arc::new(FixtureBackend::new(vec![
+        Fixture {
+            args: args(&["asset", "geometric-match", "--uuid", "ref", "-f", "json"]),
+            stdout: rows.to_string(),
+            ..Fixture::default()
+        },
+        thumbnail("ref"),
+        thumbnail("b"),
+        Fixture {
+            args: args(&["asset", "thumbnail", "--uuid", "c"]),
+            stderr: "Error: asset not found (404)".to_string(),
+            exit_code: Some(1),
+            ..Fixture::default()
+        },
+    ]));
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_visual_match_report",
+        json!({ "uuid": "ref", "top_k": 2, "format": "json" }),
+    )
+    .await;
+    let report: Value = serde_json::from_str(result_text(&response)).expect("json");
+    assert_eq!(report["match_type"], "geometric");
+    assert!(
+        report["reference"]["thumbnail_url"]
+            .as_str()
+            .unwrap()
+            .starts_with("http://localhost:8080/thumbnail/")
+    );
+    let matches = report["matches"].as_array().unwrap();
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0]["path"], "/Root/b.stl");
+    assert_eq!(matches[0]["score"], 97.0);
+    assert!(matches[0]["thumbnail_url"].is_string());
+    assert_eq!(matches[1]["path"], "/Root/c.stl");
+    assert!(matches[1]["thumbnail_url"].is_null());
+    assert!(matches[1]["thumbnail_error"].is_string());
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_visual_match_report",
+        json!({ "uuid": "ref", "top_k": 1 }),
+    )
+    .await;
+    let text = result_text(&response);
+    assert!(text.starts_with(
+        "## Geometric matches for ref\n\n![reference](http://localhost:8080/thumbnail/"
+    ));
+    assert!(text.contains("| 1 | 97 | /Root/b.stl | ![1](http://localhost:8080/thumbnail/"));
+
+    let _ = fs::remove_dir_all(cache_dir);
+}
+
+#[tokio::test]
+async fn contact_sheet_composes_thumbnails() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let png = Rgba::new(32, 32, [0, 90, 200, 255]).encode_png().unwrap();
+    let thumbnail = |flag: &str, id: &str| Fixture {
+        args: ["asset", "thumbnail", flag, id]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        file_base64: Some(BASE64_STANDARD.encode(&png)),
+        ..Fixture::default()
+    };
+    let backend = || {
+        Arc::new(FixtureBackend::new(vec![
+            thumbnail("--uuid", "a"),
+            thumbnail("--path", "/Root/b.stl"),
+        ]))
+    };
+
+    // Without a thumbnail cache the sheet comes back as an image block
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = backend();
+    let response = call_tool_json(
+        &state,
+        "pcli2_contact_sheet",
+        json!({ "assets": ["a", "/Root/b.stl", { "uuid": "missing", "label": "Missing part" }], "tile_size": 64 }),
+    )
+    .await;
+    let content = &response["result"]["content"];
+    assert_eq!(content[0]["type"], "image");
+    assert_eq!(content[0]["mimeType"], "image/png");
+    let sheet = Rgba::decode_png(
+        &BASE64_STANDARD
+            .decode(content[0]["data"].as_str().unwrap())
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!((sheet.width, sheet.height), (2 * 72 + 8, 2 * 94 + 8));
+    let legend = content[1]["text"].as_str().unwrap();
+    assert!(legend.starts_with("1. a\n2. /Root/b.stl\n3. Missing part - thumbnail unavailable:"));
+
+    // A match result is ranked by score and the sheet is cached behind a URL
+    let cache_dir =
+        std::env::temp_dir().join(format!("pcli2-mcp-sheet-test-{}", std::process::id()));
+    let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+        cache_dir.clone(),
+        std::time::Duration::from_secs(60),
+        "localhost",
+        8080,
+    ))
+    .expect("thumbnail cache");
+    let mut state = AppState::new("test", "0.0.0", Some(cache));
+    state.backend = backend();
+    let matches = json!([
+        { "candidateAssetPath": "/Root/b.stl", "matchPercentage": 88.0 },
+        { "candidateAssetPath": "/Root/a.stl", "candidateAssetUuid": "a", "matchPercentage": 99.5 }
+    ]);
+    let response = call_tool_json(
+        &state,
+        "pcli2_contact_sheet",
+        json!({ "match_result": matches.to_string() }),
+    )
+    .await;
+    let text = result_text(&response);
+    assert!(text.starts_with("![Contact sheet](http://localhost:8080/thumbnail/"));
+    assert!(text.ends_with("1. /Root/a.stl (score 99.5)\n2. /Root/b.stl (score 88)"));
+
+    let response = call_tool_json(&state, "pcli2_contact_sheet", json!({})).await;
+    assert_eq!(response["error"]["data"]["reason"], "invalid_arguments");
+
+    let _ = fs::remove_dir_all(cache_dir);
+}
+
+#[tokio::test]
+async fn cached_thumbnails_are_reused_until_refreshed() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let fixtures = FixtureBackend::new(vec![
+        Fixture {
+            args: args(&["asset", "get", "--path", "/Root/a.stl", "-f", "json"]),
+            stdout: json!({ "uuid": "a-uuid", "name": "a.stl" }).to_string(),
+            ..Fixture::default()
+        },
+        Fixture {
+            args: args(&["asset", "thumbnail", "--uuid", "a-uuid"]),
+            file_base64: Some(BASE64_STANDARD.encode(b"\x89PNG\r\n\x1a\nimage")),
+            ..Fixture::default()
+        },
+    ]);
+    let recorder = Arc::new(RecordingBackend::new(Arc::new(fixtures)));
+    let cache_dir = std::env::temp_dir().join(format!(
+        "pcli2-mcp-thumbnail-reuse-test-{}",
+        std::process::id()
+    ));
+    let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+        cache_dir.clone(),
+        std::time::Duration::from_secs(60),
+        "localhost",
+        8080,
+    ))
+    .expect("thumbnail cache");
+    let mut state = AppState::new("test", "0.0.0", Some(cache));
+    state.backend = recorder.clone();
+
+    let thumbnail_url = |response: &Value| {
+        let html = result_text(response);
+        let start = html.find("src=\"").expect("img src") + 5;
+        html[start..start + html[start..].find('"').unwrap()].to_string()
+    };
+    let first = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({ "path": "/Root/a.stl" }),
+    )
+    .await;
+    let second = call_tool_json(&state, "pcli2_asset_thumbnail", json!({ "uuid": "a-uuid" })).await;
+    assert_eq!(thumbnail_url(&first), thumbnail_url(&second));
+    assert!(thumbnail_url(&first).starts_with("http://localhost:8080/thumbnail/"));
+    let commands = |recorder: &RecordingBackend| {
+        recorder
+            .fixtures()
+            .iter()
+            .map(|fixture| fixture.args[1].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(commands(&recorder), ["get", "thumbnail"]);
+
+    let refreshed = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({ "path": "/Root/a.stl", "refresh": true }),
+    )
+    .await;
+    assert_eq!(thumbnail_url(&refreshed), thumbnail_url(&first));
+    assert_eq!(commands(&recorder), ["get", "thumbnail", "thumbnail"]);
+
+    let _ = fs::remove_dir_all(cache_dir);
+}
+
+#[tokio::test]
+async fn thumbnail_links_use_public_origin() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let fixtures = FixtureBackend::new(vec![Fixture {
+        args: ["asset", "thumbnail", "--uuid", "a-uuid"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        file_base64: Some(BASE64_STANDARD.encode(b"\x89PNG\r\n\x1a\nimage")),
+        ..Fixture::default()
+    }]);
+    let cache_dir =
+        std::env::temp_dir().join(format!("pcli2-mcp-public-url-test-{}", std::process::id()));
+    let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+        cache_dir.clone(),
+        std::time::Duration::from_secs(60),
+        "0.0.0.0",
+        8080,
+    ))
+    .expect("thumbnail cache");
+    let mut state = AppState::new("test", "0.0.0", Some(cache));
+    state.backend = Arc::new(fixtures);
+    state.public_url = PublicUrl::for_bind_host("0.0.0.0");
+
+    let mut headers = HeaderMap::new();
+    headers.insert("host", "10.1.2.3:8080".parse().unwrap());
+    let direct = call_tool_with_headers(
+        &state,
+        headers,
+        "pcli2_asset_thumbnail",
+        json!({ "uuid": "a-uuid" }),
+    )
+    .await;
+    assert!(result_text(&direct).contains("src=\"http://10.1.2.3:8080/thumbnail/"));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("host", "127.0.0.1:8080".parse().unwrap());
+    headers.insert("x-forwarded-host", "pcli2.example.com".parse().unwrap());
+    headers.insert("x-forwarded-proto", "https".parse().unwrap());
+    let proxied = call_tool_with_headers(
+        &state,
+        headers,
+        "pcli2_asset_thumbnail",
+        json!({ "uuid": "a-uuid" }),
+    )
+    .await;
+    assert!(result_text(&proxied).contains("src=\"https://pcli2.example.com/thumbnail/"));
+    assert!(!result_text(&proxied).contains("0.0.0.0"));
+
+    let _ = fs::remove_dir_all(cache_dir);
+}
+
+#[tokio::test]
+async fn thumbnail_variants_are_resized_and_reencoded() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let png = Rgba::new(300, 150, [20, 90, 200, 255])
+        .encode_png()
+        .expect("png");
+    let fixtures = FixtureBackend::new(vec![Fixture {
+        args: ["asset", "thumbnail", "--uuid", "a-uuid"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        file_base64: Some(BASE64_STANDARD.encode(&png)),
+        ..Fixture::default()
+    }]);
+    let cache_dir =
+        std::env::temp_dir().join(format!("pcli2-mcp-variant-test-{}", std::process::id()));
+    let cache = ThumbnailCache::new(ThumbnailCacheConfig::new(
+        cache_dir.clone(),
+        std::time::Duration::from_secs(60),
+        "localhost",
+        8080,
+    ))
+    .expect("thumbnail cache");
+    let mut state = AppState::new("test", "0.0.0", Some(cache));
+    state.backend = Arc::new(fixtures);
+
+    let inline = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({
+            "uuid": "a-uuid",
+            "response_mode": "data_url",
+            "max_dimension": 128,
+            "format": "jpeg"
+        }),
+    )
+    .await;
+    let html = result_text(&inline);
+    let start = html.find("data:image/jpeg;base64,").expect("jpeg data uri") + 23;
+    let encoded = &html[start..start + html[start..].find('"').unwrap()];
+    let jpeg = BASE64_STANDARD.decode(encoded).expect("base64");
+    assert!(jpeg.starts_with(&[0xff, 0xd8]));
+    assert!(jpeg.len() < png.len());
+
+    let linked = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({ "uuid": "a-uuid", "width": 64, "format": "webp" }),
+    )
+    .await;
+    assert!(result_text(&linked).contains("?width=64&format=webp\""));
+    // Each call is counted once: a miss that fetched it, then a hit
+    let stats = state.thumbnail_cache.as_ref().unwrap().stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+
+    let invalid = call_tool_json(
+        &state,
+        "pcli2_asset_thumbnail",
+        json!({ "uuid": "a-uuid", "max_dimension": 4 }),
+    )
+    .await;
+    assert!(
+        invalid["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("max_dimension")
+    );
+
+    let _ = fs::remove_dir_all(cache_dir);
+}
+
+/// Objects of the S3 stand-in, by bucket-relative key
+type Bucket = Arc<std::sync::Mutex<std::collections::BTreeMap<String, Vec<u8>>>>;
+
+/// Start a minimal S3-compatible service on its own runtime, as MinIO would run
+///
+/// It answers object PUT/GET/HEAD/DELETE and ListObjectsV2 pages of two keys,
+/// and refuses requests without a SigV4 authorization or presigned signature.
+fn start_s3_stand_in(bucket: Bucket) -> String {
+    use axum::extract::{Path as UrlPath, Query};
+    use axum::http::header::CONTENT_LENGTH;
+    use axum::routing::get;
+    use std::collections::HashMap;
+
+    fn signed(headers: &HeaderMap, query: &HashMap<String, String>) -> bool {
+        headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("AWS4-HMAC-SHA256 Credential=minio/"))
+            || query.contains_key("X-Amz-Signature")
+    }
+
+    let objects = bucket.clone();
+    let object = get(
+        |UrlPath((_, key)): UrlPath<(String, String)>,
+         Query(query): Query<HashMap<String, String>>,
+         headers: HeaderMap| async move {
+            if !signed(&headers, &query) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            match objects.lock().unwrap().get(&key) {
+                Some(data) => {
+                    ([(CONTENT_LENGTH, data.len().to_string())], data.clone()).into_response()
+                }
+                None => (
+                    StatusCode::NOT_FOUND,
+                    "<Error><Code>NoSuchKey</Code><Message>Not found</Message></Error>",
+                )
+                    .into_response(),
+            }
+        },
+    );
+    let objects = bucket.clone();
+    let object = object.put(
+        |UrlPath((_, key)): UrlPath<(String, String)>, headers: HeaderMap, body: Bytes| async move {
+            let hash = headers
+                .get("x-amz-content-sha256")
+                .and_then(|value| value.to_str().ok());
+            if !signed(&headers, &HashMap::new())
+                || hash != Some(pcli2_mcp::hash::sha256_hex(&body).as_str())
+            {
+                return StatusCode::FORBIDDEN;
+            }
+            objects.lock().unwrap().insert(key, body.to_vec());
+            StatusCode::OK
+        },
+    );
+    let objects = bucket.clone();
+    let object = object.delete(|UrlPath((_, key)): UrlPath<(String, String)>| async move {
+        objects.lock().unwrap().remove(&key);
+        StatusCode::NO_CONTENT
+    });
+    let objects = bucket;
+    let list = get(
+        |Query(query): Query<HashMap<String, String>>, headers: HeaderMap| async move {
+            if !signed(&headers, &HashMap::new())
+                || query.get("list-type").map(String::as_str) != Some("2")
+            {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let after = query.get("continuation-token").cloned().unwrap_or_default();
+            let objects = objects.lock().unwrap();
+            let keys: Vec<(&String, &Vec<u8>)> = objects
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix) && **key > after)
+                .collect();
+            let mut xml = String::from("<ListBucketResult>");
+            for (key, data) in keys.iter().take(2) {
+                xml.push_str(&format!(
+                    "<Contents><Key>{}</Key><LastModified>2026-01-01T00:00:00.000Z</LastModified><Size>{}</Size></Contents>",
+                    key,
+                    data.len()
+                ));
+            }
+            if keys.len() > 2 {
+                xml.push_str(&format!(
+                    "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                    keys[1].0
+                ));
+            } else {
+                xml.push_str("<IsTruncated>false</IsTruncated>");
+            }
+            xml.push_str("</ListBucketResult>");
+            xml.into_response()
+        },
+    );
+    let app = axum::Router::new()
+        .route("/:bucket", list)
+        .route("/:bucket/*key", object);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    listener.set_nonblocking(true).expect("nonblocking");
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().expect("runtime");
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).expect("listener");
+            axum::serve(listener, app).await.expect("serve");
+        });
+    });
+    format!("http://{}", address)
+}
+
+#[test]
+fn thumbnail_cache_works_on_s3_compatible_store() {
+    use pcli2_mcp::s3::{S3Config, S3Store};
+    use pcli2_mcp::store::ThumbnailStore;
+    use pcli2_mcp::thumbnail::PurgeFilter;
+    use pcli2_mcp::variant::VariantSpec;
+    use std::time::Duration;
+
+    let bucket = Bucket::default();
+    let endpoint = start_s3_stand_in(bucket.clone());
+    let store = Arc::new(
+        S3Store::new(
+            S3Config::new(&endpoint, "thumbs")
+                .with_prefix("replicas")
+                .with_credentials("minio", "minio-secret", None)
+                .with_presign(true),
+        )
+        .expect("store"),
+    );
+    let config =
+        ThumbnailCacheConfig::new(PathBuf::new(), Duration::from_secs(600), "localhost", 8080)
+            .with_store(store.clone());
+    let png = Rgba::new(16, 16, [10, 20, 30, 255])
+        .encode_png()
+        .expect("png");
+
+    let cache = ThumbnailCache::new(config.clone()).expect("cache");
+    let (key, _) = cache.save_thumbnail("/Root/a.stl", &png).expect("save");
+    assert!(
+        bucket
+            .lock()
+            .unwrap()
+            .contains_key(&format!("replicas/{}.png", key))
+    );
+    assert_eq!(cache.load_thumbnail(&key).expect("load"), png);
+    assert_eq!(
+        store.size(&format!("{}.png", key)).unwrap(),
+        Some(png.len() as u64)
+    );
+    assert_eq!(store.get("missing.png").unwrap(), None);
+    cache.save_thumbnail("/Root/b.stl", &png).expect("save");
+    cache.save_thumbnail("/Root/c.stl", &png).expect("save");
+
+    // Another replica sees the same thumbnails, across listing pages
+    let replica = ThumbnailCache::new(config).expect("replica");
+    assert_eq!(replica.list().expect("list").len(), 3);
+    assert_eq!(replica.load_thumbnail(&key).expect("load"), png);
+
+    // Presigned URLs fetch straight from the bucket
+    let url = replica
+        .presigned_url(&key, &VariantSpec::default(), Some(Duration::from_secs(60)))
+        .expect("presign")
+        .expect("presigned url");
+    assert!(url.starts_with(&format!("{}/thumbs/replicas/{}.png?", endpoint, key)));
+    assert!(url.contains("X-Amz-Expires=60"));
+    let mut fetched = Vec::new();
+    std::io::Read::read_to_end(
+        &mut ureq::get(&url).call().expect("fetch").into_reader(),
+        &mut fetched,
+    )
+    .expect("read");
+    assert_eq!(fetched, png);
+
+    assert_eq!(
+        replica
+            .purge(&PurgeFilter::Source("/Root/a.stl".to_string()))
+            .expect("purge"),
+        1
+    );
+    assert!(cache.load_thumbnail(&key).is_err());
+    assert_eq!(replica.purge(&PurgeFilter::All).expect("purge"), 2);
+    assert!(bucket.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn asset_create_uploads_and_waits_for_indexing() {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let fixtures = FixtureBackend::new(vec![
+        Fixture {
+            args: args(&[
+                "asset",
+                "create",
+                "--file",
+                "*",
+                "--folder-path",
+                "/Root/Parts",
+                "-f",
+                "json",
+            ]),
+            stdout: json!({ "uuid": "new-uuid", "path": "/Root/Parts/a.stl" }).to_string(),
+            ..Fixture::default()
+        },
+        // The new asset is not queued at the first poll, so waiting goes on
+        // until it has been listed as indexing and is gone again
+        Fixture {
+            args: args(&["tenant", "state", "--type", "indexing", "-f", "json"]),
+            stdout: json!([{ "uuid": "other-uuid" }]).to_string(),
+            ..Fixture::default()
+        },
+        Fixture {
+            args: args(&["tenant", "state", "--type", "indexing", "-f", "json"]),
+            stdout: json!([{ "uuid": "new-uuid" }, { "uuid": "other-uuid" }]).to_string(),
+            ..Fixture::default()
+        },
+        Fixture {
+            args: args(&["tenant", "state", "--type", "indexing", "-f", "json"]),
+            stdout: json!([{ "uuid": "other-uuid" }]).to_string(),
+            ..Fixture::default()
+        },
+        Fixture {
+            args: args(&["tenant", "state", "--type", "failed", "-f", "json"]),
+            stdout: "[]".to_string(),
+            ..Fixture::default()
+        },
+    ]);
+    let recorder = Arc::new(RecordingBackend::new(Arc::new(fixtures)));
+    let mut state = AppState::new("test", "0.0.0", None);
+    state.backend = recorder.clone();
+
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_create",
+        json!({
+            "file_base64": BASE64_STANDARD.encode(b"solid a"),
+            "file_name": "a.stl",
+            "folder_path": "/Root/Parts",
+            "wait": true
+        }),
+    )
+    .await;
+    let result: Value = serde_json::from_str(result_text(&response)).expect("upload json");
+    assert_eq!(result["assets"]["uuid"], "new-uuid");
+    assert_eq!(result["indexing"], "finished");
+    let polls: Vec<String> = recorder.fixtures()[1..]
+        .iter()
+        .map(|fixture| fixture.args[3].clone())
+        .collect();
+    assert_eq!(
+        polls,
+        ["indexing", "failed", "indexing", "indexing", "failed"]
+    );
+
+    // The staged copy is handed to pcli2 under its own name and removed afterwards
+    let staged = PathBuf::from(&recorder.fixtures()[0].args[3]);
+    assert!(staged.ends_with("a.stl"));
+    assert!(!staged.exists());
+
+    // Server files need an upload root
+    let response = call_tool_json(
+        &state,
+        "pcli2_asset_create",
+        json!({ "file_path": "/etc/hostname", "folder_path": "/Root/Parts" }),
+    )
+    .await;
+    assert!(
+        response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("--upload-root")
+    );
+}
+
+#[tokio::test]
+async fn visual_match_report_rejects_threshold() {
+    let state = AppState::new("test", "0.0.0", None);
+    let response = call_tool_json(
+        &state,
+        "pcli2_visual_match_report",
+        json!({ "uuid": "a1", "match_type": "visual", "threshold": 90 }),
+    )
+    .await;
+    assert_eq!(response["error"]["data"]["reason"], "invalid_arguments");
+}